@@ -1,7 +1,32 @@
 #include "bridge.hh"
 //#include "../target/cxxbridge/sleigh-sys/src/lib.rs.h"
 #include "sleigh-sys/src/lib.rs.h"
+
+#ifdef SLEIGH_NO_THREADS
+// Targets without threads, like WASI, have no std::mutex, and nothing to guard against
+struct Mutex {};
+struct Lock {
+  explicit Lock(Mutex &) {}
+};
+#else
 #include <mutex>
+using Mutex = std::mutex;
+using Lock = std::lock_guard<std::mutex>;
+#endif
+
+#include "../decompiler/funcdata.hh"
+#include "../decompiler/ghidra_process.hh"
+#include "../decompiler/inject_sleigh.hh"
+#include "../decompiler/pcodeparse.hh"
+#include "../decompiler/slgh_compile.hh"
+
+extern int yylex_destroy(void);
+
+unique_ptr<Disassembler> newDisassembler(RustLoadImage *loadImage,
+                                         unique_ptr<DocumentStorage> spec) {
+  auto l = unique_ptr<LoadImage>(new RustLoadImageProxy(loadImage));
+  return make_unique<Disassembler>(move(l), move(spec));
+}
 
 unique_ptr<Decompiler> newDecompiler(RustLoadImage *loadImage,
                                      unique_ptr<DocumentStorage> spec) {
@@ -9,12 +34,33 @@ unique_ptr<Decompiler> newDecompiler(RustLoadImage *loadImage,
   return make_unique<Decompiler>(move(l), move(spec));
 }
 
+unique_ptr<PcodeEmulator> newPcodeEmulator(RustLoadImage *loadImage,
+                                           unique_ptr<DocumentStorage> spec) {
+  auto l = unique_ptr<LoadImage>(new RustLoadImageProxy(loadImage));
+  return make_unique<PcodeEmulator>(move(l), move(spec));
+}
+
+unique_ptr<PcodeCacheEmulator>
+newPcodeCacheEmulator(const Decompiler &translator) {
+  return make_unique<PcodeCacheEmulator>(&translator);
+}
+
 unique_ptr<Address> newAddress() { return make_unique<Address>(); }
 
+unique_ptr<Address> copyAddress(const Address &addr) {
+  return make_unique<Address>(addr);
+}
+
 uint32_t getAddrSpaceType(const AddrSpace &space) {
   return (uint32_t)space.getType();
 }
 
+rust::Str getOpName(uint32_t opcode) {
+  if (opcode >= CPUI_MAX)
+    return rust::Str();
+  return get_opname((OpCode)opcode);
+}
+
 unique_ptr<Address> getVarnodeDataAddress(const VarnodeData &data) {
   return make_unique<Address>(data.getAddr());
 }
@@ -23,9 +69,11 @@ unique_ptr<ContextDatabase> newContext() {
   return unique_ptr<ContextDatabase>(new ContextInternal());
 }
 
+// The XML parser keeps its state in globals, so every parse is serialized.
+static Mutex xmlLock;
+
 unique_ptr<DocumentStorage> newDocumentStorage(const std::string &s) {
-  static std::mutex lock;
-  std::lock_guard<std::mutex> guard(lock);
+  Lock guard(xmlLock);
 
   auto doc = make_unique<DocumentStorage>();
   std::stringstream ss;
@@ -35,6 +83,189 @@ unique_ptr<DocumentStorage> newDocumentStorage(const std::string &s) {
   return doc;
 }
 
+// The print languages and Ghidra's commands register themselves here, the
+// first time only
+static void initializeCapabilities() {
+  static bool initialized = (CapabilityPoint::initializeAll(), true);
+  (void)initialized;
+}
+
+unique_ptr<DecompilerArchitecture>
+newDecompilerArchitecture(RustLoadImage *loadImage, RustConstantPool *pool,
+                          const std::string &sla, const std::string &pspec,
+                          const std::string &cspec) {
+  initializeCapabilities();
+
+  auto store = make_unique<DocumentStorage>();
+  {
+    Lock guard(xmlLock);
+    for (const std::string *text : {&sla, &pspec, &cspec}) {
+      std::istringstream s(*text);
+      store->registerTag(store->parseDocument(s)->getRoot());
+    }
+  }
+  return make_unique<DecompilerArchitecture>(loadImage, pool, move(store));
+}
+
+unique_ptr<Document> parseDocument(const std::string &s) {
+  Lock guard(xmlLock);
+
+  std::stringstream ss;
+  ss << s;
+  auto doc = unique_ptr<Document>(xml_tree(ss));
+  if (doc->getChildren().empty())
+    throw XmlError("Document has no root element");
+  return doc;
+}
+
+// The SLEIGH parser and lexer are globals too, and report diagnostics on cerr.
+static Mutex compileLock;
+
+rust::String compileSlaspec(const std::string &input, const std::string &output,
+                            rust::Slice<const rust::Str> names,
+                            rust::Slice<const rust::Str> values) {
+  Lock guard(compileLock);
+
+  map<string, string> defines;
+  for (size_t i = 0; i < names.size() && i < values.size(); ++i)
+    defines[std::string(names[i])] = std::string(values[i]);
+
+  std::ostringstream log;
+  auto *saved = cerr.rdbuf(log.rdbuf());
+  int4 res;
+  {
+    SleighCompile compiler;
+    compiler.setAllOptions(defines, false, false, false, false, false, true,
+                           false, false);
+    try {
+      res = compiler.run_compilation(input, output);
+    } catch (...) {
+      cerr.rdbuf(saved);
+      yylex_destroy();
+      throw;
+    }
+  }
+  cerr.rdbuf(saved);
+  if (res != 0) {
+    // A failed parse leaves the lexer pointing at the old input
+    yylex_destroy();
+    throw LowlevelError(log.str());
+  }
+  return rust::String(log.str());
+}
+
+EngineVersion engineVersion() {
+  return EngineVersion{ArchitectureCapability::getMajorVersion(),
+                       ArchitectureCapability::getMinorVersion()};
+}
+
+// The release of the vendored sources, which the bindings are written against
+EngineVersion supportedEngineVersion() { return EngineVersion{4, 1}; }
+
+// The architectures registered by the client, defined with Ghidra's commands
+extern vector<ArchitectureGhidra *> archlist;
+
+namespace {
+// Reads and writes the client's streams in blocks. Running out of input in
+// the middle of a message throws, where the decompiler would exit.
+class RustProtocolBuf : public std::streambuf {
+  RustProtocolStream *inner;
+  char input[4096];
+  char output[4096];
+  bool endAllowed = false;
+
+protected:
+  virtual int_type underflow() {
+    size_t size =
+        inner->read(rust::Slice<uint8_t>((uint8_t *)input, sizeof(input)));
+    if (size == 0) {
+      if (endAllowed)
+        return traits_type::eof();
+      throw std::runtime_error("The client closed the stream mid-message");
+    }
+    setg(input, input, input + size);
+    return traits_type::to_int_type(*gptr());
+  }
+
+  virtual int_type overflow(int_type c) {
+    sync();
+    if (!traits_type::eq_int_type(c, traits_type::eof())) {
+      *pptr() = traits_type::to_char_type(c);
+      pbump(1);
+    }
+    return traits_type::not_eof(c);
+  }
+
+  virtual int sync() {
+    inner->write(rust::Slice<const uint8_t>((const uint8_t *)pbase(),
+                                            pptr() - pbase()));
+    setp(output, output + sizeof(output));
+    inner->flush();
+    return 0;
+  }
+
+public:
+  RustProtocolBuf(RustProtocolStream *inner) : inner(inner) {
+    setp(output, output + sizeof(output));
+  }
+
+  // Whether the client closed its stream between two commands
+  bool atEnd() {
+    endAllowed = true;
+    bool end = traits_type::eq_int_type(sgetc(), traits_type::eof());
+    endAllowed = false;
+    return end;
+  }
+};
+} // namespace
+
+// Ghidra's commands always talk over cin and cout, and parse XML throughout
+static Mutex protocolLock;
+
+void serveGhidraProtocol(RustProtocolStream *stream) {
+  initializeCapabilities();
+  Lock session(protocolLock);
+  Lock guard(xmlLock);
+
+  RustProtocolBuf buf(stream);
+  auto *savedIn = cin.rdbuf(&buf);
+  auto *savedOut = cout.rdbuf(&buf);
+  // Errors of the stream surface as exceptions rather than stream states
+  cin.exceptions(std::ios::badbit);
+  cout.exceptions(std::ios::badbit);
+  auto restore = [&] {
+    cin.exceptions(std::ios::goodbit);
+    cout.exceptions(std::ios::goodbit);
+    cin.clear();
+    cout.clear();
+    cin.rdbuf(savedIn);
+    cout.rdbuf(savedOut);
+    // Programs the client didn't deregister go with the session
+    for (ArchitectureGhidra *ghidra : archlist)
+      delete ghidra;
+    archlist.clear();
+  };
+  try {
+    int4 status = 0;
+    while (status == 0 && !buf.atEnd())
+      status = GhidraCapability::readCommand(cin, cout);
+  } catch (...) {
+    restore();
+    throw;
+  }
+  restore();
+}
+
+const Element &getDocumentRoot(const Document &doc) { return *doc.getRoot(); }
+
+size_t getElementNumChildren(const Element &el) {
+  return el.getChildren().size();
+}
+
+const Element &getElementChild(const Element &el, size_t i) {
+  return *el.getChildren()[i];
+}
+
 void RustLoadImageProxy::loadFill(uint1 *ptr, int4 size,
                                   const Address &address) {
   return inner->load_fill(ptr, size, address);
@@ -47,36 +278,2245 @@ void RustLoadImageProxy::adjustVma(long adjust) {
 void RustPCodeEmitProxy::dump(const Address &addr, OpCode opc,
                               VarnodeData *outvar, VarnodeData *vars,
                               int4 isize) {
-  inner->dump(addr, (uint32_t)opc, outvar, vars, isize);
+  // Once Rust asks to stop, the rest of the instruction is built but dropped,
+  // so the translation still completes with its length
+  if (stopped)
+    return;
+  // Abort the emit rather than hand Rust an unbounded number of ops
+  if (limit != 0 && ++count > limit) {
+    exceeded = true;
+    throw LowlevelError("Too many pcode ops in one instruction");
+  }
+  stopped = !inner->dump(addr, (uint32_t)opc, outvar, vars, isize);
 }
 
-int32_t Decompiler::translate(RustPCodeEmit *emit, uint64_t addr) const {
-  auto address = Address(this->getDefaultCodeSpace(), addr);
-  auto p = RustPCodeEmitProxy(emit);
-  int32_t n = 0;
+// Collects the ops of an instruction into flat arrays, so they cross over to
+// Rust in one piece instead of one callback per op.
+class PackedPcodeEmit : public PcodeEmit {
+private:
+  PackedInstruction &out;
+  uint32_t limit; // 0 for no limit
+
+  const OverlaySpace *overlay = (const OverlaySpace *)0;
+
+  // Instructions decoded in an overlay are built with the varnodes of the
+  // space it overlays, which Ghidra moves into the overlay as they are emitted.
+  PackedVarnode pack(VarnodeData data, bool spaceId) const {
+    if (overlay != (const OverlaySpace *)0) {
+      AddrSpace *base = overlay->getBaseSpace();
+      if (data.space == base)
+        data.space = (AddrSpace *)overlay;
+      else if (spaceId && data.offset == (uintb)(uintptr_t)base)
+        data.offset = (uintb)(uintptr_t)overlay;
+    }
+    return packVarnode(data);
+  }
+
+public:
+  bool exceeded = false;
+
+  PackedPcodeEmit(PackedInstruction &out, uint32_t limit)
+      : out(out), limit(limit) {}
+
+  // Report the varnodes of instructions in space under it if it is an overlay
+  void setSpace(AddrSpace *space) {
+    overlay = space->isOverlay() ? (const OverlaySpace *)space
+                                 : (const OverlaySpace *)0;
+  }
+
+  virtual void dump(const Address &addr, OpCode opc, VarnodeData *outvar,
+                    VarnodeData *vars, int4 isize) {
+    if (limit != 0 && out.ops.size() >= limit) {
+      exceeded = true;
+      throw LowlevelError("Too many pcode ops in one instruction");
+    }
+    PackedOp op{(uint32_t)opc, outvar != (VarnodeData *)0,
+                PackedVarnode{-1, 0, 0}, (uint32_t)out.inputs.size(),
+                (uint32_t)isize};
+    if (op.has_output)
+      op.output = pack(*outvar, false);
+    bool memory = opc == CPUI_LOAD || opc == CPUI_STORE;
+    for (int4 i = 0; i < isize; ++i)
+      out.inputs.push_back(pack(vars[i], memory && i == 0));
+    out.ops.push_back(op);
+  }
+};
+
+Decompiler::~Decompiler() {
+  for (auto behave : behaviors)
+    delete behave;
+}
+
+// Runs decode, which returns the length of an instruction, and turns whatever
+// the engine throws into a status Rust can tell apart.
+template <typename F> static TranslateStatus runTranslate(F decode) {
   try {
-    n = this->oneInstruction(p, address);
+    return TranslateStatus{decode(), TranslateError::None, rust::String()};
+  } catch (UnimplError &err) {
+    return TranslateStatus{err.instruction_length, TranslateError::Unimplemented,
+                           rust::String::lossy(err.explain)};
+  } catch (BadDataError &err) {
+    return TranslateStatus{0, TranslateError::BadData,
+                           rust::String::lossy(err.explain)};
+  } catch (DataUnavailError &err) {
+    return TranslateStatus{0, TranslateError::Unavailable,
+                           rust::String::lossy(err.explain)};
+  } catch (LowlevelError &err) {
+    return TranslateStatus{0, TranslateError::Engine,
+                           rust::String::lossy(err.explain)};
   } catch (...) {
-    // TODO
+    return TranslateStatus{0, TranslateError::Engine,
+                           rust::String("unknown error")};
+  }
+}
+
+// A spec's own unimpl op if it has one, else the first index it doesn't use
+uint32_t Decompiler::getUnimplementedUserOp() const {
+  vector<string> names;
+  getUserOpNames(names);
+  for (size_t i = 0; i < names.size(); ++i)
+    if (names[i] == "unimpl")
+      return i;
+  return names.size();
+}
+
+// The first op registered reserves the spec's unimplemented index as "unimpl",
+// so the index the unimplemented policy calls doesn't change.
+uint32_t Decompiler::addUserOp(const std::string &name) {
+  if (name.empty())
+    throw LowlevelError("Empty user op name");
+  vector<string> names;
+  getUserOpNames(names);
+  for (size_t i = 0; i < names.size(); ++i)
+    if (names[i] == name)
+      return i;
+  if (addedUserOps.empty() && getUnimplementedUserOp() == names.size()) {
+    addedUserOps.push_back("unimpl");
+    if (name == "unimpl")
+      return names.size();
+    names.push_back("unimpl");
+  }
+  addedUserOps.push_back(name);
+  return names.size();
+}
+
+rust::Vec<rust::String> Decompiler::getUserOps() const {
+  vector<string> names;
+  getUserOpNames(names);
+  rust::Vec<rust::String> out;
+  for (const auto &name : names)
+    out.push_back(rust::String::lossy(name));
+  return out;
+}
+
+void Decompiler::getUserOpNames(vector<string> &res) const {
+  Disassembler::getUserOpNames(res);
+  res.insert(res.end(), addedUserOps.begin(), addedUserOps.end());
+}
+
+// Checked here rather than when the pcode is emitted, so a bad injection
+// fails the call that set it.
+void Decompiler::setInjection(uint64_t addr, uint32_t length,
+                              const PackedInstruction &pcode) {
+  auto varnode = [&](const PackedVarnode &vn) {
+    VarnodeData data;
+    data.space = spaceByIndex(vn.space);
+    data.offset = vn.offset;
+    data.size = vn.size;
+    return data;
+  };
+  Injection injection{(int4)length, {}};
+  for (const auto &op : pcode.ops) {
+    if (op.opcode == 0 || op.opcode >= CPUI_MAX)
+      throw LowlevelError("Unknown opcode " + std::to_string(op.opcode));
+    if ((size_t)op.first_input + op.input_count > pcode.inputs.size())
+      throw LowlevelError("Op inputs out of range");
+    InjectedOp injected{(OpCode)op.opcode, op.has_output, {}, {}};
+    if (op.has_output)
+      injected.output = varnode(op.output);
+    for (uint32_t i = 0; i < op.input_count; ++i)
+      injected.inputs.push_back(varnode(pcode.inputs[op.first_input + i]));
+    injection.ops.push_back(move(injected));
+  }
+  injections[Address(getDefaultCodeSpace(), addr)] = move(injection);
+}
+
+bool Decompiler::removeInjection(uint64_t addr) {
+  return injections.erase(Address(getDefaultCodeSpace(), addr)) != 0;
+}
+
+// Translates the instruction at addr, standing in for one with unimpl
+// semantics as the policy says, or with the pcode injected there.
+// substituted tells whether it did.
+int4 Decompiler::liftInstruction(PcodeEmit &emit, const Address &addr,
+                                 bool &substituted) const {
+  auto injected = injections.find(addr);
+  if (injected != injections.end()) {
+    substituted = true;
+    const Injection &injection(injected->second);
+    int4 length = injection.length != 0 ? injection.length
+                                         : instructionLength(addr);
+    for (auto op : injection.ops)
+      emit.dump(addr, op.opcode, op.hasOutput ? &op.output : (VarnodeData *)0,
+                op.inputs.data(), op.inputs.size());
+    return length;
+  }
+  substituted = false;
+  try {
+    return oneInstruction(emit, addr);
+  } catch (UnimplError &err) {
+    // Misaligned addresses are reported as unimplemented too, with no length
+    if (unimplementedPolicy == UnimplementedPolicy::Error ||
+        err.instruction_length <= 0)
+      throw;
+    substituted = true;
+    if (unimplementedPolicy == UnimplementedPolicy::CallOther) {
+      VarnodeData index;
+      index.space = getConstantSpace();
+      index.offset = getUnimplementedUserOp();
+      index.size = 4;
+      emit.dump(addr, CPUI_CALLOTHER, (VarnodeData *)0, &index, 1);
+    }
+    return err.instruction_length;
   }
-  return n;
 }
 
-int32_t Decompiler::disassemble(RustAssemblyEmit *emit, uint64_t addr) const {
+TranslateStatus Decompiler::translate(RustPCodeEmit *emit,
+                                      uint64_t addr) const {
+  auto address = Address(this->getDefaultCodeSpace(), addr);
+  auto p = RustPCodeEmitProxy(emit, opLimit);
+  bool substituted;
+  auto status = runTranslate(
+      [&] { return liftInstruction(p, address, substituted); });
+  if (p.exceeded)
+    status.error = TranslateError::OpLimit;
+  else if (p.stopped && status.error == TranslateError::None)
+    status.error = TranslateError::Stopped;
+  return status;
+}
+
+// The space with the given index, for addresses Rust names by space index.
+AddrSpace *Decompiler::spaceByIndex(int32_t index) const {
+  if (index < 0 || index >= numSpaces() || getSpace(index) == (AddrSpace *)0)
+    throw LowlevelError("No address space with index " + std::to_string(index));
+  return getSpace(index);
+}
+
+// Leaves the parse in the disassembly cache, where printAssembly and
+// oneInstruction pick it up as long as it isn't evicted
+TranslateStatus Decompiler::decodeInstruction(uint64_t addr) const {
+  return runTranslate([&] {
+    if (alignment != 1 && addr % alignment != 0)
+      throw UnimplError("Instruction address not aligned", 0);
+    Address address(getDefaultCodeSpace(), addr);
+    return obtainContext(address, ParserContext::pcode)->getLength();
+  });
+}
+
+TranslateStatus Decompiler::translatePacked(uint64_t addr,
+                                            PackedInstruction &out) const {
+  return translatePackedIn(getDefaultCodeSpace()->getIndex(), addr, out);
+}
+
+// Reuses the vectors of out, which the caller keeps around between calls.
+TranslateStatus Decompiler::translatePackedIn(int32_t space, uint64_t addr,
+                                              PackedInstruction &out) const {
+  out.length = 0;
+  out.ops.clear();
+  out.inputs.clear();
+  out.origins.clear();
+  out.bytes.clear();
+  PackedPcodeEmit emit(out, opLimit);
+  bool substituted = false;
+  auto status = runTranslate([&] {
+    AddrSpace *spc = spaceByIndex(space);
+    emit.setSpace(spc);
+    Address address(spc, addr);
+    int4 length = liftInstruction(emit, address, substituted);
+    copyInstructionBytes(address, length, out.bytes);
+    return length;
+  });
+  if (emit.exceeded)
+    status.error = TranslateError::OpLimit;
+  if (status.error == TranslateError::None) {
+    out.length = status.length;
+    // The cacher is left with whatever was built before giving up
+    const auto &origins(getPcodeCacher().getOrigins());
+    if (!substituted && !origins.empty()) {
+      uint64_t origin = addr;
+      auto next = origins.begin();
+      for (size_t i = 0; i < out.ops.size(); ++i) {
+        for (; next != origins.end() && next->first <= (int4)i; ++next)
+          origin = next->second.getOffset();
+        out.origins.push_back(origin);
+      }
+    }
+  } else {
+    out.ops.clear();
+    out.inputs.clear();
+    out.bytes.clear();
+  }
+  return status;
+}
+
+// Copies the bytes of the instruction at addr and its delay slots out of the
+// contexts translating it left in the cache, rather than reading them again
+void Decompiler::copyInstructionBytes(const Address &addr, int4 length,
+                                      rust::Vec<uint8_t> &out) const {
+  out.reserve(length);
+  // Injected pcode may stand in for bytes that don't decode
+  if (injections.count(addr) != 0) {
+    vector<uint1> buf(length);
+    loadImage->loadFill(buf.data(), length, addr);
+    for (auto b : buf)
+      out.push_back(b);
+    return;
+  }
+  int4 offset = 0;
+  while (offset < length) {
+    ParserContext *pos =
+        obtainContext(addr + offset, ParserContext::disassembly);
+    int4 len = std::min(pos->getLength(), length - offset);
+    if (len <= 0)
+      break;
+    const uint1 *buf = pos->getBuffer();
+    for (int4 i = 0; i < len; ++i)
+      out.push_back(buf[i]);
+    offset += len;
+  }
+}
+
+void Decompiler::fillBytes(uint64_t addr, rust::Slice<uint8_t> buf) const {
+  fillBytesIn(getDefaultCodeSpace()->getIndex(), addr, buf);
+}
+
+void Decompiler::fillBytesIn(int32_t space, uint64_t addr,
+                             rust::Slice<uint8_t> buf) const {
+  AddrSpace *spc = spaceByIndex(space);
+  if (buf.size() != 0)
+    loadImage->loadFill(buf.data(), buf.size(), Address(spc, addr));
+}
+
+TranslateStatus Disassembler::disassemble(RustAssemblyEmit *emit,
+                                          uint64_t addr) const {
   auto address = Address(this->getDefaultCodeSpace(), addr);
   auto p = RustAssemblyEmitProxy(emit);
-  int32_t n = 0;
-  try {
-    n = this->printAssembly(p, address);
-  } catch (...) {
-    // TODO
+  return runTranslate([&] { return printAssembly(p, address); });
+}
+
+void Disassembler::applyProcessorSpec(const Element &pspec) {
+  for (auto el : pspec.getChildren()) {
+    if (el->getName() != "context_data")
+      continue;
+    for (auto set : el->getChildren()) {
+      // tracked_set describes register values, not context variables
+      if (set->getName() != "context_set")
+        continue;
+      bool ranged = false;
+      for (int4 i = 0; i < set->getNumAttributes(); ++i)
+        if (set->getAttributeName(i) == "first" ||
+            set->getAttributeName(i) == "last")
+          ranged = true;
+      Range range;
+      if (ranged)
+        range.restoreXml(set, this);
+      for (auto var : set->getChildren()) {
+        std::istringstream s(var->getAttributeValue("val"));
+        s.unsetf(std::ios::dec | std::ios::hex | std::ios::oct);
+        uintm val;
+        s >> val;
+        const string &name(var->getAttributeValue("name"));
+        if (ranged)
+          context.setVariableRegion(name, range.getFirstAddr(),
+                                    range.getLastAddrOpen(this), val);
+        else
+          context.setVariableDefault(name, val);
+      }
+    }
+  }
+}
+
+// The database keeps its name lookup protected, so the bit range of a context
+// variable is rebuilt from its symbol instead.
+static ContextBitRange contextBits(const SleighBase &sleigh,
+                                   const std::string &name) {
+  SleighSymbol *sym = sleigh.findSymbol(name);
+  if (sym == (SleighSymbol *)0 ||
+      sym->getType() != SleighSymbol::context_symbol)
+    throw LowlevelError("Unknown context variable: " + name);
+  ContextField *field =
+      (ContextField *)((ContextSymbol *)sym)->getPatternValue();
+  return ContextBitRange(field->getStartBit(), field->getEndBit());
+}
+
+unique_ptr<Address> Decompiler::getCodeAddress(uint64_t offset) const {
+  return make_unique<Address>(getDefaultCodeSpace(), offset);
+}
+
+unique_ptr<Address> Decompiler::getDataAddress(uint64_t offset) const {
+  return make_unique<Address>(getDefaultDataSpace(), offset);
+}
+
+// Lookups go through the engine's ContextCache, which keeps the blob of the
+// last range it found, so sweeps over nearby addresses skip the tree search.
+uint32_t Disassembler::getContextVariable(const std::string &name,
+                                          uint64_t addr) const {
+  ContextBitRange bits(contextBits(*this, name));
+  vector<uintm> buf(context.getContextSize());
+  getContextCache()->getContext(Address(getDefaultCodeSpace(), addr),
+                                buf.data());
+  return bits.getValue(buf.data());
+}
+
+rust::Vec<uint32_t> Decompiler::getContextWords(uint64_t addr) const {
+  vector<uintm> buf(context.getContextSize());
+  getContextCache()->getContext(Address(getDefaultCodeSpace(), addr),
+                                buf.data());
+  rust::Vec<uint32_t> res;
+  for (auto word : buf)
+    res.push_back(word);
+  return res;
+}
+
+void Disassembler::setContextVariableRange(const std::string &name,
+                                           uint64_t start, uint64_t end,
+                                           uint32_t value) {
+  if (end <= start)
+    throw LowlevelError("Empty context range");
+  context.setVariableRegion(name, Address(getDefaultCodeSpace(), start),
+                            Address(getDefaultCodeSpace(), end), value);
+  resetCaches();
+}
+
+void Disassembler::setContextVariableFrom(const std::string &name,
+                                          uint64_t start, uint32_t value) {
+  ContextBitRange bits(contextBits(*this, name));
+  context.setContextChangePoint(Address(getDefaultCodeSpace(), start),
+                                bits.getWord(),
+                                bits.getMask() << bits.getShift(),
+                                (value & bits.getMask()) << bits.getShift());
+  resetCaches();
+}
+
+// Changes the default as well as every address painted by an earlier set,
+// in any space.
+void Disassembler::setContextVariableEverywhere(const std::string &name,
+                                                uint32_t value) {
+  contextBits(*this, name);
+  context.setVariableDefault(name, value);
+  context.setVariableRegion(name, Address(getSpace(0), 0), Address(), value);
+  resetCaches();
+}
+
+bool Disassembler::hasContextVariable(const std::string &name) const {
+  SleighSymbol *sym = findSymbol(name);
+  return sym != (SleighSymbol *)0 &&
+         sym->getType() == SleighSymbol::context_symbol;
+}
+
+// Context symbols all live in the global scope, listed here by name.
+vector<ContextSymbol *> Decompiler::contextSymbols() const {
+  vector<ContextSymbol *> res;
+  SymbolScope *scope = const_cast<SymbolTable &>(symtab).getGlobalScope();
+  for (auto iter = scope->begin(); iter != scope->end(); ++iter)
+    if ((*iter)->getType() == SleighSymbol::context_symbol)
+      res.push_back((ContextSymbol *)*iter);
+  return res;
+}
+
+rust::Vec<ContextVariableInfo> Decompiler::getContextVariables() const {
+  rust::Vec<ContextVariableInfo> res;
+  const ContextDatabase &db(context);
+  for (auto sym : contextSymbols()) {
+    ContextField *field = (ContextField *)sym->getPatternValue();
+    res.push_back(ContextVariableInfo{
+        rust::String(sym->getName()), rust::String(sym->getVarnode()->getName()),
+        (uint32_t)field->getStartBit(), (uint32_t)field->getEndBit(),
+        db.getDefaultValue(sym->getName()), sym->getFlow()});
+  }
+  return res;
+}
+
+// Reports the globalset commits of the instruction without applying them. A
+// commit is split into one entry per context variable it touches.
+rust::Vec<ContextCommitInfo>
+Decompiler::getContextCommits(uint64_t addr) const {
+  ParserContext *pos = obtainContext(Address(getDefaultCodeSpace(), addr),
+                                     ParserContext::pcode);
+  vector<ResolvedContextSet> sets;
+  pos->resolveCommits(sets);
+  rust::Vec<ContextCommitInfo> res;
+  vector<ContextSymbol *> symbols(contextSymbols());
+  for (auto &set : sets) {
+    for (auto sym : symbols) {
+      ContextField *field = (ContextField *)sym->getPatternValue();
+      ContextBitRange bits(field->getStartBit(), field->getEndBit());
+      uintm mask = bits.getMask() << bits.getShift();
+      if (bits.getWord() != set.num || (mask & set.mask) == 0)
+        continue;
+      res.push_back(ContextCommitInfo{
+          rust::String(sym->getName()),
+          (uint32_t)((set.value >> bits.getShift()) & bits.getMask()),
+          set.addr.getSpace()->getIndex(), set.addr.getOffset(), set.flow});
+    }
+  }
+  return res;
+}
+
+// The token field an operand reads directly, if any
+static const TokenField *operandField(const OperandSymbol *sym) {
+  const PatternExpression *exp = sym->getDefiningExpression();
+  TripleSymbol *triple = sym->getDefiningSymbol();
+  if (triple != (TripleSymbol *)0) {
+    FamilySymbol *family = dynamic_cast<FamilySymbol *>(triple);
+    exp = family ? family->getPatternValue() : (PatternExpression *)0;
+  }
+  return dynamic_cast<const TokenField *>(exp);
+}
+
+static PackedVarnode packHandle(AddrSpace *space, uintb offset, uint4 size) {
+  if (space == (AddrSpace *)0)
+    return PackedVarnode{-1, 0, 0};
+  return PackedVarnode{space->getIndex(), offset, size};
+}
+
+// Fills in the value of the operand the walker has been pushed into. Handles of
+// subconstructors exporting nothing are left over from earlier decodes, so they
+// are skipped.
+static void resolveOperand(const Decompiler &d, ParserWalker &walker,
+                           bool subtable, OperandSpanInfo &info) {
+  if (subtable) {
+    ConstructTpl *templ = walker.getConstructor()->getTempl();
+    if (templ == (ConstructTpl *)0 || templ->getResult() == (HandleTpl *)0)
+      return;
+  }
+  const FixedHandle &hand(walker.getParentHandle());
+  if (hand.space == (AddrSpace *)0)
+    return;
+  if (hand.offset_space != (AddrSpace *)0) {
+    info.kind = OperandKind::Dynamic;
+    info.value = packHandle(hand.space, 0, hand.size);
+    info.pointer =
+        packHandle(hand.offset_space, hand.offset_offset, hand.offset_size);
+    return;
+  }
+  info.value = packHandle(hand.space, hand.offset_offset, hand.size);
+  if (hand.space->getType() == IPTR_CONSTANT)
+    info.kind = OperandKind::Immediate;
+  else if (hand.space == d.getUniqueSpace())
+    info.kind = OperandKind::Temporary;
+  else if (!d.getRegisterName(hand.space, hand.offset_offset, hand.size)
+                .empty())
+    info.kind = OperandKind::Register;
+  else
+    info.kind = OperandKind::Address;
+}
+
+// Walks the operands of the constructor the walker is at, descending into
+// subtables. Offsets are relative to the start of the instruction.
+static void collectOperands(const Decompiler &d, ParserWalker &walker,
+                            uint32_t depth, int32_t parent,
+                            rust::Vec<OperandSpanInfo> &res) {
+  Constructor *ct = walker.getConstructor();
+  for (int4 i = 0; i < ct->getNumOperands(); ++i) {
+    OperandSymbol *sym = ct->getOperand(i);
+    std::ostringstream text;
+    sym->print(text, walker);
+    uint4 end = walker.getOffset(i);
+    walker.pushOperand(i);
+    uint4 start = walker.getOffset(-1);
+    OperandSpanInfo info{rust::String(sym->getName()),
+                         rust::String(text.str()),
+                         depth,
+                         parent,
+                         start,
+                         end - start,
+                         false,
+                         0,
+                         0,
+                         0,
+                         0,
+                         false,
+                         false,
+                         OperandKind::None,
+                         PackedVarnode{-1, 0, 0},
+                         PackedVarnode{-1, 0, 0}};
+    const TokenField *field = operandField(sym);
+    if (field != (const TokenField *)0) {
+      info.field = true;
+      info.field_offset = start + field->getByteStart();
+      info.field_length = field->getByteEnd() - field->getByteStart() + 1;
+      info.shift = field->getShift();
+      info.bits = field->numBits();
+      info.big_endian = field->isBigEndian();
+      info.sign_extended = field->isSigned();
+    }
+    TripleSymbol *triple = sym->getDefiningSymbol();
+    bool subtable = triple != (TripleSymbol *)0 &&
+                    triple->getType() == SleighSymbol::subtable_symbol;
+    resolveOperand(d, walker, subtable, info);
+    int32_t index = res.size();
+    res.push_back(move(info));
+    if (subtable)
+      collectOperands(d, walker, depth + 1, index, res);
+    walker.popOperand();
+  }
+}
+
+rust::Vec<OperandSpanInfo> Decompiler::getOperandSpans(uint64_t addr) const {
+  // The pcode state has the handles resolved
+  ParserContext *pos = obtainContext(Address(getDefaultCodeSpace(), addr),
+                                     ParserContext::pcode);
+  ParserWalker walker(pos);
+  walker.baseState();
+  rust::Vec<OperandSpanInfo> res;
+  collectOperands(*this, walker, 0, -1, res);
+  return res;
+}
+
+// Walks the constructor the walker is at and those of its subtable operands
+static void collectConstructors(SourceFileIndexer &files, ParserWalker &walker,
+                                uint32_t depth, int32_t parent, int32_t operand,
+                                uint32_t length,
+                                rust::Vec<ConstructorInfo> &res) {
+  Constructor *ct = walker.getConstructor();
+  std::ostringstream text;
+  ct->print(text, walker);
+  int32_t index = res.size();
+  res.push_back(ConstructorInfo{
+      rust::String(ct->getParent()->getName()), ct->getId(),
+      rust::String::lossy(files.getFilename(ct->getSrcIndex())),
+      ct->getLineno(), rust::String::lossy(text.str()), depth, parent,
+      operand, walker.getOffset(-1), length});
+  for (int4 i = 0; i < ct->getNumOperands(); ++i) {
+    TripleSymbol *triple = ct->getOperand(i)->getDefiningSymbol();
+    if (triple == (TripleSymbol *)0 ||
+        triple->getType() != SleighSymbol::subtable_symbol)
+      continue;
+    uint4 end = walker.getOffset(i);
+    walker.pushOperand(i);
+    collectConstructors(files, walker, depth + 1, index, i,
+                        end - walker.getOffset(-1), res);
+    walker.popOperand();
+  }
+}
+
+rust::Vec<ConstructorInfo> Decompiler::getConstructors(uint64_t addr) const {
+  ParserContext *pos = obtainContext(Address(getDefaultCodeSpace(), addr),
+                                     ParserContext::disassembly);
+  ParserWalker walker(pos);
+  walker.baseState();
+  rust::Vec<ConstructorInfo> res;
+  // Looking up a file name inserts an empty one for unknown indices
+  collectConstructors(const_cast<SourceFileIndexer &>(indexer), walker, 0, -1,
+                      -1, pos->getLength(), res);
+  return res;
+}
+
+static SymbolInfo symbolInfo(const SleighSymbol *sym) {
+  SymbolInfo info{rust::String(sym->getName()), (SymbolKind)sym->getType(),
+                  sym->getId(), PackedVarnode{-1, 0, 0}, 0, 0, 0};
+  switch (sym->getType()) {
+  case SleighSymbol::space_symbol:
+    info.index = ((const SpaceSymbol *)sym)->getSpace()->getIndex();
+    break;
+  case SleighSymbol::userop_symbol:
+    info.index = ((const UserOpSymbol *)sym)->getIndex();
+    break;
+  case SleighSymbol::varnode_symbol:
+    info.varnode = packVarnode(((const VarnodeSymbol *)sym)->getFixedVarnode());
+    break;
+  case SleighSymbol::bitrange_symbol: {
+    const BitrangeSymbol *bits = (const BitrangeSymbol *)sym;
+    info.varnode = packVarnode(bits->getParentSymbol()->getFixedVarnode());
+    info.low = bits->getBitOffset();
+    info.high = bits->getBitOffset() + bits->numBits() - 1;
+    break;
+  }
+  case SleighSymbol::context_symbol: {
+    const ContextSymbol *ctx = (const ContextSymbol *)sym;
+    info.varnode = packVarnode(ctx->getVarnode()->getFixedVarnode());
+    info.low = ctx->getLow();
+    info.high = ctx->getHigh();
+    break;
+  }
+  case SleighSymbol::subtable_symbol:
+    info.index = ((const SubtableSymbol *)sym)->getNumConstructors();
+    break;
+  default:
+    break;
+  }
+  return info;
+}
+
+rust::Vec<SymbolInfo> Decompiler::findSymbolInfo(const std::string &name) const {
+  rust::Vec<SymbolInfo> res;
+  const SleighSymbol *sym = findGlobalSymbol(name);
+  if (sym != (SleighSymbol *)0)
+    res.push_back(symbolInfo(sym));
+  return res;
+}
+
+rust::Vec<SymbolInfo> Decompiler::getSymbols() const {
+  // The global scope isn't reachable through the const interface
+  SymbolScope *scope = const_cast<SymbolTable &>(symtab).getGlobalScope();
+  rust::Vec<SymbolInfo> res;
+  for (auto iter = scope->begin(); iter != scope->end(); ++iter)
+    res.push_back(symbolInfo(*iter));
+  return res;
+}
+
+// Ordered by space and offset, larger registers first at the same offset
+static rust::Vec<RegisterInfo> registerList(const Translate &trans) {
+  map<VarnodeData, string> regs;
+  trans.getAllRegisters(regs);
+  rust::Vec<RegisterInfo> res;
+  for (auto &reg : regs)
+    res.push_back(RegisterInfo{rust::String(reg.second),
+                               reg.first.space->getIndex(), reg.first.offset,
+                               (uint32_t)reg.first.size});
+  return res;
+}
+
+rust::Vec<RegisterInfo> Decompiler::getRegisterList() const {
+  return registerList(*this);
+}
+
+// The database only saves its split points, so the defaults are written first
+// as a point set without an address, which restoreXml reads back as defaults.
+rust::String Decompiler::saveContext() const {
+  const ContextDatabase &db(context);
+  std::ostringstream s;
+  s << "<context_points>\n<context_pointset>\n";
+  for (auto sym : contextSymbols()) {
+    s << "  <set";
+    a_v(s, "name", sym->getName());
+    a_v_u(s, "val", db.getDefaultValue(sym->getName()));
+    s << "/>\n";
+  }
+  s << "</context_pointset>\n";
+  std::ostringstream points;
+  context.saveXml(points);
+  string inner = points.str();
+  const string open = "<context_points>\n", close = "</context_points>\n";
+  if (inner.size() >= open.size() + close.size())
+    s << inner.substr(open.size(), inner.size() - open.size() - close.size());
+  s << close;
+  return rust::String(s.str());
+}
+
+// Replaces the whole context, defaults included.
+void Decompiler::restoreContext(const Element &el) {
+  if (el.getName() != "context_points")
+    throw LowlevelError("Expecting <context_points>, got <" + el.getName() +
+                        ">");
+  context.clearPoints();
+  context.restoreXml(&el, this);
+  resetCaches();
+}
+
+rust::Vec<SpaceInfo> Decompiler::getSpaces() const {
+  rust::Vec<SpaceInfo> res;
+  for (int4 i = 0; i < numSpaces(); ++i) {
+    AddrSpace *spc = getSpace(i);
+    if (spc == (AddrSpace *)0)
+      continue;
+    AddrSpace *contain = spc->getContain();
+    AddrSpace *base = spc->isOverlay() ? ((OverlaySpace *)spc)->getBaseSpace()
+                                       : (AddrSpace *)0;
+    res.push_back(SpaceInfo{rust::String(spc->getName()), spc->getIndex(),
+                            (uint32_t)spc->getType(), spc->getWordSize(),
+                            spc->getAddrSize(), spc->isBigEndian(),
+                            contain ? contain->getIndex() : -1,
+                            base ? base->getIndex() : -1,
+                            spc->isOverlayBase()});
+  }
+  return res;
+}
+
+rust::Vec<SpacebaseInfo> Decompiler::getSpacebases() const {
+  rust::Vec<SpacebaseInfo> res;
+  for (int4 i = 0; i < numSpaces(); ++i) {
+    AddrSpace *spc = getSpace(i);
+    if (spc == (AddrSpace *)0)
+      continue;
+    for (int4 j = 0; j < spc->numSpacebase(); ++j) {
+      const VarnodeData &reg(spc->getSpacebase(j));
+      res.push_back(SpacebaseInfo{
+          spc->getIndex(),
+          rust::String(getRegisterName(reg.space, reg.offset, reg.size)),
+          reg.space->getIndex(), reg.offset, reg.size,
+          spc->stackGrowsNegative()});
+    }
+  }
+  return res;
+}
+
+// Sleigh only knows the spaces of the .slaspec. The stack space is added the
+// way Architecture does for the <stackpointer> of a compiler spec.
+int32_t Decompiler::addStackSpace(const std::string &base,
+                                  const std::string &reg, bool growsNegative) {
+  AddrSpace *basespace = getSpaceByName(base);
+  if (basespace == (AddrSpace *)0)
+    throw LowlevelError("Unknown space: " + base);
+  if (getStackSpace() != (AddrSpace *)0)
+    throw LowlevelError("Stack space already defined");
+  const VarnodeData &ptr(getRegister(reg));
+  int4 truncSize = ptr.size;
+  if (basespace->isTruncated() && truncSize > basespace->getAddrSize())
+    truncSize = basespace->getAddrSize();
+  int4 index = numSpaces();
+  SpacebaseSpace *spc =
+      new SpacebaseSpace(this, this, "stack", index, truncSize, basespace,
+                         ptr.space->getDelay() + 1);
+  insertSpace(spc);
+  addSpacebasePointer(spc, ptr, truncSize, growsNegative);
+  return index;
+}
+
+int32_t Decompiler::addOverlaySpace(const std::string &name,
+                                    const std::string &base) {
+  if (getSpaceByName(name) != (AddrSpace *)0)
+    throw LowlevelError("Duplicate space name: " + name);
+  int4 index = numSpaces();
+  std::ostringstream s;
+  s << "<space_overlay";
+  a_v(s, "name", name);
+  a_v_i(s, "index", index);
+  a_v(s, "base", base);
+  s << "/>";
+  std::istringstream in(s.str());
+  unique_ptr<Document> doc(xml_tree(in));
+  unique_ptr<OverlaySpace> spc(new OverlaySpace(this, this));
+  spc->restoreXml(doc->getRoot());
+  // insertSpace frees the space itself if it throws
+  insertSpace(spc.release());
+  return index;
+}
+
+int32_t Decompiler::getConstantSpaceIndex() const {
+  return getConstantSpace()->getIndex();
+}
+
+int32_t Decompiler::getDefaultCodeSpaceIndex() const {
+  return getDefaultCodeSpace()->getIndex();
+}
+
+int32_t Decompiler::getDefaultDataSpaceIndex() const {
+  return getDefaultDataSpace()->getIndex();
+}
+
+int32_t Decompiler::getInstructionAlignment() const { return getAlignment(); }
+
+// Sleigh::resolve loads this many bytes into the parser for every instruction
+uint32_t Decompiler::getMaxInstructionLength() const { return 16; }
+
+// The constant is the address of an AddrSpace. Rather than trusting it, it is
+// looked up among the spaces of this translator.
+const AddrSpace *Decompiler::getSpaceFromConst(uint64_t offset) const {
+  for (int4 i = 0; i < numSpaces(); ++i) {
+    AddrSpace *spc = getSpace(i);
+    if (spc != (AddrSpace *)0 && (uint64_t)(uintptr_t)spc == offset)
+      return spc;
   }
-  return n;
+  return (const AddrSpace *)0;
+}
+
+// The inverse of getSpaceFromConst, 0 if there is no space at index.
+uint64_t Decompiler::getSpaceConst(int32_t index) const {
+  if (index < 0 || index >= numSpaces() || getSpace(index) == (AddrSpace *)0)
+    return 0;
+  return (uint64_t)(uintptr_t)getSpace(index);
+}
+
+// getRegisterName also names registers merely containing the varnode
+rust::String Decompiler::getExactRegisterName(int32_t space, uint64_t offset,
+                                              int32_t size) const {
+  if (space < 0 || space >= numSpaces() || getSpace(space) == (AddrSpace *)0)
+    return rust::String();
+  string name = getRegisterName(getSpace(space), offset, size);
+  if (name.empty())
+    return rust::String();
+  const VarnodeData &reg(getRegister(name));
+  if (reg.offset != offset || reg.size != size)
+    return rust::String();
+  return rust::String(name);
+}
+
+// Only ops that compute their output from their inputs alone can be evaluated
+const OpBehavior &Decompiler::behavior(uint32_t opcode, bool unary) const {
+  OpBehavior *behave = opcode < behaviors.size() ? behaviors[opcode]
+                                                 : (OpBehavior *)0;
+  if (behave == (OpBehavior *)0 || behave->isSpecial())
+    throw LowlevelError("Op can't be evaluated");
+  if (behave->isUnary() != unary)
+    throw LowlevelError(unary ? "Op is not unary" : "Op is not binary");
+  return *behave;
+}
+
+static void checkEvaluationSize(int32_t size) {
+  if (size < 1 || size > 8)
+    throw LowlevelError("Bad size for evaluation");
+}
+
+uint64_t Decompiler::evaluateUnary(uint32_t opcode, int32_t sizeout,
+                                   int32_t sizein, uint64_t in) const {
+  const OpBehavior &behave(behavior(opcode, true));
+  checkEvaluationSize(sizeout);
+  checkEvaluationSize(sizein);
+  return behave.evaluateUnary(sizeout, sizein, in);
+}
+
+uint64_t Decompiler::evaluateBinary(uint32_t opcode, int32_t sizeout,
+                                    int32_t sizein, uint64_t in1,
+                                    uint64_t in2) const {
+  const OpBehavior &behave(behavior(opcode, false));
+  checkEvaluationSize(sizeout);
+  checkEvaluationSize(sizein);
+  return behave.evaluateBinary(sizeout, sizein, in1, in2);
+}
+
+// Compiles the way PcodeInjectLibrarySleigh::parseInject does and builds the
+// way ExecutablePcode::build does, binding each input and the output to a
+// temporary of its own.
+unique_ptr<CompiledSnippet>
+Decompiler::compileSnippet(const std::string &body,
+                           rust::Slice<const rust::String> inputs,
+                           rust::Slice<const uint32_t> sizes,
+                           const std::string &output,
+                           uint32_t outputSize) const {
+  const int4 maxOperands = 8;
+  if (inputs.size() != sizes.size())
+    throw LowlevelError("Snippet inputs and sizes don't match");
+  if (inputs.size() + 1 > maxOperands)
+    throw LowlevelError("Too many snippet inputs");
+  PcodeSnippet compiler(this);
+  for (size_t i = 0; i < inputs.size(); ++i)
+    compiler.addOperand(string(inputs[i]), i);
+  compiler.addOperand(output, inputs.size());
+  compiler.setUniqueBase(0x2000);
+  istringstream s(body);
+  if (!compiler.parseStream(s))
+    throw LowlevelError("Unable to compile pcode: " +
+                        compiler.getErrorMessage());
+  unique_ptr<ConstructTpl> tpl(compiler.releaseResult());
+  // EmulateSnippet reads memory through the Architecture, which there is none of
+  for (const OpTpl *op : tpl->getOpvec())
+    if (op->getOpcode() == CPUI_LOAD)
+      throw LowlevelError("Snippets can't load from memory");
+
+  auto snippet = make_unique<CompiledSnippet>();
+  AddrSpace *uniq = getUniqueSpace();
+  Address base(getDefaultCodeSpace(), 0x1000); // Fake address
+  ParserContext pos((ContextCache *)0);
+  pos.initialize(maxOperands, maxOperands, getConstantSpace());
+  pos.setAddr(base);
+  pos.setNaddr(base);
+  pos.setCalladdr(base);
+  ParserWalkerChange walker(&pos);
+  pos.deallocateState(walker);
+  uintb reserve = 0x10; // Below the unique base of the compiler
+  auto bind = [&](int4 index, uint4 size) {
+    pos.allocateOperand(index, walker);
+    FixedHandle &hand(walker.getParentHandle());
+    hand.space = uniq;
+    hand.offset_offset = reserve;
+    hand.size = size;
+    hand.offset_space = (AddrSpace *)0;
+    walker.popOperand();
+    uintb offset = reserve;
+    reserve += 0x20;
+    return offset;
+  };
+  for (size_t i = 0; i < inputs.size(); ++i)
+    snippet->inputs.push_back(bind(i, sizes[i]));
+  snippet->output = bind(inputs.size(), outputSize);
+
+  PcodeCacher cacher;
+  SleighBuilder builder(&walker, (DisassemblyCache *)0, &cacher,
+                        getConstantSpace(), uniq, 0);
+  builder.build(tpl.get(), -1);
+  cacher.resolveRelatives();
+  unique_ptr<PcodeEmit> emit(
+      snippet->emulator.buildEmitter(behaviors, reserve));
+  cacher.emit(base, emit.get());
+  if (!snippet->emulator.checkForLegalCode())
+    throw LowlevelError("Illegal p-code in executable snippet");
+  return snippet;
+}
+
+// ExecutablePcode::evaluate
+uint64_t CompiledSnippet::evaluate(rust::Slice<const uint64_t> values) {
+  if (values.size() != inputs.size())
+    throw LowlevelError(
+        "Wrong number of input parameters to executable snippet");
+  emulator.resetMemory();
+  for (size_t i = 0; i < values.size(); ++i)
+    emulator.setVarnodeValue(inputs[i], values[i]);
+  while (!emulator.getHalt())
+    emulator.executeCurrentOp();
+  return emulator.getTempValue(output);
 }
 
 uint32_t getVarnodeSize(const VarnodeData &data) { return data.size; }
 
+PackedVarnode packVarnode(const VarnodeData &data) {
+  int32_t space = data.space != (AddrSpace *)0 ? data.space->getIndex() : -1;
+  return PackedVarnode{space, data.offset, data.size};
+}
+
+rust::Vec<PackedVarnode> packVarnodes(const VarnodeData *vars, int32_t size) {
+  rust::Vec<PackedVarnode> res;
+  res.reserve(size);
+  for (int32_t i = 0; i < size; ++i)
+    res.push_back(packVarnode(vars[i]));
+  return res;
+}
+
+size_t getVarnodeDataStride() { return sizeof(VarnodeData); }
+
+const VarnodeData *getVarnodeDataAt(const VarnodeData *vars, int32_t i) {
+  return vars + i;
+}
+
 void RustAssemblyEmitProxy::dump(const Address &addr, const string &mnem,
                                  const string &body) {
   this->inner->dump(addr, mnem, body);
-}
\ No newline at end of file
+}
+
+void MemoryStateLoadImage::loadFill(uint1 *ptr, int4 size,
+                                    const Address &address) {
+  state->getChunk(ptr, address.getSpace(), address.getOffset(), size);
+}
+
+void CountingPageOverlay::insert(uintb addr, uintb val) {
+  pages.insert(addr & ~(uintb)(getPageSize() - 1));
+  MemoryPageOverlay::insert(addr, val);
+}
+
+void CountingPageOverlay::setPage(uintb addr, const uint1 *val, int4 skip,
+                                  int4 size) {
+  pages.insert(addr & ~(uintb)(getPageSize() - 1));
+  MemoryPageOverlay::setPage(addr, val, skip, size);
+}
+
+map<uintb, vector<uint1>> CountingPageOverlay::savePages() const {
+  map<uintb, vector<uint1>> saved;
+  for (uintb addr : pages) {
+    vector<uint1> &data(saved[addr]);
+    data.resize(getPageSize());
+    getChunk(addr, getPageSize(), data.data());
+  }
+  return saved;
+}
+
+// Pages written since they were saved go back to the underlying contents. They
+// stay allocated, so restoring doesn't lower the memory usage.
+void CountingPageOverlay::restorePages(const map<uintb, vector<uint1>> &saved) {
+  vector<uint1> data(getPageSize());
+  for (uintb addr : pages) {
+    if (saved.find(addr) != saved.end())
+      continue;
+    underlie->getChunk(addr, getPageSize(), data.data());
+    setChunk(addr, getPageSize(), data.data());
+  }
+  for (auto &page : saved)
+    setChunk(page.first, page.second.size(), page.second.data());
+}
+
+uintb WordMapBank::find(uintb addr) const {
+  auto iter = words.find(addr);
+  return iter == words.end() ? 0 : iter->second;
+}
+
+// Granularity at which decoded code is tracked for invalidation
+static const int4 CODE_PAGE_SHIFT = 12;
+
+PcodeEmulator::PcodeEmulator(unique_ptr<LoadImage> image,
+                             unique_ptr<DocumentStorage> spec)
+    : EmulateMemory(&state), image(move(image)), state(&translator),
+      translator(unique_ptr<LoadImage>(new MemoryStateLoadImage(&state)),
+                 move(spec)),
+      breaktable(&translator) {
+  current_op = 0;
+  instruction_length = 0;
+  instruction_start = true;
+  decoded = false;
+  history_limit = 0;
+  enforce_permissions = false;
+  fault_pending = false;
+  fault_access = 0;
+  ignore_access = 0;
+  code_invalidations = 0;
+  callother_pending = false;
+  break_pending = false;
+  break_passed = false;
+  trace_memory = false;
+  decode_failure = 0;
+  breaktable.setEmulate(this);
+  OpBehavior::registerInstructions(behaviors, &translator);
+  translator.getUserOpNames(userop_names);
+  for (int4 i = 0; i < translator.numSpaces(); ++i) {
+    AddrSpace *spc = translator.getSpace(i);
+    if (spc == (AddrSpace *)0)
+      continue;
+    MemoryBank *bank;
+    if (spc->getType() == IPTR_PROCESSOR && spc->getName() != "register") {
+      auto loaded = new MemoryImage(spc, 8, 4096, this->image.get());
+      banks.emplace_back(loaded);
+      auto overlay = new CountingPageOverlay(spc, 8, 4096, loaded);
+      overlays.push_back(overlay);
+      bank = overlay;
+    } else if (spc->getType() == IPTR_PROCESSOR ||
+               spc->getType() == IPTR_INTERNAL) {
+      auto words = new WordMapBank(spc, 8, 4096);
+      wordbanks.push_back(words);
+      bank = words;
+    } else
+      continue;
+    banks.emplace_back(bank);
+    state.setMemoryBank(bank);
+  }
+  current_address = Address(translator.getDefaultCodeSpace(), 0);
+}
+
+PcodeEmulator::~PcodeEmulator() {
+  clearCache();
+  for (auto behave : behaviors)
+    delete behave;
+}
+
+void PcodeEmulator::clearCache() {
+  for (auto op : opcache)
+    delete op;
+  for (auto var : varcache)
+    delete var;
+  opcache.clear();
+  varcache.clear();
+}
+
+// Instructions are decoded lazily, right before their first op executes, so
+// that memory written by the previous instruction is seen by the decoder.
+void PcodeEmulator::decode() {
+  if (decoded)
+    return;
+  checkAccess(4, current_address.getSpace(), current_address.getOffset(), 1);
+  clearCache();
+  PcodeEmitCache emit(opcache, varcache, behaviors, 0);
+  try {
+    instruction_length = translator.oneInstruction(emit, current_address);
+  } catch (UnimplError &err) {
+    decode_failure = 1;
+    throw;
+  } catch (BadDataError &err) {
+    decode_failure = 2;
+    throw;
+  }
+  checkAccess(4, current_address.getSpace(), current_address.getOffset() + 1,
+              instruction_length - 1);
+  // Byte by byte, as the instruction may wrap around the top of the space
+  for (int4 i = 0; i < instruction_length; ++i)
+    code_pages.insert((current_address.getOffset() + i) >> CODE_PAGE_SHIFT);
+  current_op = 0;
+  decoded = true;
+  establishOp();
+}
+
+void PcodeEmulator::establishOp() {
+  if (current_op < opcache.size()) {
+    currentOp = opcache[current_op];
+    currentBehave = currentOp->getBehavior();
+    return;
+  }
+  currentOp = (PcodeOpRaw *)0;
+  currentBehave = (OpBehavior *)0;
+}
+
+void PcodeEmulator::fallthruOp(void) {
+  instruction_start = false;
+  current_op += 1;
+  if (current_op >= opcache.size()) {
+    setExecuteAddress(current_address + instruction_length);
+    return;
+  }
+  establishOp();
+}
+
+void PcodeEmulator::executeBranch(void) {
+  const Address &destaddr(currentOp->getInput(0)->getAddr());
+  if (destaddr.isConstant()) {
+    current_op += (int4)destaddr.getOffset();
+    if (current_op == opcache.size())
+      fallthruOp();
+    else if ((current_op < 0) || (current_op >= opcache.size()))
+      throw LowlevelError("Bad intra-instruction branch");
+    else
+      establishOp();
+  } else
+    setExecuteAddress(destaddr);
+}
+
+// Access flags are 1 (read), 2 (write) and 4 (execute). Only spaces that were
+// given permissions are checked; within them unlisted addresses are unmapped.
+void PcodeEmulator::checkAccess(uint4 access, AddrSpace *spc, uintb off,
+                                int4 size) {
+  if (!enforce_permissions || size <= 0)
+    return;
+  auto space = permissions.find(spc->getIndex());
+  if (space == permissions.end())
+    return;
+  // An access running past the top of the space wraps around to its bottom
+  uintb last = off + (uintb)(size - 1);
+  if (last < off) {
+    checkRange(access, spc, space->second, off, ~(uintb)0);
+    checkRange(access, spc, space->second, 0, last);
+  } else {
+    checkRange(access, spc, space->second, off, last);
+  }
+}
+
+void PcodeEmulator::checkRange(uint4 access, AddrSpace *spc,
+                               const map<uintb, PermissionRange> &ranges,
+                               uintb first, uintb last) {
+  uintb cur = first;
+  for (;;) {
+    auto iter = ranges.upper_bound(cur);
+    bool mapped = false;
+    if (iter != ranges.begin()) {
+      --iter;
+      mapped = cur <= iter->second.last;
+    }
+    if (mapped && (iter->second.flags & access) == access) {
+      if (iter->second.last >= last)
+        return;
+      cur = iter->second.last + 1;
+      continue;
+    }
+    if (ignore_access == access && ignore_space == spc && ignore_offset == cur) {
+      // The fault handler let this access through once
+      ignore_access = 0;
+      return;
+    }
+    fault_pending = true;
+    fault_access = access;
+    fault_space = spc;
+    fault_offset = cur;
+    fault_size = (int4)(last - cur + 1);
+    fault_mapped = mapped;
+    ignore_access = 0;
+    std::ostringstream msg;
+    msg << "Memory fault: " << (mapped ? "no " : "unmapped ")
+        << (access == 1 ? "read" : access == 2 ? "write" : "execute")
+        << " access at " << spc->getName() << ":0x" << std::hex << cur;
+    throw LowlevelError(msg.str());
+  }
+}
+
+void PcodeEmulator::executeLoad(void) {
+  AddrSpace *spc =
+      Address::getSpaceFromConst(currentOp->getInput(0)->getAddr());
+  uintb off = AddrSpace::addressToByte(state.getValue(currentOp->getInput(1)),
+                                       spc->getWordSize());
+  checkAccess(1, spc, off, currentOp->getOutput()->size);
+  EmulateMemory::executeLoad();
+  traceAccess(1, spc, off, currentOp->getOutput());
+}
+
+void PcodeEmulator::executeStore(void) {
+  AddrSpace *spc =
+      Address::getSpaceFromConst(currentOp->getInput(0)->getAddr());
+  uintb off = AddrSpace::addressToByte(state.getValue(currentOp->getInput(1)),
+                                       spc->getWordSize());
+  checkAccess(2, spc, off, currentOp->getInput(2)->size);
+  EmulateMemory::executeStore();
+  invalidateCode(spc, off, currentOp->getInput(2)->size);
+  traceAccess(2, spc, off, currentOp->getInput(2));
+}
+
+// Record an executed access, with the value in the varnode that was loaded to
+// or stored from
+void PcodeEmulator::traceAccess(uint4 access, AddrSpace *spc, uintb off,
+                                VarnodeData *vn) {
+  if (!trace_memory)
+    return;
+  uintb value = vn->size <= 8 ? state.getValue(vn) : 0;
+  accesses.push_back(MemoryAccess{access, spc, off, (int4)vn->size, value,
+                                  current_address.getOffset()});
+}
+
+// User-defined ops are left to the Rust side: the op is recorded and the step
+// aborted, then completeCallOther() writes the result and moves on.
+void PcodeEmulator::executeCallother(void) {
+  callother_pending = true;
+  throw LowlevelError("Unhandled CALLOTHER: " + userOpName());
+}
+
+// The translator caches decoded instructions by address. A write to a page
+// holding decoded code throws that cache away so the new bytes are decoded
+// when next executed. The instruction being executed keeps its pcode.
+void PcodeEmulator::invalidateCode(AddrSpace *spc, uintb off, int4 size) {
+  if (size <= 0 || spc != translator.getDefaultCodeSpace())
+    return;
+  uintb last = off + (uintb)(size - 1);
+  bool touched = last < off ? touchesCode(off, ~(uintb)0) || touchesCode(0, last)
+                            : touchesCode(off, last);
+  if (!touched)
+    return;
+  translator.resetCaches();
+  code_pages.clear();
+  code_invalidations += 1;
+}
+
+// Whether code was decoded from a page holding a byte of [first, last]
+bool PcodeEmulator::touchesCode(uintb first, uintb last) const {
+  auto iter = code_pages.lower_bound(first >> CODE_PAGE_SHIFT);
+  return iter != code_pages.end() && *iter <= last >> CODE_PAGE_SHIFT;
+}
+
+void PcodeEmulator::setExecuteAddress(const Address &addr) {
+  current_address = addr;
+  current_op = 0;
+  instruction_start = true;
+  decoded = false;
+  break_passed = false;
+}
+
+void PcodeEmulator::setPc(uint64_t addr) {
+  setExecuteAddress(Address(translator.getDefaultCodeSpace(), addr));
+}
+
+// Save what the current op is about to overwrite: its output varnode, or the
+// memory targeted by a STORE.
+void PcodeEmulator::recordUndo() {
+  UndoRecord rec;
+  rec.address = current_address;
+  rec.op = current_op;
+  rec.instruction_start = instruction_start;
+  if (currentOp != (PcodeOpRaw *)0) {
+    UndoRecord::Write write;
+    int4 size = 0;
+    if (currentOp->getOpcode() == CPUI_STORE) {
+      write.space =
+          Address::getSpaceFromConst(currentOp->getInput(0)->getAddr());
+      write.offset =
+          AddrSpace::addressToByte(state.getValue(currentOp->getInput(1)),
+                                   write.space->getWordSize());
+      size = currentOp->getInput(2)->size;
+    } else if (currentOp->getOutput() != (VarnodeData *)0) {
+      write.space = currentOp->getOutput()->space;
+      write.offset = currentOp->getOutput()->offset;
+      size = currentOp->getOutput()->size;
+    }
+    if (size > 0) {
+      write.old.resize(size);
+      state.getChunk(write.old.data(), write.space, write.offset, size);
+      rec.writes.push_back(move(write));
+    }
+  }
+  history.push_back(move(rec));
+  while (history.size() > history_limit)
+    history.pop_front();
+}
+
+// Breakpoints are checked before the first op of an instruction runs. A hit
+// aborts the step for the Rust side to handle; passBreak() then lets the
+// instruction run until execution moves to another address.
+void PcodeEmulator::checkBreak() {
+  if (!instruction_start || break_passed)
+    return;
+  if (!breaktable.doAddressBreak(current_address))
+    return;
+  break_pending = true;
+  std::ostringstream msg;
+  msg << "Breakpoint at 0x" << std::hex << current_address.getOffset();
+  throw LowlevelError(msg.str());
+}
+
+void PcodeEmulator::stepOp() {
+  checkBreak();
+  decode();
+  if (history_limit == 0) {
+    executeCurrentOp();
+    return;
+  }
+  recordUndo();
+  try {
+    executeCurrentOp();
+  } catch (...) {
+    history.pop_back();
+    throw;
+  }
+}
+
+void PcodeEmulator::stepInstruction() {
+  do {
+    stepOp();
+  } while (!instruction_start);
+}
+
+uint64_t PcodeEmulator::getRegisterValue(const std::string &name) const {
+  return state.getValue(name);
+}
+
+void PcodeEmulator::setRegisterValue(const std::string &name,
+                                     uint64_t value) {
+  state.setValue(name, value);
+}
+
+rust::Vec<RegisterInfo> PcodeEmulator::getRegisterList() const {
+  return registerList(translator);
+}
+
+static AddrSpace *spaceByIndex(const Translate &trans, int32_t index) {
+  AddrSpace *spc = (index >= 0 && index < trans.numSpaces())
+                       ? trans.getSpace(index)
+                       : (AddrSpace *)0;
+  if (spc == (AddrSpace *)0)
+    throw LowlevelError("Bad address space index");
+  return spc;
+}
+
+void PcodeEmulator::readSpace(int32_t space, uint64_t offset,
+                              rust::Slice<uint8_t> buf) const {
+  state.getChunk(buf.data(), spaceByIndex(translator, space), offset,
+                 buf.size());
+}
+
+void PcodeEmulator::writeSpace(int32_t space, uint64_t offset,
+                               rust::Slice<const uint8_t> buf) {
+  AddrSpace *spc = spaceByIndex(translator, space);
+  state.setChunk(buf.data(), spc, offset, buf.size());
+  invalidateCode(spc, offset, buf.size());
+}
+
+int32_t PcodeEmulator::getDefaultDataSpaceIndex() const {
+  return translator.getDefaultDataSpace()->getIndex();
+}
+
+void PcodeEmulator::applyProcessorSpec(const Element &pspec) {
+  translator.applyProcessorSpec(pspec);
+}
+
+void PcodeEmulator::setHistoryLimit(size_t limit) {
+  history_limit = limit;
+  while (history.size() > history_limit)
+    history.pop_front();
+}
+
+bool PcodeEmulator::stepBackOp() {
+  if (history.empty())
+    return false;
+  UndoRecord rec = move(history.back());
+  history.pop_back();
+  for (auto it = rec.writes.rbegin(); it != rec.writes.rend(); ++it) {
+    state.setChunk(it->old.data(), it->space, it->offset, it->old.size());
+    invalidateCode(it->space, it->offset, it->old.size());
+  }
+  // The instruction is decoded again from the restored memory
+  setExecuteAddress(rec.address);
+  decode();
+  current_op = rec.op;
+  instruction_start = rec.instruction_start;
+  establishOp();
+  return true;
+}
+
+// Bytes of processor memory copied on write so far
+uint64_t PcodeEmulator::getMemoryUsage() const {
+  uint64_t total = 0;
+  for (auto overlay : overlays)
+    total += (uint64_t)overlay->numPages() * overlay->getPageSize();
+  return total;
+}
+
+string PcodeEmulator::userOpName() const {
+  int4 index = (int4)currentOp->getInput(0)->offset;
+  if (index >= 0 && index < userop_names.size())
+    return userop_names[index];
+  std::ostringstream s;
+  s << "userop_" << index;
+  return s.str();
+}
+
+// Inputs wider than 8 bytes are passed as 0
+CallOtherInfo PcodeEmulator::takeCallOther() {
+  CallOtherInfo info{false, rust::String(), 0, rust::Vec<uint64_t>(), 0};
+  if (!callother_pending)
+    return info;
+  callother_pending = false;
+  info.pending = true;
+  info.name = rust::String(userOpName());
+  info.index = (int32_t)currentOp->getInput(0)->offset;
+  for (int4 i = 1; i < currentOp->numInput(); ++i) {
+    VarnodeData *vn = currentOp->getInput(i);
+    info.inputs.push_back(vn->size <= 8 ? state.getValue(vn) : 0);
+  }
+  if (currentOp->getOutput() != (VarnodeData *)0)
+    info.output_size = currentOp->getOutput()->size;
+  return info;
+}
+
+void PcodeEmulator::completeCallOther(bool has_value, uint64_t value) {
+  if (currentOp == (PcodeOpRaw *)0 ||
+      currentOp->getOpcode() != CPUI_CALLOTHER)
+    throw LowlevelError("No CALLOTHER op to complete");
+  if (history_limit > 0)
+    recordUndo();
+  VarnodeData *out = currentOp->getOutput();
+  if (has_value && out != (VarnodeData *)0 && out->size <= 8)
+    state.setValue(out, value);
+  fallthruOp();
+}
+
+int32_t PcodeEmulator::getDefaultCodeSpaceIndex() const {
+  return translator.getDefaultCodeSpace()->getIndex();
+}
+
+// Ranges are kept disjoint: a new range replaces whatever it overlaps.
+void PcodeEmulator::setPermissions(int32_t space, uint64_t start,
+                                   uint64_t size, uint32_t flags) {
+  if (size == 0)
+    return;
+  spaceByIndex(translator, space);
+  map<uintb, PermissionRange> &ranges(permissions[space]);
+  // Ranges past the top of the space stop at it
+  uintb last = size - 1 > ~(uintb)0 - start ? ~(uintb)0 : start + (size - 1);
+  auto iter = ranges.lower_bound(start);
+  if (iter != ranges.begin()) {
+    auto prev = std::prev(iter);
+    if (prev->second.last >= start) {
+      if (prev->second.last > last)
+        ranges[last + 1] = PermissionRange{prev->second.last, prev->second.flags};
+      prev->second.last = start - 1;
+    }
+  }
+  iter = ranges.lower_bound(start);
+  while (iter != ranges.end() && iter->first <= last) {
+    if (iter->second.last > last)
+      ranges[last + 1] = PermissionRange{iter->second.last, iter->second.flags};
+    iter = ranges.erase(iter);
+  }
+  ranges[start] = PermissionRange{last, flags};
+}
+
+MemoryFaultInfo PcodeEmulator::takeFault() {
+  if (!fault_pending)
+    return MemoryFaultInfo{0, 0, 0, 0, 0, false};
+  fault_pending = false;
+  return MemoryFaultInfo{fault_access, fault_space->getIndex(), fault_offset,
+                         (uint32_t)fault_size, current_address.getOffset(),
+                         fault_mapped};
+}
+
+// Let the access that raised the last fault through the next time it is made
+void PcodeEmulator::ignoreFault() {
+  ignore_access = fault_access;
+  ignore_space = fault_space;
+  ignore_offset = fault_offset;
+}
+
+// BreakTableCallBack can't unregister a callback, so removed breakpoints are
+// disabled and enabled again when added back.
+void PcodeEmulator::addBreakpoint(uint64_t addr) {
+  Address address(translator.getDefaultCodeSpace(), addr);
+  auto iter = breaks.find(address);
+  if (iter != breaks.end()) {
+    iter->second->enabled = true;
+    return;
+  }
+  PendingBreak *brk = new PendingBreak();
+  breaks[address].reset(brk);
+  breaktable.registerAddressCallback(address, brk);
+}
+
+bool PcodeEmulator::removeBreakpoint(uint64_t addr) {
+  auto iter = breaks.find(Address(translator.getDefaultCodeSpace(), addr));
+  if (iter == breaks.end() || !iter->second->enabled)
+    return false;
+  iter->second->enabled = false;
+  return true;
+}
+
+bool PcodeEmulator::takeBreak() {
+  bool pending = break_pending;
+  break_pending = false;
+  return pending;
+}
+
+// Move to the next instruction without executing the current one
+void PcodeEmulator::skipInstruction() {
+  decode();
+  setExecuteAddress(current_address + instruction_length);
+}
+
+void PcodeEmulator::setTraceMemory(bool trace) {
+  trace_memory = trace;
+  if (!trace)
+    accesses.clear();
+}
+
+rust::Vec<MemoryAccessInfo> PcodeEmulator::takeMemoryAccesses() {
+  rust::Vec<MemoryAccessInfo> res;
+  for (auto &acc : accesses)
+    res.push_back(MemoryAccessInfo{acc.access, acc.space->getIndex(),
+                                   acc.offset, (uint32_t)acc.size, acc.value,
+                                   acc.pc});
+  accesses.clear();
+  return res;
+}
+
+// 1 if the last step failed on an instruction without pcode, 2 if on bytes that
+// don't decode, 0 otherwise
+uint32_t PcodeEmulator::takeDecodeFailure() {
+  uint32_t failure = decode_failure;
+  decode_failure = 0;
+  return failure;
+}
+
+unique_ptr<EmulatorState> PcodeEmulator::saveState() const {
+  unique_ptr<EmulatorState> saved(new EmulatorState());
+  saved->owner = this;
+  saved->address = current_address;
+  saved->op = current_op;
+  saved->instruction_start = instruction_start;
+  for (auto overlay : overlays)
+    saved->pages.push_back(overlay->savePages());
+  for (auto bank : wordbanks)
+    saved->words.push_back(bank->getWords());
+  return saved;
+}
+
+// Restored memory may hold other code, so decoded instructions are dropped.
+// The undo history describes states that are gone and is cleared as well.
+void PcodeEmulator::restoreState(const EmulatorState &saved) {
+  if (saved.owner != this)
+    throw LowlevelError("State saved from another emulator");
+  for (size_t i = 0; i < overlays.size(); ++i)
+    overlays[i]->restorePages(saved.pages[i]);
+  for (size_t i = 0; i < wordbanks.size(); ++i)
+    wordbanks[i]->setWords(saved.words[i]);
+  translator.resetCaches();
+  code_pages.clear();
+  history.clear();
+  fault_pending = false;
+  callother_pending = false;
+  break_pending = false;
+  decode_failure = 0;
+  accesses.clear();
+  setExecuteAddress(saved.address);
+  if (saved.op != 0) {
+    decode();
+    current_op = saved.op;
+    establishOp();
+  }
+  instruction_start = saved.instruction_start;
+}
+
+bool PcodeEmulator::stepBackInstruction() {
+  if (history.empty())
+    return false;
+  while (stepBackOp() && !instruction_start)
+    ;
+  return true;
+}
+
+// The translator is only ever used through const methods, the casts below are
+// for the vendored constructors that predate const correctness.
+PcodeCacheEmulator::PcodeCacheEmulator(const Decompiler *translator)
+    : translator(translator), state(const_cast<Decompiler *>(translator)),
+      breaktable(const_cast<Decompiler *>(translator)),
+      emulate(const_cast<Decompiler *>(translator), &state, &breaktable),
+      started(false) {
+  for (int4 i = 0; i < translator->numSpaces(); ++i) {
+    AddrSpace *spc = translator->getSpace(i);
+    if (spc == (AddrSpace *)0)
+      continue;
+    MemoryBank *bank;
+    if (spc->getType() == IPTR_PROCESSOR && spc->getName() != "register") {
+      auto loaded =
+          new MemoryImage(spc, 8, 4096, translator->getLoadImage());
+      banks.emplace_back(loaded);
+      bank = new MemoryPageOverlay(spc, 8, 4096, loaded);
+    } else if (spc->getType() == IPTR_PROCESSOR ||
+               spc->getType() == IPTR_INTERNAL) {
+      bank = new MemoryHashOverlay(spc, 8, 4096, 4096, (MemoryBank *)0);
+    } else
+      continue;
+    banks.emplace_back(bank);
+    state.setMemoryBank(bank);
+  }
+}
+
+// EmulatePcodeCache has no instruction to run until an address is set.
+void PcodeCacheEmulator::checkStarted() const {
+  if (!started)
+    throw LowlevelError("No execution address set");
+}
+
+void PcodeCacheEmulator::setExecuteAddress(uint64_t addr) {
+  started = false;
+  emulate.setExecuteAddress(Address(translator->getDefaultCodeSpace(), addr));
+  started = true;
+}
+
+uint64_t PcodeCacheEmulator::getExecuteAddress() const {
+  checkStarted();
+  return emulate.getExecuteAddress().getOffset();
+}
+
+// A failed translation leaves the cache without a current op, so execution
+// can't resume before a new address is set.
+void PcodeCacheEmulator::executeCurrentOp() {
+  checkStarted();
+  try {
+    emulate.executeCurrentOp();
+  } catch (...) {
+    started = false;
+    throw;
+  }
+}
+
+void PcodeCacheEmulator::executeInstruction() {
+  checkStarted();
+  try {
+    emulate.executeInstruction();
+  } catch (...) {
+    started = false;
+    throw;
+  }
+}
+
+void PcodeCacheEmulator::addAddressBreak(uint64_t addr) {
+  breaks.emplace_back(new HaltBreak());
+  breaks.back()->setEmulate(&emulate);
+  breaktable.registerAddressCallback(
+      Address(translator->getDefaultCodeSpace(), addr), breaks.back().get());
+}
+
+uint64_t PcodeCacheEmulator::getValue(int32_t spc, uint64_t offset,
+                                      int32_t size) const {
+  if (size < 1 || size > 8)
+    throw LowlevelError("Bad value size");
+  return state.getValue(spaceByIndex(*translator, spc), offset, size);
+}
+
+void PcodeCacheEmulator::setValue(int32_t spc, uint64_t offset, int32_t size,
+                                  uint64_t value) {
+  if (size < 1 || size > 8)
+    throw LowlevelError("Bad value size");
+  state.setValue(spaceByIndex(*translator, spc), offset, size, value);
+}
+
+void PcodeCacheEmulator::getChunk(int32_t spc, uint64_t offset,
+                                  rust::Slice<uint8_t> buf) const {
+  state.getChunk(buf.data(), spaceByIndex(*translator, spc), offset,
+                 buf.size());
+}
+
+void PcodeCacheEmulator::setChunk(int32_t spc, uint64_t offset,
+                                  rust::Slice<const uint8_t> buf) {
+  state.setChunk(buf.data(), spaceByIndex(*translator, spc), offset,
+                 buf.size());
+}
+
+uint64_t PcodeCacheEmulator::getRegisterValue(const std::string &name) const {
+  return state.getValue(name);
+}
+
+void PcodeCacheEmulator::setRegisterValue(const std::string &name,
+                                          uint64_t value) {
+  state.setValue(name, value);
+}
+
+DecompilerArchitecture::DecompilerArchitecture(RustLoadImage *image,
+                                               RustConstantPool *pool,
+                                               unique_ptr<DocumentStorage> store)
+    : image(image), pool(pool), store(move(store)) {
+  init(*this->store);
+  // Ghidra's defaults only show pre comments in the body
+  print->setInstructionComment(Comment::user2 | Comment::user3 |
+                               Comment::warning);
+}
+
+Translate *DecompilerArchitecture::buildTranslator(DocumentStorage &store) {
+  return new Sleigh(loader, context);
+}
+
+// The architecture deletes the loader, which only points at the Rust image
+void DecompilerArchitecture::buildLoader(DocumentStorage &store) {
+  loader = new RustLoadImageProxy(image);
+}
+
+PcodeInjectLibrary *DecompilerArchitecture::buildPcodeInjectLibrary(void) {
+  return new PcodeInjectLibrarySleigh(this);
+}
+
+void DecompilerArchitecture::printMessage(const string &message) const {
+  report_decompiler_message(analysed, analysing, message);
+}
+
+void DecompilerArchitecture::buildConstantPool(DocumentStorage &store) {
+  if (pool == nullptr)
+    Architecture::buildConstantPool(store);
+  else
+    cpool = new RustConstantPoolProxy(pool, this);
+}
+
+// CPoolRecord can only be filled in from its XML form
+const CPoolRecord *
+RustConstantPoolProxy::getRecord(const vector<uintb> &refs) const {
+  const CPoolRecord *rec = cache.getRecord(refs);
+  if (rec != nullptr)
+    return rec;
+  vector<uint64_t> key(refs.begin(), refs.end());
+  PoolRecord record;
+  if (!inner->resolve(rust::Slice<const uint64_t>(key.data(), key.size()),
+                      record))
+    return nullptr;
+  static const char *const tags[] = {"primitive",  "string",      "classref",
+                                     "method",     "field",       "arraylength",
+                                     "instanceof", "checkcast"};
+  uint32_t tag = (uint32_t)record.tag;
+  if (tag >= sizeof(tags) / sizeof(tags[0]))
+    throw LowlevelError("Bad constant pool record tag");
+  if (tag == CPoolRecord::string_literal && record.byte_data.empty())
+    throw LowlevelError("String literal in the constant pool has no data");
+  Datatype *type = arch->findType(std::string(record.type_name));
+
+  std::ostringstream s;
+  s << "<cpoolrec";
+  a_v(s, "tag", tags[tag]);
+  s << ">\n";
+  if (tag == CPoolRecord::primitive)
+    s << "<value>0x" << std::hex << record.value << std::dec << "</value>\n";
+  if (!record.byte_data.empty()) {
+    s << "<data length=\"" << record.byte_data.size() << "\">";
+    for (uint8_t b : record.byte_data)
+      s << std::hex << (uint32_t)b << ' ';
+    s << std::dec << "</data>\n";
+  } else {
+    s << "<token>";
+    xml_escape(s, std::string(record.token).c_str());
+    s << "</token>\n";
+  }
+  type->saveXmlRef(s);
+  s << "</cpoolrec>\n";
+
+  unique_ptr<Document> doc;
+  {
+    Lock guard(xmlLock);
+    std::istringstream in(s.str());
+    doc.reset(xml_tree(in));
+  }
+  return cache.restoreXmlRecord(refs, doc->getRoot(), *arch->types);
+}
+
+// The function is created on first use, an empty name lets the decompiler pick
+// one
+Funcdata *DecompilerArchitecture::functionAt(uint64_t entry,
+                                             const std::string &name) {
+  Address addr(getDefaultCodeSpace(), entry);
+  Scope *global = symboltab->getGlobalScope();
+  Funcdata *fd = global->queryFunction(addr);
+  if (fd == (Funcdata *)0) {
+    string fname(name);
+    if (fname.empty())
+      nameFunction(addr, fname);
+    fd = global->addFunction(addr, fname)->getFunction();
+  }
+  return fd;
+}
+
+// Follows the decompiler's console: the previous analysis of the function is
+// thrown away on every call. style names the root action to run, like
+// "decompile" or "normalize".
+Funcdata *DecompilerArchitecture::analyzeFunction(uint64_t entry,
+                                                  const std::string &name,
+                                                  const std::string &style) {
+  Funcdata *fd = functionAt(entry, name);
+  analysed = entry;
+  analysing = true;
+  struct Analysing {
+    bool &flag;
+    ~Analysing() { flag = false; }
+  } guard{analysing};
+  Action *act = allacts.getCurrentName() == style ? allacts.getCurrent()
+                                                  : allacts.setCurrent(style);
+  if (fd->isProcStarted())
+    clearAnalysis(fd);
+  act->reset(*fd);
+  if (act->perform(*fd) < 0)
+    throw LowlevelError("Decompilation of " + fd->getName() + " did not finish");
+  return fd;
+}
+
+rust::String DecompilerArchitecture::decompileFunction(uint64_t entry,
+                                                       const std::string &name,
+                                                       const std::string &style) {
+  Funcdata *fd = analyzeFunction(entry, name, style);
+  std::ostringstream s;
+  print->setOutputStream(&s);
+  print->docFunction(fd);
+  print->setOutputStream((ostream *)0);
+  return rust::String::lossy(s.str());
+}
+
+rust::String DecompilerArchitecture::getRawFunction(uint64_t entry,
+                                                    const std::string &name,
+                                                    const std::string &style) {
+  std::ostringstream s;
+  analyzeFunction(entry, name, style)->printRaw(s);
+  return rust::String::lossy(s.str());
+}
+
+// Pre-order, so every block comes after the one containing it. Only basic
+// blocks know their addresses, the others span those of their components.
+static void collectStructure(const FlowBlock *block, int32_t parent,
+                             rust::Vec<StructureNodeInfo> &out) {
+  size_t index = out.size();
+  out.push_back(StructureNodeInfo{(StructureKind)block->getType(), parent, -1,
+                                  ~(uint64_t)0, 0});
+  if (block->getType() == FlowBlock::t_copy) {
+    const FlowBlock *basic = block->subBlock(0);
+    out[index].basic = basic->getIndex();
+    out[index].start = basic->getStart().getOffset();
+    out[index].stop = basic->getStop().getOffset();
+    return;
+  }
+  // Every block made of other blocks is a graph of them
+  if (block->getType() < FlowBlock::t_graph)
+    return;
+  const BlockGraph *graph = (const BlockGraph *)block;
+  for (int4 i = 0; i < graph->getSize(); ++i) {
+    size_t child = out.size();
+    collectStructure(graph->getBlock(i), index, out);
+    out[index].start = std::min(out[index].start, out[child].start);
+    out[index].stop = std::max(out[index].stop, out[child].stop);
+  }
+}
+
+FunctionGraphInfo
+DecompilerArchitecture::getFunctionGraph(uint64_t entry,
+                                         const std::string &name) {
+  Funcdata *fd = analyzeFunction(entry, name, "decompile");
+  FunctionGraphInfo info;
+  const BlockGraph &blocks(fd->getBasicBlocks());
+  for (int4 i = 0; i < blocks.getSize(); ++i) {
+    const FlowBlock *block = blocks.getBlock(i);
+    const FlowBlock *dom = block->getImmedDom();
+    BasicBlockInfo bb{(uint32_t)block->getIndex(), block->getStart().getOffset(),
+                      block->getStop().getOffset(),
+                      dom != (const FlowBlock *)0 ? dom->getIndex() : -1,
+                      rust::Vec<BlockEdgeInfo>()};
+    for (int4 j = 0; j < block->sizeOut(); ++j)
+      bb.successors.push_back(BlockEdgeInfo{
+          (uint32_t)block->getOut(j)->getIndex(), block->isBackEdgeOut(j),
+          block->isGotoOut(j)});
+    info.blocks.push_back(move(bb));
+  }
+  collectStructure(&fd->getStructure(), -1, info.structure);
+  return info;
+}
+
+static rust::String typeName(const Datatype *type) {
+  std::ostringstream s;
+  type->printRaw(s);
+  return rust::String::lossy(s.str());
+}
+
+static ParameterInfo packParameter(const ProtoParameter *param) {
+  Address addr(param->getAddress());
+  return ParameterInfo{rust::String::lossy(param->getName()),
+                       typeName(param->getType()),
+                       packHandle(addr.getSpace(), addr.getOffset(),
+                                  param->getSize())};
+}
+
+FunctionVariablesInfo
+DecompilerArchitecture::getFunctionVariables(uint64_t entry,
+                                             const std::string &name) {
+  Funcdata *fd = analyzeFunction(entry, name, "decompile");
+  const FuncProto &proto(fd->getFuncProto());
+  FunctionVariablesInfo info{rust::String::lossy(proto.getModelName()),
+                             packParameter(proto.getOutput())};
+  for (int4 i = 0; i < proto.numParams(); ++i)
+    info.parameters.push_back(packParameter(proto.getParam(i)));
+  // Every variable is reached from its varnodes, in storage order
+  std::set<const HighVariable *> seen;
+  for (VarnodeLocSet::const_iterator iter = fd->beginLoc();
+       iter != fd->endLoc(); ++iter) {
+    const Varnode *vn = *iter;
+    if (vn->isAnnotation() || vn->isFree())
+      continue;
+    HighVariable *high = vn->getHigh();
+    if (!seen.insert(high).second)
+      continue;
+    const Symbol *sym = high->getSymbol();
+    const SymbolEntry *entry = high->getSymbolEntry();
+    const Varnode *rep = high->getNameRepresentative();
+    VariableInfo var{"", VariableKind::Temporary,
+                     packHandle(rep->getSpace(), rep->getOffset(),
+                                rep->getSize()),
+                     typeName(high->getType()), -1};
+    if (sym != (const Symbol *)0) {
+      var.name = rust::String::lossy(sym->getName());
+      if (sym->getCategory() == 0) { // parameter category
+        var.kind = VariableKind::Parameter;
+        var.parameter = sym->getCategoryIndex();
+      } else if (sym->getScope() == symboltab->getGlobalScope())
+        var.kind = VariableKind::Global;
+      else
+        var.kind = VariableKind::Local;
+    }
+    if (entry != (const SymbolEntry *)0 && !entry->isDynamic())
+      var.storage = packHandle(entry->getAddr().getSpace(),
+                               entry->getAddr().getOffset(), entry->getSize());
+    info.variables.push_back(move(var));
+  }
+  return info;
+}
+
+FunctionSsaInfo
+DecompilerArchitecture::getFunctionSsa(uint64_t entry, const std::string &name,
+                                       const std::string &style) {
+  Funcdata *fd = analyzeFunction(entry, name, style);
+  FunctionSsaInfo info;
+  // Dead ops are left out of the blocks, and so are the varnodes only they use
+  std::unordered_map<const PcodeOp *, uint32_t> ops;
+  const BlockGraph &blocks(fd->getBasicBlocks());
+  for (int4 i = 0; i < blocks.getSize(); ++i) {
+    const BlockBasic *bb = (const BlockBasic *)blocks.getBlock(i);
+    for (list<PcodeOp *>::const_iterator iter = bb->beginOp();
+         iter != bb->endOp(); ++iter) {
+      size_t index = ops.size();
+      ops[*iter] = index;
+    }
+  }
+  std::vector<const Varnode *> order;
+  std::unordered_map<const Varnode *, uint32_t> varnodes;
+  std::set<const Varnode *> spaceIds;
+  auto varnodeIndex = [&](const Varnode *vn) {
+    auto found = varnodes.emplace(vn, order.size());
+    if (found.second)
+      order.push_back(vn);
+    return found.first->second;
+  };
+  for (int4 i = 0; i < blocks.getSize(); ++i) {
+    const BlockBasic *bb = (const BlockBasic *)blocks.getBlock(i);
+    for (list<PcodeOp *>::const_iterator iter = bb->beginOp();
+         iter != bb->endOp(); ++iter) {
+      const PcodeOp *op = *iter;
+      SsaOpInfo packed{(uint32_t)op->code(), op->getAddr().getOffset(),
+                       (uint32_t)i, -1, rust::Vec<uint32_t>(), -1,
+                       rust::Vec<uint32_t>()};
+      if (op->getOut() != (const Varnode *)0)
+        packed.output = varnodeIndex(op->getOut());
+      for (int4 j = 0; j < op->numInput(); ++j) {
+        // The second input of an INDIRECT stands for the op causing it
+        if (op->code() == CPUI_INDIRECT && j == 1) {
+          auto cause =
+              ops.find(PcodeOp::getOpFromConst(op->getIn(1)->getAddr()));
+          if (cause != ops.end())
+            packed.cause = cause->second;
+          continue;
+        }
+        packed.inputs.push_back(varnodeIndex(op->getIn(j)));
+        if ((op->code() == CPUI_LOAD || op->code() == CPUI_STORE) && j == 0)
+          spaceIds.insert(op->getIn(0));
+        if (op->code() == CPUI_MULTIEQUAL)
+          packed.input_blocks.push_back(bb->getIn(j)->getIndex());
+      }
+      info.ops.push_back(move(packed));
+    }
+  }
+  for (const Varnode *vn : order) {
+    SsaVarnodeInfo packed{
+        packHandle(vn->getSpace(), vn->getOffset(), vn->getSize()), -1,
+        rust::Vec<uint32_t>(), vn->isInput()};
+    // Rather than pointers, which mean nothing to Rust, give the index of the
+    // space and the address of the function called
+    if (spaceIds.count(vn) != 0)
+      packed.storage.offset =
+          Address::getSpaceFromConst(vn->getAddr())->getIndex();
+    else if (vn->getSpace()->getType() == IPTR_FSPEC) {
+      Address callee(FuncCallSpecs::getFspecFromConst(vn->getAddr())
+                         ->getEntryAddress());
+      packed.storage = packHandle(callee.getSpace(), callee.getOffset(),
+                                  vn->getSize());
+    }
+    if (vn->isWritten()) {
+      auto def = ops.find(vn->getDef());
+      if (def != ops.end())
+        packed.definition = def->second;
+    }
+    for (list<PcodeOp *>::const_iterator iter = vn->beginDescend();
+         iter != vn->endDescend(); ++iter) {
+      auto use = ops.find(*iter);
+      if (use != ops.end())
+        packed.uses.push_back(use->second);
+    }
+    info.varnodes.push_back(move(packed));
+  }
+  return info;
+}
+
+rust::Vec<JumpTableInfo>
+DecompilerArchitecture::getJumpTables(uint64_t entry, const std::string &name) {
+  Funcdata *fd = analyzeFunction(entry, name, "decompile");
+  rust::Vec<JumpTableInfo> tables;
+  for (int4 i = 0; i < fd->numJumpTables(); ++i) {
+    const JumpTable *jt = fd->getJumpTable(i);
+    if (!jt->isRecovered())
+      continue;
+    JumpTableInfo info{jt->getOpAddress().getOffset(), rust::Vec<uint64_t>(),
+                       rust::Vec<uint64_t>(), PackedVarnode{-1, 0, 0},
+                       jt->getSwitchVarConsume(), false, 0};
+    for (int4 j = 0; j < jt->numEntries(); ++j) {
+      info.targets.push_back(jt->getAddressByIndex(j).getOffset());
+      if (jt->isLabelled())
+        info.labels.push_back(jt->getLabelByIndex(j));
+    }
+    // Normalizing hands the branch the switch variable in place of the
+    // address computed from it
+    const PcodeOp *op = jt->getIndirectOp();
+    if (op != (const PcodeOp *)0) {
+      const Varnode *vn = op->getIn(0);
+      if (jt->isLabelled())
+        info.switch_variable =
+            packHandle(vn->getSpace(), vn->getOffset(), vn->getSize());
+      const FlowBlock *block = op->getParent();
+      if (jt->getDefaultBlock() >= 0 &&
+          jt->getDefaultBlock() < block->sizeOut()) {
+        info.has_default = true;
+        info.default_target =
+            block->getOut(jt->getDefaultBlock())->getStart().getOffset();
+      }
+    }
+    tables.push_back(move(info));
+  }
+  return tables;
+}
+
+// Only named types are known, pointers and arrays are built from what follows
+// the name, spelled like printRaw does: "int4 *" or "char [16]"
+Datatype *DecompilerArchitecture::findType(const std::string &name) {
+  size_t end = name.find_first_of("*[");
+  size_t last = name.find_last_not_of(' ', end == string::npos ? end : end - 1);
+  Datatype *type = last == string::npos
+                       ? (Datatype *)0
+                       : types->findByName(name.substr(0, last + 1));
+  if (type == (Datatype *)0)
+    throw LowlevelError("Unknown data-type " + name);
+  AddrSpace *data = getDefaultDataSpace();
+  for (size_t i = end; i < name.size(); ++i) {
+    if (name[i] == '*')
+      type = types->getTypePointer(data->getAddrSize(), type,
+                                   data->getWordSize());
+    else if (name[i] == '[') {
+      size_t close = name.find(']', i);
+      int4 length = 0;
+      if (close != string::npos)
+        std::istringstream(name.substr(i + 1, close - i - 1)) >> length;
+      if (length <= 0)
+        throw LowlevelError("Bad array length in data-type " + name);
+      type = types->getTypeArray(length, type);
+      i = close;
+    } else if (name[i] != ' ')
+      throw LowlevelError("Unknown data-type " + name);
+  }
+  return type;
+}
+
+void DecompilerArchitecture::definePrimitive(const std::string &name,
+                                             uint32_t size, TypeKind kind) {
+  type_metatype meta = (type_metatype)kind;
+  if (meta != TYPE_INT && meta != TYPE_UINT && meta != TYPE_FLOAT &&
+      meta != TYPE_BOOL && meta != TYPE_UNKNOWN)
+    throw LowlevelError("Not a primitive data-type kind: " + name);
+  if (types->findByName(name) != (Datatype *)0)
+    throw LowlevelError("Data-type " + name + " already exists");
+  types->getBase(size, meta, name);
+}
+
+void DecompilerArchitecture::defineStruct(const std::string &name,
+                                          uint32_t size,
+                                          rust::Slice<const FieldInfo> fields) {
+  if (types->findByName(name) != (Datatype *)0)
+    throw LowlevelError("Data-type " + name + " already exists");
+  vector<TypeField> fd;
+  for (const FieldInfo &field : fields) {
+    fd.emplace_back();
+    fd.back().offset = field.offset;
+    fd.back().name = std::string(field.name);
+    fd.back().type = findType(std::string(field.type_name));
+  }
+  TypeStruct *ts = types->getTypeStruct(name);
+  if (!types->setFields(fd, ts, size, 0)) {
+    types->destroyType(ts);
+    throw LowlevelError("Bad fields for structure " + name);
+  }
+}
+
+void DecompilerArchitecture::setGlobal(uint64_t addr, const std::string &name,
+                                       const std::string &type_name) {
+  Datatype *type = findType(type_name);
+  Scope *global = symboltab->getGlobalScope();
+  Address ad(getDefaultDataSpace(), addr);
+  SymbolEntry *entry = global->findAddr(ad, Address());
+  if (entry != (SymbolEntry *)0 &&
+      dynamic_cast<FunctionSymbol *>(entry->getSymbol()) == (FunctionSymbol *)0)
+    global->removeSymbol(entry->getSymbol());
+  Symbol *sym = global->addSymbol(name, type, ad, Address())->getSymbol();
+  global->setAttribute(sym, Varnode::typelock | Varnode::namelock);
+}
+
+TypeInfo DecompilerArchitecture::getTypeInfo(const std::string &name) {
+  Datatype *type = findType(name);
+  TypeInfo info{typeName(type), (TypeKind)type->getMetatype(),
+                (uint32_t)type->getSize(), "", 0, rust::Vec<FieldInfo>()};
+  if (type->getMetatype() == TYPE_PTR)
+    info.element = typeName(((TypePointer *)type)->getPtrTo());
+  else if (type->getMetatype() == TYPE_ARRAY) {
+    info.element = typeName(((TypeArray *)type)->getBase());
+    info.length = ((TypeArray *)type)->numElements();
+  } else if (type->getMetatype() == TYPE_STRUCT) {
+    const TypeStruct *ts = (const TypeStruct *)type;
+    for (vector<TypeField>::const_iterator iter = ts->beginField();
+         iter != ts->endField(); ++iter)
+      info.fields.push_back(FieldInfo{rust::String::lossy(iter->name),
+                                      iter->offset, typeName(iter->type)});
+  }
+  return info;
+}
+
+// Locking the prototype makes the decompiler take it as given, both in the
+// function itself and at every call to it
+void DecompilerArchitecture::setPrototype(uint64_t entry,
+                                          const std::string &name,
+                                          const PrototypeSpec &prototype) {
+  PrototypePieces pieces;
+  pieces.model = prototype.model.empty()
+                     ? defaultfp
+                     : getModel(std::string(prototype.model));
+  pieces.name = name;
+  pieces.outtype = findType(std::string(prototype.return_type));
+  for (const ParameterSpec &param : prototype.parameters) {
+    pieces.intypes.push_back(findType(std::string(param.type_name)));
+    pieces.innames.push_back(std::string(param.name));
+  }
+  pieces.dotdotdot = prototype.variadic;
+  functionAt(entry, name)->getFuncProto().setPieces(pieces);
+}
+
+void DecompilerArchitecture::addComment(uint64_t function, uint64_t addr,
+                                        CommentKind kind,
+                                        const std::string &text) {
+  AddrSpace *code = getDefaultCodeSpace();
+  commentdb->addComment((uint4)kind, Address(code, function),
+                        Address(code, addr), text);
+}
+
+void DecompilerArchitecture::clearComments(uint64_t function) {
+  commentdb->clearType(Address(getDefaultCodeSpace(), function),
+                       Comment::user2 | Comment::user3 | Comment::header);
+}
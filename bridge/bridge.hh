@@ -1,14 +1,22 @@
 #pragma once
 
 #include <cstdint>
+#include <deque>
 #include <memory>
-#include <mutex>
+#include <set>
+#include <unordered_map>
 #include <sstream>
 #include <vector>
 
+#include "rust/cxx.h"
+
 #include "../decompiler/address.hh"
+#include "../decompiler/architecture.hh"
+#include "../decompiler/emulate.hh"
+#include "../decompiler/emulateutil.hh"
 #include "../decompiler/globalcontext.hh"
 #include "../decompiler/loadimage.hh"
+#include "../decompiler/memstate.hh"
 #include "../decompiler/opbehavior.hh"
 #include "../decompiler/sleigh.hh"
 #include "../decompiler/space.hh"
@@ -17,14 +25,35 @@ using std::make_unique;
 using std::move;
 using std::unique_ptr;
 
+namespace rust {
+namespace behavior {
+template <typename Try, typename Fail>
+static void trycatch(Try &&func, Fail &&fail) noexcept try {
+  func();
+} catch (const LowlevelError &e) {
+  fail(e.explain.c_str());
+} catch (const XmlError &e) {
+  fail(e.explain.c_str());
+} catch (const std::exception &e) {
+  fail(e.what());
+}
+} // namespace behavior
+} // namespace rust
+
 class RustPCodeEmit;
 
 class RustPCodeEmitProxy : public PcodeEmit {
 private:
   RustPCodeEmit *inner;
+  uint32_t limit; // 0 for no limit
+  uint32_t count = 0;
 
 public:
-  RustPCodeEmitProxy(RustPCodeEmit *emit) : inner(emit) {}
+  bool exceeded = false;
+  bool stopped = false;
+
+  RustPCodeEmitProxy(RustPCodeEmit *emit, uint32_t limit = 0)
+      : inner(emit), limit(limit) {}
 
   virtual void dump(const Address &addr, OpCode opc, VarnodeData *outvar,
                     VarnodeData *vars, int4 isize);
@@ -57,31 +86,564 @@ public:
   virtual void adjustVma(long adjust);
 };
 
-class Decompiler : Sleigh {
+class RustConstantPool;
+class DecompilerArchitecture;
+
+// Records come from Rust the first time they are asked for and are kept in a
+// regular pool afterwards. The pool can't be saved or restored.
+class RustConstantPoolProxy : public ConstantPool {
 private:
+  RustConstantPool *inner;
+  DecompilerArchitecture *arch;
+  mutable ConstantPoolInternal cache;
+
+  virtual CPoolRecord *createRecord(const vector<uintb> &refs) {
+    throw LowlevelError("Cannot create constant pool records");
+  }
+
+public:
+  RustConstantPoolProxy(RustConstantPool *inner, DecompilerArchitecture *arch)
+      : inner(inner), arch(arch) {}
+
+  virtual const CPoolRecord *getRecord(const vector<uintb> &refs) const;
+  virtual bool empty(void) const { return cache.empty(); }
+  virtual void clear(void) { cache.clear(); }
+  virtual void saveXml(ostream &s) const {
+    throw LowlevelError("Cannot save the constant pool");
+  }
+  virtual void restoreXml(const Element *el, TypeFactory &typegrp) {
+    throw LowlevelError("Cannot restore the constant pool");
+  }
+};
+
+struct ContextVariableInfo;
+struct EngineVersion;
+struct ContextCommitInfo;
+struct RegisterInfo;
+struct SpaceInfo;
+struct OperandSpanInfo;
+struct ConstructorInfo;
+struct SymbolInfo;
+struct FunctionGraphInfo;
+struct FunctionVariablesInfo;
+struct FunctionSsaInfo;
+struct JumpTableInfo;
+struct PrototypeSpec;
+struct PoolRecord;
+enum class CommentKind : uint32_t;
+struct FieldInfo;
+struct TypeInfo;
+enum class TypeKind : uint32_t;
+struct SpacebaseInfo;
+struct PackedVarnode;
+struct PackedInstruction;
+struct TranslateStatus;
+enum class UnimplementedPolicy : uint32_t;
+
+// Decodes and prints instructions. Decompiler adds the pcode machinery on top.
+class Disassembler : public Sleigh {
+protected:
   unique_ptr<LoadImage> loadImage;
   unique_ptr<DocumentStorage> spec;
   ContextInternal context;
 
 public:
-  Decompiler(unique_ptr<LoadImage> loadImage, unique_ptr<DocumentStorage> spec)
+  Disassembler(unique_ptr<LoadImage> loadImage,
+               unique_ptr<DocumentStorage> spec)
       : Sleigh(loadImage.get(), &this->context), loadImage(move(loadImage)),
         spec(move(spec)) {
     this->initialize(*this->spec);
   }
 
-  int32_t translate(RustPCodeEmit *emit, uint64_t addr) const;
-  int32_t disassemble(RustAssemblyEmit *emit, uint64_t addr) const;
+  TranslateStatus disassemble(RustAssemblyEmit *emit, uint64_t addr) const;
+  void applyProcessorSpec(const Element &pspec);
+  uint32_t getContextVariable(const std::string &name, uint64_t addr) const;
+  void setContextVariableRange(const std::string &name, uint64_t start,
+                               uint64_t end, uint32_t value);
+  void setContextVariableFrom(const std::string &name, uint64_t start,
+                              uint32_t value);
+  void setContextVariableEverywhere(const std::string &name, uint32_t value);
+  bool hasContextVariable(const std::string &name) const;
+};
+
+// Pcode set from Rust to stand in for an instruction's own
+struct InjectedOp {
+  OpCode opcode;
+  bool hasOutput;
+  VarnodeData output;
+  vector<VarnodeData> inputs;
+};
+
+struct Injection {
+  // 0 for the length of the instruction decoded at the address
+  int4 length;
+  vector<InjectedOp> ops;
+};
+
+// A pcode snippet, like the body of a segment op, compiled and built into ops
+// ready to evaluate. Like ExecutablePcode, but without an Architecture, so it
+// can't read memory.
+class CompiledSnippet {
+  friend class Decompiler;
+  EmulateSnippet emulator;
+  // Temporaries holding the inputs and the output
+  vector<uintb> inputs;
+  uintb output = 0;
+
+public:
+  CompiledSnippet() : emulator((Architecture *)0) {}
+  uint64_t evaluate(rust::Slice<const uint64_t> values);
+};
+
+class Decompiler : public Disassembler {
+private:
+  vector<OpBehavior *> behaviors;
+  uint32_t opLimit = 0;
+  UnimplementedPolicy unimplementedPolicy{};
+  map<Address, Injection> injections;
+  // User ops registered from Rust, numbered after the spec's own
+  vector<string> addedUserOps;
+
+  vector<ContextSymbol *> contextSymbols() const;
+  const OpBehavior &behavior(uint32_t opcode, bool unary) const;
+  AddrSpace *spaceByIndex(int32_t index) const;
+  int4 liftInstruction(PcodeEmit &emit, const Address &addr,
+                       bool &substituted) const;
+  void copyInstructionBytes(const Address &addr, int4 length,
+                            rust::Vec<uint8_t> &out) const;
+
+public:
+  Decompiler(unique_ptr<LoadImage> loadImage, unique_ptr<DocumentStorage> spec)
+      : Disassembler(move(loadImage), move(spec)) {
+    OpBehavior::registerInstructions(behaviors, this);
+  }
+  ~Decompiler();
+
+  TranslateStatus translate(RustPCodeEmit *emit, uint64_t addr) const;
+  TranslateStatus decodeInstruction(uint64_t addr) const;
+  TranslateStatus translatePacked(uint64_t addr, PackedInstruction &out) const;
+  TranslateStatus translatePackedIn(int32_t space, uint64_t addr,
+                                    PackedInstruction &out) const;
+  void fillBytes(uint64_t addr, rust::Slice<uint8_t> buf) const;
+  void fillBytesIn(int32_t space, uint64_t addr,
+                   rust::Slice<uint8_t> buf) const;
+  void setOpLimit(uint32_t limit) { opLimit = limit; }
+  uint32_t getOpLimit() const { return opLimit; }
+  void setUnimplementedPolicy(UnimplementedPolicy policy) {
+    unimplementedPolicy = policy;
+  }
+  UnimplementedPolicy getUnimplementedPolicy() const {
+    return unimplementedPolicy;
+  }
+  uint32_t getUnimplementedUserOp() const;
+  void setInjection(uint64_t addr, uint32_t length,
+                    const PackedInstruction &pcode);
+  bool removeInjection(uint64_t addr);
+  void clearInjections() { injections.clear(); }
+  uint32_t addUserOp(const std::string &name);
+  rust::Vec<rust::String> getUserOps() const;
+  virtual void getUserOpNames(vector<string> &res) const;
   ContextDatabase *getContext() { return &this->context; }
+
+  unique_ptr<Address> getCodeAddress(uint64_t offset) const;
+  unique_ptr<Address> getDataAddress(uint64_t offset) const;
+  rust::Vec<uint32_t> getContextWords(uint64_t addr) const;
+  LoadImage *getLoadImage() const { return loadImage.get(); }
+  rust::Vec<ContextVariableInfo> getContextVariables() const;
+  rust::Vec<ContextCommitInfo> getContextCommits(uint64_t addr) const;
+  rust::Vec<OperandSpanInfo> getOperandSpans(uint64_t addr) const;
+  rust::Vec<ConstructorInfo> getConstructors(uint64_t addr) const;
+  rust::Vec<SymbolInfo> findSymbolInfo(const std::string &name) const;
+  rust::Vec<SymbolInfo> getSymbols() const;
+  rust::Vec<RegisterInfo> getRegisterList() const;
+  rust::String saveContext() const;
+  void restoreContext(const Element &el);
+  rust::Vec<SpaceInfo> getSpaces() const;
+  rust::Vec<SpacebaseInfo> getSpacebases() const;
+  int32_t addStackSpace(const std::string &base, const std::string &reg,
+                        bool growsNegative);
+  int32_t addOverlaySpace(const std::string &name, const std::string &base);
+
+  int32_t getConstantSpaceIndex() const;
+  int32_t getDefaultCodeSpaceIndex() const;
+  int32_t getDefaultDataSpaceIndex() const;
+  int32_t getInstructionAlignment() const;
+  uint32_t getMaxInstructionLength() const;
+  uint32_t getMaxDelaySlotBytes() const { return maxdelayslotbytes; }
+  uint32_t getUniqueAllocateMask() const { return unique_allocatemask; }
+  const AddrSpace *getSpaceFromConst(uint64_t offset) const;
+  uint64_t getSpaceConst(int32_t index) const;
+  rust::String getExactRegisterName(int32_t space, uint64_t offset,
+                                    int32_t size) const;
+  uint64_t evaluateUnary(uint32_t opcode, int32_t sizeout, int32_t sizein,
+                         uint64_t in) const;
+  uint64_t evaluateBinary(uint32_t opcode, int32_t sizeout, int32_t sizein,
+                          uint64_t in1, uint64_t in2) const;
+  unique_ptr<CompiledSnippet>
+  compileSnippet(const std::string &body, rust::Slice<const rust::String> inputs,
+                 rust::Slice<const uint32_t> sizes, const std::string &output,
+                 uint32_t outputSize) const;
+};
+
+struct MemoryFaultInfo;
+struct CallOtherInfo;
+struct MemoryAccessInfo;
+
+/// Lets the translator of an emulator fetch instruction bytes from the emulated
+/// memory rather than from the original image.
+class MemoryStateLoadImage : public LoadImage {
+private:
+  MemoryState *state;
+
+public:
+  MemoryStateLoadImage(MemoryState *state)
+      : LoadImage("emulator"), state(state) {}
+
+  virtual void loadFill(uint1 *ptr, int4 size, const Address &address);
+  virtual string getArchType(void) const { return "emulator"; }
+  virtual void adjustVma(long adjust) {}
+};
+
+/// A page overlay that keeps track of the pages it allocated
+class CountingPageOverlay : public MemoryPageOverlay {
+private:
+  MemoryBank *underlie;
+  std::set<uintb> pages;
+
+protected:
+  virtual void insert(uintb addr, uintb val);
+  virtual void setPage(uintb addr, const uint1 *val, int4 skip, int4 size);
+
+public:
+  CountingPageOverlay(AddrSpace *spc, int4 ws, int4 ps, MemoryBank *ul)
+      : MemoryPageOverlay(spc, ws, ps, ul), underlie(ul) {}
+  size_t numPages() const { return pages.size(); }
+  map<uintb, vector<uint1>> savePages() const;
+  void restorePages(const map<uintb, vector<uint1>> &saved);
+};
+
+/// A zero-initialized bank of words, which unlike MemoryHashOverlay never
+/// fills up and can be saved and restored
+class WordMapBank : public MemoryBank {
+private:
+  std::unordered_map<uintb, uintb> words;
+
+protected:
+  virtual void insert(uintb addr, uintb val) { words[addr] = val; }
+  virtual uintb find(uintb addr) const;
+
+public:
+  WordMapBank(AddrSpace *spc, int4 ws, int4 ps) : MemoryBank(spc, ws, ps) {}
+  const std::unordered_map<uintb, uintb> &getWords() const { return words; }
+  void setWords(const std::unordered_map<uintb, uintb> &w) { words = w; }
+};
+
+class PcodeEmulator;
+
+/// The state of a PcodeEmulator at some point of its execution
+class EmulatorState {
+public:
+  const PcodeEmulator *owner;
+  Address address;
+  int4 op;
+  bool instruction_start;
+  vector<map<uintb, vector<uint1>>> pages;
+  vector<std::unordered_map<uintb, uintb>> words;
+};
+
+/// Access rights of a range of addresses, see PcodeEmulator::setPermissions
+struct PermissionRange {
+  uintb last; // inclusive, so a range can end at the top of the space
+  uint4 flags;
+};
+
+/// State needed to undo one executed pcode op
+struct UndoRecord {
+  struct Write {
+    AddrSpace *space;
+    uintb offset;
+    vector<uint1> old;
+  };
+  Address address;
+  int4 op;
+  bool instruction_start;
+  vector<Write> writes;
+};
+
+/// An address breakpoint handed to the Rust side, see PcodeEmulator::checkBreak
+class PendingBreak : public BreakCallBack {
+public:
+  bool enabled = true;
+  virtual bool addressCallback(const Address &addr) { return enabled; }
+};
+
+/// A LOAD or STORE recorded while tracing memory
+struct MemoryAccess {
+  uint4 access;
+  AddrSpace *space;
+  uintb offset;
+  int4 size;
+  uintb value;
+  uintb pc;
+};
+
+class PcodeEmulator : public EmulateMemory {
+private:
+  unique_ptr<LoadImage> image;
+  MemoryState state;
+  Decompiler translator;
+  vector<unique_ptr<MemoryBank>> banks;
+  vector<OpBehavior *> behaviors;
+  vector<PcodeOpRaw *> opcache;
+  vector<VarnodeData *> varcache;
+  Address current_address;
+  int4 current_op;
+  int4 instruction_length;
+  bool instruction_start;
+  bool decoded;
+  std::deque<UndoRecord> history;
+  size_t history_limit;
+  map<int4, map<uintb, PermissionRange>> permissions;
+  bool enforce_permissions;
+  bool fault_pending;
+  uint4 fault_access;
+  AddrSpace *fault_space;
+  uintb fault_offset;
+  int4 fault_size;
+  bool fault_mapped;
+  uint4 ignore_access;
+  AddrSpace *ignore_space;
+  uintb ignore_offset;
+  std::set<uintb> code_pages;
+  uint64_t code_invalidations;
+  vector<CountingPageOverlay *> overlays;
+  vector<WordMapBank *> wordbanks;
+  vector<string> userop_names;
+  bool callother_pending;
+  BreakTableCallBack breaktable;
+  map<Address, unique_ptr<PendingBreak>> breaks;
+  bool break_pending;
+  bool break_passed;
+  bool trace_memory;
+  vector<MemoryAccess> accesses;
+  uint4 decode_failure;
+
+  void clearCache();
+  void invalidateCode(AddrSpace *spc, uintb off, int4 size);
+  void recordUndo();
+  void checkAccess(uint4 access, AddrSpace *spc, uintb off, int4 size);
+  void checkRange(uint4 access, AddrSpace *spc,
+                  const map<uintb, PermissionRange> &ranges, uintb first,
+                  uintb last);
+  bool touchesCode(uintb first, uintb last) const;
+  void checkBreak();
+  void traceAccess(uint4 access, AddrSpace *spc, uintb off, VarnodeData *vn);
+  string userOpName() const;
+  void decode();
+  void establishOp();
+
+protected:
+  virtual void fallthruOp(void);
+  virtual void executeBranch(void);
+  virtual void executeLoad(void);
+  virtual void executeStore(void);
+  virtual void executeCallother(void);
+
+public:
+  PcodeEmulator(unique_ptr<LoadImage> image, unique_ptr<DocumentStorage> spec);
+  ~PcodeEmulator();
+
+  virtual void setExecuteAddress(const Address &addr);
+  virtual Address getExecuteAddress(void) const { return current_address; }
+
+  void setPc(uint64_t addr);
+  uint64_t getPc() const { return current_address.getOffset(); }
+  void stepOp();
+  void stepInstruction();
+  bool isInstructionStart() const { return instruction_start; }
+  bool isBigEndian() const { return translator.isBigEndian(); }
+
+  uint64_t getRegisterValue(const std::string &name) const;
+  void setRegisterValue(const std::string &name, uint64_t value);
+  rust::Vec<RegisterInfo> getRegisterList() const;
+  void readSpace(int32_t space, uint64_t offset, rust::Slice<uint8_t> buf) const;
+  void writeSpace(int32_t space, uint64_t offset,
+                  rust::Slice<const uint8_t> buf);
+  int32_t getDefaultDataSpaceIndex() const;
+  void applyProcessorSpec(const Element &pspec);
+
+  void setHistoryLimit(size_t limit);
+  size_t getHistoryLength() const { return history.size(); }
+  bool stepBackOp();
+  bool stepBackInstruction();
+
+  int32_t getDefaultCodeSpaceIndex() const;
+  void setPermissions(int32_t space, uint64_t start, uint64_t size,
+                      uint32_t flags);
+  void setEnforcePermissions(bool enforce) { enforce_permissions = enforce; }
+  MemoryFaultInfo takeFault();
+  void ignoreFault();
+
+  uint64_t getCodeInvalidations() const { return code_invalidations; }
+  uint64_t getMemoryUsage() const;
+
+  CallOtherInfo takeCallOther();
+  void completeCallOther(bool has_value, uint64_t value);
+
+  void addBreakpoint(uint64_t addr);
+  bool removeBreakpoint(uint64_t addr);
+  bool takeBreak();
+  void passBreak() { break_passed = true; }
+  void skipInstruction();
+
+  void setTraceMemory(bool trace);
+  rust::Vec<MemoryAccessInfo> takeMemoryAccesses();
+
+  uint32_t takeDecodeFailure();
+
+  unique_ptr<EmulatorState> saveState() const;
+  void restoreState(const EmulatorState &saved);
+};
+
+/// An address breakpoint that halts the emulator before the instruction runs
+class HaltBreak : public BreakCallBack {
+public:
+  virtual bool addressCallback(const Address &addr) {
+    emulate->setHalt(true);
+    return true;
+  }
+};
+
+/// The decompiler's own EmulatePcodeCache, with a MemoryState reading from the
+/// load image of a Decompiler and a table of halting address breakpoints.
+class PcodeCacheEmulator {
+private:
+  const Decompiler *translator;
+  MemoryState state;
+  vector<unique_ptr<MemoryBank>> banks;
+  BreakTableCallBack breaktable;
+  vector<unique_ptr<HaltBreak>> breaks;
+  EmulatePcodeCache emulate;
+  bool started;
+
+  void checkStarted() const;
+
+public:
+  PcodeCacheEmulator(const Decompiler *translator);
+
+  void setExecuteAddress(uint64_t addr);
+  uint64_t getExecuteAddress() const;
+  void executeCurrentOp();
+  void executeInstruction();
+  bool isInstructionStart() const { return emulate.isInstructionStart(); }
+  int32_t numCurrentOps() const { return emulate.numCurrentOps(); }
+  int32_t getCurrentOpIndex() const { return emulate.getCurrentOpIndex(); }
+  bool getHalt() const { return emulate.getHalt(); }
+  void setHalt(bool halt) { emulate.setHalt(halt); }
+  void addAddressBreak(uint64_t addr);
+
+  uint64_t getValue(int32_t space, uint64_t offset, int32_t size) const;
+  void setValue(int32_t space, uint64_t offset, int32_t size, uint64_t value);
+  void getChunk(int32_t space, uint64_t offset, rust::Slice<uint8_t> buf) const;
+  void setChunk(int32_t space, uint64_t offset,
+                rust::Slice<const uint8_t> buf);
+  uint64_t getRegisterValue(const std::string &name) const;
+  void setRegisterValue(const std::string &name, uint64_t value);
+};
+
+/// The full decompiler, built from the .sla, .pspec and .cspec of a language
+/// and reading instructions from a Rust load image.
+class DecompilerArchitecture : public Architecture {
+private:
+  friend class RustConstantPoolProxy;
+
+  RustLoadImage *image;
+  RustConstantPool *pool;
+  unique_ptr<DocumentStorage> store;
+  // The function being analysed, which messages are attributed to
+  uint64_t analysed = 0;
+  bool analysing = false;
+
+  Funcdata *functionAt(uint64_t entry, const std::string &name);
+  Funcdata *analyzeFunction(uint64_t entry, const std::string &name,
+                            const std::string &style);
+  Datatype *findType(const std::string &name);
+
+protected:
+  virtual Translate *buildTranslator(DocumentStorage &store);
+  virtual void buildLoader(DocumentStorage &store);
+  virtual PcodeInjectLibrary *buildPcodeInjectLibrary(void);
+  virtual void buildConstantPool(DocumentStorage &store);
+  // The documents are all registered before init
+  virtual void buildSpecFile(DocumentStorage &store) {}
+  virtual void modifySpaces(Translate *trans) {}
+  virtual void resolveArchitecture(void) { archid = "sleigh-sys"; }
+
+public:
+  DecompilerArchitecture(RustLoadImage *image, RustConstantPool *pool,
+                         unique_ptr<DocumentStorage> store);
+
+  // Warnings about a function also end up as comments in its C
+  virtual void printMessage(const string &message) const;
+
+  rust::String decompileFunction(uint64_t entry, const std::string &name,
+                                 const std::string &style);
+  rust::String getRawFunction(uint64_t entry, const std::string &name,
+                              const std::string &style);
+  FunctionGraphInfo getFunctionGraph(uint64_t entry, const std::string &name);
+  FunctionVariablesInfo getFunctionVariables(uint64_t entry,
+                                             const std::string &name);
+  FunctionSsaInfo getFunctionSsa(uint64_t entry, const std::string &name,
+                                 const std::string &style);
+  rust::Vec<JumpTableInfo> getJumpTables(uint64_t entry,
+                                         const std::string &name);
+  void setPrototype(uint64_t entry, const std::string &name,
+                    const PrototypeSpec &prototype);
+  void definePrimitive(const std::string &name, uint32_t size, TypeKind kind);
+  void defineStruct(const std::string &name, uint32_t size,
+                    rust::Slice<const FieldInfo> fields);
+  void setGlobal(uint64_t addr, const std::string &name,
+                 const std::string &type_name);
+  TypeInfo getTypeInfo(const std::string &name);
+  void addComment(uint64_t function, uint64_t addr, CommentKind kind,
+                  const std::string &text);
+  void clearComments(uint64_t function);
 };
 
+unique_ptr<Disassembler> newDisassembler(RustLoadImage *loadImage,
+                                         unique_ptr<DocumentStorage> spec);
 unique_ptr<Decompiler> newDecompiler(RustLoadImage *loadImage,
                                      unique_ptr<DocumentStorage> spec);
+unique_ptr<DecompilerArchitecture>
+newDecompilerArchitecture(RustLoadImage *loadImage, RustConstantPool *pool,
+                          const std::string &sla, const std::string &pspec,
+                          const std::string &cspec);
+unique_ptr<PcodeEmulator> newPcodeEmulator(RustLoadImage *loadImage,
+                                           unique_ptr<DocumentStorage> spec);
+unique_ptr<PcodeCacheEmulator>
+newPcodeCacheEmulator(const Decompiler &translator);
 unique_ptr<Address> newAddress();
+unique_ptr<Address> copyAddress(const Address &addr);
 unique_ptr<ContextDatabase> newContext();
 unique_ptr<DocumentStorage> newDocumentStorage(const std::string &s);
+unique_ptr<Document> parseDocument(const std::string &s);
+rust::String compileSlaspec(const std::string &input, const std::string &output,
+                            rust::Slice<const rust::Str> names,
+                            rust::Slice<const rust::Str> values);
+EngineVersion engineVersion();
+EngineVersion supportedEngineVersion();
+class RustProtocolStream;
+void serveGhidraProtocol(RustProtocolStream *stream);
+const Element &getDocumentRoot(const Document &doc);
+size_t getElementNumChildren(const Element &el);
+const Element &getElementChild(const Element &el, size_t i);
 
 uint32_t getAddrSpaceType(const AddrSpace &space);
+rust::Str getOpName(uint32_t opcode);
 
 uint32_t getVarnodeSize(const VarnodeData &data);
+const VarnodeData *getVarnodeDataAt(const VarnodeData *vars, int32_t i);
 unique_ptr<Address> getVarnodeDataAddress(const VarnodeData &data);
+PackedVarnode packVarnode(const VarnodeData &data);
+rust::Vec<PackedVarnode> packVarnodes(const VarnodeData *vars, int32_t size);
+size_t getVarnodeDataStride();
@@ -1,4 +1,8 @@
-use std::path::Path;
+use std::env;
+use std::fmt::Write;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
 
 const SOURCE_FILES: &[&str] = &[
     "space.cc",
@@ -59,17 +63,18 @@ const SOURCE_FILES: &[&str] = &[
     "paramid.cc",
     "transform.cc",
     "stringmanage.cc",
-    //"string_ghidra.cc",
-    //"ghidra_arch.cc",
-    //"typegrp_ghidra.cc",
-    //"cpool_ghidra.cc",
+    "string_ghidra.cc",
+    "ghidra_arch.cc",
+    "typegrp_ghidra.cc",
+    "cpool_ghidra.cc",
     "loadimage_ghidra.cc",
-    //"inject_ghidra.cc",
-    //"database_ghidra.cc",
+    "inject_ghidra.cc",
+    "database_ghidra.cc",
     "inject_sleigh.cc",
-    //"ghidra_translate.cc",
-    //"ghidra_context.cc",
-    //"comment_ghidra.cc",
+    "ghidra_translate.cc",
+    "ghidra_context.cc",
+    "comment_ghidra.cc",
+    "ghidra_process.cc",
     "sleigh_arch.cc",
     "sleigh.cc",
     "filemanage.cc",
@@ -80,21 +85,304 @@ const SOURCE_FILES: &[&str] = &[
     "slghpatexpress.cc",
     "slghpattern.cc",
     "pcodecompile.cc",
-    //"slgh_compile.cc",
+    "pcodeparse.cc",
+    "slgh_compile.cc",
     "slghscan.cc",
     "slghparse.cc",
     "xml.cc",
 ];
 
-fn main() {
-    cxx_build::bridge("src/lib.rs")
+/// Sources of the standalone `sleigh` compiler, as listed in the decompiler's Makefile
+const SLEIGH_TOOL_FILES: &[&str] = &[
+    "xml.cc",
+    "space.cc",
+    "float.cc",
+    "address.cc",
+    "pcoderaw.cc",
+    "translate.cc",
+    "opcodes.cc",
+    "globalcontext.cc",
+    "sleigh.cc",
+    "pcodeparse.cc",
+    "pcodecompile.cc",
+    "sleighbase.cc",
+    "slghsymbol.cc",
+    "slghpatexpress.cc",
+    "slghpattern.cc",
+    "semantics.cc",
+    "context.cc",
+    "filemanage.cc",
+    "slgh_compile.cc",
+    "slghparse.cc",
+    "slghscan.cc",
+];
+
+/// Directory searched (recursively) for `.slaspec` files to compile and embed
+const SLASPEC_DIR_VAR: &str = "SLEIGH_SLASPEC_DIR";
+/// Whitespace separated `NAME=VALUE` preprocessor defines for those specs
+const SLASPEC_DEFINES_VAR: &str = "SLEIGH_SLASPEC_DEFINES";
+
+fn find_slaspecs(dir: &Path, found: &mut Vec<PathBuf>) {
+    let entries = fs::read_dir(dir).unwrap_or_else(|e| panic!("{}: {}", dir.display(), e));
+    for entry in entries {
+        let path = entry.unwrap().path();
+        if path.is_dir() {
+            find_slaspecs(&path, found);
+        } else if path.extension() == Some("slaspec".as_ref()) {
+            found.push(path);
+        }
+    }
+}
+
+/// Settings the engine's sources need on any compiler: C++14, exceptions, and on Windows the
+/// `_WINDOWS` define its headers and file handling test for
+fn configure(build: &mut cc::Build, windows: bool) {
+    build
         .cpp(true)
+        .std("c++14")
+        .include("decompiler")
+        .warnings(false);
+    if windows {
+        build.define("_WINDOWS", None);
+    }
+    if build.get_compiler().is_like_msvc() {
+        // cl doesn't unwind through C++ frames without /EHsc, and the rule and action
+        // sources have more sections than its default object format holds
+        build.flag("/EHsc").flag("/bigobj");
+    }
+}
+
+/// The engine reports every error by throwing, so wasm builds need C++ exceptions, which
+/// emscripten and wasi-sdk leave off by default
+fn configure_wasm(build: &mut cc::Build, target_os: &str, threads: bool) {
+    match target_os {
+        "emscripten" => build.flag("-fexceptions"),
+        "wasi" => build.flag("-fwasm-exceptions"),
+        _ => panic!(
+            "wasm32-unknown-{} has no C++ standard library to build the engine against, \
+             build for wasm32-unknown-emscripten or wasm32-wasip1 instead",
+            target_os
+        ),
+    };
+    if !threads {
+        build.define("SLEIGH_NO_THREADS", None);
+    }
+}
+
+/// Environment variable naming a directory with a prebuilt engine library to link instead of
+/// compiling one
+const LIB_DIR_VAR: &str = "SLEIGH_SYS_LIB_DIR";
+/// Environment variable naming a directory to keep compiled engine libraries in, across target
+/// directories and `cargo clean`
+const CACHE_DIR_VAR: &str = "SLEIGH_SYS_CACHE_DIR";
+
+/// File name of the engine library `cc` produces for `build`
+fn engine_lib_file(build: &cc::Build) -> &'static str {
+    if build.get_compiler().is_like_msvc() {
+        "sleigh_decompiler.lib"
+    } else {
+        "libsleigh_decompiler.a"
+    }
+}
+
+/// FNV-1a, which unlike `DefaultHasher` gives the same key with every Rust release
+fn fnv1a(hash: &mut u64, bytes: &[u8]) {
+    for &byte in bytes {
+        *hash ^= u64::from(byte);
+        *hash = hash.wrapping_mul(0x100_0000_01b3);
+    }
+}
+
+/// Name of the cache entry for the engine built by `build`: it changes with the crate
+/// version, the target, the compiler and its flags, and the engine's sources
+fn engine_cache_key(build: &cc::Build) -> String {
+    let mut hash = 0xcbf2_9ce4_8422_2325;
+    let compiler = build.get_compiler();
+    fnv1a(
+        &mut hash,
+        compiler.path().as_os_str().to_string_lossy().as_bytes(),
+    );
+    for arg in compiler.args() {
+        fnv1a(&mut hash, arg.to_string_lossy().as_bytes());
+        fnv1a(&mut hash, &[0]);
+    }
+    let mut sources: Vec<_> = fs::read_dir("decompiler")
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| {
+            matches!(
+                path.extension().and_then(|e| e.to_str()),
+                Some("cc" | "hh" | "h")
+            )
+        })
+        .collect();
+    sources.sort();
+    for source in sources {
+        fnv1a(&mut hash, source.to_string_lossy().as_bytes());
+        fnv1a(&mut hash, &fs::read(&source).unwrap());
+    }
+    format!(
+        "{}-{}-{:016x}",
+        env::var("CARGO_PKG_VERSION").unwrap(),
+        env::var("TARGET").unwrap(),
+        hash
+    )
+}
+
+/// Link the engine library, compiling it with `build` unless `SLEIGH_SYS_LIB_DIR` provides one
+/// or it is in the `SLEIGH_SYS_CACHE_DIR` cache. The bridge only references some of the
+/// engine, but comes after it on the linker's command line, so the whole archive is linked.
+fn link_engine(build: &cc::Build, out_dir: &Path) {
+    println!("cargo:rerun-if-env-changed={}", LIB_DIR_VAR);
+    println!("cargo:rerun-if-env-changed={}", CACHE_DIR_VAR);
+    let file = engine_lib_file(build);
+    let dir = if let Some(dir) = env::var_os(LIB_DIR_VAR).filter(|dir| !dir.is_empty()) {
+        let dir = PathBuf::from(dir);
+        assert!(
+            dir.join(file).is_file(),
+            "{} is set, but {} has no {}",
+            LIB_DIR_VAR,
+            dir.display(),
+            file
+        );
+        println!("cargo:rerun-if-changed={}", dir.join(file).display());
+        dir
+    } else if let Some(cache) = env::var_os(CACHE_DIR_VAR).filter(|dir| !dir.is_empty()) {
+        let dir = PathBuf::from(cache).join(engine_cache_key(build));
+        if !dir.join(file).is_file() {
+            build.compile("sleigh_decompiler");
+            fs::create_dir_all(&dir).unwrap();
+            // Builds running side by side may fill the same entry, so it appears at once
+            let partial = dir.join(format!("{}.{}", file, std::process::id()));
+            fs::copy(out_dir.join(file), &partial).unwrap();
+            fs::rename(&partial, dir.join(file)).unwrap();
+        }
+        dir
+    } else {
+        build.compile("sleigh_decompiler");
+        out_dir.to_path_buf()
+    };
+    println!("cargo:rustc-link-search=native={}", dir.display());
+    println!("cargo:rustc-link-lib=static:+whole-archive=sleigh_decompiler");
+}
+
+/// Build the `sleigh` command line tool for the host, which differs from the target when
+/// cross-compiling
+fn build_sleigh_tool(out_dir: &Path) -> PathBuf {
+    let host = env::var("HOST").unwrap();
+    let windows = host.contains("windows");
+    let mut build = cc::Build::new();
+    build.target(&host).host(&host);
+    configure(&mut build, windows);
+    let compiler = build.get_compiler();
+    let exe = out_dir.join(if windows { "sleigh.exe" } else { "sleigh" });
+    let mut command = compiler.to_command();
+    command.args(
+        SLEIGH_TOOL_FILES
+            .iter()
+            .map(|s| Path::new("decompiler").join(s)),
+    );
+    if compiler.is_like_msvc() {
+        // cl leaves the objects in the working directory unless told otherwise
+        let objects = out_dir.join("sleigh-obj");
+        fs::create_dir_all(&objects).unwrap();
+        command
+            .arg(format!("-Fo{}\\", objects.display()))
+            .arg(format!("-Fe{}", exe.display()));
+    } else {
+        command.arg("-o").arg(&exe);
+    }
+    let status = command.status().expect("failed to run the C++ compiler");
+    assert!(status.success(), "failed to build the sleigh compiler");
+    exe
+}
+
+/// Compile the specs under `SLEIGH_SLASPEC_DIR` and generate the table of embedded `.sla`
+/// files included by `src/compile.rs`
+fn embed_slaspecs(out_dir: &Path) {
+    println!("cargo:rerun-if-env-changed={}", SLASPEC_DIR_VAR);
+    println!("cargo:rerun-if-env-changed={}", SLASPEC_DEFINES_VAR);
+
+    let mut table = String::from("pub static EMBEDDED_SLA: &[(&str, &str)] = &[\n");
+    if let Some(dir) = env::var_os(SLASPEC_DIR_VAR) {
+        let dir = PathBuf::from(dir);
+        println!("cargo:rerun-if-changed={}", dir.display());
+        let mut specs = Vec::new();
+        find_slaspecs(&dir, &mut specs);
+        specs.sort();
+
+        let defines = env::var(SLASPEC_DEFINES_VAR).unwrap_or_default();
+        let sleigh = build_sleigh_tool(out_dir);
+        let sla_dir = out_dir.join("sla");
+        fs::create_dir_all(&sla_dir).unwrap();
+        let mut names = Vec::new();
+        for spec in specs {
+            println!("cargo:rerun-if-changed={}", spec.display());
+            let name = spec.file_stem().unwrap().to_string_lossy().into_owned();
+            assert!(
+                !names.contains(&name),
+                "{} contains more than one {}.slaspec",
+                dir.display(),
+                name
+            );
+            let sla = sla_dir.join(format!("{}.sla", name));
+            let status = Command::new(&sleigh)
+                .args(defines.split_whitespace().map(|d| format!("-D{}", d)))
+                .arg(&spec)
+                .arg(&sla)
+                .status()
+                .expect("failed to run the sleigh compiler");
+            assert!(status.success(), "failed to compile {}", spec.display());
+            writeln!(table, "    ({:?}, include_str!({:?})),", name, sla).unwrap();
+            names.push(name);
+        }
+    }
+    table.push_str("];\n");
+    fs::write(out_dir.join("embedded_sla.rs"), table).unwrap();
+}
+
+fn main() {
+    let out_dir = PathBuf::from(env::var_os("OUT_DIR").unwrap());
+    embed_slaspecs(&out_dir);
+
+    println!("cargo:rerun-if-changed=bridge");
+    println!("cargo:rerun-if-changed=decompiler");
+    let target_os = env::var("CARGO_CFG_TARGET_OS").unwrap();
+    let target_env = env::var("CARGO_CFG_TARGET_ENV").unwrap_or_default();
+    let target_features = env::var("CARGO_CFG_TARGET_FEATURE").unwrap_or_default();
+    let has_feature = |feature: &str| target_features.split(',').any(|f| f == feature);
+
+    let windows = target_os == "windows";
+    let wasm = env::var("CARGO_CFG_TARGET_ARCH").unwrap() == "wasm32";
+
+    let mut engine = cc::Build::new();
+    configure(&mut engine, windows);
+    if wasm {
+        configure_wasm(&mut engine, &target_os, has_feature("atomics"));
+    }
+    engine
         .define("PACKAGE", "cppserver")
+        .define("SLEIGH_COMPILE_NO_MAIN", None)
+        .define("GHIDRA_PROCESS_NO_MAIN", None)
         .files(SOURCE_FILES.iter().map(|s| Path::new("decompiler").join(s)))
+        .cargo_metadata(false);
+    link_engine(&engine, &out_dir);
+
+    let mut build = cxx_build::bridge("src/lib.rs");
+    configure(&mut build, windows);
+    // A fully static musl binary can't load libstdc++.so at run time
+    if target_env == "musl" && has_feature("crt-static") {
+        build.cpp_link_stdlib_static(true);
+    }
+    if wasm {
+        configure_wasm(&mut build, &target_os, has_feature("atomics"));
+        match target_os.as_str() {
+            "emscripten" => println!("cargo:rustc-link-arg=-fexceptions"),
+            _ => println!("cargo:rustc-link-lib=unwind"),
+        }
+    }
+    build
         .file("bridge/bridge.cc")
-        .includes(["decompiler", "bridge"])
-        .flag("-lbfd -lz")
-        .warnings(false)
-        .flag_if_supported("-std=c++14")
+        .include("bridge")
         .compile("sleigh");
 }
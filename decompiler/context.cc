@@ -130,7 +130,7 @@ void ParserContext::addCommit(TripleSymbol *sym,int4 num,uintm mask,bool flow,Co
   set.flow = flow;
 }
 
-void ParserContext::applyCommits(void)
+void ParserContext::resolveCommits(vector<ResolvedContextSet> &res)
 
 {
   if (contextcommit.empty()) return;
@@ -162,7 +162,27 @@ void ParserContext::applyCommits(void)
       uintb newoff = AddrSpace::addressToByte(commitaddr.getOffset(),addr.getSpace()->getWordSize());
       commitaddr = Address(addr.getSpace(),newoff);
     }
+    res.emplace_back();
+    ResolvedContextSet &set(res.back());
+    set.addr = commitaddr;
+    set.num = (*iter).num;
+    set.mask = (*iter).mask;
+    set.value = (*iter).value;
+    set.flow = (*iter).flow;
+  }
+}
+
+void ParserContext::applyCommits(void)
+
+{
+  if (contextcommit.empty()) return;
+  vector<ResolvedContextSet> sets;
+  resolveCommits(sets);
+
+  vector<ResolvedContextSet>::iterator iter;
 
+  for(iter=sets.begin();iter!=sets.end();++iter) {
+    const Address &commitaddr((*iter).addr);
 				// Commit context change
     if ((*iter).flow)		// The context flows
       contcache->setContext(commitaddr,(*iter).num,(*iter).mask,(*iter).value);
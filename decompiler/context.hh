@@ -62,6 +62,14 @@ struct ContextSet {		// Instructions for setting a global context value
   bool flow;			// Does the new context flow from its set point
 };
 
+struct ResolvedContextSet {	// A ContextSet with the address where it takes effect
+  Address addr;			// Address where the setting takes effect
+  int4 num;			// Number of context word affected
+  uintm mask;			// Bits within word affected
+  uintm value;			// New setting for bits
+  bool flow;			// Does the new context flow from its set point
+};
+
 class ParserWalker;		// Forward declaration
 class ParserWalkerChange;
 
@@ -103,6 +111,7 @@ public:
   void setCalladdr(const Address &ad) { calladdr = ad; }
   void addCommit(TripleSymbol *sym,int4 num,uintm mask,bool flow,ConstructState *point);
   void clearCommits(void) { contextcommit.clear(); }
+  void resolveCommits(vector<ResolvedContextSet> &res);
   void applyCommits(void);
   const Address &getAddr(void) const { return addr; }
   const Address &getNaddr(void) const { return naddr; }
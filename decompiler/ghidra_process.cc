@@ -510,6 +510,8 @@ void GhidraDecompCapability::initialize(void)
   commandmap["setOptions"] = new SetOptions();
 }
 
+#ifndef GHIDRA_PROCESS_NO_MAIN
+
 int main(int argc,char **argv)
 
 {
@@ -522,3 +524,4 @@ int main(int argc,char **argv)
   GhidraCapability::shutDown();
 }
 
+#endif
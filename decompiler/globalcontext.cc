@@ -355,6 +355,11 @@ void ContextInternal::restoreContext(const Element *el,const Address &addr1,cons
   const List &list(el->getChildren());
   List::const_iterator iter = list.begin();
 
+  if (addr1.isInvalid()) {		// Clear the default once, so every variable listed survives
+    uintm *defaultBuffer = getDefaultValue();
+    for(int4 i=0;i<size;++i)
+      defaultBuffer[i] = 0;
+  }
   while(iter != list.end()) {
     const Element *subel = *iter;
     istringstream s(subel->getAttributeValue("val"));
@@ -363,12 +368,8 @@ void ContextInternal::restoreContext(const Element *el,const Address &addr1,cons
     s >> val;
     ContextBitRange &var(getVariable(subel->getAttributeValue("name")));
     vector<uintm *> vec;
-    if (addr1.isInvalid()) {		// Invalid addr1, indicates we should set default value
-      uintm *defaultBuffer = getDefaultValue();
-      for(int4 i=0;i<size;++i)
-	defaultBuffer[i] = 0;
-      vec.push_back(defaultBuffer);
-    }
+    if (addr1.isInvalid())		// Invalid addr1, indicates we should set default value
+      vec.push_back(getDefaultValue());
     else
       getRegionForSet(vec,addr1,addr2,var.getWord(),var.getMask()<<var.getShift());
     for(int4 i=0;i<vec.size();++i)
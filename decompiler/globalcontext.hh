@@ -299,6 +299,7 @@ public:
   virtual void saveXml(ostream &s) const;
   virtual void restoreXml(const Element *el,const AddrSpaceManager *manage);
   virtual void restoreFromSpec(const Element *el,const AddrSpaceManager *manage);
+  void clearPoints(void) { database.clear(); trackbase.clear(); }	///< Drop all split points, keeping default values
 };
 
 /// \brief A helper class for caching the active context blob to minimize database lookups
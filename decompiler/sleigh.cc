@@ -110,6 +110,7 @@ void PcodeCacher::clear(void)
   issued.clear();
   label_refs.clear();
   labels.clear();
+  origins.clear();
 }
 
 /// Assuming all the PcodeData has been generated for an
@@ -380,10 +381,12 @@ void SleighBuilder::delaySlot(OpTpl *op)
     ParserWalker newwalker( pos );
     walker = &newwalker;
     walker->baseState();
+    cache->addOrigin(newaddr);
     build(walker->getConstructor()->getTempl(),-1); // Build the whole delay slot
     fallOffset += len;
     bytecount += len;
   } while(bytecount < delaySlotByteCnt);
+  cache->addOrigin(baseaddr);
   walker = tmp;			// Restore original context
   uniqueoffset = olduniqueoffset;
 }
@@ -562,6 +565,25 @@ void Sleigh::initialize(DocumentStorage &store)
   discache = new DisassemblyCache(cache,getConstantSpace(),parser_cachesize,parser_windowsize);
 }
 
+/// Context blobs and instruction parses are cached by address range.  If the ContextDatabase
+/// is modified directly, rather than through the engine, those caches must be rebuilt.
+void Sleigh::resetCaches(void)
+
+{
+  bool initialized = (discache != (DisassemblyCache *)0);
+  clearForDelete();
+  cache = new ContextCache(context_db);
+  discache = (DisassemblyCache *)0;
+  if (!initialized) return;
+  uint4 parser_cachesize = 2;
+  uint4 parser_windowsize = 32;
+  if ((maxdelayslotbytes > 1)||(unique_allocatemask != 0)) {
+    parser_cachesize = 8;
+    parser_windowsize = 256;
+  }
+  discache = new DisassemblyCache(cache,getConstantSpace(),parser_cachesize,parser_windowsize);
+}
+
 /// \brief Obtain a parse tree for the instruction at the given address
 ///
 /// The tree may be cached from a previous access.  If the address
@@ -60,6 +60,7 @@ class PcodeCacher {
   vector<PcodeData> issued;		///< P-code ops issued for the current instruction
   list<RelativeRecord> label_refs;	///< References to labels
   vector<uintb> labels;			///< Locations of labels
+  vector<pair<int4,Address> > origins;	///< Index of the first op built for each instruction switched to
   VarnodeData *expandPool(uint4 size);	///< Expand the memory pool
 public:
   PcodeCacher(void);		///< Constructor
@@ -91,6 +92,8 @@ public:
   }
   void addLabelRef(VarnodeData *ptr);	///< Denote a Varnode holding a \e relative \e branch offset
   void addLabel(uint4 id);		///< Attach a label to the \e next p-code instruction
+  void addOrigin(const Address &addr) { origins.push_back(pair<int4,Address>(issued.size(),addr)); }	///< Ops from here on are built for the instruction at \e addr
+  const vector<pair<int4,Address> > &getOrigins(void) const { return origins; }	///< Instructions switched to, such as delay slots, and their first op
   void clear(void);			///< Reset the cache so that all objects are unallocated
   void resolveRelatives(void);		///< Rewrite branch target Varnodes as \e relative offsets
   void emit(const Address &addr,PcodeEmit *emt) const;	///< Pass the cached p-code data to the emitter
@@ -167,6 +170,7 @@ protected:
   ParserContext *obtainContext(const Address &addr,int4 state) const;
   void resolve(ParserContext &pos) const;	///< Generate a parse tree suitable for disassembly
   void resolveHandles(ParserContext &pos) const;	///< Prepare the parse tree for p-code generation
+  const PcodeCacher &getPcodeCacher(void) const { return pcode_cache; }	///< The p-code data of the last instruction translated
 public:
   Sleigh(LoadImage *ld,ContextDatabase *c_db);		///< Constructor
   virtual ~Sleigh(void);				///< Destructor
@@ -175,6 +179,8 @@ public:
   virtual void registerContext(const string &name,int4 sbit,int4 ebit);
   virtual void setContextDefault(const string &nm,uintm val);
   virtual void allowContextSet(bool val) const;
+  void resetCaches(void);			///< Drop cached context and parsed instructions
+  const ContextCache *getContextCache(void) const { return cache; }	///< Get the cache of recently used context values
   virtual int4 instructionLength(const Address &baseaddr) const;
   virtual int4 oneInstruction(PcodeEmit &emit,const Address &baseaddr) const;
   virtual int4 printAssembly(AssemblyEmit &emit,const Address &baseaddr) const;
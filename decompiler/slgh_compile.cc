@@ -3664,6 +3664,8 @@ void SleighCompile::setAllOptions(const map<string,string> &defines, bool unnece
   setInsensitiveDuplicateError(!caseSensitiveRegisterNames);
 }
 
+#ifndef SLEIGH_COMPILE_NO_MAIN
+
 static void segvHandler(int sig) {
   exit(1);			// Just die - prevents OS from popping-up a dialog
 }
@@ -3832,3 +3834,5 @@ int main(int argc,char **argv)
   }
   return retval;
 }
+
+#endif
@@ -96,6 +96,12 @@ class TokenField : public PatternValue {
 public:
   TokenField(void) {}		// For use with restoreXml
   TokenField(Token *tk,bool s,int4 bstart,int4 bend);
+  int4 getByteStart(void) const { return bytestart; }	///< First byte holding the field, relative to the token
+  int4 getByteEnd(void) const { return byteend; }	///< Last byte holding the field, relative to the token
+  int4 getShift(void) const { return shift; }	///< Position of the field in the value read from its bytes
+  int4 numBits(void) const { return bitend-bitstart+1; }	///< Width of the field in bits
+  bool isBigEndian(void) const { return bigendian; }	///< Whether the field's bytes are read most significant first
+  bool isSigned(void) const { return signbit; }	///< Whether the field is sign extended
   virtual intb getValue(ParserWalker &walker) const;
   virtual TokenPattern genMinPattern(const vector<TokenPattern> &ops) const { return TokenPattern(tok); }
   virtual TokenPattern genPattern(intb val) const;
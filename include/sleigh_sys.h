@@ -0,0 +1,51 @@
+/* C interface of sleigh-sys, built with the `capi` feature. See src/capi.rs. */
+#ifndef SLEIGH_SYS_H
+#define SLEIGH_SYS_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct SleighDecompiler SleighDecompiler;
+typedef struct SleighPcode SleighPcode;
+
+/* The version of this interface, raised when a function changes incompatibly */
+uint32_t sleigh_abi_version(void);
+
+/* The message of the last call that failed on this thread, valid until another
+   one fails */
+const char *sleigh_last_error(void);
+
+/* Create a decompiler for the language with these spec files. cspec may be
+   NULL. Returns NULL on failure. */
+SleighDecompiler *sleigh_decompiler_new(const char *sla, const char *pspec,
+                                        const char *cspec);
+
+/* Lift the instructions in the len bytes at bytes, loaded at address, one
+   after the other from its start. Lifting stops at the end of the buffer, or
+   at the first instruction that doesn't decode or runs past the end. Returns
+   NULL on failure. */
+SleighPcode *sleigh_lift_buffer(SleighDecompiler *decompiler, uint64_t address,
+                                const uint8_t *bytes, size_t len);
+
+/* The pcode of the lifted instructions in Ghidra's packed format, one after
+   the other, and its length in len. Valid until pcode is freed. */
+const uint8_t *sleigh_pcode_packed(const SleighPcode *pcode, size_t *len);
+
+/* How many instructions were lifted */
+size_t sleigh_pcode_instructions(const SleighPcode *pcode);
+
+/* How many bytes of the buffer the lifted instructions cover */
+uint64_t sleigh_pcode_lifted_bytes(const SleighPcode *pcode);
+
+void sleigh_pcode_free(SleighPcode *pcode);
+void sleigh_decompiler_free(SleighDecompiler *decompiler);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
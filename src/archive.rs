@@ -0,0 +1,113 @@
+//! Lifted pcode archived on disk.
+//!
+//! Lifting a whole binary takes far longer than reading its pcode back. An archive written by
+//! [`write_archive`] is laid out the way rkyv accesses it in place, so [`PcodeArchive::open`]
+//! only memory-maps and validates the file: instructions are read straight from the mapping
+//! and only those actually used are ever paged in or converted back with
+//! [`PcodeArchive::lift`].
+
+use std::fs::File;
+use std::io;
+use std::path::Path;
+
+use memmap2::Mmap;
+use rkyv::rancor;
+use rkyv::vec::ArchivedVec;
+
+use crate::pcode::{ArchivedLiftedInstruction, LiftedInstruction};
+
+type Archived = ArchivedVec<ArchivedLiftedInstruction>;
+
+fn invalid(e: rancor::Error) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+/// Write `instructions` to a new archive at `path`. They are stored sorted by address, and
+/// only the first of several instructions at one address is kept.
+pub fn write_archive(path: impl AsRef<Path>, instructions: &[LiftedInstruction]) -> io::Result<()> {
+    let mut sorted = instructions.to_vec();
+    sorted.sort_by_key(|inst| inst.address);
+    sorted.dedup_by_key(|inst| inst.address);
+    let bytes = rkyv::to_bytes::<rancor::Error>(&sorted).map_err(invalid)?;
+    std::fs::write(path, bytes)
+}
+
+/// A memory-mapped archive written by [`write_archive`]
+pub struct PcodeArchive {
+    map: Mmap,
+}
+
+impl PcodeArchive {
+    /// Map and validate the archive at `path`. The file must not be changed while the
+    /// archive is open.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let file = File::open(path)?;
+        // Other processes writing to the file is the caller's to rule out
+        let map = unsafe { Mmap::map(&file)? };
+        rkyv::access::<Archived, rancor::Error>(&map).map_err(invalid)?;
+        Ok(Self { map })
+    }
+
+    /// All instructions, sorted by address
+    pub fn instructions(&self) -> &Archived {
+        // Validated in open, and the mapping is read-only
+        unsafe { rkyv::access_unchecked::<Archived>(&self.map) }
+    }
+
+    pub fn len(&self) -> usize {
+        self.instructions().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.instructions().is_empty()
+    }
+
+    /// The instruction at `address`, in place
+    pub fn get(&self, address: u64) -> Option<&ArchivedLiftedInstruction> {
+        let instructions = self.instructions();
+        instructions
+            .binary_search_by_key(&address, |inst| inst.address.to_native())
+            .ok()
+            .map(|i| &instructions[i])
+    }
+
+    /// An owned copy of the instruction at `address`
+    pub fn lift(&self, address: u64) -> Option<LiftedInstruction> {
+        let archived = self.get(address)?;
+        Some(rkyv::deserialize::<LiftedInstruction, rancor::Error>(archived).unwrap())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::toy;
+    use crate::Opcode;
+
+    #[test]
+    fn test_archive() {
+        // movi r0, 5 ; add r0, r1 ; ret
+        let code = [0x01, 0x00, 0x05, 0x02, 0x01, 0x03];
+        let mut program = toy::program(0x1000, &code);
+        let sweep = program.decompiler().linear_sweep(0x1000..0x1006).unwrap();
+        let path = std::env::temp_dir().join(format!("sleigh-archive-{}", std::process::id()));
+        let mut instructions = sweep.instructions.clone();
+        instructions.reverse();
+        write_archive(&path, &instructions).unwrap();
+
+        let archive = PcodeArchive::open(&path).unwrap();
+        assert_eq!(archive.len(), 3);
+        let add = archive.get(0x1003).unwrap();
+        assert_eq!(add.length.to_native(), 2);
+        assert!(add.ops[0].opcode == Opcode::IntAdd);
+        assert_eq!(
+            archive.lift(0x1003).as_ref(),
+            Some(program.lift(0x1003).unwrap())
+        );
+        assert!(archive.get(0x1004).is_none());
+
+        std::fs::write(&path, b"not an archive").unwrap();
+        assert!(PcodeArchive::open(&path).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
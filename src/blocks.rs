@@ -0,0 +1,198 @@
+//! Basic-block discovery.
+//!
+//! Starting from an entry point, instructions are lifted along every static branch target and
+//! fall-through, then cut into blocks at each target and after each instruction that changes
+//! flow. Blocks follow instructions by their start address, so code that overlaps other code,
+//! like a jump into the middle of an instruction, gets blocks of its own. Delay slots are part
+//! of the pcode and length of the branch owning them and never start a block.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::cancel::{Cancel, Cancelled};
+use crate::ffi;
+use crate::pcode::{FlowInfo, LiftedInstruction, PcodeOp};
+
+/// How far [`ffi::Decompiler::basic_blocks`] explores
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlockLimits {
+    /// Stop lifting after this many instructions
+    pub max_instructions: usize,
+    /// Also discover the blocks of called functions
+    pub follow_calls: bool,
+}
+
+impl Default for BlockLimits {
+    fn default() -> Self {
+        Self {
+            max_instructions: 10_000,
+            follow_calls: false,
+        }
+    }
+}
+
+/// A run of instructions entered only at its start and left only at its end
+#[derive(Debug, Clone)]
+pub struct Block {
+    pub start: u64,
+    /// Address just past the last instruction, or `u64::MAX` for a block ending on the last
+    /// address
+    pub end: u64,
+    pub instructions: Vec<LiftedInstruction>,
+    /// Where control goes after the block: static branch targets, then the fall-through
+    pub successors: Vec<u64>,
+    /// Whether the block can also leave through a BRANCHIND or RETURN, whose targets aren't
+    /// known statically
+    pub indirect: bool,
+}
+
+impl Block {
+    /// The pcode of all instructions in the block, in order
+    pub fn pcode(&self) -> impl Iterator<Item = &PcodeOp> {
+        self.instructions.iter().flat_map(|inst| inst.ops.iter())
+    }
+}
+
+impl FlowInfo {
+    /// Whether the instruction has to be the last of its block
+    fn ends_block(&self) -> bool {
+        !self.branches.is_empty() || self.indirect_branch || self.returns || self.terminates()
+    }
+}
+
+impl ffi::Decompiler {
+    /// The basic blocks reachable from `entry`, sorted by address. Exploration stops at
+    /// instructions that can't be decoded, which end the block before them.
+    pub fn basic_blocks(&self, entry: u64, limits: BlockLimits) -> Vec<Block> {
+        self.basic_blocks_cancellable(entry, limits, &Cancel::new())
+            .expect("discovery cancelled without a token")
+    }
+
+    /// [`basic_blocks`](Self::basic_blocks), checking `cancel` before each instruction
+    pub fn basic_blocks_cancellable(
+        &self,
+        entry: u64,
+        limits: BlockLimits,
+        cancel: &Cancel,
+    ) -> Result<Vec<Block>, Cancelled> {
+        let mut instructions = BTreeMap::new();
+        let mut leaders = BTreeSet::from([entry]);
+        let mut pending = vec![entry];
+        while let Some(addr) = pending.pop() {
+            if instructions.len() >= limits.max_instructions {
+                break;
+            }
+            if instructions.contains_key(&addr) {
+                continue;
+            }
+            cancel.check()?;
+            let inst = match self.lift(addr) {
+                Some(inst) => inst,
+                None => continue,
+            };
+            let flow = inst.flow(self);
+            for &target in &flow.branches {
+                leaders.insert(target);
+                pending.push(target);
+            }
+            if limits.follow_calls {
+                for &target in &flow.calls {
+                    leaders.insert(target);
+                    pending.push(target);
+                }
+            }
+            if let Some(next) = flow.fall_through {
+                if flow.ends_block() {
+                    leaders.insert(next);
+                }
+                pending.push(next);
+            }
+            instructions.insert(addr, (inst, flow));
+        }
+
+        let mut blocks = Vec::new();
+        for &start in &leaders {
+            let mut addr = start;
+            let mut block = Block {
+                start,
+                end: start,
+                instructions: Vec::new(),
+                successors: Vec::new(),
+                indirect: false,
+            };
+            while let Some((inst, flow)) = instructions.get(&addr) {
+                block.instructions.push(inst.clone());
+                // Nothing follows an instruction ending on the last address in this block
+                let end = addr.checked_add(inst.length as u64);
+                block.end = end.unwrap_or(u64::MAX);
+                let next = flow.fall_through;
+                if end.is_none()
+                    || flow.ends_block()
+                    || next.is_none_or(|next| leaders.contains(&next))
+                {
+                    block.successors.extend(&flow.branches);
+                    block.successors.extend(next);
+                    block.indirect = flow.indirect_branch || flow.returns;
+                    break;
+                }
+                // Only reached if the fall-through is set
+                addr = block.end;
+            }
+            if !block.instructions.is_empty() {
+                blocks.push(block);
+            }
+        }
+        Ok(blocks)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::toy;
+
+    // movi r1, 0 ; jz r0, 0x0b ; add r1, r0 ; jmp 0x03 ; nop ; ret
+    const CODE: [u8; 12] = [
+        0x01, 0x10, 0x00, 0x0e, 0x00, 0x0b, 0x02, 0x10, 0x0c, 0x03, 0x00, 0x03,
+    ];
+
+    #[test]
+    fn test_basic_blocks() {
+        let program = toy::program(0, &CODE);
+        let blocks = program.decompiler().basic_blocks(0, BlockLimits::default());
+        let summary: Vec<_> = blocks
+            .iter()
+            .map(|b| (b.start, b.end, b.successors.clone(), b.indirect))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                (0x00, 0x03, vec![0x03], false),
+                (0x03, 0x06, vec![0x0b, 0x06], false),
+                (0x06, 0x0a, vec![0x03], false),
+                (0x0b, 0x0c, vec![], true),
+            ]
+        );
+        assert_eq!(blocks[2].instructions.len(), 2);
+        assert_eq!(blocks[2].pcode().count(), 2);
+
+        let limits = BlockLimits {
+            max_instructions: 2,
+            ..BlockLimits::default()
+        };
+        // The jump back to 0x03 isn't lifted, so nothing splits the first two instructions
+        let blocks = program.decompiler().basic_blocks(0, limits);
+        assert_eq!(blocks.len(), 1);
+        assert_eq!(
+            (blocks[0].end, blocks[0].successors.clone()),
+            (0x06, vec![0x0b, 0x06])
+        );
+
+        // ret, on the last address
+        let program = toy::program(u64::MAX, &[0x03]);
+        let blocks = program
+            .decompiler()
+            .basic_blocks(u64::MAX, BlockLimits::default());
+        assert_eq!(blocks.len(), 1);
+        assert_eq!((blocks[0].start, blocks[0].end), (u64::MAX, u64::MAX));
+    }
+}
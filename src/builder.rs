@@ -0,0 +1,193 @@
+//! One-stop setup of a decompiler.
+//!
+//! Getting a working decompiler takes a compiled `.sla`, the `.pspec` whose context defaults
+//! it applies, a load image that outlives it and often a few context variables set before the
+//! first decode. [`DecompilerBuilder`] gathers all of that and hands back a [`Program`], which
+//! owns the decompiler together with its image, so none of the raw pointers involved leak
+//! out.
+
+use std::path::PathBuf;
+
+use crate::image::SegmentedImage;
+use crate::languages::{LanguageError, LanguageFiles};
+use crate::project::Program;
+use crate::{ffi, LoadImage, SleighError};
+
+/// Builds a [`Program`] from spec files, a load image and context settings
+pub struct DecompilerBuilder<L = SegmentedImage> {
+    sla: Option<PathBuf>,
+    pspec: Option<PathBuf>,
+    cspec: Option<PathBuf>,
+    image: L,
+    context: Vec<(String, u32)>,
+    op_limit: Option<u32>,
+    unimplemented_policy: Option<ffi::UnimplementedPolicy>,
+}
+
+impl DecompilerBuilder {
+    /// A builder with an empty image, in which every byte reads as zero
+    pub fn new() -> Self {
+        Self {
+            sla: None,
+            pspec: None,
+            cspec: None,
+            image: SegmentedImage::new(),
+            context: Vec::new(),
+            op_limit: None,
+            unimplemented_policy: None,
+        }
+    }
+}
+
+impl Default for DecompilerBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<L: LoadImage + 'static> DecompilerBuilder<L> {
+    /// The compiled `.sla` file. Without [`pspec_file`](Self::pspec_file), the `.pspec` next
+    /// to it with the same name is used.
+    pub fn spec_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.sla = Some(path.into());
+        self
+    }
+
+    pub fn pspec_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.pspec = Some(path.into());
+        self
+    }
+
+    /// The `.cspec` file, which gives the program its `stack` space and calling conventions
+    pub fn cspec_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.cspec = Some(path.into());
+        self
+    }
+
+    /// All spec files of a language, e.g. from [`LanguageDefinitions::resolve`]
+    ///
+    /// [`LanguageDefinitions::resolve`]: crate::languages::LanguageDefinitions::resolve
+    pub fn language(mut self, files: LanguageFiles) -> Self {
+        self.sla = Some(files.sla);
+        self.pspec = Some(files.pspec);
+        self.cspec = files.cspec;
+        self
+    }
+
+    pub fn load_image<M: LoadImage + 'static>(self, image: M) -> DecompilerBuilder<M> {
+        DecompilerBuilder {
+            sla: self.sla,
+            pspec: self.pspec,
+            cspec: self.cspec,
+            image,
+            context: self.context,
+            op_limit: self.op_limit,
+            unimplemented_policy: self.unimplemented_policy,
+        }
+    }
+
+    /// Set the context variable `name` to `value` at every address, overriding the `.pspec`
+    /// default. Settings are applied in order.
+    pub fn context(mut self, name: &str, value: u32) -> Self {
+        self.context.push((name.to_string(), value));
+        self
+    }
+
+    /// See [`Program::set_op_limit`]
+    pub fn op_limit(mut self, limit: u32) -> Self {
+        self.op_limit = Some(limit);
+        self
+    }
+
+    /// See [`Program::set_unimplemented_policy`]
+    pub fn unimplemented_policy(mut self, policy: ffi::UnimplementedPolicy) -> Self {
+        self.unimplemented_policy = Some(policy);
+        self
+    }
+
+    pub fn build(self) -> Result<Program, SleighError> {
+        let sla = self.sla.ok_or(LanguageError::MissingSpec("sla"))?;
+        let pspec = self.pspec.unwrap_or_else(|| sla.with_extension("pspec"));
+        let files = LanguageFiles {
+            sla,
+            pspec,
+            cspec: self.cspec,
+        };
+        let mut program = Program::new(files, self.image)?;
+        for (name, value) in &self.context {
+            program.set_context_everywhere(name, *value)?;
+        }
+        if self.op_limit.is_some() {
+            program.set_op_limit(self.op_limit);
+        }
+        if let Some(policy) = self.unimplemented_policy {
+            program.set_unimplemented_policy(policy);
+        }
+        Ok(program)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::ErrorKind;
+    use crate::toy;
+
+    #[test]
+    fn test_builder() {
+        let files = toy::files();
+        let mut image = SegmentedImage::new();
+        image.add_segment("code", 0x1000, vec![0x00, 0x0b, 0x10], Default::default());
+        let mut program = DecompilerBuilder::new()
+            .spec_file(&files.sla)
+            .load_image(image)
+            .context("mode", 1)
+            .op_limit(2)
+            .build()
+            .unwrap();
+        assert_eq!(
+            program.decompiler().disassemble_one(0x1000).unwrap().0,
+            "alt"
+        );
+        assert!(program.compiler_spec().is_none());
+        assert_eq!(program.decompiler().op_limit(), Some(2));
+        assert!(program.lift(0x1001).is_none());
+
+        let program = DecompilerBuilder::new()
+            .language(files.clone())
+            .build()
+            .unwrap();
+        assert!(program.compiler_spec().is_some());
+        assert_eq!(program.decompiler().disassemble_one(0).unwrap().0, "nop");
+
+        let err = DecompilerBuilder::new().build().err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::Spec);
+        let err = DecompilerBuilder::new()
+            .spec_file(&files.sla)
+            .context("bogus", 1)
+            .build()
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), ErrorKind::Engine);
+
+        // A broken .sla is an error, not an abort
+        let dir = std::env::temp_dir().join(format!("sleigh-builder-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let sla = dir.join("broken.sla");
+        for text in ["garbage <<", "<sleigh version=\"1\"/>", "<spaces/>"] {
+            std::fs::write(&sla, text).unwrap();
+            let err = DecompilerBuilder::new()
+                .language(LanguageFiles {
+                    sla: sla.clone(),
+                    pspec: files.pspec.clone(),
+                    cspec: None,
+                })
+                .build()
+                .err()
+                .unwrap();
+            assert_eq!(err.kind(), ErrorKind::Spec);
+            assert!(err.to_string().contains("broken.sla"), "{}", err);
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
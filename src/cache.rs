@@ -0,0 +1,179 @@
+//! A cache of lifted pcode.
+//!
+//! Emulators and fuzzers lift the same hot instructions over and over. [`PcodeCache`] keeps
+//! the pcode of every instruction it lifted together with the context it was decoded in and
+//! the bytes it was decoded from, and hands it out again as long as both are unchanged. The
+//! bytes are read back from the load image on every lookup, so an image whose contents change
+//! is picked up without any help; [`PcodeCache::invalidate`] is for code written behind the
+//! cache's back and for forcing a fresh decode.
+
+use std::collections::HashMap;
+use std::ops::{Bound, RangeBounds};
+use std::pin::Pin;
+
+use crate::pcode::LiftedInstruction;
+use crate::{ffi, SleighError};
+
+#[derive(Debug)]
+struct Entry {
+    bytes: Vec<u8>,
+    inst: LiftedInstruction,
+}
+
+/// How often the cache could answer a lookup
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+}
+
+/// Lifted instructions by address and context
+#[derive(Debug, Default)]
+pub struct PcodeCache {
+    entries: HashMap<(u64, Vec<u32>), Entry>,
+    stats: CacheStats,
+    /// The engine's own decode cache only goes by address and has to be dropped once code
+    /// changed
+    stale_engine: bool,
+    /// Holds the current bytes of an instruction while they are compared
+    scratch: Vec<u8>,
+}
+
+impl PcodeCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The instruction at `addr`, lifted with `decompiler` unless it was already with the
+    /// same bytes and context
+    pub fn lift(
+        &mut self,
+        mut decompiler: Pin<&mut ffi::Decompiler>,
+        addr: u64,
+    ) -> Result<&LiftedInstruction, SleighError> {
+        let key = (addr, decompiler.context_words(addr));
+        let scratch = &mut self.scratch;
+        let fresh = self.entries.get(&key).is_some_and(|entry| {
+            scratch.resize(entry.bytes.len(), 0);
+            decompiler.fillBytes(addr, scratch).is_ok() && *scratch == entry.bytes
+        });
+        if fresh {
+            self.stats.hits += 1;
+        } else {
+            self.stats.misses += 1;
+            if self.stale_engine || self.entries.contains_key(&key) {
+                decompiler.as_mut().resetCaches();
+                self.stale_engine = false;
+            }
+            let inst = decompiler.try_lift(addr)?;
+            let bytes = decompiler.read_bytes(addr, inst.length)?;
+            self.entries.insert(key.clone(), Entry { bytes, inst });
+        }
+        Ok(&self.entries[&key].inst)
+    }
+
+    /// Drop every instruction with a byte in `range`. An inclusive range like `addr..=addr`
+    /// can reach the last address of the space.
+    pub fn invalidate(&mut self, range: impl RangeBounds<u64>) {
+        let first = match range.start_bound() {
+            Bound::Included(&start) => Some(start),
+            Bound::Excluded(&start) => start.checked_add(1),
+            Bound::Unbounded => Some(0),
+        };
+        let last = match range.end_bound() {
+            Bound::Included(&end) => Some(end),
+            Bound::Excluded(&end) => end.checked_sub(1),
+            Bound::Unbounded => Some(u64::MAX),
+        };
+        let (first, last) = match (first, last) {
+            (Some(first), Some(last)) if first <= last => (first, last),
+            _ => return,
+        };
+        let before = self.entries.len();
+        self.entries.retain(|&(addr, _), entry| {
+            let end = addr.saturating_add((entry.bytes.len() as u64).max(1) - 1);
+            end < first || addr > last
+        });
+        self.stale_engine |= self.entries.len() != before;
+    }
+
+    pub fn clear(&mut self) {
+        self.stale_engine |= !self.entries.is_empty();
+        self.entries.clear();
+    }
+
+    /// Number of instructions cached
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        self.stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::project::Program;
+    use crate::{toy, LoadImage};
+
+    /// An image whose bytes can be changed while a program uses it
+    struct SharedImage(Rc<RefCell<Vec<u8>>>);
+
+    impl LoadImage for SharedImage {
+        fn load_fill(&mut self, ptr: &mut [u8], addr: &ffi::Address) {
+            let code = self.0.borrow();
+            for (i, b) in ptr.iter_mut().enumerate() {
+                let off = (addr.getOffset() as usize).checked_add(i);
+                *b = off.and_then(|off| code.get(off)).copied().unwrap_or(0);
+            }
+        }
+    }
+
+    #[test]
+    fn test_pcode_cache() {
+        // movi r0, 1 ; nop
+        let code = Rc::new(RefCell::new(vec![0x01, 0x00, 0x01, 0x00]));
+        let mut program = Program::new(toy::files().clone(), SharedImage(code.clone())).unwrap();
+        let lift = |program: &mut Program, addr| {
+            let inst = program.lift(addr).unwrap();
+            (inst.length, inst.ops.len())
+        };
+        assert_eq!(lift(&mut program, 0), (3, 1));
+        assert_eq!(lift(&mut program, 0), (3, 1));
+        assert_eq!(lift(&mut program, 3), (1, 0));
+        let stats = program.pcode_cache().stats();
+        assert_eq!(stats, CacheStats { hits: 1, misses: 2 });
+
+        // Overwritten code is noticed without invalidating: now add r0, r0
+        code.borrow_mut()[..2].copy_from_slice(&[0x02, 0x00]);
+        assert_eq!(lift(&mut program, 0), (2, 1));
+        assert_eq!(program.pcode_cache().stats().misses, 3);
+
+        program.invalidate(2..3);
+        assert_eq!(program.pcode_cache().len(), 2);
+        program.invalidate(1..2);
+        assert_eq!(program.pcode_cache().len(), 1);
+        program.clear_lifted();
+        assert!(program.pcode_cache().is_empty());
+
+        // Instructions ending at the top of the address space
+        let top = u64::MAX - 1;
+        assert_eq!(lift(&mut program, top), (1, 0));
+        assert_eq!(lift(&mut program, top + 1), (1, 0));
+        program.invalidate(0..top);
+        assert_eq!(program.pcode_cache().len(), 2);
+        program.invalidate(top..top + 1);
+        assert_eq!(program.pcode_cache().len(), 1);
+        program.invalidate(u64::MAX..=u64::MAX);
+        assert!(program.pcode_cache().is_empty());
+    }
+}
@@ -0,0 +1,318 @@
+//! A C ABI over [`Program`], for hosts that can't use the cxx bridge.
+//!
+//! `include/sleigh_sys.h` declares these functions. A host creates a decompiler from the spec
+//! files of a language, lifts buffers of code with it and reads back the pcode in Ghidra's
+//! packed format (see [`wire`](crate::wire)), then frees the results and the decompiler.
+//! Functions that fail return null and leave a message for [`sleigh_last_error`].
+//!
+//! New functions may be added, but the signatures of existing ones stay as they are for a
+//! given [`sleigh_abi_version`].
+
+use std::cell::RefCell;
+use std::ffi::{c_char, CStr, CString};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::{ptr, slice};
+
+use crate::languages::LanguageFiles;
+use crate::project::Program;
+use crate::{ffi, LoadImage};
+
+const ABI_VERSION: u32 = 1;
+
+thread_local! {
+    static LAST_ERROR: RefCell<CString> = RefCell::new(CString::default());
+}
+
+fn set_error(message: impl ToString) {
+    let message = message.to_string().replace('\0', " ");
+    LAST_ERROR.with(|error| *error.borrow_mut() = CString::new(message).unwrap());
+}
+
+/// Run `f`, recording its error, or the panic it raised, for `sleigh_last_error`
+fn guard<T>(f: impl FnOnce() -> Result<T, String>) -> Option<T> {
+    match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(value)) => Some(value),
+        Ok(Err(message)) => {
+            set_error(message);
+            None
+        }
+        Err(_) => {
+            set_error("panic in sleigh-sys");
+            None
+        }
+    }
+}
+
+/// The code being lifted, replaced by each call to `sleigh_lift_buffer`. Bytes outside of it
+/// read as zero.
+#[derive(Default)]
+struct Buffer {
+    address: u64,
+    bytes: Vec<u8>,
+}
+
+struct BufferImage(Rc<RefCell<Buffer>>);
+
+impl LoadImage for BufferImage {
+    fn load_fill(&mut self, ptr: &mut [u8], addr: &ffi::Address) {
+        let buffer = self.0.borrow();
+        let start = addr.getOffset();
+        for (i, b) in ptr.iter_mut().enumerate() {
+            let offset = start.wrapping_add(i as u64).wrapping_sub(buffer.address);
+            *b = usize::try_from(offset)
+                .ok()
+                .and_then(|offset| buffer.bytes.get(offset))
+                .copied()
+                .unwrap_or(0);
+        }
+    }
+}
+
+pub struct SleighDecompiler {
+    program: Program,
+    buffer: Rc<RefCell<Buffer>>,
+}
+
+/// The pcode of the instructions lifted from a buffer
+pub struct SleighPcode {
+    packed: Vec<u8>,
+    instructions: usize,
+    lifted: u64,
+}
+
+fn path_arg(path: *const c_char, name: &str) -> Result<PathBuf, String> {
+    if path.is_null() {
+        return Err(format!("{} is null", name));
+    }
+    let path = unsafe { CStr::from_ptr(path) }
+        .to_str()
+        .map_err(|_| format!("{} isn't UTF-8", name))?;
+    Ok(PathBuf::from(path))
+}
+
+/// The version of this ABI, raised when a function changes incompatibly
+#[no_mangle]
+pub extern "C" fn sleigh_abi_version() -> u32 {
+    ABI_VERSION
+}
+
+/// The message of the last call that failed on this thread, valid until another one fails
+#[no_mangle]
+pub extern "C" fn sleigh_last_error() -> *const c_char {
+    LAST_ERROR.with(|error| error.borrow().as_ptr())
+}
+
+/// Create a decompiler for the language with these spec files. `cspec` may be null.
+///
+/// # Safety
+///
+/// The paths must be null or point to NUL-terminated strings.
+#[no_mangle]
+pub unsafe extern "C" fn sleigh_decompiler_new(
+    sla: *const c_char,
+    pspec: *const c_char,
+    cspec: *const c_char,
+) -> *mut SleighDecompiler {
+    guard(|| {
+        let language = LanguageFiles {
+            sla: path_arg(sla, "sla")?,
+            pspec: path_arg(pspec, "pspec")?,
+            cspec: if cspec.is_null() {
+                None
+            } else {
+                Some(path_arg(cspec, "cspec")?)
+            },
+        };
+        let buffer = Rc::new(RefCell::new(Buffer::default()));
+        let program =
+            Program::new(language, BufferImage(buffer.clone())).map_err(|e| e.to_string())?;
+        Ok(Box::into_raw(Box::new(SleighDecompiler {
+            program,
+            buffer,
+        })))
+    })
+    .unwrap_or(ptr::null_mut())
+}
+
+/// Lift the instructions in the `len` bytes at `bytes`, loaded at `address`, one after the
+/// other from its start. Lifting stops at the end of the buffer, or at the first instruction
+/// that doesn't decode or runs past the end.
+///
+/// # Safety
+///
+/// `decompiler` must come from `sleigh_decompiler_new`, and `bytes` point to `len` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn sleigh_lift_buffer(
+    decompiler: *mut SleighDecompiler,
+    address: u64,
+    bytes: *const u8,
+    len: usize,
+) -> *mut SleighPcode {
+    guard(|| {
+        let decompiler = decompiler.as_mut().ok_or("decompiler is null")?;
+        if bytes.is_null() && len != 0 {
+            return Err("bytes is null".to_string());
+        }
+        let bytes = if len == 0 {
+            &[]
+        } else {
+            slice::from_raw_parts(bytes, len)
+        };
+        *decompiler.buffer.borrow_mut() = Buffer {
+            address,
+            bytes: bytes.to_vec(),
+        };
+
+        let end = address.saturating_add(len as u64);
+        let mut pcode = SleighPcode {
+            packed: Vec::new(),
+            instructions: 0,
+            lifted: 0,
+        };
+        let mut addr = address;
+        while addr < end {
+            let inst = match decompiler.program.lift(addr).cloned() {
+                Some(inst) if inst.length > 0 && addr + inst.length as u64 <= end => inst,
+                _ => break,
+            };
+            let packed = inst
+                .to_packed_pcode(decompiler.program.decompiler())
+                .map_err(|e| e.to_string())?;
+            pcode.packed.extend_from_slice(&packed);
+            pcode.instructions += 1;
+            addr += inst.length as u64;
+        }
+        pcode.lifted = addr - address;
+        Ok(Box::into_raw(Box::new(pcode)))
+    })
+    .unwrap_or(ptr::null_mut())
+}
+
+/// The packed pcode of the lifted instructions, one after the other, and its length in `len`.
+/// Valid until `pcode` is freed.
+///
+/// # Safety
+///
+/// `pcode` must come from `sleigh_lift_buffer`, and `len` point to a `size_t`.
+#[no_mangle]
+pub unsafe extern "C" fn sleigh_pcode_packed(
+    pcode: *const SleighPcode,
+    len: *mut usize,
+) -> *const u8 {
+    let pcode = &*pcode;
+    *len = pcode.packed.len();
+    pcode.packed.as_ptr()
+}
+
+/// How many instructions were lifted
+///
+/// # Safety
+///
+/// `pcode` must come from `sleigh_lift_buffer`.
+#[no_mangle]
+pub unsafe extern "C" fn sleigh_pcode_instructions(pcode: *const SleighPcode) -> usize {
+    (*pcode).instructions
+}
+
+/// How many bytes of the buffer the lifted instructions cover
+///
+/// # Safety
+///
+/// `pcode` must come from `sleigh_lift_buffer`.
+#[no_mangle]
+pub unsafe extern "C" fn sleigh_pcode_lifted_bytes(pcode: *const SleighPcode) -> u64 {
+    (*pcode).lifted
+}
+
+/// # Safety
+///
+/// `pcode` must be null or come from `sleigh_lift_buffer`, and not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn sleigh_pcode_free(pcode: *mut SleighPcode) {
+    if !pcode.is_null() {
+        drop(Box::from_raw(pcode));
+    }
+}
+
+/// # Safety
+///
+/// `decompiler` must be null or come from `sleigh_decompiler_new`, and not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn sleigh_decompiler_free(decompiler: *mut SleighDecompiler) {
+    if !decompiler.is_null() {
+        drop(Box::from_raw(decompiler));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::{CStr, CString};
+    use std::path::Path;
+    use std::ptr;
+
+    use super::*;
+    use crate::toy;
+
+    fn c_path(path: &Path) -> CString {
+        CString::new(path.to_str().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_lift_buffer() {
+        let files = toy::files();
+        let sla = c_path(&files.sla);
+        let pspec = c_path(&files.pspec);
+        unsafe {
+            let decompiler = sleigh_decompiler_new(sla.as_ptr(), pspec.as_ptr(), ptr::null());
+            assert!(!decompiler.is_null());
+
+            // movi r0, 1 ; add r0, r1 ; <data>
+            let code = [0x01, 0x00, 0x01, 0x02, 0x01, 0xff];
+            let pcode = sleigh_lift_buffer(decompiler, 0x1000, code.as_ptr(), code.len());
+            assert_eq!(sleigh_pcode_instructions(pcode), 2);
+            assert_eq!(sleigh_pcode_lifted_bytes(pcode), 5);
+            let mut len = 0;
+            let packed = sleigh_pcode_packed(pcode, &mut len);
+            let packed = slice::from_raw_parts(packed, len);
+
+            let program = toy::program(0x1000, &code);
+            let mut expected = Vec::new();
+            for addr in [0x1000, 0x1003] {
+                let inst = program.decompiler().try_lift(addr).unwrap();
+                expected.extend(inst.to_packed_pcode(program.decompiler()).unwrap());
+            }
+            assert_eq!(packed, expected);
+            sleigh_pcode_free(pcode);
+
+            // The same address with other bytes
+            let pcode = sleigh_lift_buffer(decompiler, 0x1000, [0x00].as_ptr(), 1);
+            assert_eq!(sleigh_pcode_instructions(pcode), 1);
+            assert_eq!(sleigh_pcode_lifted_bytes(pcode), 1);
+            sleigh_pcode_free(pcode);
+
+            assert!(sleigh_lift_buffer(decompiler, 0, ptr::null(), 1).is_null());
+            let error = CStr::from_ptr(sleigh_last_error()).to_str().unwrap();
+            assert_eq!(error, "bytes is null");
+            sleigh_decompiler_free(decompiler);
+
+            let missing = CString::new("/nonexistent.sla").unwrap();
+            let decompiler = sleigh_decompiler_new(missing.as_ptr(), pspec.as_ptr(), ptr::null());
+            assert!(decompiler.is_null());
+            let error = CStr::from_ptr(sleigh_last_error()).to_str().unwrap();
+            assert!(error.contains("/nonexistent.sla"), "{}", error);
+
+            let dir = std::env::temp_dir().join(format!("sleigh-capi-{}", std::process::id()));
+            std::fs::create_dir_all(&dir).unwrap();
+            let broken = dir.join("broken.sla");
+            std::fs::write(&broken, "not a spec").unwrap();
+            let broken = c_path(&broken);
+            let decompiler = sleigh_decompiler_new(broken.as_ptr(), pspec.as_ptr(), ptr::null());
+            assert!(decompiler.is_null());
+            let error = CStr::from_ptr(sleigh_last_error()).to_str().unwrap();
+            assert!(error.contains("broken.sla"), "{}", error);
+            std::fs::remove_dir_all(&dir).unwrap();
+        }
+    }
+}
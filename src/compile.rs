@@ -0,0 +1,128 @@
+//! Runtime compilation of SLEIGH specifications.
+//!
+//! Processor modules are written as `.slaspec` sources and compiled into the `.sla` files
+//! loaded by the decompiler. The vendored sources include the compiler itself, so a spec can
+//! be rebuilt without a Ghidra installation.
+//!
+//! Specs can also be compiled when this crate is built: point `SLEIGH_SLASPEC_DIR` at a
+//! directory of `.slaspec` sources (and optionally set `SLEIGH_SLASPEC_DEFINES` to
+//! whitespace separated `NAME=VALUE` pairs) and every spec found is compiled and embedded,
+//! retrievable with [`embedded_sla`].
+
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use crate::ffi;
+use crate::messages::{self, Level};
+
+#[derive(Debug)]
+pub enum CompileError {
+    /// The compiler only accepts UTF-8 paths
+    NonUtf8Path(PathBuf),
+    /// The spec did not compile; holds the compiler's diagnostics
+    Failed(String),
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CompileError::NonUtf8Path(path) => write!(f, "path is not UTF-8: {}", path.display()),
+            CompileError::Failed(log) => write!(f, "slaspec compilation failed:\n{}", log),
+        }
+    }
+}
+
+impl std::error::Error for CompileError {}
+
+fn path_str(path: &Path) -> Result<&str, CompileError> {
+    path.to_str()
+        .ok_or_else(|| CompileError::NonUtf8Path(path.to_path_buf()))
+}
+
+/// Compile the `.slaspec` at `input` into a `.sla` file at `output`. `defines` are
+/// preprocessor macros, as passed with `-DNAME=VALUE` to the `sleigh` tool.
+///
+/// Returns the compiler's warnings, which are also reported line by line to the
+/// [`messages`] handler, as are the diagnostics of a failed compilation.
+pub fn compile_slaspec<P: AsRef<Path>, Q: AsRef<Path>>(
+    input: P,
+    output: Q,
+    defines: &[(&str, &str)],
+) -> Result<String, CompileError> {
+    cxx::let_cxx_string!(input = path_str(input.as_ref())?);
+    cxx::let_cxx_string!(output = path_str(output.as_ref())?);
+    let names: Vec<&str> = defines.iter().map(|(name, _)| *name).collect();
+    let values: Vec<&str> = defines.iter().map(|(_, value)| *value).collect();
+    match ffi::compileSlaspec(&input, &output, &names, &values) {
+        Ok(warnings) => {
+            messages::report_compiler_output(Level::Warning, &warnings);
+            Ok(warnings)
+        }
+        Err(e) => {
+            messages::report_compiler_output(Level::Error, e.what());
+            Err(CompileError::Failed(e.what().to_string()))
+        }
+    }
+}
+
+// Generated by the build script: `EMBEDDED_SLA`, pairs of spec name and `.sla` contents.
+include!(concat!(env!("OUT_DIR"), "/embedded_sla.rs"));
+
+/// The `.sla` compiled at build time from `<name>.slaspec`
+pub fn embedded_sla(name: &str) -> Option<&'static str> {
+    EMBEDDED_SLA
+        .iter()
+        .find(|(spec, _)| *spec == name)
+        .map(|(_, sla)| *sla)
+}
+
+/// Names of the specs embedded at build time
+pub fn embedded_specs() -> impl Iterator<Item = &'static str> {
+    EMBEDDED_SLA.iter().map(|(name, _)| *name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SLASPEC: &str = r#"
+define endian=little;
+define alignment=1;
+define space ram type=ram_space size=4 default;
+define space register type=register_space size=4;
+define register offset=0 size=4 [ r0 r1 sp pc ];
+
+define token instr(8)
+  op = (0,7)
+;
+
+:nop is op=0 { }
+@ifdef WITH_INC
+:inc is op=1 { r0 = r0 + 1; }
+@endif
+"#;
+
+    #[test]
+    fn test_compile() {
+        let dir = std::env::temp_dir().join(format!("sleigh-sys-compile-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let input = dir.join("toy.slaspec");
+        let output = dir.join("toy.sla");
+        std::fs::write(&input, SLASPEC).unwrap();
+
+        compile_slaspec(&input, &output, &[("WITH_INC", "1")]).unwrap();
+        let sla = std::fs::read_to_string(&output).unwrap();
+        assert!(sla.contains("<sleigh"));
+        assert!(sla.contains("inc"));
+
+        let bad = dir.join("bad.slaspec");
+        std::fs::write(&bad, SLASPEC.replace("r0 + 1", "r9 + 1")).unwrap();
+        let err = compile_slaspec(&bad, dir.join("bad.sla"), &[("WITH_INC", "1")]).unwrap_err();
+        assert!(matches!(err, CompileError::Failed(log) if log.contains("r9")));
+
+        // The parser state must survive a failed compilation
+        compile_slaspec(&input, &output, &[]).unwrap();
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
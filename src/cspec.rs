@@ -0,0 +1,292 @@
+//! Compiler specification (`.cspec`) parsing.
+//!
+//! A compiler spec describes the ABI used by code compiled for a language: where the stack
+//! pointer lives, which way the stack grows, where the return address is kept and the
+//! calling conventions (prototype models) that may be in use.
+
+use std::path::Path;
+
+use crate::languages::LanguageError;
+use crate::segment::SegmentOp;
+use crate::xml::{parse_int, XmlElement};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StackGrowth {
+    Negative,
+    Positive,
+}
+
+/// A storage location as written in a spec
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Storage {
+    /// `<register name="..."/>`
+    Register(String),
+    /// `<varnode space=".." offset=".." size=".."/>` or `<addr space=".." offset=".."/>`
+    Memory {
+        space: String,
+        offset: i64,
+        size: Option<u32>,
+    },
+}
+
+impl Storage {
+    pub(crate) fn from_xml(el: &XmlElement) -> Option<Self> {
+        match el.name.as_str() {
+            "register" => Some(Storage::Register(el.attribute("name")?.to_string())),
+            "varnode" | "addr" => Some(Storage::Memory {
+                space: el.attribute("space")?.to_string(),
+                offset: el.attribute("offset").and_then(parse_int).unwrap_or(0),
+                size: el.attribute("size").and_then(parse_int).map(|s| s as u32),
+            }),
+            _ => None,
+        }
+    }
+
+    fn list_from_xml(el: &XmlElement) -> Vec<Self> {
+        el.children.iter().filter_map(Storage::from_xml).collect()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StackPointer {
+    pub register: String,
+    pub space: String,
+    pub growth: StackGrowth,
+}
+
+/// A `<pentry>` of a prototype's input or output list
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParamEntry {
+    pub min_size: u32,
+    pub max_size: u32,
+    pub align: u32,
+    /// `metatype` attribute, e.g. `float`
+    pub metatype: Option<String>,
+    pub storage: Storage,
+}
+
+impl ParamEntry {
+    fn from_xml(el: &XmlElement) -> Option<Self> {
+        let size = |name| el.attribute(name).and_then(parse_int).map(|v| v as u32);
+        Some(Self {
+            min_size: size("minsize").unwrap_or(1),
+            max_size: size("maxsize").unwrap_or(0),
+            align: size("align").unwrap_or(0),
+            metatype: el.attribute("metatype").map(str::to_string),
+            storage: el.children.iter().find_map(Storage::from_xml)?,
+        })
+    }
+
+    fn list_from_xml(el: Option<&XmlElement>) -> Vec<Self> {
+        el.map(|el| {
+            el.children_named("pentry")
+                .filter_map(ParamEntry::from_xml)
+                .collect()
+        })
+        .unwrap_or_default()
+    }
+}
+
+/// A calling convention (`<prototype>` model)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Prototype {
+    pub name: String,
+    /// Bytes popped by the callee, `None` if the spec says `unknown`
+    pub extrapop: Option<i64>,
+    pub stackshift: i64,
+    pub inputs: Vec<ParamEntry>,
+    pub outputs: Vec<ParamEntry>,
+    pub unaffected: Vec<Storage>,
+    pub killed_by_call: Vec<Storage>,
+}
+
+impl Prototype {
+    fn from_xml(el: &XmlElement) -> Result<Self, LanguageError> {
+        let name = el
+            .attribute("name")
+            .ok_or_else(|| LanguageError::MissingAttribute {
+                tag: el.name.clone(),
+                attribute: "name",
+            })?;
+        Ok(Self {
+            name: name.to_string(),
+            extrapop: el.attribute("extrapop").and_then(parse_int),
+            stackshift: el.attribute("stackshift").and_then(parse_int).unwrap_or(0),
+            inputs: ParamEntry::list_from_xml(el.child("input")),
+            outputs: ParamEntry::list_from_xml(el.child("output")),
+            unaffected: el
+                .child("unaffected")
+                .map(Storage::list_from_xml)
+                .unwrap_or_default(),
+            killed_by_call: el
+                .child("killedbycall")
+                .map(Storage::list_from_xml)
+                .unwrap_or_default(),
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompilerSpec {
+    pub stack_pointer: Option<StackPointer>,
+    pub return_address: Option<Storage>,
+    /// Name of the default prototype model
+    pub default_prototype: Option<String>,
+    pub prototypes: Vec<Prototype>,
+    /// How addresses are formed on segmented targets
+    pub segment_ops: Vec<SegmentOp>,
+}
+
+impl CompilerSpec {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, LanguageError> {
+        let path = path.as_ref();
+        let text =
+            std::fs::read_to_string(path).map_err(|e| LanguageError::Io(path.to_path_buf(), e))?;
+        Self::parse(&text)
+    }
+
+    pub fn parse(text: &str) -> Result<Self, LanguageError> {
+        let root = XmlElement::parse(text).map_err(|e| LanguageError::Xml(e.what().to_string()))?;
+        if root.name != "compiler_spec" {
+            return Err(LanguageError::Xml(format!(
+                "expected <compiler_spec>, found <{}>",
+                root.name
+            )));
+        }
+
+        let stack_pointer = root.child("stackpointer").and_then(|sp| {
+            Some(StackPointer {
+                register: sp.attribute("register")?.to_string(),
+                space: sp.attribute("space").unwrap_or("ram").to_string(),
+                growth: match sp.attribute("growth") {
+                    Some("positive") => StackGrowth::Positive,
+                    _ => StackGrowth::Negative,
+                },
+            })
+        });
+        let return_address = root
+            .child("returnaddress")
+            .and_then(|ra| ra.children.iter().find_map(Storage::from_xml));
+
+        let mut prototypes = Vec::new();
+        let mut default_prototype = None;
+        if let Some(proto) = root
+            .child("default_proto")
+            .and_then(|d| d.child("prototype"))
+        {
+            let proto = Prototype::from_xml(proto)?;
+            default_prototype = Some(proto.name.clone());
+            prototypes.push(proto);
+        }
+        for proto in root.children_named("prototype") {
+            prototypes.push(Prototype::from_xml(proto)?);
+        }
+
+        let segment_ops = root
+            .children_named("segmentop")
+            .map(SegmentOp::from_xml)
+            .collect::<Result<_, _>>()?;
+
+        Ok(Self {
+            stack_pointer,
+            return_address,
+            default_prototype,
+            prototypes,
+            segment_ops,
+        })
+    }
+
+    pub fn stack_growth(&self) -> StackGrowth {
+        self.stack_pointer
+            .as_ref()
+            .map(|sp| sp.growth)
+            .unwrap_or(StackGrowth::Negative)
+    }
+
+    /// Look up a calling convention by name
+    pub fn prototype(&self, name: &str) -> Option<&Prototype> {
+        self.prototypes.iter().find(|p| p.name == name)
+    }
+
+    pub fn default_prototype(&self) -> Option<&Prototype> {
+        self.prototype(self.default_prototype.as_deref()?)
+    }
+
+    /// The segment op implementing the user op `userop`
+    pub fn segment_op(&self, userop: &str) -> Option<&SegmentOp> {
+        self.segment_ops.iter().find(|op| op.userop == userop)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CSPEC: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<compiler_spec>
+  <stackpointer register="RSP" space="ram"/>
+  <returnaddress>
+    <varnode space="stack" offset="0" size="8"/>
+  </returnaddress>
+  <default_proto>
+    <prototype name="__stdcall" extrapop="8" stackshift="8">
+      <input>
+        <pentry minsize="1" maxsize="8"><register name="RDI"/></pentry>
+        <pentry minsize="1" maxsize="500" align="8"><addr offset="8" space="stack"/></pentry>
+      </input>
+      <output>
+        <pentry minsize="1" maxsize="8"><register name="RAX"/></pentry>
+      </output>
+      <unaffected><register name="RBX"/></unaffected>
+    </prototype>
+  </default_proto>
+  <segmentop space="ram" userop="segment" farpointer="yes">
+    <pcode>
+      <input name="base" size="2"/>
+      <input name="inner" size="2"/>
+      <output name="res" size="4"/>
+      <body><![CDATA[ res = (zext(base) << 4) + zext(inner); ]]></body>
+    </pcode>
+  </segmentop>
+  <prototype name="syscall" extrapop="unknown" stackshift="8">
+    <input><pentry minsize="1" maxsize="8"><register name="RDI"/></pentry></input>
+    <output><pentry minsize="1" maxsize="8"><register name="RAX"/></pentry></output>
+  </prototype>
+</compiler_spec>
+"#;
+
+    #[test]
+    fn test_parse() {
+        let spec = CompilerSpec::parse(CSPEC).unwrap();
+        let sp = spec.stack_pointer.as_ref().unwrap();
+        assert_eq!(sp.register, "RSP");
+        assert_eq!(spec.stack_growth(), StackGrowth::Negative);
+        assert_eq!(
+            spec.return_address,
+            Some(Storage::Memory {
+                space: "stack".into(),
+                offset: 0,
+                size: Some(8)
+            })
+        );
+
+        let proto = spec.default_prototype().unwrap();
+        assert_eq!(proto.name, "__stdcall");
+        assert_eq!(proto.extrapop, Some(8));
+        assert_eq!(proto.inputs.len(), 2);
+        assert_eq!(proto.inputs[0].storage, Storage::Register("RDI".into()));
+        assert_eq!(proto.inputs[1].align, 8);
+        assert_eq!(proto.outputs[0].storage, Storage::Register("RAX".into()));
+        assert_eq!(proto.unaffected, vec![Storage::Register("RBX".into())]);
+
+        assert_eq!(spec.prototype("syscall").unwrap().extrapop, None);
+
+        let segment = spec.segment_op("segment").unwrap();
+        assert_eq!(segment.output, ("res".to_string(), 4));
+        assert_eq!(
+            segment.body.trim(),
+            "res = (zext(base) << 4) + zext(inner);"
+        );
+        assert!(spec.segment_op("bogus").is_none());
+    }
+}
@@ -0,0 +1,1267 @@
+//! Rust-side conveniences on top of the bridged `Decompiler`.
+
+use std::cell::RefCell;
+use std::ops::{ControlFlow, Range};
+use std::pin::Pin;
+
+use crate::error::{DecodeError, DecodeReason, OpLimitError};
+use crate::pcode::{DecodedInstruction, LiftedInstruction, PcodeOp, TaggedInstruction, Varnode};
+use crate::{ffi, AssemblyEmit, PCodeEmit, RustAssemblyEmit, RustPCodeEmit, SleighError};
+
+const EMPTY_PACKED: ffi::PackedInstruction = ffi::PackedInstruction {
+    length: 0,
+    ops: Vec::new(),
+    inputs: Vec::new(),
+    origins: Vec::new(),
+    bytes: Vec::new(),
+};
+
+const NO_VARNODE: ffi::PackedVarnode = ffi::PackedVarnode {
+    space: -1,
+    offset: 0,
+    size: 0,
+};
+
+fn pack_varnode(vn: &Varnode) -> ffi::PackedVarnode {
+    ffi::PackedVarnode {
+        space: vn.space,
+        offset: vn.offset,
+        size: vn.size,
+    }
+}
+
+/// Collects the mnemonic and operands of an instruction into two strings
+pub(crate) struct AssemblyText<'a>(pub &'a mut String, pub &'a mut String);
+
+impl AssemblyEmit for AssemblyText<'_> {
+    fn dump(&mut self, _addr: &ffi::Address, mnem: &str, body: &str) {
+        self.0.push_str(mnem);
+        self.1.push_str(body);
+    }
+}
+
+impl ffi::TranslateStatus {
+    /// The length of the instruction at `address`, or the error that stopped `decompiler`
+    fn into_result(self, address: u64, decompiler: &ffi::Decompiler) -> Result<usize, SleighError> {
+        if self.error == ffi::TranslateError::OpLimit {
+            return Err(OpLimitError {
+                address,
+                limit: decompiler.getOpLimit(),
+            }
+            .into());
+        }
+        self.into_decode_result(address)
+    }
+
+    /// The length of the instruction at `address`, or why it couldn't be decoded
+    pub(crate) fn into_decode_result(self, address: u64) -> Result<usize, SleighError> {
+        let reason = match self.error {
+            ffi::TranslateError::None => return Ok(self.length as usize),
+            ffi::TranslateError::BadData => DecodeReason::Invalid,
+            ffi::TranslateError::Unimplemented => DecodeReason::Unimplemented,
+            ffi::TranslateError::Unavailable => DecodeReason::Unavailable,
+            _ => DecodeReason::Engine,
+        };
+        Err(DecodeError {
+            address,
+            reason,
+            message: self.message,
+            length: self.length.max(0) as usize,
+        }
+        .into())
+    }
+}
+
+impl ffi::ContextVariableInfo {
+    /// Number of bits in the variable
+    pub fn width(&self) -> u32 {
+        self.end_bit - self.start_bit + 1
+    }
+}
+
+impl ffi::OperandSpanInfo {
+    /// The bytes of the instruction spanned by the operand
+    pub fn byte_range(&self) -> Range<usize> {
+        self.offset as usize..(self.offset + self.length) as usize
+    }
+
+    /// The bytes of the instruction holding the operand's field, if it is a single field
+    pub fn field_range(&self) -> Option<Range<usize>> {
+        let start = self.field_offset as usize;
+        self.field
+            .then(|| start..start + self.field_length as usize)
+    }
+
+    /// Rewrite the field of the operand in `instruction`, the bytes of the whole instruction,
+    /// to hold `value`. Returns `false`, leaving `instruction` untouched, if the operand isn't a
+    /// single field or `value` doesn't fit in it.
+    pub fn encode_field(&self, instruction: &mut [u8], value: i64) -> bool {
+        let bytes = match self.field_range().and_then(|r| instruction.get_mut(r)) {
+            Some(bytes) if self.shift + self.bits <= 64 && (1..=64).contains(&self.bits) => bytes,
+            _ => return false,
+        };
+        let bits = self.bits;
+        let fits = if self.sign_extended {
+            let half = 1i128 << (bits - 1);
+            (-half..half).contains(&(value as i128))
+        } else {
+            (0..1i128 << bits).contains(&(value as i128))
+        };
+        if !fits {
+            return false;
+        }
+        let mut order: Vec<usize> = (0..bytes.len()).collect();
+        if !self.big_endian {
+            order.reverse();
+        }
+        let word = order
+            .iter()
+            .fold(0u64, |word, &i| (word << 8) | bytes[i] as u64);
+        let mask = (u64::MAX >> (64 - bits)) << self.shift;
+        let word = (word & !mask) | (((value as u64) << self.shift) & mask);
+        for (n, &i) in order.iter().rev().enumerate() {
+            bytes[i] = (word >> (8 * n)) as u8;
+        }
+        true
+    }
+
+    /// The register, address or temporary the operand resolves to
+    pub fn varnode(&self) -> Option<Varnode> {
+        matches!(
+            self.kind,
+            ffi::OperandKind::Register | ffi::OperandKind::Address | ffi::OperandKind::Temporary
+        )
+        .then(|| Varnode::from_packed(&self.value))
+    }
+
+    /// The value of the operand, if it resolves to an immediate
+    pub fn immediate(&self) -> Option<u64> {
+        (self.kind == ffi::OperandKind::Immediate).then_some(self.value.offset)
+    }
+
+    /// Where the pointer of a dynamic operand is held
+    pub fn pointer_varnode(&self) -> Option<Varnode> {
+        (self.kind == ffi::OperandKind::Dynamic).then(|| Varnode::from_packed(&self.pointer))
+    }
+}
+
+impl ffi::RegisterInfo {
+    pub fn varnode(&self) -> Varnode {
+        Varnode {
+            space: self.space,
+            offset: self.offset,
+            size: self.size,
+        }
+    }
+
+    /// Whether the register holds all of `varnode`
+    pub fn contains(&self, varnode: &Varnode) -> bool {
+        // Ends are computed in u128 so varnodes at the top of the space don't wrap
+        self.space == varnode.space
+            && self.offset <= varnode.offset
+            && varnode.offset as u128 + varnode.size as u128
+                <= self.offset as u128 + self.size as u128
+    }
+
+    /// Whether the register shares a byte with `varnode`
+    pub fn overlaps(&self, varnode: &Varnode) -> bool {
+        self.space == varnode.space
+            && (self.offset as u128) < varnode.offset as u128 + varnode.size as u128
+            && (varnode.offset as u128) < self.offset as u128 + self.size as u128
+    }
+}
+
+impl ffi::Decompiler {
+    /// Apply the `<context_data>` defaults of a `.pspec` document. Unranged `<context_set>`
+    /// entries become context defaults, ranged ones are painted over their address range.
+    pub fn apply_processor_spec(self: Pin<&mut Self>, pspec: &str) -> Result<(), cxx::Exception> {
+        cxx::let_cxx_string!(pspec = pspec);
+        let doc = ffi::parseDocument(&pspec)?;
+        self.applyProcessorSpec(ffi::getDocumentRoot(&doc))
+    }
+
+    /// Value of the context variable `name` at `addr` in the default code space. The context
+    /// of the last range looked up is cached, so sweeping through nearby addresses is cheap.
+    pub fn context_variable(&self, name: &str, addr: u64) -> Result<u32, cxx::Exception> {
+        cxx::let_cxx_string!(name = name);
+        self.getContextVariable(&name, addr)
+    }
+
+    /// The raw context words at `addr` in the default code space, as used to decode the
+    /// instruction there. Variables are packed from the most significant bit of the first word.
+    pub fn context_words(&self, addr: u64) -> Vec<u32> {
+        self.getContextWords(addr)
+    }
+
+    /// Set the context variable `name` to `value` over `[start, end)` of the default code
+    /// space, leaving the rest of the address space untouched
+    pub fn set_context_range(
+        self: Pin<&mut Self>,
+        name: &str,
+        start: u64,
+        end: u64,
+        value: u32,
+    ) -> Result<(), cxx::Exception> {
+        cxx::let_cxx_string!(name = name);
+        self.setContextVariableRange(&name, start, end, value)
+    }
+
+    /// Set the context variable `name` to `value` from `start` up to the next address where
+    /// it was explicitly changed
+    pub fn set_context_from(
+        self: Pin<&mut Self>,
+        name: &str,
+        start: u64,
+        value: u32,
+    ) -> Result<(), cxx::Exception> {
+        cxx::let_cxx_string!(name = name);
+        self.setContextVariableFrom(&name, start, value)
+    }
+
+    /// Whether the language defines the context variable `name`
+    pub fn has_context_variable(&self, name: &str) -> bool {
+        cxx::let_cxx_string!(name = name);
+        self.hasContextVariable(&name)
+    }
+
+    /// Set the context variable `name` to `value` at every address, replacing the default and
+    /// any ranges set before
+    pub fn set_context_everywhere(
+        self: Pin<&mut Self>,
+        name: &str,
+        value: u32,
+    ) -> Result<(), cxx::Exception> {
+        cxx::let_cxx_string!(name = name);
+        self.setContextVariableEverywhere(&name, value)
+    }
+
+    /// Every context variable the spec defines, with its bit range and default value
+    pub fn context_variables(&self) -> Vec<ffi::ContextVariableInfo> {
+        self.getContextVariables()
+    }
+
+    /// Serialize the context database: default values, values painted over ranges and
+    /// tracked registers
+    pub fn save_context(&self) -> String {
+        self.saveContext()
+    }
+
+    /// Replace the context database with one serialized by
+    /// [`save_context`](Self::save_context)
+    pub fn restore_context(self: Pin<&mut Self>, xml: &str) -> Result<(), cxx::Exception> {
+        cxx::let_cxx_string!(xml = xml);
+        let doc = ffi::parseDocument(&xml)?;
+        self.restoreContext(ffi::getDocumentRoot(&doc))
+    }
+
+    /// Context changes the instruction at `addr` makes for other addresses with `globalset`,
+    /// such as an ARM `BX` switching the target to Thumb. They are applied when the instruction
+    /// is lifted; reading them doesn't change the context.
+    pub fn context_commits(
+        &self,
+        addr: u64,
+    ) -> Result<Vec<ffi::ContextCommitInfo>, cxx::Exception> {
+        self.getContextCommits(addr)
+    }
+
+    /// The operands of the instruction at `addr` with the bytes encoding them and the values
+    /// they resolve to, listed depth first: each operand is followed by the operands of its
+    /// subconstructor, if any.
+    pub fn operand_spans(&self, addr: u64) -> Result<Vec<ffi::OperandSpanInfo>, cxx::Exception> {
+        self.getOperandSpans(addr)
+    }
+
+    /// The constructors that matched the instruction at `addr`, listed depth first like
+    /// [`operand_spans`](Self::operand_spans), with where the `.slaspec` defines them
+    pub fn constructors(&self, addr: u64) -> Result<Vec<ffi::ConstructorInfo>, cxx::Exception> {
+        self.getConstructors(addr)
+    }
+
+    /// All registers of the language, ordered by space and offset, larger registers first at
+    /// the same offset
+    pub fn registers(&self) -> Vec<ffi::RegisterInfo> {
+        self.getRegisterList()
+    }
+
+    /// The largest register holding all of `varnode`, like `RAX` for the varnode of `AL` on
+    /// x86
+    pub fn containing_register(&self, varnode: &Varnode) -> Option<ffi::RegisterInfo> {
+        self.getRegisterList()
+            .into_iter()
+            .filter(|reg| reg.contains(varnode))
+            .reduce(|largest, reg| {
+                if reg.size > largest.size {
+                    reg
+                } else {
+                    largest
+                }
+            })
+    }
+
+    /// Every register sharing a byte with `varnode`, ordered like
+    /// [`registers`](Self::registers)
+    pub fn overlapping_registers(&self, varnode: &Varnode) -> Vec<ffi::RegisterInfo> {
+        self.getRegisterList()
+            .into_iter()
+            .filter(|reg| reg.overlaps(varnode))
+            .collect()
+    }
+
+    /// Parse the instruction at `addr` once, to render both its assembly and its pcode
+    /// without decoding it twice. Fails if it can't be decoded; an instruction whose
+    /// semantics are `unimpl` decodes, and fails when its pcode is asked for.
+    pub fn decode(&self, addr: u64) -> Result<DecodedInstruction<'_>, SleighError> {
+        let length = self.decodeInstruction(addr).into_result(addr, self)?;
+        Ok(DecodedInstruction {
+            decompiler: self,
+            address: addr,
+            length,
+        })
+    }
+
+    /// Lift the instruction at `addr` into owned pcode. Returns `None` if it can't be decoded
+    /// or exceeds the op limit.
+    pub fn lift(&self, addr: u64) -> Option<LiftedInstruction> {
+        self.try_lift(addr).ok()
+    }
+
+    /// Lift the instruction at `addr`, failing with [`SleighError::Decode`] if it can't be
+    /// decoded and [`SleighError::OpLimit`] if it has too many pcode ops. The pcode is
+    /// collected by the engine and crosses over in one piece, which is much cheaper than
+    /// going through a [`PCodeEmit`](crate::PCodeEmit) when lifting many instructions.
+    pub fn try_lift(&self, addr: u64) -> Result<LiftedInstruction, SleighError> {
+        self.try_lift_in(self.getDefaultCodeSpaceIndex(), addr)
+    }
+
+    /// Lift the instruction at `addr` in the space with index `space` rather than the default
+    /// code space, like the program memory of an AVR whose default space is its RAM. The
+    /// lifted instruction doesn't record the space, only the offset. In an overlay space,
+    /// varnodes and LOAD or STORE accesses in the space it overlays are moved into the
+    /// overlay, as Ghidra does, so a jump within a ROM bank stays in that bank.
+    pub fn try_lift_in(&self, space: i32, addr: u64) -> Result<LiftedInstruction, SleighError> {
+        // The buffer is kept per thread so its vectors are only allocated once
+        thread_local! {
+            static PACKED: RefCell<ffi::PackedInstruction> = const { RefCell::new(EMPTY_PACKED) };
+        }
+        PACKED.with(|packed| match packed.try_borrow_mut() {
+            Ok(mut packed) => self.unpack(space, addr, &mut packed),
+            // Lifting from within the load image
+            Err(_) => {
+                let mut packed = EMPTY_PACKED;
+                self.unpack(space, addr, &mut packed)
+            }
+        })
+    }
+
+    fn unpack(
+        &self,
+        space: i32,
+        addr: u64,
+        packed: &mut ffi::PackedInstruction,
+    ) -> Result<LiftedInstruction, SleighError> {
+        self.translatePackedIn(space, addr, packed)
+            .into_result(addr, self)?;
+        Ok(LiftedInstruction::from_packed(addr, packed)?)
+    }
+
+    /// Lift the instruction at `addr` like [`try_lift`](Self::try_lift), telling which ops
+    /// were built for its delay slots, if it has any
+    pub fn lift_tagged(&self, addr: u64) -> Result<TaggedInstruction, SleighError> {
+        let mut packed = EMPTY_PACKED;
+        self.translatePacked(addr, &mut packed)
+            .into_result(addr, self)?;
+        Ok(TaggedInstruction::from_packed(addr, &packed)?)
+    }
+
+    /// Hand the pcode of the instruction at `addr` to `emit`, one op at a time. Returns the
+    /// length of the instruction, in `Break` if `emit` asked to stop, after which it gets no
+    /// more ops, so a caller walking instructions knows to stop too. Ops already emitted when an
+    /// error stops the translation aren't taken back. An op with an opcode [`crate::Opcode`]
+    /// doesn't know is an error unless `emit` handles it, see [`PCodeEmit::dump_unknown`].
+    pub fn emit_pcode(
+        &self,
+        addr: u64,
+        emit: &mut dyn PCodeEmit,
+    ) -> Result<ControlFlow<usize, usize>, SleighError> {
+        let mut emit = RustPCodeEmit::from_internal(emit);
+        let status = unsafe { self.translate(&mut emit, addr) };
+        if status.error == ffi::TranslateError::Stopped {
+            if let Some(opcode) = emit.unknown {
+                return Err(DecodeError {
+                    address: addr,
+                    reason: DecodeReason::UnknownOpcode,
+                    message: format!("unknown pcode opcode {}", opcode),
+                    length: status.length.max(0) as usize,
+                }
+                .into());
+            }
+            return Ok(ControlFlow::Break(status.length as usize));
+        }
+        status.into_result(addr, self).map(ControlFlow::Continue)
+    }
+
+    /// Hand the disassembly of the instruction at `addr` to `emit`. Returns the length of the
+    /// instruction.
+    pub fn emit_assembly(
+        &self,
+        addr: u64,
+        emit: &mut dyn AssemblyEmit,
+    ) -> Result<usize, SleighError> {
+        let mut emit = RustAssemblyEmit::from_internal(emit);
+        unsafe { self.disassemble(&mut emit, addr) }.into_result(addr, self)
+    }
+
+    /// Most pcode ops [`lift`](Self::lift) accepts from a single instruction, `None` for no
+    /// limit. Instructions over the limit are abandoned as soon as it is reached.
+    pub fn set_op_limit(self: Pin<&mut Self>, limit: Option<u32>) {
+        self.setOpLimit(limit.unwrap_or(0));
+    }
+
+    pub fn op_limit(&self) -> Option<u32> {
+        Some(self.getOpLimit()).filter(|&limit| limit != 0)
+    }
+
+    /// How [`lift`](Self::lift) and [`emit_pcode`](Self::emit_pcode) treat instructions
+    /// whose semantics are `unimpl`. They fail by default.
+    pub fn set_unimplemented_policy(self: Pin<&mut Self>, policy: ffi::UnimplementedPolicy) {
+        self.setUnimplementedPolicy(policy);
+    }
+
+    pub fn unimplemented_policy(&self) -> ffi::UnimplementedPolicy {
+        self.getUnimplementedPolicy()
+    }
+
+    /// The user op [`UnimplementedPolicy::CallOther`](ffi::UnimplementedPolicy::CallOther)
+    /// calls: the spec's own `unimpl` op if it defines one, otherwise the index just past its
+    /// user ops, which no `CALLOTHER` of its own uses
+    pub fn unimplemented_userop(&self) -> u32 {
+        self.getUnimplementedUserOp()
+    }
+
+    /// Lift `ops` in place of the pcode of the instruction at `addr` from now on, the way
+    /// Ghidra's call fixups stand in for a call, e.g. to model a syscall or an obfuscated stub.
+    /// The instruction is taken to be `length` bytes long, or as long as the instruction
+    /// decoded there if `None`, in which case the bytes must still decode. The varnodes are in
+    /// this decompiler's spaces, see [`Varnode::new`]. Only lifting sees injected pcode; the
+    /// assembly, the emulator and
+    /// [`decompile_function`](crate::project::Program::decompile_function) don't.
+    pub fn inject(
+        self: Pin<&mut Self>,
+        addr: u64,
+        length: Option<usize>,
+        ops: &[PcodeOp],
+    ) -> Result<(), cxx::Exception> {
+        let mut pcode = EMPTY_PACKED;
+        for op in ops {
+            pcode.ops.push(ffi::PackedOp {
+                opcode: op.opcode as u32,
+                has_output: op.output.is_some(),
+                output: op.output.as_ref().map_or(NO_VARNODE, pack_varnode),
+                first_input: pcode.inputs.len() as u32,
+                input_count: op.inputs.len() as u32,
+            });
+            pcode.inputs.extend(op.inputs.iter().map(pack_varnode));
+        }
+        self.setInjection(addr, length.unwrap_or(0) as u32, &pcode)
+    }
+
+    /// Lift the instruction at `addr` from its bytes again. Returns whether pcode was injected
+    /// there.
+    pub fn remove_injection(self: Pin<&mut Self>, addr: u64) -> bool {
+        self.removeInjection(addr)
+    }
+
+    pub fn clear_injections(self: Pin<&mut Self>) {
+        self.clearInjections();
+    }
+
+    /// Register the user op `name`, so injected pcode can call it with a CALLOTHER, see
+    /// [`PcodeOp::call_other`]. Returns its index, which is that of the spec's own op if it
+    /// already has one called `name`. Registered ops are numbered after the spec's; if the spec
+    /// has no `unimpl` op, the first one registered also adds `unimpl` at
+    /// [`unimplemented_userop`](Self::unimplemented_userop). Like injected pcode, only lifting
+    /// knows of them.
+    pub fn add_user_op(self: Pin<&mut Self>, name: &str) -> Result<u32, cxx::Exception> {
+        cxx::let_cxx_string!(name = name);
+        self.addUserOp(&name)
+    }
+
+    /// The names of the spec's user ops followed by those registered, by CALLOTHER index
+    pub fn user_ops(&self) -> Vec<String> {
+        self.getUserOps()
+    }
+
+    /// The index of the user op `name`
+    pub fn user_op(&self, name: &str) -> Option<u32> {
+        self.getUserOps()
+            .iter()
+            .position(|op| op == name)
+            .map(|index| index as u32)
+    }
+
+    /// Instructions start at multiples of this many bytes
+    pub fn instruction_alignment(&self) -> u64 {
+        self.getInstructionAlignment().max(1) as u64
+    }
+
+    /// Most bytes a single instruction can span, not counting its delay slots. Instructions
+    /// are decoded from a window of this many bytes.
+    pub fn max_instruction_length(&self) -> usize {
+        self.getMaxInstructionLength() as usize
+    }
+
+    /// Most bytes the delay slots of one instruction can cover, 0 if the language has none
+    pub fn max_delay_slot_bytes(&self) -> usize {
+        self.getMaxDelaySlotBytes() as usize
+    }
+
+    pub fn has_delay_slots(&self) -> bool {
+        self.getMaxDelaySlotBytes() > 0
+    }
+
+    /// Lift the instruction at `addr`, which must fit in the `max_len` bytes from there. The
+    /// engine reads a fixed window of bytes whatever the instruction, so this keeps bytes past
+    /// the end of a buffer, whatever the loader fills them with, from being decoded as part
+    /// of it.
+    pub fn lift_bounded(
+        &self,
+        addr: u64,
+        max_len: usize,
+    ) -> Result<LiftedInstruction, SleighError> {
+        let inst = self.try_lift(addr)?;
+        if inst.length > max_len {
+            return Err(DecodeError {
+                address: addr,
+                reason: DecodeReason::Invalid,
+                message: format!(
+                    "instruction needs {} bytes, only {} available",
+                    inst.length, max_len
+                ),
+                length: inst.length,
+            }
+            .into());
+        }
+        Ok(inst)
+    }
+
+    /// Disassemble the instruction at `addr` into `(mnemonic, operands, length)`
+    pub fn disassemble_one(&self, addr: u64) -> Result<(String, String, usize), SleighError> {
+        let (mut mnemonic, mut operands) = (String::new(), String::new());
+        let length = self.disassemble_into(addr, &mut mnemonic, &mut operands)?;
+        Ok((mnemonic, operands, length))
+    }
+
+    /// Disassemble the instruction at `addr` into buffers the caller reuses, replacing their
+    /// contents. Returns the length of the instruction.
+    pub fn disassemble_into(
+        &self,
+        addr: u64,
+        mnemonic: &mut String,
+        operands: &mut String,
+    ) -> Result<usize, SleighError> {
+        mnemonic.clear();
+        operands.clear();
+        self.emit_assembly(addr, &mut AssemblyText(mnemonic, operands))
+    }
+
+    /// Read `len` bytes at `addr` in the default code space from the load image
+    pub fn read_bytes(&self, addr: u64, len: usize) -> Result<Vec<u8>, cxx::Exception> {
+        let mut buf = vec![0; len];
+        self.fillBytes(addr, &mut buf)?;
+        Ok(buf)
+    }
+
+    /// Read `len` bytes at `addr` in the space with index `space` from the load image
+    pub fn read_bytes_in(
+        &self,
+        space: i32,
+        addr: u64,
+        len: usize,
+    ) -> Result<Vec<u8>, cxx::Exception> {
+        let mut buf = vec![0; len];
+        self.fillBytesIn(space, addr, &mut buf)?;
+        Ok(buf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::pcode::PcodeCollector;
+    use crate::toy;
+    use crate::Opcode;
+
+    #[test]
+    fn test_lift_bounded() {
+        // movi r0, 1 ; movi r1, <cut off>
+        let program = toy::program(0x1000, &[0x01, 0x00, 0x01, 0x01, 0x10]);
+        let decompiler = program.decompiler();
+        assert_eq!(decompiler.lift_bounded(0x1000, 5).unwrap().length, 3);
+        let err = decompiler.lift_bounded(0x1003, 2).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "can't decode instruction at 0x1003: instruction needs 3 bytes, only 2 available"
+        );
+        assert!(decompiler.lift_bounded(0x1000, 0).is_err());
+    }
+
+    #[test]
+    fn test_translate_errors() {
+        // hlt ; <invalid> ; bump r1
+        let mut program = toy::program(0x1000, &[0x0a, 0xff, 0x0b, 0x10]);
+        let decompiler = program.decompiler();
+        let reason = |addr| match decompiler.try_lift(addr) {
+            Err(SleighError::Decode(e)) => Some(e.reason),
+            _ => None,
+        };
+        assert_eq!(reason(0x1000), Some(DecodeReason::Unimplemented));
+        assert_eq!(reason(0x1001), Some(DecodeReason::Invalid));
+        assert_eq!(decompiler.disassemble_one(0x1000).unwrap().2, 1);
+        let err = decompiler.disassemble_one(0x1001).unwrap_err();
+        assert_eq!(err.kind(), crate::error::ErrorKind::Decode);
+
+        let mut collector = PcodeCollector::default();
+        assert_eq!(
+            decompiler.emit_pcode(0x1002, &mut collector).unwrap(),
+            ControlFlow::Continue(2)
+        );
+        program.set_op_limit(Some(2));
+        let mut collector = PcodeCollector::default();
+        let err = program
+            .decompiler()
+            .emit_pcode(0x1002, &mut collector)
+            .unwrap_err();
+        assert!(matches!(err, SleighError::OpLimit(_)));
+    }
+
+    #[test]
+    fn test_emit_stop() {
+        /// Takes ops until it has `budget` of them
+        struct Budget {
+            ops: Vec<Opcode>,
+            budget: usize,
+        }
+        impl PCodeEmit for Budget {
+            fn dump(
+                &mut self,
+                _address: &ffi::Address,
+                opcode: Opcode,
+                _outvar: Option<&ffi::VarnodeData>,
+                _vars: &[&ffi::VarnodeData],
+            ) -> ControlFlow<()> {
+                self.ops.push(opcode);
+                if self.ops.len() < self.budget {
+                    ControlFlow::Continue(())
+                } else {
+                    ControlFlow::Break(())
+                }
+            }
+        }
+
+        // bump r1
+        let program = toy::program(0x1000, &[0x0b, 0x10]);
+        let decompiler = program.decompiler();
+        let mut emit = Budget {
+            ops: Vec::new(),
+            budget: 1,
+        };
+        let flow = decompiler.emit_pcode(0x1000, &mut emit).unwrap();
+        assert_eq!(flow, ControlFlow::Break(2));
+        assert_eq!(emit.ops.len(), 1);
+
+        let mut emit = Budget {
+            ops: Vec::new(),
+            budget: 10,
+        };
+        let flow = decompiler.emit_pcode(0x1000, &mut emit).unwrap();
+        assert_eq!(flow, ControlFlow::Continue(2));
+        assert_eq!(
+            emit.ops.len(),
+            decompiler.try_lift(0x1000).unwrap().ops.len()
+        );
+    }
+
+    #[test]
+    fn test_unimplemented_policy() {
+        // hlt ; bump r1
+        let mut program = toy::program(0x1000, &[0x0a, 0x0b, 0x10]);
+        match program.decompiler().try_lift(0x1000) {
+            Err(SleighError::Decode(e)) => {
+                assert_eq!(e.reason, DecodeReason::Unimplemented);
+                assert_eq!(e.length, 1);
+            }
+            res => panic!("{:?}", res),
+        }
+
+        program.set_unimplemented_policy(ffi::UnimplementedPolicy::CallOther);
+        let decompiler = program.decompiler();
+        // The toy language has no unimpl op of its own
+        assert_eq!(decompiler.unimplemented_userop(), 3);
+        let inst = decompiler.try_lift(0x1000).unwrap();
+        assert_eq!(inst.length, 1);
+        assert_eq!(inst.ops.len(), 1);
+        assert_eq!(inst.ops[0].opcode, Opcode::CallOther);
+        assert_eq!(
+            inst.ops[0].inputs[0].space,
+            decompiler.getConstantSpaceIndex()
+        );
+        assert_eq!(inst.ops[0].inputs[0].offset, 3);
+        let mut collector = PcodeCollector::default();
+        assert_eq!(
+            decompiler.emit_pcode(0x1000, &mut collector).unwrap(),
+            ControlFlow::Continue(1)
+        );
+        assert_eq!(collector.ops, inst.ops);
+        // Other instructions lift as before
+        assert_eq!(decompiler.try_lift(0x1001).unwrap().length, 2);
+
+        program.set_unimplemented_policy(ffi::UnimplementedPolicy::Skip);
+        let inst = program.decompiler().try_lift(0x1000).unwrap();
+        assert_eq!(inst.length, 1);
+        assert_eq!(inst.bytes, [0x0a]);
+        assert!(inst.ops.is_empty());
+    }
+
+    #[test]
+    fn test_inject() {
+        // movi r0, 1 ; <data> ; nop
+        let mut program = toy::program(0x1000, &[0x01, 0x00, 0x01, 0xff, 0xfe, 0x00]);
+        let original = program.lift(0x1000).unwrap().clone();
+        let decompiler = program.decompiler();
+        let r0 = Varnode::register(decompiler, "r0").unwrap();
+        let syscall = vec![PcodeOp::copy(
+            r0.clone(),
+            Varnode::constant(decompiler, 7, 4),
+        )];
+        let stub = vec![PcodeOp::new(
+            Opcode::Return,
+            None,
+            vec![Varnode::register(decompiler, "lr").unwrap()],
+        )];
+
+        program.inject(0x1000, None, syscall.clone()).unwrap();
+        let inst = program.lift(0x1000).unwrap();
+        assert_eq!(inst.length, 3);
+        assert_eq!(inst.ops, syscall);
+        assert_eq!(inst.bytes, original.bytes);
+        let mut collector = PcodeCollector::default();
+        assert_eq!(
+            program
+                .decompiler()
+                .emit_pcode(0x1000, &mut collector)
+                .unwrap(),
+            ControlFlow::Continue(3)
+        );
+        assert_eq!(collector.ops, syscall);
+
+        // Bytes that don't decode need a length
+        program.inject(0x1003, Some(2), stub.clone()).unwrap();
+        let inst = program.lift(0x1003).unwrap();
+        assert_eq!((inst.length, &inst.bytes[..]), (2, &[0xff, 0xfe][..]));
+        assert_eq!(inst.ops, stub);
+        program.inject(0x1005, None, Vec::new()).unwrap();
+        assert!(program.lift(0x1005).unwrap().ops.is_empty());
+
+        let bad = vec![PcodeOp::copy(Varnode::new(99, 0, 4), r0)];
+        assert!(program.inject(0x1005, None, bad).is_err());
+
+        assert!(program.remove_injection(0x1000));
+        assert!(!program.remove_injection(0x1000));
+        // The last address of the space
+        assert!(!program.remove_injection(u64::MAX));
+        assert_eq!(program.lift(0x1000).unwrap(), &original);
+        assert!(program.decompiler().try_lift(0x1003).is_ok());
+    }
+
+    #[test]
+    fn test_user_ops() {
+        let mut program = toy::program(0x1000, &[0x00, 0x00]);
+        let ops = |program: &crate::project::Program| program.decompiler().user_ops();
+        assert_eq!(ops(&program), ["rdrand", "rdtsc", "cpuid"]);
+        assert_eq!(program.add_user_op("cpuid").unwrap(), 2);
+        assert_eq!(ops(&program).len(), 3);
+
+        // The toy spec has no unimpl op, so the first registered one reserves it
+        assert_eq!(program.add_user_op("vmcall").unwrap(), 4);
+        assert_eq!(program.add_user_op("vmcall").unwrap(), 4);
+        assert_eq!(program.add_user_op("unimpl").unwrap(), 3);
+        assert_eq!(
+            ops(&program),
+            ["rdrand", "rdtsc", "cpuid", "unimpl", "vmcall"]
+        );
+        let decompiler = program.decompiler();
+        assert_eq!(decompiler.unimplemented_userop(), 3);
+        assert_eq!(decompiler.user_op("vmcall"), Some(4));
+        assert_eq!(decompiler.user_op("vmread"), None);
+
+        let r0 = Varnode::register(decompiler, "r0").unwrap();
+        let call = PcodeOp::call_other(decompiler, 4, Some(r0.clone()), vec![r0]);
+        program.inject(0x1000, None, vec![call.clone()]).unwrap();
+        let inst = program.lift(0x1000).unwrap();
+        assert_eq!(inst.ops, [call]);
+        assert_eq!(inst.ops[0].inputs[0].offset, 4);
+        assert!(program.add_user_op("").is_err());
+    }
+
+    #[test]
+    fn test_packed_matches_emit() {
+        // bump r1 ; cpuid r2 ; st [r1], r0
+        let code = [0x0b, 0x10, 0x09, 0x20, 0x05, 0x10];
+        let program = toy::program(0x1000, &code);
+        let decompiler = program.decompiler();
+        for addr in [0x1000, 0x1002, 0x1004] {
+            let mut collector = PcodeCollector::default();
+            assert!(decompiler
+                .emit_pcode(addr, &mut collector)
+                .unwrap()
+                .is_continue());
+            let packed = decompiler.try_lift(addr).unwrap();
+            assert_eq!(packed.ops, collector.ops);
+        }
+    }
+
+    #[test]
+    fn test_reused_buffers() {
+        // movi r0, 1 ; nop
+        let program = toy::program(0x1000, &[0x01, 0x00, 0x01, 0x00]);
+        let decompiler = program.decompiler();
+        let (mut mnemonic, mut operands) = (String::new(), String::new());
+        assert_eq!(
+            decompiler
+                .disassemble_into(0x1000, &mut mnemonic, &mut operands)
+                .unwrap(),
+            3
+        );
+        assert_eq!((mnemonic.as_str(), operands.as_str()), ("movi", "r0, 0x1"));
+        decompiler
+            .disassemble_into(0x1003, &mut mnemonic, &mut operands)
+            .unwrap();
+        assert_eq!((mnemonic.as_str(), operands.as_str()), ("nop", ""));
+        assert_eq!(
+            decompiler.read_bytes(0x1002, 3).unwrap(),
+            [0x01, 0x00, 0x00]
+        );
+    }
+
+    #[test]
+    fn test_op_limit() {
+        // bump r1
+        let mut program = toy::program(0x1000, &[0x0b, 0x10]);
+        program.set_op_limit(Some(4));
+        let err = program.decompiler().try_lift(0x1000).unwrap_err();
+        assert!(matches!(
+            err,
+            SleighError::OpLimit(OpLimitError {
+                address: 0x1000,
+                limit: 4
+            })
+        ));
+        program.set_op_limit(None);
+        assert_eq!(program.decompiler().op_limit(), None);
+        assert_eq!(program.lift(0x1000).unwrap().ops.len(), 5);
+    }
+
+    #[test]
+    fn test_context_range() {
+        let mut program = toy::program(0x1000, &[0; 0x10]);
+        program
+            .set_context_range("mode", 0x1004, 0x1008, 1)
+            .unwrap();
+        let decompiler = program.decompiler();
+        assert_eq!(decompiler.context_variable("mode", 0x1003).unwrap(), 0);
+        assert_eq!(decompiler.context_variable("mode", 0x1004).unwrap(), 1);
+        assert_eq!(decompiler.context_variable("mode", 0x1008).unwrap(), 0);
+        assert_eq!(decompiler.disassemble_one(0x1003).unwrap().0, "nop");
+        assert_eq!(decompiler.disassemble_one(0x1007).unwrap().0, "alt");
+        assert_eq!(decompiler.disassemble_one(0x1008).unwrap().0, "nop");
+
+        program.set_context_from("mode", 0x100c, 1).unwrap();
+        let decompiler = program.decompiler();
+        assert_eq!(decompiler.disassemble_one(0x100b).unwrap().0, "nop");
+        assert_eq!(decompiler.disassemble_one(0x2000).unwrap().0, "alt");
+
+        assert!(program.set_context_range("bogus", 0, 4, 1).is_err());
+        assert!(program.set_context_range("mode", 4, 4, 1).is_err());
+    }
+
+    #[test]
+    fn test_context_cache() {
+        let mut program = toy::program(0x1000, &[0; 0x10]);
+        program
+            .set_context_range("mode", 0x1004, 0x1008, 1)
+            .unwrap();
+        let decompiler = program.decompiler();
+        // Alternate between ranges so each lookup refills the cache
+        for addr in [0x1000, 0x1004, 0x1003, 0x1007, 0x1008, 0x1005] {
+            let expected = (0x1004..0x1008).contains(&addr) as u32;
+            assert_eq!(decompiler.context_variable("mode", addr).unwrap(), expected);
+            assert_eq!(decompiler.context_words(addr), [expected << 31]);
+        }
+        assert!(decompiler.context_variable("bogus", 0x1000).is_err());
+
+        // Changes made after a lookup are seen by the next one
+        program.set_context_everywhere("mode", 1).unwrap();
+        assert_eq!(
+            program
+                .decompiler()
+                .context_variable("mode", 0x1000)
+                .unwrap(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_operand_spans() {
+        // movi r1, 0x40 ; switch 0x10
+        let mut code = vec![0x01, 0x10, 0x40, 0x06, 0x10];
+        let program = toy::program(0x1000, &code);
+        let decompiler = program.decompiler();
+        let spans = decompiler.operand_spans(0x1000).unwrap();
+        assert_eq!(spans.len(), 2);
+        let (rd, imm) = (&spans[0], &spans[1]);
+        assert_eq!((rd.name.as_str(), rd.text.as_str()), ("rd", "r1"));
+        assert_eq!((rd.byte_range(), rd.field_range()), (1..2, Some(1..2)));
+        assert_eq!((rd.shift, rd.bits), (4, 4));
+        assert_eq!((imm.text.as_str(), imm.byte_range()), ("0x40", 2..3));
+
+        assert!(!imm.encode_field(&mut code[..3], 0x100));
+        assert!(imm.encode_field(&mut code[..3], 0x7f));
+        assert!(rd.encode_field(&mut code[..3], 2));
+        assert_eq!(code[..3], [0x01, 0x20, 0x7f]);
+        let patched = toy::program(0x1000, &code);
+        assert_eq!(
+            patched.decompiler().disassemble_one(0x1000).unwrap().1,
+            "r2, 0x7f"
+        );
+
+        // Subtable operands are followed by their own operands
+        let spans = decompiler.operand_spans(0x1003).unwrap();
+        assert_eq!(spans.len(), 2);
+        assert_eq!((spans[0].name.as_str(), spans[0].field), ("target", false));
+        assert_eq!((spans[1].depth, spans[1].parent), (1, 0));
+        assert_eq!(spans[1].field_range(), Some(1..2));
+    }
+
+    #[test]
+    fn test_operand_values() {
+        // movi r1, 0x40 ; jmp 0x10 ; ldx r2, [r1]
+        let code = [0x01, 0x10, 0x40, 0x0c, 0x10, 0x0f, 0x21];
+        let program = toy::program(0x1000, &code);
+        let decompiler = program.decompiler();
+        let register = decompiler
+            .space_hierarchy()
+            .by_name("register")
+            .unwrap()
+            .index;
+        let ram = decompiler.getDefaultCodeSpaceIndex();
+
+        let spans = decompiler.operand_spans(0x1000).unwrap();
+        assert_eq!(spans[0].kind, ffi::OperandKind::Register);
+        assert_eq!(
+            spans[0].varnode(),
+            Some(Varnode {
+                space: register,
+                offset: 4,
+                size: 4
+            })
+        );
+        assert_eq!(spans[1].kind, ffi::OperandKind::Immediate);
+        assert_eq!(
+            (spans[1].immediate(), spans[1].varnode()),
+            (Some(0x40), None)
+        );
+
+        let spans = decompiler.operand_spans(0x1003).unwrap();
+        assert_eq!(spans[0].kind, ffi::OperandKind::Address);
+        assert_eq!(
+            spans[0].varnode(),
+            Some(Varnode {
+                space: ram,
+                offset: 0x10,
+                size: 1
+            })
+        );
+        assert_eq!(spans[1].immediate(), Some(0x10));
+
+        let spans = decompiler.operand_spans(0x1005).unwrap();
+        let ptr = &spans[1];
+        assert_eq!(
+            (ptr.name.as_str(), ptr.kind),
+            ("ptr", ffi::OperandKind::Dynamic)
+        );
+        assert_eq!((ptr.value.space, ptr.value.size), (ram, 4));
+        assert_eq!(ptr.pointer_varnode().unwrap().offset, 4);
+        assert_eq!(ptr.varnode(), None);
+        // Lifting afterwards is unaffected
+        assert_eq!(decompiler.lift(0x1005).unwrap().ops.len(), 2);
+    }
+
+    #[test]
+    fn test_constructors() {
+        // ldx r2, [r1] ; 0xff
+        let program = toy::program(0x1000, &[0x0f, 0x21, 0xff]);
+        let decompiler = program.decompiler();
+        let line = |prefix: &str| {
+            toy::SLASPEC
+                .lines()
+                .position(|l| l.starts_with(prefix))
+                .unwrap() as i32
+                + 1
+        };
+        let constructors = decompiler.constructors(0x1000).unwrap();
+        assert_eq!(constructors.len(), 2);
+        let (ldx, ptr) = (&constructors[0], &constructors[1]);
+        assert_eq!(
+            (ldx.table.as_str(), ldx.text.as_str()),
+            ("instruction", "ldx r2, [r1]")
+        );
+        assert!(ldx.file.ends_with("toy.slaspec"));
+        assert_eq!(ldx.line, line(":ldx"));
+        assert_eq!((ldx.offset, ldx.length), (0, 2));
+        assert_eq!((ptr.table.as_str(), ptr.text.as_str()), ("ptr", "[r1]"));
+        assert_eq!(ptr.line, line("ptr:"));
+        assert_eq!((ptr.depth, ptr.parent, ptr.operand), (1, 0, 1));
+        assert_eq!((ptr.offset, ptr.length), (1, 1));
+        assert!(decompiler.constructors(0x1002).is_err());
+    }
+
+    #[test]
+    fn test_register_aliases() {
+        let program = toy::program(0, &[]);
+        let decompiler = program.decompiler();
+        let space = decompiler
+            .space_hierarchy()
+            .by_name("register")
+            .unwrap()
+            .index;
+        let varnode = |offset, size| Varnode {
+            space,
+            offset,
+            size,
+        };
+        let names = |regs: Vec<ffi::RegisterInfo>| -> Vec<String> {
+            regs.into_iter().map(|reg| reg.name).collect()
+        };
+
+        let r0b = varnode(0, 1);
+        assert_eq!(r0b.register_name(decompiler).unwrap(), "r0b");
+        assert_eq!(decompiler.containing_register(&r0b).unwrap().name, "r0");
+        assert_eq!(
+            names(decompiler.overlapping_registers(&r0b)),
+            ["r0", "r0w", "r0b"]
+        );
+        // The upper half of r0 is no register of its own
+        assert_eq!(
+            decompiler.containing_register(&varnode(2, 2)).unwrap().name,
+            "r0"
+        );
+        assert_eq!(
+            names(decompiler.overlapping_registers(&varnode(2, 4))),
+            ["r0", "r1"]
+        );
+        assert!(decompiler.containing_register(&varnode(2, 4)).is_none());
+        assert!(decompiler
+            .overlapping_registers(&varnode(0x80, 4))
+            .is_empty());
+        // Varnodes running past the end of the space
+        let top = varnode(u64::MAX - 1, 4);
+        assert!(decompiler.containing_register(&top).is_none());
+        assert!(decompiler.overlapping_registers(&top).is_empty());
+
+        let registers = decompiler.registers();
+        assert_eq!(registers[0].varnode(), varnode(0, 4));
+        assert!(registers.iter().any(|reg| reg.name == "contextreg"));
+    }
+
+    #[test]
+    fn test_decode_once() {
+        /// Counts the reads of the toy image
+        struct Counting(toy::ToyImage, Rc<Cell<usize>>);
+        impl crate::LoadImage for Counting {
+            fn load_fill(&mut self, ptr: &mut [u8], addr: &ffi::Address) {
+                self.1.set(self.1.get() + 1);
+                self.0.load_fill(ptr, addr);
+            }
+        }
+
+        // movi r0, 0x41 ; <invalid>
+        let image = toy::ToyImage {
+            base: 0x1000,
+            code: vec![0x01, 0x00, 0x41, 0xff],
+        };
+        let reads = Rc::new(Cell::new(0));
+        let program =
+            crate::project::Program::new(toy::files().clone(), Counting(image, reads.clone()))
+                .unwrap();
+        let decompiler = program.decompiler();
+        let before = reads.get();
+        let inst = decompiler.decode(0x1000).unwrap();
+        assert_eq!((inst.address, inst.length), (0x1000, 3));
+        assert_eq!(reads.get(), before + 1);
+        let (mnemonic, operands) = inst.assembly().unwrap();
+        let pcode = inst.pcode().unwrap();
+        assert_eq!(reads.get(), before + 1);
+        assert_eq!(
+            (mnemonic, operands, 3),
+            decompiler.disassemble_one(0x1000).unwrap()
+        );
+        assert_eq!(pcode, decompiler.try_lift(0x1000).unwrap());
+
+        let err = decompiler.decode(0x1003).unwrap_err();
+        assert_eq!(err.kind(), crate::error::ErrorKind::Decode);
+    }
+
+    #[test]
+    fn test_delay_slots() {
+        // djmp 0x20 ; movi r0, 5 ; movi r1, 6
+        let code = [0x10, 0x20, 0x01, 0x00, 0x05, 0x01, 0x10, 0x06];
+        let program = toy::program(0x1000, &code);
+        let decompiler = program.decompiler();
+        let tagged = decompiler.lift_tagged(0x1000).unwrap();
+        assert_eq!(tagged.instruction, decompiler.lift(0x1000).unwrap());
+        assert_eq!(tagged.instruction.length, 5);
+        assert_eq!(tagged.instruction.bytes, code[..5]);
+        let ops: Vec<_> = tagged.ops().map(|(addr, op)| (addr, op.opcode)).collect();
+        assert_eq!(ops, [(0x1002, Opcode::Copy), (0x1000, Opcode::Branch)]);
+        assert!(tagged.has_delay_slot());
+        assert_eq!(tagged.delay_slot_ops().count(), 1);
+
+        let movi = decompiler.lift_tagged(0x1005).unwrap();
+        assert_eq!(movi.origins, [0x1005]);
+        assert_eq!(movi.instruction.bytes, code[5..]);
+        assert!(!movi.has_delay_slot());
+        // The previous delay slot doesn't stick
+        assert_eq!(decompiler.lift_tagged(0x1000).unwrap(), tagged);
+    }
+
+    #[test]
+    fn test_instruction_limits() {
+        let program = toy::program(0, &[]);
+        let decompiler = program.decompiler();
+        assert_eq!(decompiler.instruction_alignment(), 1);
+        assert_eq!(decompiler.max_instruction_length(), 16);
+        assert!(decompiler.has_delay_slots());
+        assert_eq!(decompiler.max_delay_slot_bytes(), 1);
+    }
+
+    #[test]
+    fn test_context_commits() {
+        let mut code = vec![0x06, 0x10];
+        code.resize(0x20, 0);
+        let program = toy::program(0, &code);
+        let decompiler = program.decompiler();
+        let commits = decompiler.context_commits(0).unwrap();
+        assert_eq!(commits.len(), 1);
+        assert_eq!(commits[0].variable, "mode");
+        assert_eq!(commits[0].value, 1);
+        assert_eq!(commits[0].address, 0x10);
+        assert!(commits[0].flow);
+        assert!(decompiler.context_commits(2).unwrap().is_empty());
+
+        // Only lifting applies them
+        assert_eq!(decompiler.disassemble_one(0x10).unwrap().0, "nop");
+        decompiler.lift(0).unwrap();
+        assert_eq!(decompiler.disassemble_one(0x0f).unwrap().0, "nop");
+        assert_eq!(decompiler.context_variable("mode", 0x10).unwrap(), 1);
+    }
+
+    #[test]
+    fn test_save_context() {
+        let mut program = toy::program(0x1000, &[0; 0x10]);
+        program
+            .set_context_range("mode", 0x1004, 0x1008, 1)
+            .unwrap();
+        let saved = program.decompiler().save_context();
+
+        program.set_context_everywhere("mode", 1).unwrap();
+        program.restore_context(&saved).unwrap();
+        let decompiler = program.decompiler();
+        assert_eq!(decompiler.context_variable("mode", 0x1000).unwrap(), 0);
+        assert_eq!(decompiler.context_variable("mode", 0x1004).unwrap(), 1);
+        assert_eq!(decompiler.context_variable("mode", 0x1008).unwrap(), 0);
+        assert_eq!(decompiler.disassemble_one(0x1008).unwrap().0, "nop");
+        assert_eq!(decompiler.save_context(), saved);
+
+        // A second program picks up the first one's context
+        let mut other = toy::program(0x1000, &[0; 0x10]);
+        other.restore_context(&saved).unwrap();
+        assert_eq!(other.decompiler().disassemble_one(0x1004).unwrap().0, "alt");
+        assert!(other.restore_context("<bogus/>").is_err());
+    }
+
+    #[test]
+    fn test_context_variables() {
+        let mut program = toy::program(0x1000, &[]);
+        let vars = program.decompiler().context_variables();
+        assert_eq!(vars.len(), 1);
+        assert_eq!(vars[0].name, "mode");
+        assert_eq!(vars[0].context_register, "contextreg");
+        assert_eq!((vars[0].start_bit, vars[0].width()), (0, 1));
+        assert_eq!(vars[0].default_value, 0);
+        assert!(vars[0].flow);
+
+        program.set_context_everywhere("mode", 1).unwrap();
+        assert_eq!(program.decompiler().context_variables()[0].default_value, 1);
+    }
+
+    #[test]
+    fn test_lift_in_space() {
+        // ret everywhere in flash, zeros (nop) elsewhere
+        struct FlashImage;
+        impl crate::LoadImage for FlashImage {
+            fn load_fill(&mut self, ptr: &mut [u8], addr: &ffi::Address) {
+                let space = unsafe { &*addr.getSpace() };
+                let byte = if space.getName() == "flash" { 0x03 } else { 0 };
+                ptr.fill(byte);
+            }
+        }
+        let mut program = crate::project::Program::new(toy::files().clone(), FlashImage).unwrap();
+        let flash = program.add_overlay_space("flash", "ram").unwrap();
+        let decompiler = program.decompiler();
+        let ret = decompiler.try_lift_in(flash, 0x10).unwrap();
+        assert_eq!((ret.address, ret.length), (0x10, 1));
+        assert_eq!(ret.ops.last().unwrap().opcode, Opcode::Return);
+        assert!(decompiler.try_lift(0x10).unwrap().ops.is_empty());
+        assert_eq!(decompiler.read_bytes_in(flash, 0, 2).unwrap(), [3, 3]);
+        assert_eq!(decompiler.read_bytes(0, 2).unwrap(), [0, 0]);
+
+        let err = decompiler.try_lift_in(999, 0).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "can't decode instruction at 0x0: No address space with index 999"
+        );
+        assert!(decompiler.read_bytes_in(-1, 0, 1).is_err());
+    }
+
+    #[test]
+    fn test_overlay_pcode() {
+        // jmp 0x20 ; ld r0, [r1]
+        let mut program = toy::program(0, &[0x0c, 0x20, 0x04, 0x01]);
+        let bank = program.add_overlay_space("bank1", "ram").unwrap();
+        let decompiler = program.decompiler();
+        let ram = decompiler.getDefaultCodeSpaceIndex();
+        let target = |space| {
+            let jmp = decompiler.try_lift_in(space, 0).unwrap();
+            assert_eq!(jmp.ops[0].opcode, Opcode::Branch);
+            jmp.ops[0].inputs[0].clone()
+        };
+        assert_eq!((target(bank).space, target(bank).offset), (bank, 0x20));
+        assert_eq!(target(ram).space, ram);
+
+        let ld = decompiler.try_lift_in(bank, 2).unwrap();
+        let load = ld.ops.iter().find(|op| op.opcode == Opcode::Load).unwrap();
+        assert_eq!(load.accessed_space(decompiler), Some(bank));
+        let register = decompiler
+            .space_hierarchy()
+            .by_name("register")
+            .unwrap()
+            .index;
+        assert_eq!(load.output.as_ref().unwrap().space, register);
+        let ld = decompiler.try_lift(2).unwrap();
+        let load = ld.ops.iter().find(|op| op.opcode == Opcode::Load).unwrap();
+        assert_eq!(load.accessed_space(decompiler), Some(ram));
+    }
+}
@@ -0,0 +1,148 @@
+//! Listings without pcode.
+//!
+//! A [`Program`](crate::project::Program) sets up everything lifting and decompiling take: the
+//! compiler spec, its stack space, the op behaviors evaluating pcode and a cache of lifted
+//! instructions. [`Disassembler`] only loads the `.sla` and the `.pspec` context defaults, and
+//! decodes instructions no further than their text and length, which is all a listing needs.
+
+use std::ops::Range;
+use std::pin::Pin;
+
+use cxx::UniquePtr;
+
+use crate::decompiler::AssemblyText;
+use crate::languages::{LanguageError, LanguageFiles};
+use crate::{ffi, LoadImage, RustAssemblyEmit, RustLoadImage, SleighError};
+
+impl ffi::Disassembler {
+    /// Apply the `<context_data>` defaults of a `.pspec` document, see
+    /// [`ffi::Decompiler::apply_processor_spec`]
+    pub fn apply_processor_spec(self: Pin<&mut Self>, pspec: &str) -> Result<(), cxx::Exception> {
+        cxx::let_cxx_string!(pspec = pspec);
+        let doc = ffi::parseDocument(&pspec)?;
+        self.applyProcessorSpec(ffi::getDocumentRoot(&doc))
+    }
+}
+
+/// Disassembles the instructions of a load image
+pub struct Disassembler {
+    // Declared first so it is dropped before the load image it points into.
+    disassembler: UniquePtr<ffi::Disassembler>,
+    _proxy: Box<RustLoadImage<'static>>,
+    image: *mut dyn LoadImage,
+}
+
+impl Disassembler {
+    /// Load `image` with the given language. Only the `.sla` and `.pspec` are read.
+    pub fn new<L: LoadImage + 'static>(
+        language: &LanguageFiles,
+        image: L,
+    ) -> Result<Self, LanguageError> {
+        let image: *mut dyn LoadImage = Box::into_raw(Box::new(image));
+        // As in `Program`, the image is only reached through the proxy until `Drop` frees it
+        let mut proxy = Box::new(RustLoadImage::from_internal(unsafe { &mut *image }));
+        match unsafe { language.new_disassembler(&mut *proxy) } {
+            Ok(disassembler) => Ok(Self {
+                disassembler,
+                _proxy: proxy,
+                image,
+            }),
+            Err(e) => {
+                drop(proxy);
+                drop(unsafe { Box::from_raw(image) });
+                Err(e)
+            }
+        }
+    }
+
+    /// The mnemonic and operands of the instruction at `addr`, and its length
+    pub fn disassemble(&self, addr: u64) -> Result<(String, String, usize), SleighError> {
+        let (mut mnemonic, mut operands) = (String::new(), String::new());
+        let length = self.disassemble_into(addr, &mut mnemonic, &mut operands)?;
+        Ok((mnemonic, operands, length))
+    }
+
+    /// Disassemble the instruction at `addr` into buffers the caller reuses, replacing their
+    /// contents. Returns the length of the instruction.
+    pub fn disassemble_into(
+        &self,
+        addr: u64,
+        mnemonic: &mut String,
+        operands: &mut String,
+    ) -> Result<usize, SleighError> {
+        mnemonic.clear();
+        operands.clear();
+        let mut text = AssemblyText(mnemonic, operands);
+        let mut emit = RustAssemblyEmit::from_internal(&mut text);
+        unsafe { self.disassembler.disassemble(&mut emit, addr) }.into_decode_result(addr)
+    }
+
+    /// Value of the context variable `name` at `addr`
+    pub fn context_variable(&self, name: &str, addr: u64) -> Result<u32, cxx::Exception> {
+        cxx::let_cxx_string!(name = name);
+        self.disassembler.getContextVariable(&name, addr)
+    }
+
+    /// Set the context variable `name` to `value` over `range`
+    pub fn set_context_range(
+        &mut self,
+        name: &str,
+        range: Range<u64>,
+        value: u32,
+    ) -> Result<(), cxx::Exception> {
+        cxx::let_cxx_string!(name = name);
+        self.disassembler
+            .pin_mut()
+            .setContextVariableRange(&name, range.start, range.end, value)
+    }
+
+    /// Set the context variable `name` to `value` at every address
+    pub fn set_context_everywhere(&mut self, name: &str, value: u32) -> Result<(), cxx::Exception> {
+        cxx::let_cxx_string!(name = name);
+        self.disassembler
+            .pin_mut()
+            .setContextVariableEverywhere(&name, value)
+    }
+}
+
+impl Drop for Disassembler {
+    fn drop(&mut self) {
+        self.disassembler = UniquePtr::null();
+        unsafe { drop(Box::from_raw(self.image)) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::ErrorKind;
+    use crate::toy;
+
+    #[test]
+    fn test_disassembler() {
+        // nop ; movi r0, 0x41 ; st [r1], r0 ; <invalid>
+        let code = [0x00, 0x01, 0x00, 0x41, 0x05, 0x10, 0xff];
+        let image = toy::ToyImage {
+            base: 0x1000,
+            code: code.to_vec(),
+        };
+        let mut disassembler = Disassembler::new(toy::files(), image).unwrap();
+        let program = toy::program(0x1000, &code);
+        for addr in [0x1000, 0x1001, 0x1004] {
+            assert_eq!(
+                disassembler.disassemble(addr).unwrap(),
+                program.decompiler().disassemble_one(addr).unwrap()
+            );
+        }
+        let err = disassembler.disassemble(0x1006).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Decode);
+
+        disassembler
+            .set_context_range("mode", 0x1000..0x1001, 1)
+            .unwrap();
+        assert_eq!(disassembler.context_variable("mode", 0x1000).unwrap(), 1);
+        assert_eq!(disassembler.context_variable("mode", 0x1001).unwrap(), 0);
+        assert_eq!(disassembler.disassemble(0x1000).unwrap().0, "alt");
+        assert!(disassembler.set_context_everywhere("nosuchvar", 1).is_err());
+    }
+}
@@ -0,0 +1,151 @@
+//! Address breakpoints.
+//!
+//! Breakpoints live in the engine's `BreakTableCallBack` and are checked right before an
+//! instruction starts. A hit stops the step and calls the breakpoint's handler, which can
+//! read and change the emulator's state before deciding whether the instruction runs, is
+//! skipped, or execution stops with [`EmulatorError::Breakpoint`]. Tracers and unpackers are
+//! built from these: log or patch at an address, then carry on.
+
+use std::collections::HashMap;
+
+use super::{Emulator, EmulatorError};
+
+/// What a breakpoint handler wants done with the instruction it stopped at
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BreakAction {
+    /// Run the instruction the emulator is at once the handler returns. If the handler moved
+    /// the program counter, a breakpoint at the new address is hit in turn.
+    Continue,
+    /// Move past the instruction the emulator is at without running it
+    Skip,
+    /// Fail the step with [`EmulatorError::Breakpoint`], leaving the instruction unexecuted
+    Stop,
+}
+
+pub(super) type BreakHandler = Box<dyn FnMut(&mut Emulator, u64) -> BreakAction>;
+
+/// Handlers by address. A slot is empty while its handler runs.
+pub(super) type BreakHandlers = HashMap<u64, Option<BreakHandler>>;
+
+impl Emulator {
+    /// Call `handler` with the address every time execution reaches the instruction at `addr`
+    /// in the default code space, replacing any handler it had
+    pub fn add_breakpoint<F>(&mut self, addr: u64, handler: F)
+    where
+        F: FnMut(&mut Emulator, u64) -> BreakAction + 'static,
+    {
+        self.breakpoints.insert(addr, Some(Box::new(handler)));
+        self.inner.pin_mut().addBreakpoint(addr);
+    }
+
+    /// Returns `false` if there was no breakpoint at `addr`
+    pub fn remove_breakpoint(&mut self, addr: u64) -> bool {
+        self.breakpoints.remove(&addr);
+        self.inner.pin_mut().removeBreakpoint(addr)
+    }
+
+    /// Hand the breakpoint that aborted the last step to its handler. Returns `None` if the
+    /// step didn't stop at one, `Ok(true)` if the step should run again and `Ok(false)` if
+    /// the instruction was skipped.
+    pub(super) fn dispatch_breakpoint(&mut self) -> Option<Result<bool, EmulatorError>> {
+        if !self.inner.pin_mut().takeBreak() {
+            return None;
+        }
+        let addr = self.pc();
+        let mut handler = match self.breakpoints.get_mut(&addr).and_then(Option::take) {
+            Some(handler) => handler,
+            None => return Some(Err(EmulatorError::Breakpoint(addr))),
+        };
+        let action = handler(self, addr);
+        // The handler may have removed its breakpoint or installed a replacement
+        if let Some(slot @ None) = self.breakpoints.get_mut(&addr) {
+            *slot = Some(handler);
+        }
+        Some(match action {
+            BreakAction::Continue => {
+                if self.pc() == addr {
+                    self.inner.pin_mut().passBreak();
+                }
+                Ok(true)
+            }
+            BreakAction::Skip => self
+                .inner
+                .pin_mut()
+                .skipInstruction()
+                .map(|()| false)
+                .map_err(Into::into),
+            BreakAction::Stop => Err(EmulatorError::Breakpoint(addr)),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::toy;
+
+    // movi r0, 1 ; movi r1, 2 ; add r0, r1 ; nop
+    const CODE: [u8; 9] = [0x01, 0x00, 0x01, 0x01, 0x10, 0x02, 0x02, 0x01, 0x00];
+
+    #[test]
+    fn test_breakpoints() {
+        let mut emu = toy::emulator(0x1000, &CODE);
+        let hits = Rc::new(RefCell::new(Vec::new()));
+        let log = hits.clone();
+        emu.add_breakpoint(0x1003, move |emu, addr| {
+            log.borrow_mut().push(addr);
+            // Replace the immediate the instruction would load
+            emu.set_register("r1", 40).unwrap();
+            BreakAction::Skip
+        });
+        emu.add_breakpoint(0x1006, |_, _| BreakAction::Stop);
+        emu.execute_instruction().unwrap();
+        emu.execute_instruction().unwrap();
+        assert_eq!(emu.pc(), 0x1006);
+        assert_eq!(emu.register("r1").unwrap(), 40);
+
+        // Stopping leaves the instruction unexecuted, every time it is reached
+        for _ in 0..2 {
+            assert!(matches!(
+                emu.execute_instruction(),
+                Err(EmulatorError::Breakpoint(0x1006))
+            ));
+            assert_eq!(emu.pc(), 0x1006);
+        }
+        assert!(emu.remove_breakpoint(0x1006));
+        assert!(!emu.remove_breakpoint(0x1006));
+        emu.execute_instruction().unwrap();
+        assert_eq!(emu.register("r0").unwrap(), 41);
+
+        emu.set_pc(0x1003);
+        emu.execute_op().unwrap();
+        assert_eq!(*hits.borrow(), [0x1003, 0x1003]);
+    }
+
+    #[test]
+    fn test_continue() {
+        let mut emu = toy::emulator(0x1000, &CODE);
+        // A one-shot breakpoint redirecting execution to another breakpoint
+        emu.add_breakpoint(0x1000, |emu, addr| {
+            emu.remove_breakpoint(addr);
+            emu.set_pc(0x1003);
+            BreakAction::Continue
+        });
+        emu.add_breakpoint(0x1003, |emu, _| {
+            emu.set_register("r0", 5).unwrap();
+            BreakAction::Continue
+        });
+        emu.execute_instruction().unwrap();
+        assert_eq!(emu.pc(), 0x1006);
+        assert_eq!(
+            (emu.register("r0").unwrap(), emu.register("r1").unwrap()),
+            (5, 2)
+        );
+        emu.set_pc(0x1000);
+        emu.execute_instruction().unwrap();
+        assert_eq!(emu.register("r0").unwrap(), 1);
+    }
+}
@@ -0,0 +1,202 @@
+//! Resource budgets.
+//!
+//! A budget bounds how much work the emulator does from the moment it is set: the number of
+//! instructions executed, the wall time spent and the guest memory written. Budgets are
+//! checked before every step, so an exhausted budget makes every following step fail with
+//! [`EmulatorError::Halted`] until it is replaced or cleared. This lets untrusted code be run
+//! in a service without a watchdog thread.
+
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use super::{Emulator, EmulatorError};
+
+/// Limits on the work done by an emulator. `None` means unlimited.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Budget {
+    pub instructions: Option<u64>,
+    pub time: Option<Duration>,
+    /// Bytes of guest memory that may be copied on write, counted in whole pages
+    pub memory_growth: Option<u64>,
+}
+
+impl Budget {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn instructions(mut self, limit: u64) -> Self {
+        self.instructions = Some(limit);
+        self
+    }
+
+    pub fn time(mut self, limit: Duration) -> Self {
+        self.time = Some(limit);
+        self
+    }
+
+    pub fn memory_growth(mut self, bytes: u64) -> Self {
+        self.memory_growth = Some(bytes);
+        self
+    }
+}
+
+/// The budget that stopped execution
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HaltReason {
+    Instructions,
+    Time,
+    MemoryGrowth,
+}
+
+impl fmt::Display for HaltReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HaltReason::Instructions => write!(f, "instruction budget exhausted"),
+            HaltReason::Time => write!(f, "time budget exhausted"),
+            HaltReason::MemoryGrowth => write!(f, "memory budget exhausted"),
+        }
+    }
+}
+
+/// Resources consumed since the budget was set
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BudgetUsage {
+    pub instructions: u64,
+    pub time: Duration,
+    pub memory_growth: u64,
+}
+
+#[derive(Debug)]
+pub(super) struct BudgetState {
+    budget: Budget,
+    start: Instant,
+    memory_base: u64,
+    instructions: u64,
+}
+
+impl Emulator {
+    /// Start enforcing `budget`, counting from now
+    pub fn set_budget(&mut self, budget: Budget) {
+        self.budget = Some(BudgetState {
+            budget,
+            start: Instant::now(),
+            memory_base: self.inner.getMemoryUsage(),
+            instructions: 0,
+        });
+    }
+
+    pub fn clear_budget(&mut self) {
+        self.budget = None;
+    }
+
+    pub fn budget(&self) -> Option<&Budget> {
+        self.budget.as_ref().map(|state| &state.budget)
+    }
+
+    /// What was consumed since [`set_budget`](Self::set_budget), or `None` without a budget
+    pub fn budget_usage(&self) -> Option<BudgetUsage> {
+        self.budget.as_ref().map(|state| BudgetUsage {
+            instructions: state.instructions,
+            time: state.start.elapsed(),
+            memory_growth: self
+                .inner
+                .getMemoryUsage()
+                .saturating_sub(state.memory_base),
+        })
+    }
+
+    /// Bytes of guest memory copied on write since the emulator was created
+    pub fn memory_usage(&self) -> u64 {
+        self.inner.getMemoryUsage()
+    }
+
+    pub(super) fn check_budget(&self) -> Result<(), EmulatorError> {
+        let state = match &self.budget {
+            Some(state) => state,
+            None => return Ok(()),
+        };
+        let budget = &state.budget;
+        if budget
+            .instructions
+            .is_some_and(|max| state.instructions >= max)
+        {
+            return Err(EmulatorError::Halted(HaltReason::Instructions));
+        }
+        if budget.time.is_some_and(|max| state.start.elapsed() >= max) {
+            return Err(EmulatorError::Halted(HaltReason::Time));
+        }
+        if let Some(max) = budget.memory_growth {
+            let growth = self
+                .inner
+                .getMemoryUsage()
+                .saturating_sub(state.memory_base);
+            if growth > max {
+                return Err(EmulatorError::Halted(HaltReason::MemoryGrowth));
+            }
+        }
+        Ok(())
+    }
+
+    /// Count an instruction that finished executing
+    pub(super) fn charge_instruction(&mut self) {
+        if let Some(state) = &mut self.budget {
+            state.instructions += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::toy;
+
+    #[test]
+    fn test_instruction_budget() {
+        let mut emu = toy::emulator(0x1000, &[0; 0x10]);
+        emu.set_budget(Budget::new().instructions(3));
+        for _ in 0..3 {
+            emu.execute_instruction().unwrap();
+        }
+        assert!(matches!(
+            emu.execute_instruction(),
+            Err(EmulatorError::Halted(HaltReason::Instructions))
+        ));
+        assert_eq!(emu.pc(), 0x1003);
+        assert_eq!(emu.budget_usage().unwrap().instructions, 3);
+
+        emu.clear_budget();
+        emu.execute_instruction().unwrap();
+
+        emu.set_budget(Budget::new().time(Duration::ZERO));
+        assert!(matches!(
+            emu.execute_op(),
+            Err(EmulatorError::Halted(HaltReason::Time))
+        ));
+    }
+
+    #[test]
+    fn test_memory_budget() {
+        // movi r1, 0x40 ; st [r1], r0 ; movi r1, 0x80 ; st [r1], r0 ; nop
+        let code = [
+            0x01, 0x10, 0x40, 0x05, 0x10, 0x01, 0x10, 0x80, 0x05, 0x10, 0x00,
+        ];
+        let mut emu = toy::emulator(0x1000, &code);
+        // Writing the page at 0 is enough to exceed a budget smaller than a page
+        emu.set_budget(Budget::new().memory_growth(0x100));
+        emu.execute_instruction().unwrap();
+        emu.execute_instruction().unwrap();
+        assert_eq!(emu.budget_usage().unwrap().memory_growth, 0x1000);
+        assert!(matches!(
+            emu.execute_instruction(),
+            Err(EmulatorError::Halted(HaltReason::MemoryGrowth))
+        ));
+
+        // Further writes to the same page don't grow memory
+        emu.set_budget(Budget::new().memory_growth(0));
+        for _ in 0..3 {
+            emu.execute_instruction().unwrap();
+        }
+        assert_eq!(emu.budget_usage().unwrap().memory_growth, 0);
+    }
+}
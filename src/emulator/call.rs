@@ -0,0 +1,159 @@
+//! Calling guest functions from Rust.
+//!
+//! The call is set up the way a caller following the compiler spec's default prototype
+//! would: arguments go where the prototype says, the return address points at a sentinel
+//! that is never mapped, and execution stops once control reaches it.
+
+use super::{Emulator, EmulatorError};
+use crate::cspec::Storage;
+
+/// Instructions executed by [`Emulator::call_function`] before giving up
+pub const DEFAULT_CALL_LIMIT: u64 = 10_000_000;
+
+/// The outcome of a guest function call
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReturnValue {
+    /// Contents of the first output location of the prototype
+    pub value: u64,
+    /// Number of instructions executed by the call
+    pub instructions: u64,
+}
+
+impl Emulator {
+    /// Call the function at `addr` with integer `args` and run it until it returns. The stack
+    /// must already be set up (see [`setup_stack`](Self::setup_stack)); the stack pointer is
+    /// restored once the call returns.
+    pub fn call_function(&mut self, addr: u64, args: &[u64]) -> Result<ReturnValue, EmulatorError> {
+        self.call_function_with_limit(addr, args, DEFAULT_CALL_LIMIT)
+    }
+
+    /// [`call_function`](Self::call_function), failing with
+    /// [`EmulatorError::InstructionLimit`] after `limit` instructions
+    pub fn call_function_with_limit(
+        &mut self,
+        addr: u64,
+        args: &[u64],
+        limit: u64,
+    ) -> Result<ReturnValue, EmulatorError> {
+        let ptr = self.pointer_size()?;
+        let cspec = self
+            .compiler_spec
+            .as_ref()
+            .ok_or(EmulatorError::NoCompilerSpec)?;
+        let return_address = cspec
+            .return_address
+            .clone()
+            .ok_or(EmulatorError::MissingAbiInfo("return address"))?;
+        let output = cspec
+            .default_prototype()
+            .ok_or(EmulatorError::MissingAbiInfo("default prototype"))?
+            .outputs
+            .first()
+            .map(|e| e.storage.clone())
+            .ok_or(EmulatorError::MissingAbiInfo("output location"))?;
+
+        let sp_name = self.stack_pointer_register()?.to_string();
+        let saved_sp = self.register(&sp_name)?;
+        let sentinel = return_sentinel(ptr);
+        match &return_address {
+            Storage::Register(name) => self.set_register(name, sentinel)?,
+            Storage::Memory { space, offset, .. } if space == "stack" => {
+                let sp = if self.stack_grows_negative() {
+                    saved_sp.wrapping_sub(ptr as u64)
+                } else {
+                    saved_sp.wrapping_add(ptr as u64)
+                };
+                self.set_register(&sp_name, sp)?;
+                self.write_uint(sp.wrapping_add(*offset as u64), sentinel, ptr as usize)?;
+            }
+            Storage::Memory { .. } => {
+                return Err(EmulatorError::MissingAbiInfo(
+                    "stack-relative return address",
+                ))
+            }
+        }
+        self.set_arguments(args)?;
+
+        self.set_pc(addr);
+        let mut instructions = 0;
+        while self.pc() != sentinel {
+            if instructions == limit {
+                return Err(EmulatorError::InstructionLimit(limit));
+            }
+            self.execute_instruction()?;
+            instructions += 1;
+        }
+
+        let value = match &output {
+            Storage::Register(name) => self.register(name)?,
+            Storage::Memory {
+                space,
+                offset,
+                size,
+            } if space == "stack" => {
+                let sp = self.register(&sp_name)?;
+                self.read_uint(
+                    sp.wrapping_add(*offset as u64),
+                    size.unwrap_or(ptr) as usize,
+                )?
+            }
+            Storage::Memory { .. } => return Err(EmulatorError::MissingAbiInfo("output location")),
+        };
+        self.set_register(&sp_name, saved_sp)?;
+        Ok(ReturnValue {
+            value,
+            instructions,
+        })
+    }
+}
+
+/// An address in the last page of a `ptr`-byte address space, where code is unlikely to live
+pub(super) fn return_sentinel(ptr: u32) -> u64 {
+    let mask = if ptr >= 8 {
+        u64::MAX
+    } else {
+        (1 << (ptr * 8)) - 1
+    };
+    mask & !0xfff
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::toy;
+
+    #[test]
+    fn test_return_sentinel() {
+        assert_eq!(return_sentinel(4), 0xffff_f000);
+        assert_eq!(return_sentinel(8), 0xffff_ffff_ffff_f000);
+    }
+
+    #[test]
+    fn test_call_function() {
+        // add r0, r1 ; movi r2, 5 ; add r0, r2 ; ret
+        let code = [0x02, 0x01, 0x01, 0x20, 0x05, 0x02, 0x02, 0x03];
+        let mut emu = toy::emulator(0x1000, &code);
+        let sp = emu.setup_stack(0x8000, 0x1000).unwrap();
+
+        let ret = emu.call_function(0x1000, &[2, 3]).unwrap();
+        assert_eq!(ret.value, 10);
+        assert_eq!(ret.instructions, 4);
+        assert_eq!(emu.register("sp").unwrap(), sp);
+
+        // Calls can be repeated from the same state
+        let ret = emu.call_function(0x1000, &[1, 1]).unwrap();
+        assert_eq!(ret.value, 7);
+    }
+
+    #[test]
+    fn test_call_limit() {
+        // movi r0, 0, followed by an endless run of nops
+        let code = [0x01, 0x00, 0x00];
+        let mut emu = toy::emulator(0x1000, &code);
+        emu.setup_stack(0x8000, 0x1000).unwrap();
+        assert!(matches!(
+            emu.call_function_with_limit(0x1000, &[], 100),
+            Err(EmulatorError::InstructionLimit(100))
+        ));
+    }
+}
@@ -0,0 +1,192 @@
+//! The emulated machine's environment: time, randomness and processor identification.
+//!
+//! User-defined ops (CALLOTHER) that read the environment, like x86's `rdtsc`, `rdrand` and
+//! `cpuid`, are handled by the emulator itself with values from an [`Environment`], unless a
+//! handler was set for them. The default environment is a [`DeterministicEnvironment`], so
+//! that runs are reproducible across machines; install another with
+//! [`Emulator::set_environment`].
+
+use std::collections::HashMap;
+
+use super::{call, Emulator, EmulatorError};
+use crate::ffi;
+
+/// Ops answered with [`Environment::timestamp`]
+const TIMESTAMP_OPS: &[&str] = &["rdtsc", "rdtscp"];
+/// Ops answered with [`Environment::random`]
+const RANDOM_OPS: &[&str] = &["rdrand", "rdseed"];
+
+/// Values observed by emulated code that depend on the machine it runs on
+pub trait Environment {
+    /// A timestamp counter, as read by `rdtsc`
+    fn timestamp(&mut self) -> u64;
+    /// A random number, as produced by `rdrand`
+    fn random(&mut self) -> u64;
+    /// Processor identification for a `cpuid` leaf and subleaf, as `[eax, ebx, ecx, edx]`
+    fn cpuid(&mut self, leaf: u32, subleaf: u32) -> [u32; 4];
+}
+
+/// An environment whose values only depend on its configuration and on the order of queries
+#[derive(Debug, Clone)]
+pub struct DeterministicEnvironment {
+    rng: u64,
+    clock: u64,
+    tick: u64,
+    cpuid: HashMap<(u32, u32), [u32; 4]>,
+}
+
+impl DeterministicEnvironment {
+    /// Random numbers are drawn from a generator seeded with `seed`; the clock starts at 0 and
+    /// advances by 1000 per read. Every `cpuid` leaf reads as zeros.
+    pub fn new(seed: u64) -> Self {
+        Self {
+            rng: seed,
+            clock: 0,
+            tick: 1000,
+            cpuid: HashMap::new(),
+        }
+    }
+
+    /// Start the clock at `start` and advance it by `tick` per read
+    pub fn with_clock(mut self, start: u64, tick: u64) -> Self {
+        self.clock = start;
+        self.tick = tick;
+        self
+    }
+
+    /// Answer `cpuid` leaf `leaf`, subleaf `subleaf` with `[eax, ebx, ecx, edx]`
+    pub fn set_cpuid(&mut self, leaf: u32, subleaf: u32, regs: [u32; 4]) {
+        self.cpuid.insert((leaf, subleaf), regs);
+    }
+}
+
+impl Default for DeterministicEnvironment {
+    fn default() -> Self {
+        Self::new(0)
+    }
+}
+
+impl Environment for DeterministicEnvironment {
+    fn timestamp(&mut self) -> u64 {
+        let now = self.clock;
+        self.clock = self.clock.wrapping_add(self.tick);
+        now
+    }
+
+    // SplitMix64
+    fn random(&mut self) -> u64 {
+        self.rng = self.rng.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.rng;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    fn cpuid(&mut self, leaf: u32, subleaf: u32) -> [u32; 4] {
+        self.cpuid
+            .get(&(leaf, subleaf))
+            .or_else(|| self.cpuid.get(&(leaf, 0)))
+            .copied()
+            .unwrap_or_default()
+    }
+}
+
+impl Emulator {
+    pub fn set_environment<E: Environment + 'static>(&mut self, environment: E) {
+        self.environment = Box::new(environment);
+    }
+
+    pub fn environment_mut(&mut self) -> &mut dyn Environment {
+        &mut *self.environment
+    }
+
+    /// Answer the ops that read the environment
+    pub(super) fn builtin_callother(
+        &mut self,
+        info: &ffi::CallOtherInfo,
+    ) -> Result<Option<u64>, EmulatorError> {
+        let name = info.name.as_str();
+        if TIMESTAMP_OPS.contains(&name) {
+            return Ok(Some(self.environment.timestamp()));
+        }
+        if RANDOM_OPS.contains(&name) {
+            return Ok(Some(self.environment.random()));
+        }
+        if name == "cpuid" || name.starts_with("cpuid_") {
+            return self.cpuid(info).map(Some);
+        }
+        Err(EmulatorError::UnhandledUserOp(info.name.clone()))
+    }
+
+    /// The x86 `cpuid` ops return a pointer to `eax`, `ebx`, `edx` and `ecx`. They are
+    /// written to a scratch area in the last page of memory, next to the return sentinel used
+    /// by [`call_function`](Self::call_function).
+    fn cpuid(&mut self, info: &ffi::CallOtherInfo) -> Result<u64, EmulatorError> {
+        let leaf = info.inputs.first().copied().unwrap_or(0) as u32;
+        let subleaf = self.register("ECX").unwrap_or(0) as u32;
+        let [eax, ebx, ecx, edx] = self.environment.cpuid(leaf, subleaf);
+        let size = info.output_size.clamp(1, 8);
+        let scratch = call::return_sentinel(size) + 0x800;
+        for (i, value) in [eax, ebx, edx, ecx].into_iter().enumerate() {
+            self.write_uint(scratch + 4 * i as u64, value as u64, 4)?;
+        }
+        Ok(scratch)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::toy;
+
+    #[test]
+    fn test_deterministic() {
+        let mut a = DeterministicEnvironment::new(7);
+        let mut b = DeterministicEnvironment::new(7);
+        let first: Vec<u64> = (0..4).map(|_| a.random()).collect();
+        assert_eq!(first, (0..4).map(|_| b.random()).collect::<Vec<_>>());
+        assert_ne!(first[0], first[1]);
+
+        let mut env = DeterministicEnvironment::new(0).with_clock(100, 10);
+        assert_eq!((env.timestamp(), env.timestamp()), (100, 110));
+    }
+
+    #[test]
+    fn test_environment_ops() {
+        // rnd r0 ; tsc r1 ; tsc r1 ; cpuid r2
+        let code = [0x07, 0x00, 0x08, 0x10, 0x08, 0x10, 0x09, 0x20];
+        let run = |env: DeterministicEnvironment| {
+            let mut emu = toy::emulator(0x1000, &code);
+            emu.set_register("r2", 4).unwrap();
+            emu.set_environment(env);
+            for _ in 0..4 {
+                emu.execute_instruction().unwrap();
+            }
+            ["r0", "r1", "r2"].map(|r| emu.register(r).unwrap())
+        };
+        let mut env = DeterministicEnvironment::new(1).with_clock(5000, 3);
+        env.set_cpuid(4, 0, [0x1234, 0, 0, 0]);
+        let regs = run(env.clone());
+        assert_eq!(
+            regs[0],
+            DeterministicEnvironment::new(1).random() & 0xffff_ffff
+        );
+        assert_eq!(regs[1], 5003);
+        assert_eq!(regs[2], 0x1234);
+        assert_eq!(run(env), regs);
+    }
+
+    #[test]
+    fn test_op_by_op() {
+        // The user op completes in the middle of an instruction stepped op by op
+        let mut emu = toy::emulator(0x1000, &[0x08, 0x00, 0x00]);
+        emu.set_environment(DeterministicEnvironment::new(0).with_clock(42, 1));
+        emu.set_history_limit(4);
+        while emu.pc() == 0x1000 {
+            emu.execute_op().unwrap();
+        }
+        assert_eq!(emu.register("r0").unwrap(), 42);
+        assert!(emu.step_back_instruction().unwrap());
+        assert_eq!(emu.register("r0").unwrap(), 0);
+    }
+}
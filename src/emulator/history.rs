@@ -0,0 +1,84 @@
+//! Reverse execution.
+//!
+//! When enabled, every executed pcode op records the bytes it is about to overwrite, in a
+//! buffer holding the most recent ops. Stepping back restores those bytes along with the
+//! program counter. Changes made through the emulator's API between steps (register or
+//! memory writes, [`Emulator::set_pc`]) are not recorded and survive stepping back.
+
+use super::Emulator;
+
+impl Emulator {
+    /// Keep undo information for up to `ops` executed pcode ops, dropping the oldest first.
+    /// `0` disables recording and clears the history.
+    pub fn set_history_limit(&mut self, ops: usize) {
+        self.inner.pin_mut().setHistoryLimit(ops)
+    }
+
+    /// Number of pcode ops that can currently be undone
+    pub fn history_len(&self) -> usize {
+        self.inner.getHistoryLength()
+    }
+
+    /// Undo the last executed pcode op. Returns `false` if there is nothing to undo.
+    pub fn step_back_op(&mut self) -> Result<bool, cxx::Exception> {
+        self.inner.pin_mut().stepBackOp()
+    }
+
+    /// Undo pcode ops back to the start of the last executed instruction, or of the current
+    /// one if it was only partly executed. Returns `false` if there is nothing to undo.
+    pub fn step_back_instruction(&mut self) -> Result<bool, cxx::Exception> {
+        self.inner.pin_mut().stepBackInstruction()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::toy;
+
+    #[test]
+    fn test_step_back() {
+        // movi r0, 7 ; movi r1, 0x40 ; st [r1], r0 ; add r0, r0
+        let code = [0x01, 0x00, 0x07, 0x01, 0x10, 0x40, 0x05, 0x10, 0x02, 0x00];
+        let mut emu = toy::emulator(0x1000, &code);
+        emu.set_history_limit(16);
+        for _ in 0..4 {
+            emu.execute_instruction().unwrap();
+        }
+        assert_eq!(emu.register("r0").unwrap(), 14);
+        assert_eq!(emu.read_uint(0x40, 4).unwrap(), 7);
+
+        assert!(emu.step_back_instruction().unwrap());
+        assert_eq!(emu.pc(), 0x1008);
+        assert_eq!(emu.register("r0").unwrap(), 7);
+
+        assert!(emu.step_back_instruction().unwrap());
+        assert_eq!(emu.pc(), 0x1006);
+        assert_eq!(emu.read_uint(0x40, 4).unwrap(), 0);
+
+        // Replaying gives the same result
+        emu.execute_instruction().unwrap();
+        emu.execute_instruction().unwrap();
+        assert_eq!(emu.register("r0").unwrap(), 14);
+        assert_eq!(emu.read_uint(0x40, 4).unwrap(), 7);
+    }
+
+    #[test]
+    fn test_history_limit() {
+        let code = [0x01, 0x00, 0x01, 0x01, 0x00, 0x02, 0x01, 0x00, 0x03];
+        let mut emu = toy::emulator(0x1000, &code);
+        emu.set_history_limit(2);
+        for _ in 0..3 {
+            emu.execute_instruction().unwrap();
+        }
+        assert_eq!(emu.history_len(), 2);
+        assert!(emu.step_back_op().unwrap());
+        assert!(emu.step_back_op().unwrap());
+        assert!(!emu.step_back_op().unwrap());
+        assert_eq!(emu.pc(), 0x1003);
+        assert_eq!(emu.register("r0").unwrap(), 1);
+
+        emu.set_history_limit(0);
+        emu.execute_instruction().unwrap();
+        assert_eq!(emu.history_len(), 0);
+    }
+}
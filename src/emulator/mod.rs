@@ -0,0 +1,381 @@
+//! A pcode emulator built on the decompiler's `MemoryState` and `EmulateMemory`.
+//!
+//! Instructions are translated with a dedicated SLEIGH instance whose bytes come from the
+//! emulated memory, so code written at run time is executed as written: writes to memory
+//! holding already decoded instructions, by the guest or through [`Emulator::write_memory`],
+//! drop the translator's instruction cache. Memory in processor
+//! address spaces is backed by the load image and copied on write; registers and temporaries
+//! start out zeroed.
+//!
+//! [`PcodeCacheEmulator`] binds the decompiler's own `EmulatePcodeCache` instead, for callers
+//! who want the vendored engine without the additions made here.
+
+use std::fmt;
+use std::pin::Pin;
+
+use cxx::UniquePtr;
+
+use crate::cspec::CompilerSpec;
+use crate::languages::{LanguageError, LanguageFiles};
+use crate::{ffi, LoadImage, RustLoadImage};
+
+mod breakpoints;
+mod budget;
+mod call;
+mod environment;
+mod history;
+mod pcode_cache;
+mod permissions;
+mod registers;
+mod run;
+mod setup;
+mod state;
+mod tracing;
+mod user_ops;
+
+pub use breakpoints::BreakAction;
+pub use budget::{Budget, BudgetUsage, HaltReason};
+pub use call::{ReturnValue, DEFAULT_CALL_LIMIT};
+pub use environment::{DeterministicEnvironment, Environment};
+pub use pcode_cache::PcodeCacheEmulator;
+pub use permissions::{Access, FaultAction, MemoryFault};
+pub use registers::{RegisterChange, RegisterDiff, RegisterSnapshot, RegisterValue};
+pub use run::StopReason;
+pub use setup::{GuestHeap, MemoryRegion};
+pub use state::StateSnapshot;
+pub use tracing::MemoryAccess;
+pub use user_ops::UserOpCall;
+
+#[derive(Debug)]
+pub enum EmulatorError {
+    /// The decompiler raised an error
+    Engine(String),
+    /// The operation needs a compiler spec and none was loaded
+    NoCompilerSpec,
+    /// The compiler spec lacks the named piece of information
+    MissingAbiInfo(&'static str),
+    /// A guest heap allocation didn't fit
+    OutOfMemory { requested: u64 },
+    /// A region to map runs past the end of the address space, or is too small for its use
+    BadRegion { start: u64, size: u64 },
+    /// Execution didn't finish within the given number of instructions
+    InstructionLimit(u64),
+    /// A memory access was refused and no fault handler let it through
+    MemoryFault(MemoryFault),
+    /// The emulator's budget is exhausted
+    Halted(HaltReason),
+    /// Execution reached a user-defined op nothing handles
+    UnhandledUserOp(String),
+    /// A breakpoint handler stopped execution at this address
+    Breakpoint(u64),
+    /// The instruction at this address has no pcode in the spec
+    Unimplemented(u64),
+    /// The bytes at this address don't decode to an instruction
+    InvalidInstruction(u64),
+}
+
+impl fmt::Display for EmulatorError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EmulatorError::Engine(msg) => write!(f, "emulation error: {}", msg),
+            EmulatorError::NoCompilerSpec => write!(f, "no compiler spec loaded"),
+            EmulatorError::MissingAbiInfo(what) => write!(f, "compiler spec has no {}", what),
+            EmulatorError::OutOfMemory { requested } => {
+                write!(f, "guest heap can't fit {:#x} bytes", requested)
+            }
+            EmulatorError::BadRegion { start, size } => {
+                write!(f, "can't map {:#x} bytes at {:#x}", size, start)
+            }
+            EmulatorError::InstructionLimit(limit) => {
+                write!(f, "instruction limit of {} reached", limit)
+            }
+            EmulatorError::MemoryFault(fault) => write!(f, "memory fault: {}", fault),
+            EmulatorError::Halted(reason) => write!(f, "emulation halted: {}", reason),
+            EmulatorError::UnhandledUserOp(name) => write!(f, "unhandled user op {}", name),
+            EmulatorError::Breakpoint(addr) => write!(f, "stopped at breakpoint {:#x}", addr),
+            EmulatorError::Unimplemented(addr) => {
+                write!(f, "instruction at {:#x} is not implemented", addr)
+            }
+            EmulatorError::InvalidInstruction(addr) => {
+                write!(f, "invalid instruction at {:#x}", addr)
+            }
+        }
+    }
+}
+
+impl std::error::Error for EmulatorError {}
+
+impl From<cxx::Exception> for EmulatorError {
+    fn from(e: cxx::Exception) -> Self {
+        EmulatorError::Engine(e.what().to_string())
+    }
+}
+
+pub struct Emulator {
+    // Declared first so it is dropped before the load image it points into.
+    inner: UniquePtr<ffi::PcodeEmulator>,
+    _proxy: Box<RustLoadImage<'static>>,
+    image: *mut dyn LoadImage,
+    /// Registers not contained in a larger register, used for snapshots
+    base_registers: Vec<ffi::RegisterInfo>,
+    compiler_spec: Option<CompilerSpec>,
+    regions: Vec<MemoryRegion>,
+    heap: Option<GuestHeap>,
+    fault_handler: Option<permissions::FaultHandler>,
+    breakpoints: breakpoints::BreakHandlers,
+    user_ops: user_ops::UserOpHandlers,
+    memory_hook: Option<tracing::MemoryHook>,
+    budget: Option<budget::BudgetState>,
+    environment: Box<dyn Environment>,
+}
+
+impl Emulator {
+    /// Create an emulator for `language` running code from `image`. The `.pspec` context
+    /// defaults are applied like they are for a decompiler, and the `.cspec` (if any) is
+    /// used for stack and argument setup.
+    pub fn new<L: LoadImage + 'static>(
+        language: &LanguageFiles,
+        image: L,
+    ) -> Result<Self, LanguageError> {
+        let sla = std::fs::read_to_string(&language.sla)
+            .map_err(|e| LanguageError::Io(language.sla.clone(), e))?;
+        let pspec = std::fs::read_to_string(&language.pspec)
+            .map_err(|e| LanguageError::Io(language.pspec.clone(), e))?;
+        let mut emulator = Self::from_spec(&sla, image)
+            .map_err(|e| LanguageError::Spec(language.sla.clone(), e.what().to_string()))?;
+        cxx::let_cxx_string!(pspec = pspec);
+        ffi::parseDocument(&pspec)
+            .and_then(|doc| {
+                emulator
+                    .inner
+                    .pin_mut()
+                    .applyProcessorSpec(ffi::getDocumentRoot(&doc))
+            })
+            .map_err(|e| LanguageError::Spec(language.pspec.clone(), e.what().to_string()))?;
+        if let Some(cspec) = &language.cspec {
+            emulator.compiler_spec = Some(CompilerSpec::load(cspec)?);
+        }
+        Ok(emulator)
+    }
+
+    /// Create an emulator from the contents of a `.sla` file. If `image` has segments, their
+    /// permissions are enforced.
+    pub fn from_spec<L: LoadImage + 'static>(sla: &str, image: L) -> Result<Self, cxx::Exception> {
+        cxx::let_cxx_string!(sla = sla);
+        let segments = image.segments();
+        let spec = ffi::newDocumentStorage(&sla)?;
+        let image: *mut dyn LoadImage = Box::into_raw(Box::new(image));
+        // See `Program::new`: the image is freed in `Drop`, after the emulator.
+        let mut proxy = Box::new(RustLoadImage::from_internal(unsafe { &mut *image }));
+        let inner = match unsafe { ffi::newPcodeEmulator(&mut *proxy, spec) } {
+            Ok(inner) => inner,
+            Err(e) => {
+                drop(proxy);
+                drop(unsafe { Box::from_raw(image) });
+                return Err(e);
+            }
+        };
+        let base_registers = registers::base_registers(inner.getRegisterList());
+        let mut emulator = Self {
+            inner,
+            _proxy: proxy,
+            image,
+            base_registers,
+            compiler_spec: None,
+            regions: Vec::new(),
+            heap: None,
+            fault_handler: None,
+            breakpoints: Default::default(),
+            user_ops: Default::default(),
+            memory_hook: None,
+            budget: None,
+            environment: Box::new(DeterministicEnvironment::default()),
+        };
+        for segment in &segments {
+            emulator.set_permissions(segment.start, segment.size, segment.permissions)?;
+        }
+        emulator.set_enforce_permissions(!segments.is_empty());
+        Ok(emulator)
+    }
+
+    pub fn compiler_spec(&self) -> Option<&CompilerSpec> {
+        self.compiler_spec.as_ref()
+    }
+
+    /// Use `spec` for stack and argument setup
+    pub fn set_compiler_spec(&mut self, spec: CompilerSpec) {
+        self.compiler_spec = Some(spec);
+    }
+
+    pub fn raw(&self) -> &ffi::PcodeEmulator {
+        &self.inner
+    }
+
+    pub fn raw_mut(&mut self) -> Pin<&mut ffi::PcodeEmulator> {
+        self.inner.pin_mut()
+    }
+
+    /// Address of the instruction being executed
+    pub fn pc(&self) -> u64 {
+        self.inner.getPc()
+    }
+
+    /// Continue execution at `addr` in the default code space
+    pub fn set_pc(&mut self, addr: u64) {
+        self.inner.pin_mut().setPc(addr)
+    }
+
+    /// Execute the rest of the current machine instruction
+    pub fn execute_instruction(&mut self) -> Result<(), EmulatorError> {
+        self.check_budget()?;
+        self.advance(true)?;
+        self.charge_instruction();
+        Ok(())
+    }
+
+    /// Execute a single pcode op
+    pub fn execute_op(&mut self) -> Result<(), EmulatorError> {
+        self.check_budget()?;
+        self.advance(false)?;
+        if self.inner.isInstructionStart() {
+            self.charge_instruction();
+        }
+        Ok(())
+    }
+
+    /// Execute an op or the rest of an instruction, then trace the memory it accessed
+    fn advance(&mut self, whole_instruction: bool) -> Result<(), EmulatorError> {
+        let result = self.dispatch_step(whole_instruction);
+        self.dispatch_accesses();
+        result
+    }
+
+    /// Execute an op or the rest of an instruction, dispatching breakpoints, memory faults and
+    /// user-defined ops to their handlers
+    fn dispatch_step(&mut self, whole_instruction: bool) -> Result<(), EmulatorError> {
+        loop {
+            let result = if whole_instruction {
+                self.inner.pin_mut().stepInstruction()
+            } else {
+                self.inner.pin_mut().stepOp()
+            };
+            let err = match result {
+                Ok(()) => return Ok(()),
+                Err(e) => e,
+            };
+            if let Some(result) = self.dispatch_breakpoint() {
+                if result? {
+                    continue;
+                }
+                return Ok(());
+            }
+            if let Some(result) = self.dispatch_fault() {
+                result?;
+                continue;
+            }
+            if let Some(result) = self.dispatch_callother() {
+                result?;
+                if whole_instruction && !self.inner.isInstructionStart() {
+                    continue;
+                }
+                return Ok(());
+            }
+            return Err(match self.inner.pin_mut().takeDecodeFailure() {
+                1 => EmulatorError::Unimplemented(self.pc()),
+                2 => EmulatorError::InvalidInstruction(self.pc()),
+                _ => err.into(),
+            });
+        }
+    }
+
+    pub fn register(&self, name: &str) -> Result<u64, cxx::Exception> {
+        cxx::let_cxx_string!(name = name);
+        self.inner.getRegisterValue(&name)
+    }
+
+    pub fn set_register(&mut self, name: &str, value: u64) -> Result<(), cxx::Exception> {
+        cxx::let_cxx_string!(name = name);
+        self.inner.pin_mut().setRegisterValue(&name, value)
+    }
+
+    /// Read from the default data space
+    pub fn read_memory(&self, addr: u64, buf: &mut [u8]) -> Result<(), cxx::Exception> {
+        self.inner
+            .readSpace(self.inner.getDefaultDataSpaceIndex(), addr, buf)
+    }
+
+    /// Write to the default data space
+    pub fn write_memory(&mut self, addr: u64, buf: &[u8]) -> Result<(), cxx::Exception> {
+        let space = self.inner.getDefaultDataSpaceIndex();
+        self.inner.pin_mut().writeSpace(space, addr, buf)
+    }
+
+    /// Number of times writes to already decoded code made the emulator drop its decoded
+    /// instructions
+    pub fn code_invalidations(&self) -> u64 {
+        self.inner.getCodeInvalidations()
+    }
+
+    /// Every register defined by the spec, including aliases like `EAX` inside `RAX`
+    pub fn registers(&self) -> Vec<ffi::RegisterInfo> {
+        self.inner.getRegisterList()
+    }
+
+    /// Capture the value of every register that isn't part of a larger one
+    pub fn register_snapshot(&self) -> Result<RegisterSnapshot, cxx::Exception> {
+        RegisterSnapshot::capture(&self.inner, &self.base_registers)
+    }
+}
+
+impl Drop for Emulator {
+    fn drop(&mut self) {
+        self.inner = UniquePtr::null();
+        unsafe { drop(Box::from_raw(self.image)) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Emulator;
+    use crate::image::SegmentedImage;
+    use crate::toy;
+
+    #[test]
+    fn test_bad_spec() {
+        for sla in ["garbage <<", "<sleigh version=\"4\"/>"] {
+            assert!(Emulator::from_spec(sla, SegmentedImage::new()).is_err());
+        }
+    }
+
+    #[test]
+    fn test_self_modifying_code() {
+        // 0x1000: movi r0, 1 ; ret   (called twice)
+        // The patch rewrites the immediate at 0x1002 between the calls.
+        let mut emu = toy::emulator(0x1000, &[0x01, 0x00, 0x01, 0x03]);
+        emu.setup_stack(0x8000, 0x1000).unwrap();
+        assert_eq!(emu.call_function(0x1000, &[]).unwrap().value, 1);
+        assert_eq!(emu.code_invalidations(), 0);
+
+        emu.write_memory(0x1002, &[0x2a]).unwrap();
+        assert_eq!(emu.call_function(0x1000, &[]).unwrap().value, 0x2a);
+        assert_eq!(emu.code_invalidations(), 1);
+
+        // A guest store patching the immediate of `movi r0, 1` at 0x10 once it was run:
+        // movi r1, 0x12 ; movi r2, 7 ; st [r1], r2
+        let mut code = vec![0x01, 0x10, 0x12, 0x01, 0x20, 0x07, 0x05, 0x12];
+        code.resize(0x10, 0);
+        code.extend([0x01, 0x00, 0x01]);
+        let mut emu = toy::emulator(0, &code);
+        emu.set_pc(0x10);
+        emu.execute_instruction().unwrap();
+        assert_eq!(emu.register("r0").unwrap(), 1);
+        emu.set_pc(0);
+        for _ in 0..3 {
+            emu.execute_instruction().unwrap();
+        }
+        assert_eq!(emu.code_invalidations(), 1);
+        emu.set_pc(0x10);
+        emu.execute_instruction().unwrap();
+        assert_eq!(emu.register("r0").unwrap(), 7);
+    }
+}
@@ -0,0 +1,168 @@
+//! The decompiler's own `EmulatePcodeCache`, bound as is.
+//!
+//! Unlike [`Emulator`](super::Emulator), which has its own translator and tracks history,
+//! permissions and budgets, this is a thin layer over the vendored engine: it translates with
+//! the [`ffi::Decompiler`] it is created from, reads unwritten memory from that decompiler's
+//! load image and keeps writes in its own `MemoryState`. Address breakpoints come from a
+//! `BreakTable` and halt execution before the instruction runs. User-defined ops are not
+//! hooked and fail the step.
+
+use std::marker::PhantomData;
+
+use cxx::UniquePtr;
+
+use crate::ffi;
+
+pub struct PcodeCacheEmulator<'a> {
+    inner: UniquePtr<ffi::PcodeCacheEmulator>,
+    _translator: PhantomData<&'a ffi::Decompiler>,
+}
+
+impl<'a> PcodeCacheEmulator<'a> {
+    /// Create an emulator translating with `translator`. Execution can only start once an
+    /// address is set with [`set_execute_address`](Self::set_execute_address).
+    pub fn new(translator: &'a ffi::Decompiler) -> Self {
+        Self {
+            inner: unsafe { ffi::newPcodeCacheEmulator(translator) },
+            _translator: PhantomData,
+        }
+    }
+
+    pub fn raw(&self) -> &ffi::PcodeCacheEmulator {
+        &self.inner
+    }
+
+    /// Continue execution at `addr` in the default code space. This is also needed to resume
+    /// after a step failed.
+    pub fn set_execute_address(&mut self, addr: u64) -> Result<(), cxx::Exception> {
+        self.inner.pin_mut().setExecuteAddress(addr)
+    }
+
+    /// Address of the instruction being executed
+    pub fn execute_address(&self) -> Result<u64, cxx::Exception> {
+        self.inner.getExecuteAddress()
+    }
+
+    /// Execute a single pcode op
+    pub fn execute_op(&mut self) -> Result<(), cxx::Exception> {
+        self.inner.pin_mut().executeCurrentOp()
+    }
+
+    /// Execute the rest of the current machine instruction. If it starts at a breakpoint, the
+    /// emulator halts instead and nothing is executed.
+    pub fn execute_instruction(&mut self) -> Result<(), cxx::Exception> {
+        self.inner.pin_mut().executeInstruction()
+    }
+
+    /// Execute up to `limit` instructions, stopping early at a breakpoint. Returns the number
+    /// of instructions executed.
+    pub fn run(&mut self, limit: u64) -> Result<u64, cxx::Exception> {
+        self.inner.pin_mut().setHalt(false);
+        for count in 0..limit {
+            self.execute_instruction()?;
+            if self.inner.getHalt() {
+                return Ok(count);
+            }
+        }
+        Ok(limit)
+    }
+
+    pub fn is_halted(&self) -> bool {
+        self.inner.getHalt()
+    }
+
+    /// Halt before executing the instruction at `addr` in the default code space
+    pub fn add_breakpoint(&mut self, addr: u64) {
+        self.inner.pin_mut().addAddressBreak(addr)
+    }
+
+    /// Whether the next op is the first of its instruction
+    pub fn is_instruction_start(&self) -> bool {
+        self.inner.isInstructionStart()
+    }
+
+    /// Index of the next op within the current instruction, and the number of ops in it
+    pub fn op_position(&self) -> (usize, usize) {
+        (
+            self.inner.getCurrentOpIndex() as usize,
+            self.inner.numCurrentOps() as usize,
+        )
+    }
+
+    pub fn register(&self, name: &str) -> Result<u64, cxx::Exception> {
+        cxx::let_cxx_string!(name = name);
+        self.inner.getRegisterValue(&name)
+    }
+
+    pub fn set_register(&mut self, name: &str, value: u64) -> Result<(), cxx::Exception> {
+        cxx::let_cxx_string!(name = name);
+        self.inner.pin_mut().setRegisterValue(&name, value)
+    }
+
+    /// Read a value of `size` bytes, at most 8, from the space with index `space`
+    pub fn value(&self, space: i32, offset: u64, size: u32) -> Result<u64, cxx::Exception> {
+        self.inner.getValue(space, offset, size as i32)
+    }
+
+    pub fn set_value(
+        &mut self,
+        space: i32,
+        offset: u64,
+        size: u32,
+        value: u64,
+    ) -> Result<(), cxx::Exception> {
+        self.inner
+            .pin_mut()
+            .setValue(space, offset, size as i32, value)
+    }
+
+    pub fn read(&self, space: i32, offset: u64, buf: &mut [u8]) -> Result<(), cxx::Exception> {
+        self.inner.getChunk(space, offset, buf)
+    }
+
+    pub fn write(&mut self, space: i32, offset: u64, buf: &[u8]) -> Result<(), cxx::Exception> {
+        self.inner.pin_mut().setChunk(space, offset, buf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::toy;
+
+    #[test]
+    fn test_pcode_cache() {
+        // movi r0, 5 ; movi r1, 0x40 ; st [r1], r0 ; add r0, r0 ; nop
+        let code = [
+            0x01, 0x00, 0x05, 0x01, 0x10, 0x40, 0x05, 0x10, 0x02, 0x00, 0x00,
+        ];
+        let program = toy::program(0x1000, &code);
+        let decompiler = program.decompiler();
+        let ram = decompiler.space_hierarchy().by_name("ram").unwrap().index;
+
+        let mut emu = PcodeCacheEmulator::new(decompiler);
+        assert!(emu.execute_op().is_err());
+        emu.set_execute_address(0x1000).unwrap();
+        emu.add_breakpoint(0x1008);
+        assert_eq!(emu.run(10).unwrap(), 3);
+        assert!(emu.is_halted());
+        assert_eq!(emu.execute_address().unwrap(), 0x1008);
+        assert_eq!(emu.value(ram, 0x40, 4).unwrap(), 5);
+
+        // Ops can be stepped one by one, bypassing breakpoints
+        assert_eq!(emu.op_position(), (0, 1));
+        emu.execute_op().unwrap();
+        assert!(emu.is_instruction_start());
+        assert_eq!(emu.register("r0").unwrap(), 10);
+
+        // Unwritten memory reads from the load image, writes stay in the emulator
+        let mut buf = [0; 3];
+        emu.read(ram, 0x1000, &mut buf).unwrap();
+        assert_eq!(buf, [0x01, 0x00, 0x05]);
+        emu.write(ram, 0x1000, &[0x03]).unwrap();
+        emu.read(ram, 0x1000, &mut buf[..1]).unwrap();
+        assert_eq!(buf[0], 0x03);
+        assert!(emu.value(-1, 0, 4).is_err());
+        assert_eq!(decompiler.disassemble_one(0x1000).unwrap().0, "movi");
+    }
+}
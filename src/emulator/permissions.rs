@@ -0,0 +1,318 @@
+//! Memory permission enforcement.
+//!
+//! Once enforcement is on, loads, stores and instruction fetches in the default address
+//! space are checked against the permissions given to each range; addresses outside every
+//! range are unmapped. A refused access raises a [`MemoryFault`], which is handed to the
+//! fault handler if one is installed and otherwise ends the step with
+//! [`EmulatorError::MemoryFault`].
+
+use std::fmt;
+
+use super::{Emulator, EmulatorError};
+use crate::ffi;
+use crate::image::Permissions;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+    Execute,
+}
+
+impl Access {
+    fn from_flags(flags: u32) -> Option<Self> {
+        match flags {
+            1 => Some(Access::Read),
+            2 => Some(Access::Write),
+            4 => Some(Access::Execute),
+            _ => None,
+        }
+    }
+}
+
+/// An access refused by the permission checks
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MemoryFault {
+    pub access: Access,
+    /// First refused address
+    pub address: u64,
+    /// Bytes of the access from `address` on
+    pub size: u32,
+    /// Address of the faulting instruction
+    pub pc: u64,
+    /// Whether `address` lies in a range with insufficient rights rather than in no range
+    pub mapped: bool,
+}
+
+impl MemoryFault {
+    fn from_info(info: &ffi::MemoryFaultInfo) -> Option<Self> {
+        Some(Self {
+            access: Access::from_flags(info.access)?,
+            address: info.offset,
+            size: info.size,
+            pc: info.pc,
+            mapped: info.mapped,
+        })
+    }
+}
+
+impl fmt::Display for MemoryFault {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let access = match self.access {
+            Access::Read => "read",
+            Access::Write => "write",
+            Access::Execute => "execute",
+        };
+        let kind = if self.mapped { "denied" } else { "unmapped" };
+        write!(
+            f,
+            "{} {} access at {:#x} (pc {:#x})",
+            kind, access, self.address, self.pc
+        )
+    }
+}
+
+/// What a fault handler wants done with the faulting step
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FaultAction {
+    /// Fail the step with [`EmulatorError::MemoryFault`]
+    Stop,
+    /// Run the step again, e.g. after the handler changed permissions or mapped memory
+    Retry,
+    /// Let the faulting access through once and continue the step
+    Ignore,
+}
+
+pub(super) type FaultHandler = Box<dyn FnMut(&mut Emulator, &MemoryFault) -> FaultAction>;
+
+impl Emulator {
+    /// Give `[start, start + size)` of the default address space the rights `permissions`,
+    /// replacing whatever rights overlapping ranges had
+    pub fn set_permissions(
+        &mut self,
+        start: u64,
+        size: u64,
+        permissions: Permissions,
+    ) -> Result<(), cxx::Exception> {
+        let data = self.inner.getDefaultDataSpaceIndex();
+        let code = self.inner.getDefaultCodeSpaceIndex();
+        self.inner
+            .pin_mut()
+            .setPermissions(data, start, size, permissions.bits())?;
+        if code != data {
+            self.inner
+                .pin_mut()
+                .setPermissions(code, start, size, permissions.bits())?;
+        }
+        Ok(())
+    }
+
+    /// Turn permission checks on or off. Emulators created from an image with segments
+    /// start with checks on.
+    pub fn set_enforce_permissions(&mut self, enforce: bool) {
+        self.inner.pin_mut().setEnforcePermissions(enforce)
+    }
+
+    /// Call `handler` on every memory fault. The handler may change memory, registers and
+    /// permissions before deciding how to go on.
+    pub fn set_fault_handler<F>(&mut self, handler: F)
+    where
+        F: FnMut(&mut Emulator, &MemoryFault) -> FaultAction + 'static,
+    {
+        self.fault_handler = Some(Box::new(handler));
+    }
+
+    pub fn clear_fault_handler(&mut self) {
+        self.fault_handler = None;
+    }
+
+    /// Hand the memory fault that aborted the last step to the fault handler. Returns `None`
+    /// if the step didn't fault, `Ok` if it should run again.
+    pub(super) fn dispatch_fault(&mut self) -> Option<Result<(), EmulatorError>> {
+        let info = self.inner.pin_mut().takeFault();
+        let fault = MemoryFault::from_info(&info)?;
+        let mut handler = match self.fault_handler.take() {
+            Some(handler) => handler,
+            None => return Some(Err(EmulatorError::MemoryFault(fault))),
+        };
+        let action = handler(self, &fault);
+        // The handler may have installed a replacement for itself
+        if self.fault_handler.is_none() {
+            self.fault_handler = Some(handler);
+        }
+        Some(match action {
+            FaultAction::Stop => Err(EmulatorError::MemoryFault(fault)),
+            FaultAction::Retry => Ok(()),
+            FaultAction::Ignore => {
+                self.inner.pin_mut().ignoreFault();
+                Ok(())
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::compile::compile_slaspec;
+    use crate::emulator::Emulator;
+    use crate::image::SegmentedImage;
+    use crate::languages::LanguageFiles;
+    use crate::toy;
+
+    // movi r1, 0x40 ; st [r1], r0 ; ld r2, [r1]
+    const CODE: [u8; 7] = [0x01, 0x10, 0x40, 0x05, 0x10, 0x04, 0x21];
+
+    fn segmented(data: Permissions) -> Emulator {
+        let mut image = SegmentedImage::new();
+        image
+            .add_segment(".data", 0x40, vec![0; 0x10], data)
+            .add_segment(".text", 0x1000, CODE.to_vec(), Permissions::READ_EXECUTE);
+        let mut emu = Emulator::new(toy::files(), image).unwrap();
+        emu.set_pc(0x1000);
+        emu
+    }
+
+    #[test]
+    fn test_write_fault() {
+        let mut emu = segmented(Permissions::READ);
+        emu.execute_instruction().unwrap();
+        let err = emu.execute_instruction().unwrap_err();
+        let fault = match err {
+            EmulatorError::MemoryFault(fault) => fault,
+            e => panic!("unexpected error: {}", e),
+        };
+        assert_eq!(fault.access, Access::Write);
+        assert_eq!(fault.address, 0x40);
+        assert_eq!(fault.pc, 0x1003);
+        assert!(fault.mapped);
+
+        // The handler grants the missing right and retries
+        let seen = Rc::new(RefCell::new(Vec::new()));
+        let log = seen.clone();
+        emu.set_fault_handler(move |emu, fault| {
+            log.borrow_mut().push(fault.clone());
+            emu.set_permissions(0x40, 0x10, Permissions::READ_WRITE)
+                .unwrap();
+            FaultAction::Retry
+        });
+        emu.set_register("r0", 5).unwrap();
+        emu.execute_instruction().unwrap();
+        emu.execute_instruction().unwrap();
+        assert_eq!(emu.register("r2").unwrap(), 5);
+        assert_eq!(seen.borrow().len(), 1);
+    }
+
+    #[test]
+    fn test_ignore_fault() {
+        let mut emu = segmented(Permissions::NONE);
+        emu.set_fault_handler(|_, _| FaultAction::Ignore);
+        emu.set_register("r0", 9).unwrap();
+        for _ in 0..3 {
+            emu.execute_instruction().unwrap();
+        }
+        assert_eq!(emu.register("r2").unwrap(), 9);
+
+        // Without enforcement nothing is checked
+        let mut emu = segmented(Permissions::NONE);
+        emu.set_enforce_permissions(false);
+        for _ in 0..3 {
+            emu.execute_instruction().unwrap();
+        }
+    }
+
+    #[test]
+    fn test_execute_fault() {
+        let mut emu = segmented(Permissions::READ_WRITE);
+        emu.set_pc(0x40);
+        let err = emu.execute_op().unwrap_err();
+        assert!(matches!(
+            err,
+            EmulatorError::MemoryFault(MemoryFault {
+                access: Access::Execute,
+                address: 0x40,
+                mapped: true,
+                ..
+            })
+        ));
+
+        // Images without segments are not checked
+        let mut emu = toy::emulator(0x1000, &CODE);
+        emu.set_enforce_permissions(true);
+        for _ in 0..3 {
+            emu.execute_instruction().unwrap();
+        }
+    }
+
+    #[test]
+    fn test_top_of_space() {
+        // A 64-bit cut of the toy, so accesses can run past the last address
+        let dir = std::env::temp_dir().join(format!("sleigh-top-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let slaspec = dir.join("wide.slaspec");
+        let files = LanguageFiles {
+            sla: dir.join("wide.sla"),
+            pspec: dir.join("wide.pspec"),
+            cspec: None,
+        };
+        std::fs::write(
+            &slaspec,
+            r#"
+define endian=little;
+define alignment=1;
+define space ram type=ram_space size=8 default;
+define space register type=register_space size=4;
+define register offset=0 size=8 [ r0 r1 r2 r3 sp lr pc ];
+define token instr(8) op = (0,7);
+define token regs(8) rd = (4,7) rs = (0,3);
+define token imm(8) imm8 = (0,7);
+attach variables [ rd rs ] [ r0 r1 r2 r3 sp lr pc _ _ _ _ _ _ _ _ _ ];
+:nop is op=0x00 { }
+:movi rd, imm8 is op=0x01; rd; imm8 { rd = imm8; }
+"#,
+        )
+        .unwrap();
+        std::fs::write(
+            &files.pspec,
+            r#"<processor_spec><programcounter register="pc"/></processor_spec>"#,
+        )
+        .unwrap();
+        compile_slaspec(&slaspec, &files.sla, &[]).unwrap();
+
+        // The last byte of movi wraps around to the unmapped address 0
+        let top = u64::MAX - 1;
+        let mut image = SegmentedImage::new();
+        image.add_segment(".text", top, vec![0x01, 0x10], Permissions::READ_EXECUTE);
+        let mut emu = Emulator::new(&files, image).unwrap();
+        emu.set_pc(top);
+        let err = emu.execute_instruction().unwrap_err();
+        assert!(matches!(
+            err,
+            EmulatorError::MemoryFault(MemoryFault {
+                access: Access::Execute,
+                address: 0,
+                mapped: false,
+                ..
+            })
+        ));
+
+        // A range ending on the last address keeps its permissions
+        emu.set_pc(top + 1);
+        emu.set_permissions(u64::MAX - 0xf, 0x10, Permissions::READ)
+            .unwrap();
+        let err = emu.execute_op().unwrap_err();
+        assert!(matches!(
+            err,
+            EmulatorError::MemoryFault(MemoryFault {
+                access: Access::Execute,
+                address: u64::MAX,
+                mapped: true,
+                ..
+            })
+        ));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
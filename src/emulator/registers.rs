@@ -0,0 +1,176 @@
+//! Register snapshots and diffs, mostly for differential testing and tracing.
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+use crate::ffi;
+
+/// A register value, most significant byte first
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RegisterValue(pub Vec<u8>);
+
+impl RegisterValue {
+    /// The value as an integer, if it fits in 64 bits
+    pub fn as_u64(&self) -> Option<u64> {
+        if self.0.len() > 8 {
+            return None;
+        }
+        Some(self.0.iter().fold(0, |acc, b| (acc << 8) | *b as u64))
+    }
+}
+
+impl fmt::Display for RegisterValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let digits: String = self.0.iter().map(|b| format!("{:02x}", b)).collect();
+        let digits = digits.trim_start_matches('0');
+        write!(f, "0x{}", if digits.is_empty() { "0" } else { digits })
+    }
+}
+
+/// Keep only the registers that aren't contained in a larger one
+pub(super) fn base_registers(mut regs: Vec<ffi::RegisterInfo>) -> Vec<ffi::RegisterInfo> {
+    regs.sort_by(|a, b| {
+        (a.space, a.offset, std::cmp::Reverse(a.size)).cmp(&(
+            b.space,
+            b.offset,
+            std::cmp::Reverse(b.size),
+        ))
+    });
+    let mut base: Vec<ffi::RegisterInfo> = Vec::new();
+    for reg in regs {
+        if let Some(last) = base.last() {
+            if last.space == reg.space && reg.offset < last.offset + last.size as u64 {
+                continue;
+            }
+        }
+        base.push(reg);
+    }
+    base
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RegisterSnapshot {
+    values: BTreeMap<String, RegisterValue>,
+}
+
+impl RegisterSnapshot {
+    pub(super) fn capture(
+        emu: &ffi::PcodeEmulator,
+        regs: &[ffi::RegisterInfo],
+    ) -> Result<Self, cxx::Exception> {
+        let mut values = BTreeMap::new();
+        for reg in regs {
+            let mut bytes = vec![0; reg.size as usize];
+            emu.readSpace(reg.space, reg.offset, &mut bytes)?;
+            if !emu.isBigEndian() {
+                bytes.reverse();
+            }
+            values.insert(reg.name.clone(), RegisterValue(bytes));
+        }
+        Ok(Self { values })
+    }
+
+    pub fn get(&self, name: &str) -> Option<&RegisterValue> {
+        self.values.get(name)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &RegisterValue)> {
+        self.values.iter().map(|(k, v)| (k.as_str(), v))
+    }
+
+    /// Registers whose value differs in `after`
+    pub fn diff(&self, after: &RegisterSnapshot) -> RegisterDiff {
+        let changes = after
+            .values
+            .iter()
+            .filter_map(|(name, new)| {
+                let old = self.values.get(name)?;
+                (old != new).then(|| RegisterChange {
+                    name: name.clone(),
+                    old: old.clone(),
+                    new: new.clone(),
+                })
+            })
+            .collect();
+        RegisterDiff { changes }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RegisterChange {
+    pub name: String,
+    pub old: RegisterValue,
+    pub new: RegisterValue,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RegisterDiff {
+    pub changes: Vec<RegisterChange>,
+}
+
+impl RegisterDiff {
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    pub fn get(&self, name: &str) -> Option<&RegisterChange> {
+        self.changes.iter().find(|c| c.name == name)
+    }
+}
+
+/// One `NAME: old -> new` entry per line
+impl fmt::Display for RegisterDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for c in &self.changes {
+            writeln!(f, "{}: {} -> {}", c.name, c.old, c.new)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reg(name: &str, offset: u64, size: u32) -> ffi::RegisterInfo {
+        ffi::RegisterInfo {
+            name: name.into(),
+            space: 1,
+            offset,
+            size,
+        }
+    }
+
+    #[test]
+    fn test_base_registers() {
+        let regs = vec![
+            reg("AL", 0, 1),
+            reg("RAX", 0, 8),
+            reg("EAX", 0, 4),
+            reg("AH", 1, 1),
+            reg("RCX", 8, 8),
+        ];
+        let names: Vec<_> = base_registers(regs).into_iter().map(|r| r.name).collect();
+        assert_eq!(names, ["RAX", "RCX"]);
+    }
+
+    #[test]
+    fn test_diff() {
+        let mut before = RegisterSnapshot::default();
+        before
+            .values
+            .insert("RAX".into(), RegisterValue(vec![0, 0, 0, 1]));
+        before
+            .values
+            .insert("RCX".into(), RegisterValue(vec![0, 2]));
+        let mut after = before.clone();
+        after
+            .values
+            .insert("RAX".into(), RegisterValue(vec![0, 0, 1, 0]));
+
+        let diff = before.diff(&after);
+        assert_eq!(diff.changes.len(), 1);
+        assert_eq!(diff.get("RAX").unwrap().new.as_u64(), Some(0x100));
+        assert_eq!(diff.to_string(), "RAX: 0x1 -> 0x100\n");
+    }
+}
@@ -0,0 +1,136 @@
+//! Running until something happens.
+//!
+//! [`Emulator::step`], [`Emulator::run`] and [`Emulator::run_until`] execute whole
+//! instructions and turn the ways execution normally ends into a [`StopReason`]: a breakpoint
+//! asking to stop, an exhausted budget, a memory fault, a user op nothing handles or an
+//! instruction that can't be executed. Only engine failures are left as errors.
+
+use super::{Emulator, EmulatorError, HaltReason, MemoryFault};
+
+/// Why [`Emulator::run`] or [`Emulator::run_until`] returned
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StopReason {
+    /// Execution reached the address given to [`Emulator::run_until`]
+    Reached,
+    /// The maximum number of instructions given to [`Emulator::run`] was executed
+    InstructionLimit,
+    /// A breakpoint handler stopped execution at this address
+    Breakpoint(u64),
+    /// The emulator's budget is exhausted
+    Halted(HaltReason),
+    /// A memory access was refused and no fault handler let it through
+    MemoryFault(MemoryFault),
+    /// Execution reached a user-defined op nothing handles
+    UnhandledUserOp(String),
+    /// The instruction at this address has no pcode in the spec
+    Unimplemented(u64),
+    /// The bytes at this address don't decode to an instruction
+    InvalidInstruction(u64),
+}
+
+impl StopReason {
+    /// The reason an instruction failed with `err`, or `err` back if it isn't a reason to stop
+    fn from_error(err: EmulatorError) -> Result<Self, EmulatorError> {
+        Ok(match err {
+            EmulatorError::Breakpoint(addr) => StopReason::Breakpoint(addr),
+            EmulatorError::Halted(reason) => StopReason::Halted(reason),
+            EmulatorError::MemoryFault(fault) => StopReason::MemoryFault(fault),
+            EmulatorError::UnhandledUserOp(name) => StopReason::UnhandledUserOp(name),
+            EmulatorError::Unimplemented(addr) => StopReason::Unimplemented(addr),
+            EmulatorError::InvalidInstruction(addr) => StopReason::InvalidInstruction(addr),
+            err => return Err(err),
+        })
+    }
+}
+
+impl Emulator {
+    /// Execute the rest of the current instruction. Returns why it couldn't, if it didn't.
+    pub fn step(&mut self) -> Result<Option<StopReason>, EmulatorError> {
+        match self.execute_instruction() {
+            Ok(()) => Ok(None),
+            Err(err) => StopReason::from_error(err).map(Some),
+        }
+    }
+
+    /// Execute up to `max_instructions` instructions
+    pub fn run(&mut self, max_instructions: u64) -> Result<StopReason, EmulatorError> {
+        for _ in 0..max_instructions {
+            if let Some(reason) = self.step()? {
+                return Ok(reason);
+            }
+        }
+        Ok(StopReason::InstructionLimit)
+    }
+
+    /// Execute instructions until the next one is at `addr`. At least one instruction is
+    /// executed, so that a loop can be run up to its next iteration. Set a
+    /// [`Budget`](super::Budget) to bound the run.
+    pub fn run_until(&mut self, addr: u64) -> Result<StopReason, EmulatorError> {
+        loop {
+            if let Some(reason) = self.step()? {
+                return Ok(reason);
+            }
+            if self.pc() == addr {
+                return Ok(StopReason::Reached);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emulator::{Access, BreakAction, Budget};
+    use crate::image::Permissions;
+    use crate::toy;
+
+    #[test]
+    fn test_stop_reasons() {
+        // movi r0, 1 ; add r0, r0 ; hlt ; <invalid>
+        let code = [0x01, 0x00, 0x01, 0x02, 0x00, 0x0a, 0xff];
+        let mut emu = toy::emulator(0x1000, &code);
+        assert_eq!(emu.run(1).unwrap(), StopReason::InstructionLimit);
+        assert_eq!(emu.run(10).unwrap(), StopReason::Unimplemented(0x1005));
+        assert_eq!(emu.register("r0").unwrap(), 2);
+        assert!(matches!(
+            emu.execute_instruction(),
+            Err(EmulatorError::Unimplemented(0x1005))
+        ));
+        emu.set_pc(0x1006);
+        assert_eq!(
+            emu.step().unwrap(),
+            Some(StopReason::InvalidInstruction(0x1006))
+        );
+
+        emu.set_pc(0x1000);
+        emu.add_breakpoint(0x1003, |_, _| BreakAction::Stop);
+        assert_eq!(emu.run(10).unwrap(), StopReason::Breakpoint(0x1003));
+
+        // ld r1, [r0] from unmapped memory
+        let mut emu = toy::emulator(0x1000, &[0x04, 0x10]);
+        emu.set_permissions(0x1000, 2, Permissions::READ_EXECUTE)
+            .unwrap();
+        emu.set_enforce_permissions(true);
+        match emu.step().unwrap() {
+            Some(StopReason::MemoryFault(fault)) => assert_eq!(fault.access, Access::Read),
+            reason => panic!("unexpected stop: {:?}", reason),
+        }
+    }
+
+    #[test]
+    fn test_run_until() {
+        // movi r0, 1 ; add r0, r0 ; add r0, r0 ; add r0, r0
+        let code = [0x01, 0x00, 0x01, 0x02, 0x00, 0x02, 0x00, 0x02, 0x00];
+        let mut emu = toy::emulator(0x1000, &code);
+        assert_eq!(emu.run_until(0x1007).unwrap(), StopReason::Reached);
+        assert_eq!(emu.register("r0").unwrap(), 4);
+
+        // The budget ends runs that never get there
+        emu.set_pc(0x1000);
+        emu.set_budget(Budget::new().instructions(3));
+        assert_eq!(
+            emu.run_until(0x2000).unwrap(),
+            StopReason::Halted(HaltReason::Instructions)
+        );
+    }
+}
@@ -0,0 +1,313 @@
+//! Guest memory layout helpers: stack and heap regions, and argument passing following the
+//! calling convention of the loaded compiler spec.
+
+use super::{Emulator, EmulatorError};
+use crate::cspec::Storage;
+use crate::image::Permissions;
+
+/// A region of guest memory set up by the emulator's user
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MemoryRegion {
+    pub name: String,
+    pub start: u64,
+    pub size: u64,
+    pub permissions: Permissions,
+}
+
+impl MemoryRegion {
+    pub fn end(&self) -> u64 {
+        self.start.wrapping_add(self.size)
+    }
+
+    pub fn contains(&self, addr: u64) -> bool {
+        addr >= self.start && addr - self.start < self.size
+    }
+}
+
+/// A bump allocator handing out guest memory from a mapped region
+#[derive(Debug, Clone)]
+pub struct GuestHeap {
+    start: u64,
+    size: u64,
+    used: u64,
+}
+
+impl GuestHeap {
+    pub fn new(start: u64, size: u64) -> Self {
+        Self {
+            start,
+            size,
+            used: 0,
+        }
+    }
+
+    /// Reserve `size` bytes aligned to `align`, which must be a power of two
+    pub fn allocate(&mut self, size: u64, align: u64) -> Option<u64> {
+        let addr = self.start.checked_add(self.used)?.checked_add(align - 1)? & !(align - 1);
+        // Offsets from the start, so a heap ending at the top of the space still fills up
+        let used = (addr - self.start).checked_add(size)?;
+        if used > self.size {
+            return None;
+        }
+        self.used = used;
+        Some(addr)
+    }
+
+    pub fn remaining(&self) -> u64 {
+        self.size - self.used
+    }
+}
+
+fn to_bytes(value: u64, size: usize, big_endian: bool) -> Vec<u8> {
+    let le = value.to_le_bytes();
+    let mut bytes: Vec<u8> = (0..size).map(|i| le.get(i).copied().unwrap_or(0)).collect();
+    if big_endian {
+        bytes.reverse();
+    }
+    bytes
+}
+
+impl Emulator {
+    /// Zero `size` bytes at `start`, give them `permissions` and record the region. Fails if
+    /// the region runs past the end of the address space.
+    pub fn map_region(
+        &mut self,
+        name: &str,
+        start: u64,
+        size: u64,
+        permissions: Permissions,
+    ) -> Result<(), EmulatorError> {
+        if size > 0 && start.checked_add(size - 1).is_none() {
+            return Err(EmulatorError::BadRegion { start, size });
+        }
+        const CHUNK: u64 = 0x10000;
+        let zero = vec![0; CHUNK as usize];
+        let mut off = 0;
+        while off < size {
+            let len = CHUNK.min(size - off);
+            self.write_memory(start + off, &zero[..len as usize])?;
+            off += len;
+        }
+        self.set_permissions(start, size, permissions)?;
+        self.regions.push(MemoryRegion {
+            name: name.to_string(),
+            start,
+            size,
+            permissions,
+        });
+        Ok(())
+    }
+
+    pub fn regions(&self) -> &[MemoryRegion] {
+        &self.regions
+    }
+
+    /// Name of the stack pointer register according to the compiler spec
+    pub fn stack_pointer_register(&self) -> Result<&str, EmulatorError> {
+        let cspec = self
+            .compiler_spec
+            .as_ref()
+            .ok_or(EmulatorError::NoCompilerSpec)?;
+        cspec
+            .stack_pointer
+            .as_ref()
+            .map(|sp| sp.register.as_str())
+            .ok_or(EmulatorError::MissingAbiInfo("stack pointer"))
+    }
+
+    /// Size in bytes of a pointer, taken from the stack pointer register
+    pub fn pointer_size(&self) -> Result<u32, EmulatorError> {
+        let sp = self.stack_pointer_register()?;
+        self.registers()
+            .into_iter()
+            .find(|r| r.name == sp)
+            .map(|r| r.size)
+            .ok_or(EmulatorError::MissingAbiInfo("stack pointer register"))
+    }
+
+    pub(super) fn stack_grows_negative(&self) -> bool {
+        self.compiler_spec
+            .as_ref()
+            .map(|c| c.stack_growth() == crate::cspec::StackGrowth::Negative)
+            .unwrap_or(true)
+    }
+
+    /// Read a pointer-sized or smaller integer in target byte order
+    pub fn read_uint(&self, addr: u64, size: usize) -> Result<u64, EmulatorError> {
+        let mut bytes = vec![0; size];
+        self.read_memory(addr, &mut bytes)?;
+        if !self.inner.isBigEndian() {
+            bytes.reverse();
+        }
+        Ok(bytes.iter().fold(0, |acc, b| (acc << 8) | *b as u64))
+    }
+
+    /// Write an integer of `size` bytes in target byte order
+    pub fn write_uint(&mut self, addr: u64, value: u64, size: usize) -> Result<(), EmulatorError> {
+        let bytes = to_bytes(value, size, self.inner.isBigEndian());
+        Ok(self.write_memory(addr, &bytes)?)
+    }
+
+    /// Map a stack of `size` bytes at `base` and point the stack pointer at its top (or its
+    /// bottom for stacks growing upwards), leaving a pointer-sized red zone. Returns the new
+    /// stack pointer. Fails if the stack has no room for the red zone.
+    pub fn setup_stack(&mut self, base: u64, size: u64) -> Result<u64, EmulatorError> {
+        let ptr = self.pointer_size()? as u64;
+        let top = size
+            .checked_sub(ptr)
+            .ok_or(EmulatorError::BadRegion { start: base, size })?;
+        self.map_region("stack", base, size, Permissions::READ_WRITE)?;
+        // The region was checked not to wrap, so the offset fits
+        let sp = if self.stack_grows_negative() {
+            (base + top) & !(ptr - 1)
+        } else {
+            base
+        };
+        let name = self.stack_pointer_register()?.to_string();
+        self.set_register(&name, sp)?;
+        Ok(sp)
+    }
+
+    /// Push a pointer-sized value, returning the new stack pointer
+    pub fn push(&mut self, value: u64) -> Result<u64, EmulatorError> {
+        let ptr = self.pointer_size()?;
+        let name = self.stack_pointer_register()?.to_string();
+        let sp = self.register(&name)?;
+        let (slot, new_sp) = if self.stack_grows_negative() {
+            (sp.wrapping_sub(ptr as u64), sp.wrapping_sub(ptr as u64))
+        } else {
+            (sp, sp.wrapping_add(ptr as u64))
+        };
+        self.write_uint(slot, value, ptr as usize)?;
+        self.set_register(&name, new_sp)?;
+        Ok(new_sp)
+    }
+
+    /// Map a region at `start` and use it for [`heap_alloc`](Self::heap_alloc)
+    pub fn setup_heap(&mut self, start: u64, size: u64) -> Result<(), EmulatorError> {
+        self.map_region("heap", start, size, Permissions::READ_WRITE)?;
+        self.heap = Some(GuestHeap::new(start, size));
+        Ok(())
+    }
+
+    /// Allocate pointer-aligned guest memory from the heap
+    pub fn heap_alloc(&mut self, size: u64) -> Result<u64, EmulatorError> {
+        let align = self.pointer_size().unwrap_or(8) as u64;
+        self.heap
+            .as_mut()
+            .and_then(|h| h.allocate(size, align))
+            .ok_or(EmulatorError::OutOfMemory { requested: size })
+    }
+
+    /// Copy `data` into freshly allocated heap memory
+    pub fn heap_write(&mut self, data: &[u8]) -> Result<u64, EmulatorError> {
+        let addr = self.heap_alloc(data.len() as u64)?;
+        self.write_memory(addr, data)?;
+        Ok(addr)
+    }
+
+    /// Pass integer arguments according to the default calling convention. The stack pointer
+    /// must hold its value at function entry: stack arguments are placed relative to it.
+    pub fn set_arguments(&mut self, args: &[u64]) -> Result<(), EmulatorError> {
+        let ptr = self.pointer_size()?;
+        let proto = self
+            .compiler_spec
+            .as_ref()
+            .ok_or(EmulatorError::NoCompilerSpec)?
+            .default_prototype()
+            .ok_or(EmulatorError::MissingAbiInfo("default prototype"))?
+            .clone();
+        let sp_name = self.stack_pointer_register()?.to_string();
+        let sp = self.register(&sp_name)?;
+
+        let mut args = args.iter();
+        for entry in proto.inputs.iter().filter(|e| e.metatype.is_none()) {
+            match &entry.storage {
+                Storage::Register(name) => match args.next() {
+                    Some(arg) => self.set_register(name, *arg)?,
+                    None => return Ok(()),
+                },
+                Storage::Memory { space, offset, .. } if space == "stack" => {
+                    let slot = (entry.align.max(ptr)) as i64;
+                    for (i, arg) in args.by_ref().enumerate() {
+                        let addr = sp.wrapping_add((offset + i as i64 * slot) as u64);
+                        self.write_uint(addr, *arg, ptr as usize)?;
+                    }
+                    return Ok(());
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Lay out C-style `argc`/`argv` on the heap and pass them as the first two arguments.
+    /// Returns `(argc, argv)`.
+    pub fn set_argv(&mut self, argv: &[&str]) -> Result<(u64, u64), EmulatorError> {
+        let ptr = self.pointer_size()? as usize;
+        let mut pointers = Vec::with_capacity(argv.len() + 1);
+        for arg in argv {
+            let mut bytes = arg.as_bytes().to_vec();
+            bytes.push(0);
+            pointers.push(self.heap_write(&bytes)?);
+        }
+        pointers.push(0);
+        let table = self.heap_alloc((pointers.len() * ptr) as u64)?;
+        for (i, p) in pointers.iter().enumerate() {
+            self.write_uint(table + (i * ptr) as u64, *p, ptr)?;
+        }
+        let argc = argv.len() as u64;
+        self.set_arguments(&[argc, table])?;
+        Ok((argc, table))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::toy;
+
+    #[test]
+    fn test_heap() {
+        let mut heap = GuestHeap::new(0x1000, 0x20);
+        assert_eq!(heap.allocate(3, 8), Some(0x1000));
+        assert_eq!(heap.allocate(8, 8), Some(0x1008));
+        assert_eq!(heap.allocate(0x11, 8), None);
+        assert_eq!(heap.allocate(8, 8), Some(0x1010));
+        assert_eq!(heap.remaining(), 8);
+
+        // A heap ending at the top of the address space
+        let mut heap = GuestHeap::new(u64::MAX - 0xf, 0x10);
+        assert_eq!(heap.allocate(8, 8), Some(u64::MAX - 0xf));
+        assert_eq!(heap.allocate(8, 8), Some(u64::MAX - 7));
+        assert_eq!(heap.allocate(1, 8), None);
+        assert_eq!(heap.allocate(0, 1), None);
+    }
+
+    #[test]
+    fn test_bad_regions() {
+        let mut emu = toy::emulator(0x1000, &[0x00]);
+        let err = emu
+            .map_region("top", u64::MAX, 2, Permissions::READ)
+            .unwrap_err();
+        assert_eq!(err.to_string(), "can't map 0x2 bytes at 0xffffffffffffffff");
+        // Ending on the last address is fine
+        emu.map_region("top", u64::MAX - 1, 2, Permissions::READ)
+            .unwrap();
+        assert!(matches!(
+            emu.setup_stack(0x8000, 2),
+            Err(EmulatorError::BadRegion {
+                start: 0x8000,
+                size: 2
+            })
+        ));
+        assert!(emu.setup_stack(u64::MAX - 0xff, 0x200).is_err());
+        assert_eq!(emu.regions().len(), 1);
+    }
+
+    #[test]
+    fn test_to_bytes() {
+        assert_eq!(to_bytes(0x0102, 4, false), [2, 1, 0, 0]);
+        assert_eq!(to_bytes(0x0102, 4, true), [0, 0, 1, 2]);
+    }
+}
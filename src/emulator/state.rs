@@ -0,0 +1,96 @@
+//! Saving and restoring the whole machine state.
+//!
+//! A [`StateSnapshot`] holds the program counter, every register and temporary, the memory
+//! pages written so far and the guest heap's allocations. Restoring it puts the emulator back
+//! exactly where it was, which is much cheaper than building a new emulator: fuzzers and
+//! other repeated runs snapshot once after setup and restore before every run. Only pages
+//! written since the emulator was created are saved, so snapshots stay small.
+//!
+//! Configuration isn't part of the snapshot: permissions, handlers, hooks, the budget and the
+//! environment keep their current settings. The undo history is cleared on restore.
+
+use cxx::UniquePtr;
+
+use super::setup::GuestHeap;
+use super::Emulator;
+use crate::ffi;
+
+pub struct StateSnapshot {
+    state: UniquePtr<ffi::EmulatorState>,
+    heap: Option<GuestHeap>,
+}
+
+impl Emulator {
+    pub fn snapshot(&self) -> StateSnapshot {
+        StateSnapshot {
+            state: self.inner.saveState(),
+            heap: self.heap.clone(),
+        }
+    }
+
+    /// Go back to `snapshot`, which must have been taken from this emulator
+    pub fn restore(&mut self, snapshot: &StateSnapshot) -> Result<(), cxx::Exception> {
+        self.inner.pin_mut().restoreState(&snapshot.state)?;
+        self.heap = snapshot.heap.clone();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::toy;
+
+    #[test]
+    fn test_restore() {
+        // movi r0, 7 ; movi r1, 0x40 ; st [r1], r0 ; add r0, r0 ; movi r2, 0x2a
+        let code = [
+            0x01, 0x00, 0x07, 0x01, 0x10, 0x40, 0x05, 0x10, 0x02, 0x00, 0x01, 0x20, 0x2a,
+        ];
+        let mut emu = toy::emulator(0x1000, &code);
+        emu.write_memory(0x80, &[1, 2, 3, 4]).unwrap();
+        emu.execute_instruction().unwrap();
+        let snapshot = emu.snapshot();
+
+        for _ in 0..2 {
+            for _ in 0..3 {
+                emu.execute_instruction().unwrap();
+            }
+            // A page written after the snapshot, and a patch to the code not yet run
+            emu.write_memory(0x80, &[9]).unwrap();
+            emu.write_memory(0x100c, &[0x2b]).unwrap();
+            emu.execute_instruction().unwrap();
+            assert_eq!(emu.register("r0").unwrap(), 14);
+            assert_eq!(emu.register("r2").unwrap(), 0x2b);
+            assert_eq!(emu.read_uint(0x40, 4).unwrap(), 7);
+
+            emu.restore(&snapshot).unwrap();
+            assert_eq!(emu.pc(), 0x1003);
+            assert_eq!(
+                ["r0", "r1", "r2"].map(|r| emu.register(r).unwrap()),
+                [7, 0, 0]
+            );
+            assert_eq!(emu.read_uint(0x40, 4).unwrap(), 0);
+            assert_eq!(emu.read_uint(0x80, 4).unwrap(), 0x0403_0201);
+            assert_eq!(emu.read_uint(0x100c, 1).unwrap(), 0x2a);
+        }
+
+        // Snapshots only apply to the emulator they were taken from
+        let mut other = toy::emulator(0x1000, &code);
+        assert!(other.restore(&snapshot).is_err());
+    }
+
+    #[test]
+    fn test_restore_mid_instruction() {
+        // cpuid r2 runs a user op, then a load
+        let mut emu = toy::emulator(0x1000, &[0x09, 0x20]);
+        emu.execute_op().unwrap();
+        assert!(!emu.raw().isInstructionStart());
+        let snapshot = emu.snapshot();
+        emu.execute_op().unwrap();
+        assert_eq!(emu.pc(), 0x1002);
+        emu.restore(&snapshot).unwrap();
+        assert_eq!(emu.pc(), 0x1000);
+        emu.execute_op().unwrap();
+        assert_eq!(emu.pc(), 0x1002);
+    }
+}
@@ -0,0 +1,119 @@
+//! Memory access tracing.
+//!
+//! With a memory hook installed, every LOAD and STORE the emulator executes is recorded along
+//! with the value moved and the instruction doing it, and handed to the hook once the step
+//! is over, in execution order. This is what taint trackers, memory maps and heap analyses
+//! are built on. Accesses made through the emulator's API, like
+//! [`Emulator::write_memory`], are not traced.
+
+use super::{Access, Emulator};
+use crate::ffi;
+
+/// A LOAD or STORE executed by the emulator
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MemoryAccess {
+    /// [`Access::Read`] for loads, [`Access::Write`] for stores
+    pub access: Access,
+    /// Index of the address space accessed
+    pub space: i32,
+    pub address: u64,
+    pub size: u32,
+    /// The value loaded or stored, in the target's byte order; 0 for accesses wider than 8
+    /// bytes
+    pub value: u64,
+    /// Address of the instruction making the access
+    pub pc: u64,
+}
+
+impl MemoryAccess {
+    fn from_info(info: &ffi::MemoryAccessInfo) -> Self {
+        Self {
+            access: if info.access == 2 {
+                Access::Write
+            } else {
+                Access::Read
+            },
+            space: info.space,
+            address: info.offset,
+            size: info.size,
+            value: info.value,
+            pc: info.pc,
+        }
+    }
+}
+
+pub(super) type MemoryHook = Box<dyn FnMut(&mut Emulator, &MemoryAccess)>;
+
+impl Emulator {
+    /// Call `hook` on every load and store executed from now on, replacing any previous hook
+    pub fn set_memory_hook<F>(&mut self, hook: F)
+    where
+        F: FnMut(&mut Emulator, &MemoryAccess) + 'static,
+    {
+        self.memory_hook = Some(Box::new(hook));
+        self.inner.pin_mut().setTraceMemory(true);
+    }
+
+    pub fn clear_memory_hook(&mut self) {
+        self.memory_hook = None;
+        self.inner.pin_mut().setTraceMemory(false);
+    }
+
+    /// Hand the accesses made by the last step to the memory hook
+    pub(super) fn dispatch_accesses(&mut self) {
+        let mut hook = match self.memory_hook.take() {
+            Some(hook) => hook,
+            None => return,
+        };
+        for info in self.inner.pin_mut().takeMemoryAccesses() {
+            hook(self, &MemoryAccess::from_info(&info));
+        }
+        // The hook may have installed a replacement for itself
+        if self.memory_hook.is_none() {
+            self.memory_hook = Some(hook);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::toy;
+
+    #[test]
+    fn test_memory_hook() {
+        // movi r0, 9 ; movi r1, 0x40 ; st [r1], r0 ; ld r2, [r1]
+        let code = [0x01, 0x00, 0x09, 0x01, 0x10, 0x40, 0x05, 0x10, 0x04, 0x21];
+        let mut emu = toy::emulator(0x1000, &code);
+        let seen = Rc::new(RefCell::new(Vec::new()));
+        let log = seen.clone();
+        emu.set_memory_hook(move |_, access| log.borrow_mut().push(access.clone()));
+        emu.execute_instruction().unwrap();
+        emu.execute_instruction().unwrap();
+        emu.write_memory(0x40, &[1]).unwrap();
+        emu.execute_instruction().unwrap();
+        emu.execute_instruction().unwrap();
+
+        let ram = emu.raw().getDefaultDataSpaceIndex();
+        let access = |access, pc| MemoryAccess {
+            access,
+            space: ram,
+            address: 0x40,
+            size: 4,
+            value: 9,
+            pc,
+        };
+        assert_eq!(
+            *seen.borrow(),
+            [access(Access::Write, 0x1006), access(Access::Read, 0x1008)]
+        );
+
+        emu.clear_memory_hook();
+        emu.set_pc(0x1006);
+        emu.execute_instruction().unwrap();
+        assert_eq!(seen.borrow().len(), 2);
+    }
+}
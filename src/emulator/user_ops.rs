@@ -0,0 +1,139 @@
+//! Handlers for user-defined ops.
+//!
+//! A CALLOTHER op stops the step and is looked up by name: a handler registered with
+//! [`Emulator::set_user_op_handler`] runs first, then the ops the emulator answers itself from
+//! its [`Environment`](super::Environment). Whatever the handler returns is written to the
+//! op's output before execution moves on to the next op. Ops nothing handles fail the step
+//! with [`EmulatorError::UnhandledUserOp`].
+
+use std::collections::HashMap;
+
+use super::{Emulator, EmulatorError};
+
+/// A user-defined op being executed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UserOpCall {
+    pub name: String,
+    /// Index of the op in the spec
+    pub index: i32,
+    /// Values of the op's inputs; inputs wider than 8 bytes read as 0
+    pub inputs: Vec<u64>,
+    /// Size of the output, 0 if the op has none
+    pub output_size: u32,
+    /// Address of the instruction containing the op
+    pub pc: u64,
+}
+
+/// Returns the value of the op's output, `None` to leave it unchanged
+pub(super) type UserOpHandler =
+    Box<dyn FnMut(&mut Emulator, &UserOpCall) -> Result<Option<u64>, EmulatorError>>;
+
+/// Handlers by op name. A slot is empty while its handler runs.
+pub(super) type UserOpHandlers = HashMap<String, Option<UserOpHandler>>;
+
+impl Emulator {
+    /// Run `handler` for every user-defined op called `name`, instead of the emulator's own
+    /// handling if it has any. Errors returned by the handler fail the step.
+    pub fn set_user_op_handler<F>(&mut self, name: &str, handler: F)
+    where
+        F: FnMut(&mut Emulator, &UserOpCall) -> Result<Option<u64>, EmulatorError> + 'static,
+    {
+        self.user_ops
+            .insert(name.to_string(), Some(Box::new(handler)));
+    }
+
+    /// Returns `false` if no handler was set for `name`
+    pub fn remove_user_op_handler(&mut self, name: &str) -> bool {
+        self.user_ops.remove(name).is_some()
+    }
+
+    /// Run the user-defined op that aborted the last step, if any. Returns `None` if the step
+    /// didn't stop at one, `Ok` once the op completed.
+    pub(super) fn dispatch_callother(&mut self) -> Option<Result<(), EmulatorError>> {
+        let info = self.inner.pin_mut().takeCallOther();
+        if !info.pending {
+            return None;
+        }
+        let value = match self.user_ops.get_mut(&info.name).and_then(Option::take) {
+            Some(mut handler) => {
+                let call = UserOpCall {
+                    name: info.name.clone(),
+                    index: info.index,
+                    inputs: info.inputs.clone(),
+                    output_size: info.output_size,
+                    pc: self.pc(),
+                };
+                let value = handler(self, &call);
+                // The handler may have removed itself or installed a replacement
+                if let Some(slot @ None) = self.user_ops.get_mut(&info.name) {
+                    *slot = Some(handler);
+                }
+                value
+            }
+            None => self.builtin_callother(&info),
+        };
+        Some(value.and_then(|value| self.complete_callother(value)))
+    }
+
+    fn complete_callother(&mut self, value: Option<u64>) -> Result<(), EmulatorError> {
+        self.inner
+            .pin_mut()
+            .completeCallOther(value.is_some(), value.unwrap_or(0))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::toy;
+
+    #[test]
+    fn test_user_op_handler() {
+        // rnd r0 ; cpuid r2 ; tsc r1
+        let code = [0x07, 0x00, 0x09, 0x20, 0x08, 0x10];
+        let mut emu = toy::emulator(0x1000, &code);
+        emu.set_register("r2", 4).unwrap();
+        let calls = Rc::new(RefCell::new(Vec::new()));
+        let log = calls.clone();
+        emu.set_user_op_handler("rdrand", move |_, call| {
+            log.borrow_mut().push(call.clone());
+            Ok(Some(4))
+        });
+        // Overrides the built-in cpuid, which writes to a scratch area
+        emu.set_user_op_handler("cpuid", |emu, call| {
+            emu.write_uint(0x40, call.inputs[0] + 1, 4)?;
+            Ok(Some(0x40))
+        });
+        emu.execute_instruction().unwrap();
+        emu.execute_instruction().unwrap();
+        assert_eq!(emu.register("r0").unwrap(), 4);
+        assert_eq!(emu.register("r2").unwrap(), 5);
+        assert_eq!(
+            *calls.borrow(),
+            [UserOpCall {
+                name: "rdrand".to_string(),
+                index: 0,
+                inputs: vec![],
+                output_size: 4,
+                pc: 0x1000,
+            }]
+        );
+
+        // Handler errors fail the step, which can be run again
+        emu.set_user_op_handler("rdtsc", |_, call| {
+            Err(EmulatorError::UnhandledUserOp(call.name.clone()))
+        });
+        assert!(matches!(
+            emu.execute_instruction(),
+            Err(EmulatorError::UnhandledUserOp(_))
+        ));
+        assert_eq!(emu.pc(), 0x1004);
+        assert!(emu.remove_user_op_handler("rdtsc"));
+        emu.execute_instruction().unwrap();
+        assert_eq!(emu.pc(), 0x1006);
+    }
+}
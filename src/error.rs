@@ -0,0 +1,298 @@
+//! The crate-wide error type.
+//!
+//! Each module reports failures with its own error type: [`LanguageError`] for spec files,
+//! [`CompileError`] for `.slaspec` compilation, [`EmulatorError`] for emulation,
+//! [`Cancelled`] for cancelled operations, [`WireError`] for packed pcode and
+//! `cxx::Exception` for whatever the engine throws. All of them convert into [`SleighError`],
+//! so code going through several modules can use `?` throughout and still tell failures apart
+//! with [`SleighError::kind`]. The original error stays available as the
+//! [`source`](std::error::Error::source).
+
+use std::fmt;
+
+use crate::cancel::Cancelled;
+use crate::compile::CompileError;
+use crate::emulator::EmulatorError;
+use crate::languages::LanguageError;
+use crate::wire::WireError;
+
+/// Why an instruction couldn't be decoded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecodeReason {
+    /// The bytes don't match any instruction
+    Invalid,
+    /// The instruction decodes, but the spec marks its semantics `unimpl`
+    Unimplemented,
+    /// The load image has no bytes there
+    Unavailable,
+    /// The engine failed for some other reason
+    Engine,
+    /// The engine produced an op whose opcode has no [`Opcode`](crate::Opcode) variant, like
+    /// one added by a newer engine
+    UnknownOpcode,
+}
+
+/// Bytes that don't decode to an instruction
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DecodeError {
+    /// Address of the first byte, in the default code space
+    pub address: u64,
+    pub reason: DecodeReason,
+    pub message: String,
+    /// Length of the instruction when it decodes but can't be lifted, as for
+    /// [`DecodeReason::Unimplemented`], 0 otherwise
+    pub length: usize,
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.reason {
+            DecodeReason::Unimplemented => {
+                write!(f, "instruction at {:#x} is unimplemented", self.address)?
+            }
+            DecodeReason::Unavailable => write!(f, "no bytes at {:#x}", self.address)?,
+            DecodeReason::UnknownOpcode => {
+                write!(f, "can't lift instruction at {:#x}", self.address)?
+            }
+            DecodeReason::Invalid | DecodeReason::Engine => {
+                write!(f, "can't decode instruction at {:#x}", self.address)?
+            }
+        }
+        if !self.message.is_empty() {
+            write!(f, ": {}", self.message)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+/// An instruction had more pcode ops than the decompiler's op limit, see
+/// [`ffi::Decompiler::set_op_limit`](crate::ffi::Decompiler::set_op_limit)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OpLimitError {
+    pub address: u64,
+    pub limit: u32,
+}
+
+impl fmt::Display for OpLimitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "instruction at {:#x} has more than {} pcode ops",
+            self.address, self.limit
+        )
+    }
+}
+
+impl std::error::Error for OpLimitError {}
+
+/// A number that doesn't stand for any variant of the enum it was converted to, like an
+/// opcode added by a newer engine
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UnknownValue(pub u32);
+
+impl fmt::Display for UnknownValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "no variant for the value {}", self.0)
+    }
+}
+
+impl std::error::Error for UnknownValue {}
+
+/// What went wrong, independently of the module that reported it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// A spec file is missing, malformed or lacks something that was needed
+    Spec,
+    /// Bytes don't decode to an instruction, or to packed pcode
+    Decode,
+    /// Execution stopped in a handler, or needed one that isn't installed
+    Callback,
+    /// A budget, instruction or op limit or guest memory was exhausted, or the operation was
+    /// cancelled
+    Limit,
+    /// The engine raised an error
+    Engine,
+}
+
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum SleighError {
+    Language(LanguageError),
+    Compile(CompileError),
+    Decode(DecodeError),
+    OpLimit(OpLimitError),
+    Emulator(EmulatorError),
+    Engine(cxx::Exception),
+    Cancelled(Cancelled),
+    Wire(WireError),
+}
+
+impl SleighError {
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            SleighError::Language(_) | SleighError::Compile(_) => ErrorKind::Spec,
+            SleighError::Decode(e)
+                if matches!(e.reason, DecodeReason::Engine | DecodeReason::UnknownOpcode) =>
+            {
+                ErrorKind::Engine
+            }
+            SleighError::Decode(_) => ErrorKind::Decode,
+            SleighError::OpLimit(_) => ErrorKind::Limit,
+            SleighError::Emulator(e) => match e {
+                EmulatorError::Engine(_) => ErrorKind::Engine,
+                EmulatorError::Unimplemented(_) | EmulatorError::InvalidInstruction(_) => {
+                    ErrorKind::Decode
+                }
+                EmulatorError::NoCompilerSpec | EmulatorError::MissingAbiInfo(_) => ErrorKind::Spec,
+                EmulatorError::OutOfMemory { .. }
+                | EmulatorError::BadRegion { .. }
+                | EmulatorError::InstructionLimit(_)
+                | EmulatorError::Halted(_) => ErrorKind::Limit,
+                EmulatorError::MemoryFault(_)
+                | EmulatorError::UnhandledUserOp(_)
+                | EmulatorError::Breakpoint(_) => ErrorKind::Callback,
+            },
+            SleighError::Engine(_) => ErrorKind::Engine,
+            SleighError::Cancelled(_) => ErrorKind::Limit,
+            SleighError::Wire(_) => ErrorKind::Decode,
+        }
+    }
+}
+
+impl fmt::Display for SleighError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SleighError::Language(e) => e.fmt(f),
+            SleighError::Compile(e) => e.fmt(f),
+            SleighError::Decode(e) => e.fmt(f),
+            SleighError::OpLimit(e) => e.fmt(f),
+            SleighError::Emulator(e) => e.fmt(f),
+            SleighError::Engine(e) => write!(f, "engine error: {}", e.what()),
+            SleighError::Cancelled(e) => e.fmt(f),
+            SleighError::Wire(e) => e.fmt(f),
+        }
+    }
+}
+
+/// The variants other than `Engine` display as the error they wrap, so its source is theirs,
+/// and error chains don't show the same message twice
+impl std::error::Error for SleighError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SleighError::Language(e) => e.source(),
+            SleighError::Compile(e) => e.source(),
+            SleighError::Decode(e) => e.source(),
+            SleighError::OpLimit(e) => e.source(),
+            SleighError::Emulator(e) => e.source(),
+            // The message of the exception is part of ours
+            SleighError::Engine(_) => None,
+            SleighError::Cancelled(e) => e.source(),
+            SleighError::Wire(e) => e.source(),
+        }
+    }
+}
+
+impl From<LanguageError> for SleighError {
+    fn from(e: LanguageError) -> Self {
+        SleighError::Language(e)
+    }
+}
+
+impl From<CompileError> for SleighError {
+    fn from(e: CompileError) -> Self {
+        SleighError::Compile(e)
+    }
+}
+
+impl From<DecodeError> for SleighError {
+    fn from(e: DecodeError) -> Self {
+        SleighError::Decode(e)
+    }
+}
+
+impl From<OpLimitError> for SleighError {
+    fn from(e: OpLimitError) -> Self {
+        SleighError::OpLimit(e)
+    }
+}
+
+impl From<EmulatorError> for SleighError {
+    fn from(e: EmulatorError) -> Self {
+        SleighError::Emulator(e)
+    }
+}
+
+impl From<Cancelled> for SleighError {
+    fn from(e: Cancelled) -> Self {
+        SleighError::Cancelled(e)
+    }
+}
+
+impl From<WireError> for SleighError {
+    fn from(e: WireError) -> Self {
+        SleighError::Wire(e)
+    }
+}
+
+impl From<cxx::Exception> for SleighError {
+    fn from(e: cxx::Exception) -> Self {
+        SleighError::Engine(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+
+    use super::*;
+    use crate::emulator::{BreakAction, HaltReason};
+    use crate::toy;
+
+    #[test]
+    fn test_kinds() {
+        fn run() -> Result<u64, SleighError> {
+            let mut program = toy::program(0x1000, &[0; 4]);
+            program.set_context_everywhere("mode", 1)?;
+            let mut emu = toy::emulator(0x1000, &[0x00]);
+            emu.add_breakpoint(0x1000, |_, _| BreakAction::Stop);
+            emu.execute_instruction()?;
+            Ok(emu.pc())
+        }
+        let err = run().unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Callback);
+        assert_eq!(err.to_string(), "stopped at breakpoint 0x1000");
+
+        let program = toy::program(0x1000, &[0; 4]);
+        let err = SleighError::from(
+            program
+                .decompiler()
+                .context_variable("bogus", 0)
+                .unwrap_err(),
+        );
+        assert_eq!(err.kind(), ErrorKind::Engine);
+        assert!(err.to_string().starts_with("engine error: "));
+        assert!(err.source().is_none());
+
+        let err = SleighError::from(EmulatorError::Halted(HaltReason::Time));
+        assert_eq!(err.kind(), ErrorKind::Limit);
+        let err = SleighError::from(DecodeError {
+            address: 0x10,
+            reason: DecodeReason::Invalid,
+            message: String::new(),
+            length: 0,
+        });
+        assert_eq!(err.to_string(), "can't decode instruction at 0x10");
+
+        // Each message appears once along the chain
+        let err = SleighError::from(LanguageError::UnknownLanguage("x86:LE:64".to_string()));
+        let mut chain = vec![err.to_string()];
+        let mut source = err.source();
+        while let Some(e) = source {
+            chain.push(e.to_string());
+            source = e.source();
+        }
+        assert_eq!(chain, ["unknown language id `x86:LE:64`"]);
+    }
+}
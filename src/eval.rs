@@ -0,0 +1,114 @@
+//! Evaluating single pcode ops.
+//!
+//! The decompiler's `OpBehavior` classes implement the arithmetic of every pcode op the way
+//! the emulator runs it, including float ops in the language's float formats. Evaluating an
+//! op on known inputs is enough for constant folding, lightweight symbolic evaluation and
+//! recovering jump targets, without setting up an emulator.
+
+use crate::pcode::PcodeOp;
+use crate::{ffi, Opcode};
+
+impl ffi::Decompiler {
+    /// Apply the unary op `opcode` to `input`. Sizes are in bytes, at most 8. Fails for ops
+    /// that aren't unary or can't be evaluated, like LOAD or CALLOTHER.
+    pub fn evaluate_unary(
+        &self,
+        opcode: Opcode,
+        size_out: u32,
+        size_in: u32,
+        input: u64,
+    ) -> Result<u64, cxx::Exception> {
+        self.evaluateUnary(opcode as u32, size_out as i32, size_in as i32, input)
+    }
+
+    /// Apply the binary op `opcode` to `in1` and `in2`. `size_in` is the size of `in1`, which
+    /// for most ops is that of `in2` too. Fails for ops that aren't binary or can't be
+    /// evaluated, and on division by zero.
+    pub fn evaluate_binary(
+        &self,
+        opcode: Opcode,
+        size_out: u32,
+        size_in: u32,
+        in1: u64,
+        in2: u64,
+    ) -> Result<u64, cxx::Exception> {
+        self.evaluateBinary(opcode as u32, size_out as i32, size_in as i32, in1, in2)
+    }
+
+    /// The value of `op`'s output if all of its inputs are constants and the op can be
+    /// evaluated
+    pub fn fold(&self, op: &PcodeOp) -> Option<u64> {
+        let constant = self.getConstantSpaceIndex();
+        let output = op.output.as_ref()?;
+        if op.inputs.iter().any(|vn| vn.space != constant) {
+            return None;
+        }
+        match op.inputs.as_slice() {
+            [a] => self
+                .evaluate_unary(op.opcode, output.size, a.size, a.offset)
+                .ok(),
+            [a, b] => self
+                .evaluate_binary(op.opcode, output.size, a.size, a.offset, b.offset)
+                .ok(),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pcode::Varnode;
+    use crate::toy;
+
+    #[test]
+    fn test_evaluate() {
+        let program = toy::program(0x1000, &[0; 4]);
+        let decompiler = program.decompiler();
+        let add = |a, b| decompiler.evaluate_binary(Opcode::IntAdd, 1, 1, a, b);
+        assert_eq!(add(0xff, 2).unwrap(), 1);
+        assert_eq!(
+            decompiler
+                .evaluate_unary(Opcode::IntSExt, 4, 1, 0x80)
+                .unwrap(),
+            0xffff_ff80
+        );
+        assert_eq!(
+            decompiler
+                .evaluate_binary(Opcode::IntSLess, 1, 4, 0xffff_ffff, 0)
+                .unwrap(),
+            1
+        );
+        assert!(decompiler
+            .evaluate_binary(Opcode::IntDiv, 4, 4, 1, 0)
+            .is_err());
+        assert!(decompiler.evaluate_unary(Opcode::IntAdd, 4, 4, 1).is_err());
+        assert!(decompiler
+            .evaluate_binary(Opcode::Load, 4, 4, 1, 2)
+            .is_err());
+        assert!(decompiler.evaluate_unary(Opcode::Copy, 9, 9, 0).is_err());
+    }
+
+    #[test]
+    fn test_fold() {
+        // movi r0, 5 ; add r0, r1
+        let mut program = toy::program(0x1000, &[0x01, 0x00, 0x05, 0x02, 0x01]);
+        let constant = program.decompiler().getConstantSpaceIndex();
+        let copy = program.lift(0x1000).unwrap().ops[0].clone();
+        assert_eq!(program.decompiler().fold(&copy), Some(5));
+        let add = program.lift(0x1003).unwrap().ops[0].clone();
+        assert_eq!(program.decompiler().fold(&add), None);
+
+        let konst = |offset| Varnode {
+            space: constant,
+            offset,
+            size: 4,
+        };
+        let shift = PcodeOp {
+            opcode: Opcode::IntLeft,
+            output: add.output.clone(),
+            inputs: vec![konst(3), konst(4)],
+        };
+        assert_eq!(program.decompiler().fold(&shift), Some(0x30));
+    }
+}
@@ -0,0 +1,166 @@
+//! Fuzzing support.
+//!
+//! With the `arbitrary` feature, the owned pcode types implement [`arbitrary::Arbitrary`], so
+//! fuzz targets can build structured pcode, and [`FuzzLifter`] runs raw input bytes through
+//! the decoder of a language. Building a program loads and parses its spec files, which takes
+//! far longer than a fuzz iteration, so a lifter is created once and fed every input in turn.
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::languages::{LanguageError, LanguageFiles};
+use crate::pcode::LiftedInstruction;
+use crate::project::Program;
+use crate::{ffi, LoadImage};
+
+/// The current input, mapped at `base`; bytes past its end read as 0
+struct InputImage {
+    base: u64,
+    bytes: Rc<RefCell<Vec<u8>>>,
+}
+
+impl LoadImage for InputImage {
+    fn load_fill(&mut self, ptr: &mut [u8], addr: &ffi::Address) {
+        let bytes = self.bytes.borrow();
+        for (i, b) in ptr.iter_mut().enumerate() {
+            let off = addr
+                .getOffset()
+                .wrapping_add(i as u64)
+                .wrapping_sub(self.base);
+            *b = bytes.get(off as usize).copied().unwrap_or(0);
+        }
+    }
+}
+
+/// Lifts fuzzer inputs with one language
+pub struct FuzzLifter {
+    program: Program,
+    bytes: Rc<RefCell<Vec<u8>>>,
+    base: u64,
+}
+
+impl FuzzLifter {
+    /// A lifter mapping every input at `base` in the default code space
+    pub fn new(language: LanguageFiles, base: u64) -> Result<Self, LanguageError> {
+        let bytes = Rc::new(RefCell::new(Vec::new()));
+        let image = InputImage {
+            base,
+            bytes: bytes.clone(),
+        };
+        Ok(Self {
+            program: Program::new(language, image)?,
+            bytes,
+            base,
+        })
+    }
+
+    /// Lift `input` from its first byte on, resuming after each instruction and one byte past
+    /// anything that doesn't decode. Bytes that would lie past the last address are left out.
+    /// Every instruction is also formatted, analysed and round-tripped through the packed
+    /// format, and the function panics if any of that yields something inconsistent, so
+    /// fuzzers report it.
+    pub fn lift(&mut self, input: &[u8]) -> Vec<LiftedInstruction> {
+        *self.bytes.borrow_mut() = input.to_vec();
+        // The previous input's instructions are stale, including in the engine's own cache
+        self.program.clear_lifted();
+        let spaces = self.program.decompiler().space_hierarchy();
+        let mut lifted = Vec::new();
+        let mut off = 0;
+        while off < input.len() as u64 {
+            let addr = match self.base.checked_add(off) {
+                Some(addr) => addr,
+                None => break,
+            };
+            let inst = match self.program.lift(addr) {
+                Some(inst) => inst.clone(),
+                None => {
+                    off += 1;
+                    continue;
+                }
+            };
+            assert!(inst.length > 0, "empty instruction at {:#x}", addr);
+            assert_eq!(inst.bytes.len(), inst.length, "bytes of {:#x}", addr);
+            let decompiler = self.program.decompiler();
+            let _ = inst.listing(&spaces).to_string();
+            let _ = inst.flow(decompiler);
+            if let Ok(packed) = inst.to_packed_pcode(decompiler) {
+                let unpacked = LiftedInstruction::from_packed_pcode(&packed, decompiler);
+                let expected = LiftedInstruction {
+                    bytes: Vec::new(),
+                    ..inst.clone()
+                };
+                assert_eq!(unpacked, Ok(expected), "packed pcode differs");
+            }
+            off += inst.length as u64;
+            lifted.push(inst);
+        }
+        lifted
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use arbitrary::{Arbitrary, Unstructured};
+
+    use super::*;
+    use crate::pcode::Varnode;
+    use crate::{toy, Opcode};
+
+    #[test]
+    fn test_fuzz_lifter() {
+        let mut lifter = FuzzLifter::new(toy::files().clone(), 0x1000).unwrap();
+        // movi r0, 5 ; 0xff ; ret
+        let lifted = lifter.lift(&[0x01, 0x00, 0x05, 0xff, 0x03]);
+        let addresses: Vec<_> = lifted.iter().map(|inst| inst.address).collect();
+        assert_eq!(addresses, [0x1000, 0x1004]);
+        // Same address, new bytes: nop ; nop
+        let lifted = lifter.lift(&[0x00, 0x00]);
+        assert_eq!(lifted.len(), 2);
+        assert_eq!(lifted[0].length, 1);
+
+        // Inputs running past the last address
+        let mut lifter = FuzzLifter::new(toy::files().clone(), u64::MAX - 1).unwrap();
+        assert_eq!(lifter.lift(&[0x00; 4]).len(), 2);
+    }
+
+    #[test]
+    fn test_arbitrary_round_trip() {
+        let program = toy::program(0x1000, &[]);
+        let decompiler = program.decompiler();
+        let spaces: Vec<i32> = decompiler
+            .space_hierarchy()
+            .spaces()
+            .map(|space| space.index)
+            .collect();
+        let ram = decompiler.getDefaultCodeSpaceIndex();
+        // A fixed xorshift stream, so every run builds the same instructions
+        let mut state = 0x9e37_79b9_7f4a_7c15u64;
+        let bytes: Vec<u8> = (0..0x10000)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect();
+        let mut data = Unstructured::new(&bytes);
+        for _ in 0..256 {
+            let mut inst = LiftedInstruction::arbitrary(&mut data).unwrap();
+            inst.bytes.clear();
+            // Arbitrary spaces and sizes are mostly out of range, so pin them to packable ones
+            for op in &mut inst.ops {
+                for vn in op.output.iter_mut().chain(&mut op.inputs) {
+                    vn.space = spaces[vn.space.unsigned_abs() as usize % spaces.len()];
+                    vn.size %= 0x40;
+                }
+                if matches!(op.opcode, Opcode::Load | Opcode::Store) && !op.inputs.is_empty() {
+                    op.inputs[0] = Varnode::space_id(decompiler, ram);
+                }
+            }
+            let packed = inst.to_packed_pcode(decompiler).unwrap();
+            let unpacked = LiftedInstruction::from_packed_pcode(&packed, decompiler);
+            assert_eq!(unpacked, Ok(inst));
+        }
+        assert!(!data.is_empty());
+    }
+}
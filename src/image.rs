@@ -0,0 +1,163 @@
+//! Load images made of several segments, each with its own access permissions.
+
+use std::fmt;
+use std::ops::BitOr;
+
+use crate::{ffi, LoadImage};
+
+/// Read/write/execute access rights
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Permissions(u32);
+
+impl Permissions {
+    pub const NONE: Self = Self(0);
+    pub const READ: Self = Self(1);
+    pub const WRITE: Self = Self(2);
+    pub const EXECUTE: Self = Self(4);
+    pub const READ_WRITE: Self = Self(1 | 2);
+    pub const READ_EXECUTE: Self = Self(1 | 4);
+    pub const ALL: Self = Self(1 | 2 | 4);
+
+    pub fn from_bits(bits: u32) -> Self {
+        Self(bits & Self::ALL.0)
+    }
+
+    pub fn bits(self) -> u32 {
+        self.0
+    }
+
+    /// Whether every right in `other` is granted
+    pub fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl BitOr for Permissions {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
+impl fmt::Debug for Permissions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self)
+    }
+}
+
+/// `rwx` notation, with `-` for missing rights
+impl fmt::Display for Permissions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let flag = |p, c| if self.contains(p) { c } else { '-' };
+        write!(
+            f,
+            "{}{}{}",
+            flag(Self::READ, 'r'),
+            flag(Self::WRITE, 'w'),
+            flag(Self::EXECUTE, 'x')
+        )
+    }
+}
+
+/// A contiguous range of an image's default address space
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Segment {
+    pub name: String,
+    pub start: u64,
+    pub size: u64,
+    pub permissions: Permissions,
+}
+
+impl Segment {
+    pub fn end(&self) -> u64 {
+        self.start.wrapping_add(self.size)
+    }
+
+    pub fn contains(&self, addr: u64) -> bool {
+        addr >= self.start && addr - self.start < self.size
+    }
+}
+
+/// A load image assembled from segments. Bytes outside every segment read as zero.
+#[derive(Debug, Clone, Default)]
+pub struct SegmentedImage {
+    segments: Vec<(Segment, Vec<u8>)>,
+}
+
+impl SegmentedImage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a segment holding `data`. Its size is that of `data`.
+    pub fn add_segment(
+        &mut self,
+        name: &str,
+        start: u64,
+        data: Vec<u8>,
+        permissions: Permissions,
+    ) -> &mut Self {
+        let segment = Segment {
+            name: name.to_string(),
+            start,
+            size: data.len() as u64,
+            permissions,
+        };
+        self.segments.push((segment, data));
+        self
+    }
+
+    /// The segment containing `addr`
+    pub fn segment_at(&self, addr: u64) -> Option<&Segment> {
+        self.segments
+            .iter()
+            .map(|(s, _)| s)
+            .find(|s| s.contains(addr))
+    }
+}
+
+impl LoadImage for SegmentedImage {
+    fn load_fill(&mut self, ptr: &mut [u8], addr: &ffi::Address) {
+        let start = addr.getOffset();
+        ptr.fill(0);
+        for (segment, data) in &self.segments {
+            for (i, b) in ptr.iter_mut().enumerate() {
+                let addr = start.wrapping_add(i as u64);
+                if segment.contains(addr) {
+                    *b = data[(addr - segment.start) as usize];
+                }
+            }
+        }
+    }
+
+    fn segments(&self) -> Vec<Segment> {
+        self.segments.iter().map(|(s, _)| s.clone()).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_permissions() {
+        let rx = Permissions::READ | Permissions::EXECUTE;
+        assert_eq!(rx, Permissions::READ_EXECUTE);
+        assert!(rx.contains(Permissions::EXECUTE));
+        assert!(!rx.contains(Permissions::READ_WRITE));
+        assert_eq!(rx.to_string(), "r-x");
+        assert_eq!(Permissions::from_bits(0xff), Permissions::ALL);
+    }
+
+    #[test]
+    fn test_segments() {
+        let mut image = SegmentedImage::new();
+        image
+            .add_segment(".text", 0x1000, vec![1, 2, 3], Permissions::READ_EXECUTE)
+            .add_segment(".data", 0x2000, vec![4; 0x10], Permissions::READ_WRITE);
+        assert_eq!(image.segment_at(0x1002).unwrap().name, ".text");
+        assert!(image.segment_at(0x1003).is_none());
+        assert_eq!(image.segments().len(), 2);
+    }
+}
@@ -0,0 +1,105 @@
+//! Instruction set mode switches for languages that decode several ISAs.
+//!
+//! Each helper sets the context variables the corresponding Ghidra processor module uses, and
+//! fails with an "Unknown context variable" error when the loaded language doesn't define
+//! them.
+
+use std::ops::Range;
+use std::pin::Pin;
+
+use crate::ffi;
+
+/// ARM: Thumb instead of ARM encoding
+pub const ARM_THUMB: &str = "TMode";
+/// MIPS: MIPS16e (or microMIPS, depending on the language variant) instead of MIPS32/64
+pub const MIPS_ISA_MODE: &str = "ISA_MODE";
+/// x86: address size, 0 for 16-bit, 1 for 32-bit, 2 for 64-bit
+pub const X86_ADDRSIZE: &str = "addrsize";
+/// x86: default operand size, 0 for 16-bit and 1 for 32-bit
+pub const X86_OPSIZE: &str = "opsize";
+/// x86-64: long mode
+pub const X86_LONG_MODE: &str = "longMode";
+
+/// Operating mode of an x86 processor
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Bits {
+    B16,
+    B32,
+    B64,
+}
+
+impl Bits {
+    fn addrsize(self) -> u32 {
+        match self {
+            Bits::B16 => 0,
+            Bits::B32 => 1,
+            Bits::B64 => 2,
+        }
+    }
+
+    fn opsize(self) -> u32 {
+        match self {
+            Bits::B16 => 0,
+            // Operands stay 32 bits wide in long mode unless REX.W says otherwise
+            Bits::B32 | Bits::B64 => 1,
+        }
+    }
+}
+
+impl ffi::Decompiler {
+    /// Decode `range` as Thumb (`true`) or ARM (`false`) code
+    pub fn set_thumb(
+        self: Pin<&mut Self>,
+        range: Range<u64>,
+        thumb: bool,
+    ) -> Result<(), cxx::Exception> {
+        self.set_context_range(ARM_THUMB, range.start, range.end, thumb as u32)
+    }
+
+    /// Decode `range` as MIPS16e (`true`) or standard MIPS (`false`) code
+    pub fn set_mips16(
+        self: Pin<&mut Self>,
+        range: Range<u64>,
+        mips16: bool,
+    ) -> Result<(), cxx::Exception> {
+        self.set_context_range(MIPS_ISA_MODE, range.start, range.end, mips16 as u32)
+    }
+
+    /// Decode all code in the given x86 mode. 64-bit mode needs an x86-64 language.
+    pub fn set_x86_mode(mut self: Pin<&mut Self>, bits: Bits) -> Result<(), cxx::Exception> {
+        if bits == Bits::B64 || self.has_context_variable(X86_LONG_MODE) {
+            self.as_mut()
+                .set_context_everywhere(X86_LONG_MODE, (bits == Bits::B64) as u32)?;
+        }
+        self.as_mut()
+            .set_context_everywhere(X86_ADDRSIZE, bits.addrsize())?;
+        self.set_context_everywhere(X86_OPSIZE, bits.opsize())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::toy;
+
+    #[test]
+    fn test_context_everywhere() {
+        let mut program = toy::program(0x1000, &[0; 0x10]);
+        program
+            .set_context_range("mode", 0x1004, 0x1008, 0)
+            .unwrap();
+        program.set_context_everywhere("mode", 1).unwrap();
+        let decompiler = program.decompiler();
+        assert!(decompiler.has_context_variable("mode"));
+        assert!(!decompiler.has_context_variable(ARM_THUMB));
+        for addr in [0, 0x1000, 0x1004, 0x1008, 0xffff_0000] {
+            assert_eq!(decompiler.disassemble_one(addr).unwrap().0, "alt");
+        }
+
+        // The toy language has none of the ISA switches
+        assert!(program.set_thumb(0x1000..0x1004, true).is_err());
+        assert!(program.set_mips16(0x1000..0x1004, true).is_err());
+        assert!(program.set_x86_mode(Bits::B64).is_err());
+        assert!(program.set_context_everywhere("bogus", 1).is_err());
+    }
+}
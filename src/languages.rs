@@ -0,0 +1,470 @@
+//! Language id resolution through Ghidra's `.ldefs` files.
+//!
+//! Every processor module ships a `.ldefs` file describing the languages it provides. Each
+//! language is identified by an id like `x86:LE:64:default` and names the `.sla`, `.pspec`
+//! and `.cspec` files that implement it. A [`SpecLocator`] gathers the `.ldefs` files of an
+//! existing Ghidra installation.
+
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use cxx::UniquePtr;
+
+use crate::ffi;
+use crate::xml::XmlElement;
+use crate::{RustConstantPool, RustLoadImage};
+
+#[derive(Debug)]
+pub enum LanguageError {
+    Io(PathBuf, std::io::Error),
+    Xml(String),
+    MissingAttribute {
+        tag: String,
+        attribute: &'static str,
+    },
+    UnknownLanguage(String),
+    UnknownCompiler {
+        language: String,
+        compiler: String,
+    },
+    Spec(PathBuf, String),
+    /// The directory doesn't look like a Ghidra installation
+    NoProcessors(PathBuf),
+    /// No spec file of this kind, like `sla`, was given
+    MissingSpec(&'static str),
+}
+
+impl fmt::Display for LanguageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LanguageError::Io(path, err) => write!(f, "{}: {}", path.display(), err),
+            LanguageError::Xml(msg) => write!(f, "malformed language definitions: {}", msg),
+            LanguageError::MissingAttribute { tag, attribute } => {
+                write!(f, "<{}> is missing attribute `{}`", tag, attribute)
+            }
+            LanguageError::UnknownLanguage(id) => write!(f, "unknown language id `{}`", id),
+            LanguageError::UnknownCompiler { language, compiler } => {
+                write!(f, "language `{}` has no compiler `{}`", language, compiler)
+            }
+            LanguageError::Spec(path, msg) => write!(f, "{}: {}", path.display(), msg),
+            LanguageError::NoProcessors(path) => {
+                write!(f, "no processor modules found in {}", path.display())
+            }
+            LanguageError::MissingSpec(kind) => write!(f, "no .{} file given", kind),
+        }
+    }
+}
+
+impl std::error::Error for LanguageError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            LanguageError::Io(_, err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Endian {
+    Little,
+    Big,
+}
+
+/// A `<compiler>` entry of a language
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompilerDescription {
+    pub id: String,
+    pub name: String,
+    /// Path of the `.cspec` file
+    pub spec: PathBuf,
+}
+
+/// A `<language>` entry of a `.ldefs` file. Spec file names are resolved against the
+/// directory containing the `.ldefs` file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LanguageDescription {
+    pub id: String,
+    pub processor: String,
+    pub endian: Endian,
+    pub size: u32,
+    pub variant: String,
+    pub version: String,
+    pub description: String,
+    pub deprecated: bool,
+    /// Path of the compiled `.sla` file
+    pub sla_file: PathBuf,
+    /// Path of the `.pspec` file
+    pub processor_spec: PathBuf,
+    pub compilers: Vec<CompilerDescription>,
+}
+
+/// The set of spec files needed to instantiate a language
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LanguageFiles {
+    pub sla: PathBuf,
+    pub pspec: PathBuf,
+    pub cspec: Option<PathBuf>,
+}
+
+impl LanguageFiles {
+    /// Instantiate a decompiler for this language. The `.pspec` context defaults (e.g.
+    /// `addrsize`, `opsize`, `longMode`) are applied to its context database.
+    ///
+    /// # Safety
+    ///
+    /// `load_image` must outlive the returned decompiler.
+    pub unsafe fn new_decompiler(
+        &self,
+        load_image: *mut RustLoadImage,
+    ) -> Result<UniquePtr<ffi::Decompiler>, LanguageError> {
+        let sla = read_spec(&self.sla)?;
+        cxx::let_cxx_string!(sla = sla);
+        let mut decompiler = ffi::newDocumentStorage(&sla)
+            .and_then(|spec| ffi::newDecompiler(load_image, spec))
+            .map_err(|e| LanguageError::Spec(self.sla.clone(), e.what().to_string()))?;
+        let pspec = read_spec(&self.pspec)?;
+        decompiler
+            .pin_mut()
+            .apply_processor_spec(&pspec)
+            .map_err(|e| LanguageError::Spec(self.pspec.clone(), e.what().to_string()))?;
+        Ok(decompiler)
+    }
+
+    /// Instantiate a disassembler for this language, with the `.pspec` context defaults
+    /// applied like [`new_decompiler`](Self::new_decompiler) does
+    ///
+    /// # Safety
+    ///
+    /// `load_image` must outlive the returned disassembler.
+    pub unsafe fn new_disassembler(
+        &self,
+        load_image: *mut RustLoadImage,
+    ) -> Result<UniquePtr<ffi::Disassembler>, LanguageError> {
+        let sla = read_spec(&self.sla)?;
+        cxx::let_cxx_string!(sla = sla);
+        let mut disassembler = ffi::newDocumentStorage(&sla)
+            .and_then(|spec| ffi::newDisassembler(load_image, spec))
+            .map_err(|e| LanguageError::Spec(self.sla.clone(), e.what().to_string()))?;
+        let pspec = read_spec(&self.pspec)?;
+        disassembler
+            .pin_mut()
+            .apply_processor_spec(&pspec)
+            .map_err(|e| LanguageError::Spec(self.pspec.clone(), e.what().to_string()))?;
+        Ok(disassembler)
+    }
+
+    /// Set up the full decompiler, which turns functions into C, for this language. Needs a
+    /// `.cspec`. Constant pool references are resolved through `pool` if it isn't null.
+    ///
+    /// # Safety
+    ///
+    /// `load_image` and `pool` must outlive the returned architecture.
+    pub unsafe fn new_architecture(
+        &self,
+        load_image: *mut RustLoadImage,
+        pool: *mut RustConstantPool,
+    ) -> Result<UniquePtr<ffi::DecompilerArchitecture>, LanguageError> {
+        let cspec_path = self
+            .cspec
+            .as_ref()
+            .ok_or(LanguageError::MissingSpec("cspec"))?;
+        let sla = read_spec(&self.sla)?;
+        let pspec = read_spec(&self.pspec)?;
+        let cspec = read_spec(cspec_path)?;
+        cxx::let_cxx_string!(sla = sla);
+        cxx::let_cxx_string!(pspec = pspec);
+        cxx::let_cxx_string!(cspec = cspec);
+        ffi::newDecompilerArchitecture(load_image, pool, &sla, &pspec, &cspec)
+            .map_err(|e| LanguageError::Spec(cspec_path.clone(), e.what().to_string()))
+    }
+}
+
+fn read_spec(path: &Path) -> Result<String, LanguageError> {
+    std::fs::read_to_string(path).map_err(|e| LanguageError::Io(path.to_path_buf(), e))
+}
+
+fn required<'a>(el: &'a XmlElement, attribute: &'static str) -> Result<&'a str, LanguageError> {
+    el.attribute(attribute)
+        .ok_or_else(|| LanguageError::MissingAttribute {
+            tag: el.name.clone(),
+            attribute,
+        })
+}
+
+impl LanguageDescription {
+    fn from_xml(el: &XmlElement, base: &Path) -> Result<Self, LanguageError> {
+        let endian = match required(el, "endian")? {
+            "big" => Endian::Big,
+            _ => Endian::Little,
+        };
+        let size = required(el, "size")?.parse().map_err(|_| {
+            LanguageError::Xml(format!("bad size in language {:?}", el.attribute("id")))
+        })?;
+        let compilers = el
+            .children_named("compiler")
+            .map(|c| {
+                Ok(CompilerDescription {
+                    id: required(c, "id")?.to_string(),
+                    name: c.attribute("name").unwrap_or_default().to_string(),
+                    spec: base.join(required(c, "spec")?),
+                })
+            })
+            .collect::<Result<_, LanguageError>>()?;
+        Ok(Self {
+            id: required(el, "id")?.to_string(),
+            processor: required(el, "processor")?.to_string(),
+            endian,
+            size,
+            variant: el.attribute("variant").unwrap_or("default").to_string(),
+            version: el.attribute("version").unwrap_or_default().to_string(),
+            description: el
+                .child("description")
+                .map(|d| d.content.trim().to_string())
+                .unwrap_or_default(),
+            deprecated: el.attribute("deprecated") == Some("true"),
+            sla_file: base.join(required(el, "slafile")?),
+            processor_spec: base.join(required(el, "processorspec")?),
+            compilers,
+        })
+    }
+
+    /// The compiler spec called `id`, or the first one when `id` is `None`
+    pub fn compiler(&self, id: Option<&str>) -> Option<&CompilerDescription> {
+        match id {
+            Some(id) => self.compilers.iter().find(|c| c.id == id),
+            None => self
+                .compilers
+                .iter()
+                .find(|c| c.id == "default")
+                .or_else(|| self.compilers.first()),
+        }
+    }
+}
+
+/// A collection of languages gathered from one or more `.ldefs` files
+#[derive(Debug, Clone, Default)]
+pub struct LanguageDefinitions {
+    languages: Vec<LanguageDescription>,
+}
+
+impl LanguageDefinitions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Read a `.ldefs` file, resolving spec names relative to its directory
+    pub fn load_file<P: AsRef<Path>>(&mut self, path: P) -> Result<(), LanguageError> {
+        let path = path.as_ref();
+        let text = read_spec(path)?;
+        self.load_str(&text, path.parent().unwrap_or_else(|| Path::new("")))
+    }
+
+    /// Parse `.ldefs` content, resolving spec names relative to `base`
+    pub fn load_str<P: AsRef<Path>>(&mut self, text: &str, base: P) -> Result<(), LanguageError> {
+        let root = XmlElement::parse(text).map_err(|e| LanguageError::Xml(e.what().to_string()))?;
+        if root.name != "language_definitions" {
+            return Err(LanguageError::Xml(format!(
+                "expected <language_definitions>, found <{}>",
+                root.name
+            )));
+        }
+        for el in root.children_named("language") {
+            let lang = LanguageDescription::from_xml(el, base.as_ref())?;
+            self.languages.retain(|l| l.id != lang.id);
+            self.languages.push(lang);
+        }
+        Ok(())
+    }
+
+    pub fn languages(&self) -> &[LanguageDescription] {
+        &self.languages
+    }
+
+    /// Look up a language by its exact id
+    pub fn find(&self, id: &str) -> Option<&LanguageDescription> {
+        self.languages.iter().find(|l| l.id == id)
+    }
+
+    /// Resolve an id to its spec files. The id may carry a fifth `:compiler` component
+    /// (e.g. `x86:LE:64:default:gcc`) to pick a specific `.cspec`; otherwise the language's
+    /// default compiler is used.
+    pub fn resolve(&self, id: &str) -> Result<LanguageFiles, LanguageError> {
+        let (lang_id, compiler) = match self.find(id) {
+            Some(_) => (id, None),
+            None => match id.rsplit_once(':') {
+                Some((lang, compiler)) if self.find(lang).is_some() => (lang, Some(compiler)),
+                _ => return Err(LanguageError::UnknownLanguage(id.to_string())),
+            },
+        };
+        let lang = self.find(lang_id).unwrap();
+        let cspec = match compiler {
+            Some(c) => Some(
+                lang.compiler(Some(c))
+                    .ok_or_else(|| LanguageError::UnknownCompiler {
+                        language: lang.id.clone(),
+                        compiler: c.to_string(),
+                    })?
+                    .spec
+                    .clone(),
+            ),
+            None => lang.compiler(None).map(|c| c.spec.clone()),
+        };
+        Ok(LanguageFiles {
+            sla: lang.sla_file.clone(),
+            pspec: lang.processor_spec.clone(),
+            cspec,
+        })
+    }
+}
+
+/// Environment variable naming the Ghidra installation used by [`SpecLocator::from_env`]
+pub const GHIDRA_INSTALL_DIR: &str = "GHIDRA_INSTALL_DIR";
+
+/// Finds the processor modules of a Ghidra installation. Each module keeps its `.ldefs`,
+/// `.sla` and spec files in `Ghidra/Processors/<name>/data/languages`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SpecLocator {
+    root: PathBuf,
+}
+
+impl SpecLocator {
+    /// Search the installation at `root`. A `Processors` directory can be given directly too.
+    pub fn new<P: Into<PathBuf>>(root: P) -> Self {
+        Self { root: root.into() }
+    }
+
+    /// Search the installation named by the `GHIDRA_INSTALL_DIR` environment variable
+    pub fn from_env() -> Option<Self> {
+        std::env::var_os(GHIDRA_INSTALL_DIR).map(Self::new)
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    fn processors_dir(&self) -> Result<PathBuf, LanguageError> {
+        let installed = self.root.join("Ghidra").join("Processors");
+        if installed.is_dir() {
+            Ok(installed)
+        } else if self.root.file_name() == Some("Processors".as_ref()) && self.root.is_dir() {
+            Ok(self.root.clone())
+        } else {
+            Err(LanguageError::NoProcessors(self.root.clone()))
+        }
+    }
+
+    /// Directories of the installed processor modules, sorted by name
+    pub fn processors(&self) -> Result<Vec<PathBuf>, LanguageError> {
+        let dir = self.processors_dir()?;
+        let entries = std::fs::read_dir(&dir).map_err(|e| LanguageError::Io(dir.clone(), e))?;
+        let mut processors = Vec::new();
+        for entry in entries {
+            let path = entry.map_err(|e| LanguageError::Io(dir.clone(), e))?.path();
+            if path.join("data").join("languages").is_dir() {
+                processors.push(path);
+            }
+        }
+        processors.sort();
+        Ok(processors)
+    }
+
+    /// Every `.ldefs` file of every processor module
+    pub fn ldefs_files(&self) -> Result<Vec<PathBuf>, LanguageError> {
+        let mut files = Vec::new();
+        for processor in self.processors()? {
+            let dir = processor.join("data").join("languages");
+            let entries = std::fs::read_dir(&dir).map_err(|e| LanguageError::Io(dir.clone(), e))?;
+            for entry in entries {
+                let path = entry.map_err(|e| LanguageError::Io(dir.clone(), e))?.path();
+                if path.extension() == Some("ldefs".as_ref()) {
+                    files.push(path);
+                }
+            }
+        }
+        files.sort();
+        Ok(files)
+    }
+
+    /// Load the language definitions of every processor module
+    pub fn load(&self) -> Result<LanguageDefinitions, LanguageError> {
+        let mut defs = LanguageDefinitions::new();
+        for file in self.ldefs_files()? {
+            defs.load_file(file)?;
+        }
+        Ok(defs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LDEFS: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<language_definitions>
+  <language processor="x86" endian="little" size="64" variant="default" version="2.9"
+            slafile="x86-64.sla" processorspec="x86-64.pspec" id="x86:LE:64:default">
+    <description>Intel/AMD 64-bit x86</description>
+    <compiler name="Visual Studio" spec="x86-64-win.cspec" id="windows"/>
+    <compiler name="gcc" spec="x86-64-gcc.cspec" id="gcc"/>
+  </language>
+</language_definitions>
+"#;
+
+    #[test]
+    fn test_resolve() {
+        let mut defs = LanguageDefinitions::new();
+        defs.load_str(LDEFS, "/ghidra/x86/data/languages").unwrap();
+        let lang = defs.find("x86:LE:64:default").unwrap();
+        assert_eq!(lang.size, 64);
+        assert_eq!(lang.endian, Endian::Little);
+        assert_eq!(lang.description, "Intel/AMD 64-bit x86");
+
+        let files = defs.resolve("x86:LE:64:default").unwrap();
+        let base = Path::new("/ghidra/x86/data/languages");
+        assert_eq!(files.sla, base.join("x86-64.sla"));
+        assert_eq!(files.pspec, base.join("x86-64.pspec"));
+        assert_eq!(files.cspec, Some(base.join("x86-64-win.cspec")));
+
+        let files = defs.resolve("x86:LE:64:default:gcc").unwrap();
+        assert_eq!(files.cspec, Some(base.join("x86-64-gcc.cspec")));
+
+        assert!(matches!(
+            defs.resolve("ARM:LE:32:v7"),
+            Err(LanguageError::UnknownLanguage(_))
+        ));
+        assert!(matches!(
+            defs.resolve("x86:LE:64:default:clang"),
+            Err(LanguageError::UnknownCompiler { .. })
+        ));
+    }
+
+    #[test]
+    fn test_locator() {
+        let root = std::env::temp_dir().join(format!("sleigh-sys-ghidra-{}", std::process::id()));
+        let languages = root.join("Ghidra/Processors/x86/data/languages");
+        std::fs::create_dir_all(&languages).unwrap();
+        std::fs::create_dir_all(root.join("Ghidra/Processors/empty")).unwrap();
+        std::fs::write(languages.join("x86.ldefs"), LDEFS).unwrap();
+
+        let locator = SpecLocator::new(&root);
+        assert_eq!(
+            locator.processors().unwrap(),
+            vec![root.join("Ghidra/Processors/x86")]
+        );
+        let defs = locator.load().unwrap();
+        let files = defs.resolve("x86:LE:64:default").unwrap();
+        assert_eq!(files.sla, languages.join("x86-64.sla"));
+
+        let processors = SpecLocator::new(root.join("Ghidra/Processors"));
+        assert_eq!(
+            processors.ldefs_files().unwrap(),
+            vec![languages.join("x86.ldefs")]
+        );
+
+        assert!(matches!(
+            SpecLocator::new(&languages).load(),
+            Err(LanguageError::NoProcessors(_))
+        ));
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -1,11 +1,56 @@
+use std::ops::ControlFlow;
+
 use cxx::CxxString;
-use cxx::CxxVector;
-use cxx::UniquePtr;
-use std::os::raw::c_char;
 
 use num_derive::FromPrimitive;
 
-#[derive(Debug, FromPrimitive)]
+#[cfg(feature = "rkyv")]
+pub mod archive;
+pub mod blocks;
+pub mod builder;
+pub mod cache;
+pub mod cancel;
+#[cfg(feature = "capi")]
+pub mod capi;
+pub mod compile;
+pub mod cspec;
+mod decompiler;
+pub mod disassembler;
+pub mod emulator;
+pub mod error;
+pub mod eval;
+#[cfg(feature = "arbitrary")]
+pub mod fuzz;
+pub mod image;
+pub mod isa;
+pub mod languages;
+pub mod messages;
+pub mod parallel;
+pub mod pcode;
+pub mod project;
+pub mod protocol;
+pub mod segment;
+#[cfg(test)]
+mod snapshot;
+pub mod spaces;
+pub mod ssa;
+pub mod structure;
+pub mod sweep;
+pub mod symbols;
+#[cfg(test)]
+mod toy;
+pub mod version;
+pub mod wire;
+pub mod xml;
+
+pub use error::{SleighError, UnknownValue};
+use messages::report_decompiler_message;
+use protocol::RustProtocolStream;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, FromPrimitive)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[non_exhaustive]
 pub enum SpaceType {
     Constant = 0,
     Processor = 1,
@@ -22,7 +67,29 @@ impl SpaceType {
     }
 }
 
-#[derive(Debug, FromPrimitive)]
+impl TryFrom<u32> for SpaceType {
+    type Error = UnknownValue;
+
+    fn try_from(val: u32) -> Result<Self, UnknownValue> {
+        Self::from_u32(val).ok_or(UnknownValue(val))
+    }
+}
+
+impl From<SpaceType> for u32 {
+    fn from(space_type: SpaceType) -> u32 {
+        space_type as u32
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, FromPrimitive)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(
+    feature = "rkyv",
+    derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize),
+    rkyv(compare(PartialEq), derive(Debug))
+)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[non_exhaustive]
 pub enum Opcode {
     Copy = 1,
     ///< Copy one operand to another
@@ -177,10 +244,112 @@ pub enum Opcode {
     Max = 73,
 }
 
+impl TryFrom<u32> for Opcode {
+    type Error = UnknownValue;
+
+    fn try_from(val: u32) -> Result<Self, UnknownValue> {
+        Self::from_u32(val).ok_or(UnknownValue(val))
+    }
+}
+
+impl From<Opcode> for u32 {
+    fn from(opcode: Opcode) -> u32 {
+        opcode as u32
+    }
+}
+
 impl Opcode {
     pub fn from_u32(val: u32) -> Option<Self> {
         num::FromPrimitive::from_u32(val)
     }
+
+    /// The name Ghidra uses for the opcode, like `INT_ADD` or `CBRANCH`. Empty for
+    /// [`Opcode::Max`].
+    pub fn name(self) -> &'static str {
+        match self {
+            // The engine's table calls these after the SLEIGH compiler's placeholders for them
+            Opcode::MultiEqual => "MULTIEQUAL",
+            Opcode::Indirect => "INDIRECT",
+            Opcode::PtrAdd => "PTRADD",
+            Opcode::PtrSub => "PTRSUB",
+            _ => ffi::getOpName(self as u32),
+        }
+    }
+
+    /// BRANCH, CBRANCH and BRANCHIND
+    pub fn is_branch(self) -> bool {
+        matches!(self, Opcode::Branch | Opcode::CBranch | Opcode::BranchInd)
+    }
+
+    /// CALL and CALLIND
+    pub fn is_call(self) -> bool {
+        matches!(self, Opcode::Call | Opcode::CallInd)
+    }
+
+    pub fn is_return(self) -> bool {
+        matches!(self, Opcode::Return)
+    }
+
+    /// Ops whose destination is a varnode's value rather than the varnode itself
+    pub fn is_indirect(self) -> bool {
+        matches!(self, Opcode::BranchInd | Opcode::CallInd | Opcode::Return)
+    }
+
+    /// Branches, calls and returns
+    pub fn is_flow(self) -> bool {
+        self.is_branch() || self.is_call() || self.is_return()
+    }
+
+    /// Whether execution can go on with the next op. Calls fall through once the callee
+    /// returns.
+    pub fn has_fall_through(self) -> bool {
+        !matches!(self, Opcode::Branch | Opcode::BranchInd | Opcode::Return)
+    }
+
+    pub fn is_memory_access(self) -> bool {
+        matches!(self, Opcode::Load | Opcode::Store)
+    }
+
+    /// Ops producing a boolean from two values
+    pub fn is_comparison(self) -> bool {
+        matches!(
+            self,
+            Opcode::IntEqual
+                | Opcode::IntNotEqual
+                | Opcode::IntSLess
+                | Opcode::IntSLessEqual
+                | Opcode::IntLess
+                | Opcode::IntLessEqual
+                | Opcode::IntCarry
+                | Opcode::IntSCarry
+                | Opcode::IntSBorrow
+                | Opcode::FloatEqual
+                | Opcode::FloatNotEqual
+                | Opcode::FloatLess
+                | Opcode::FloatLessEqual
+        )
+    }
+
+    /// Ops on booleans
+    pub fn is_bool_op(self) -> bool {
+        matches!(
+            self,
+            Opcode::BoolNegate | Opcode::BoolXor | Opcode::BoolAnd | Opcode::BoolOr
+        )
+    }
+
+    /// Ops reading or producing floating-point values, including conversions
+    pub fn is_float_op(self) -> bool {
+        (Opcode::FloatEqual as u32..=Opcode::FloatRound as u32).contains(&(self as u32))
+    }
+
+    /// Ops the decompiler introduces during analysis, never found in lifted pcode
+    pub fn is_analysis_only(self) -> bool {
+        matches!(
+            self,
+            Opcode::MultiEqual | Opcode::Indirect | Opcode::Cast | Opcode::PtrAdd | Opcode::PtrSub
+        )
+    }
 }
 
 //unsafe impl cxx::ExternType for ffi::spacetype {
@@ -214,23 +383,49 @@ pub trait PCodeEmit {
     /// - address: the address of the machine instruction
     /// - opcode: the opcode of the particular pcode instruction
     /// - outvar: a data about the output varnode
-    /// - vars: an array of VarnodeData for each input varnode
+    /// - vars: the VarnodeData of each input varnode
+    ///
+    /// Returning `ControlFlow::Break` drops the remaining ops of the instruction, see
+    /// [`ffi::Decompiler::emit_pcode`].
     fn dump(
         &mut self,
         address: &ffi::Address,
         opcode: Opcode,
         outvar: Option<&ffi::VarnodeData>,
-        vars: &[ffi::VarnodeData],
-    );
+        vars: &[&ffi::VarnodeData],
+    ) -> ControlFlow<()>;
+
+    /// Callback for ops whose opcode has no [`Opcode`] variant, like one added by a newer
+    /// engine, in place of [`dump`](Self::dump). Returning `ControlFlow::Break` stops the
+    /// translation and makes [`ffi::Decompiler::emit_pcode`] fail with
+    /// [`DecodeReason::UnknownOpcode`](error::DecodeReason::UnknownOpcode), which is what
+    /// happens unless it is overridden; return `Continue` to skip or handle these ops.
+    fn dump_unknown(
+        &mut self,
+        _address: &ffi::Address,
+        _opcode: u32,
+        _outvar: Option<&ffi::VarnodeData>,
+        _vars: &[&ffi::VarnodeData],
+    ) -> ControlFlow<()> {
+        ControlFlow::Break(())
+    }
 }
 
 pub struct RustPCodeEmit<'a> {
     pub internal: &'a mut dyn PCodeEmit,
+    inputs: Vec<*const ffi::VarnodeData>,
+    /// The unknown opcode the emitter stopped on, if it did
+    unknown: Option<u32>,
 }
 
 pub trait LoadImage {
     fn load_fill(&mut self, ptr: &mut [u8], addr: &ffi::Address);
     fn adjust_vma(&mut self, _adjust: isize) {}
+    /// The image's segments and their access rights. Images without segments are treated as
+    /// a flat, fully accessible memory.
+    fn segments(&self) -> Vec<image::Segment> {
+        Vec::new()
+    }
 }
 
 pub struct RustLoadImage<'a> {
@@ -252,9 +447,43 @@ impl<'a> RustLoadImage<'a> {
     }
 }
 
+/// Resolves the constant pool references of managed-code languages (JVM, Dalvik, CLI), whose
+/// specs turn instructions like `ldc` or `getfield` into `cpool` ops
+pub trait ConstantPool {
+    /// The record for `refs`, the inputs of a `cpool` op after the first one. `None` leaves the
+    /// reference unresolved.
+    fn resolve(&mut self, refs: &[u64]) -> Option<ffi::PoolRecord>;
+}
+
+pub struct RustConstantPool {
+    internal: Box<dyn ConstantPool>,
+}
+
+impl RustConstantPool {
+    pub fn new(internal: impl ConstantPool + 'static) -> Self {
+        Self {
+            internal: Box::new(internal),
+        }
+    }
+
+    fn resolve(&mut self, refs: &[u64], record: &mut ffi::PoolRecord) -> bool {
+        match self.internal.resolve(refs) {
+            Some(resolved) => {
+                *record = resolved;
+                true
+            }
+            None => false,
+        }
+    }
+}
+
 impl<'a> RustPCodeEmit<'a> {
     pub fn from_internal(internal: &'a mut dyn PCodeEmit) -> Self {
-        Self { internal }
+        Self {
+            internal,
+            inputs: Vec::new(),
+            unknown: None,
+        }
     }
 
     unsafe fn dump(
@@ -264,19 +493,39 @@ impl<'a> RustPCodeEmit<'a> {
         outvar: *const ffi::VarnodeData,
         vars: *const ffi::VarnodeData,
         size: i32,
-    ) {
+    ) -> bool {
         let outvar = if outvar.is_null() {
             None
         } else {
             Some(&*outvar)
         };
-        let vars = std::slice::from_raw_parts(vars, size as usize);
-        let opcode = num::FromPrimitive::from_u32(opcode).unwrap();
-        self.internal.dump(address, opcode, outvar, vars);
+        // VarnodeData is opaque to Rust, so the array is indexed on the C++ side. The pointers
+        // are collected in a buffer kept across ops rather than a new vector each time.
+        self.inputs.clear();
+        self.inputs
+            .extend((0..size).map(|i| ffi::getVarnodeDataAt(vars, i)));
+        // References and non-null pointers have the same layout, and the varnodes live until
+        // the callback returns
+        let vars: &[&ffi::VarnodeData] =
+            std::slice::from_raw_parts(self.inputs.as_ptr().cast(), self.inputs.len());
+        match Opcode::from_u32(opcode) {
+            Some(opcode) => self
+                .internal
+                .dump(address, opcode, outvar, vars)
+                .is_continue(),
+            None => {
+                let flow = self.internal.dump_unknown(address, opcode, outvar, vars);
+                if flow.is_break() {
+                    self.unknown = Some(opcode);
+                }
+                flow.is_continue()
+            }
+        }
     }
 }
 
 #[cxx::bridge]
+#[allow(clippy::missing_safety_doc)]
 pub mod ffi {
     extern "Rust" {
         type RustAssemblyEmit<'a>;
@@ -290,12 +539,627 @@ pub mod ffi {
             outvar: *const VarnodeData,
             vars: *const VarnodeData,
             size: i32,
-        );
+        ) -> bool;
 
         type RustLoadImage<'a>;
         unsafe fn load_fill(self: &mut RustLoadImage, ptr: *mut u8, size: u32, addr: &Address);
         //fn get_arch_type(self: &RustLoadImage) -> String;
         fn adjust_vma(self: &mut RustLoadImage, adjust: isize);
+
+        type RustConstantPool;
+        fn resolve(self: &mut RustConstantPool, refs: &[u64], record: &mut PoolRecord) -> bool;
+
+        fn report_decompiler_message(function: u64, analysing: bool, text: &CxxString);
+
+        type RustProtocolStream<'a>;
+        fn read(self: &mut RustProtocolStream, buf: &mut [u8]) -> Result<usize>;
+        fn write(self: &mut RustProtocolStream, buf: &[u8]) -> Result<()>;
+        fn flush(self: &mut RustProtocolStream) -> Result<()>;
+    }
+
+    /// A register as defined by the spec
+    #[derive(Debug, Clone)]
+    pub struct RegisterInfo {
+        pub name: String,
+        /// Index of the register's address space
+        pub space: i32,
+        pub offset: u64,
+        pub size: u32,
+    }
+
+    /// A context variable defined by the spec
+    #[derive(Debug, Clone)]
+    pub struct ContextVariableInfo {
+        pub name: String,
+        /// The context register holding the variable
+        pub context_register: String,
+        /// First bit of the variable, counted from the most significant bit of the context
+        pub start_bit: u32,
+        /// Last bit of the variable, inclusive
+        pub end_bit: u32,
+        /// Value at addresses where the variable wasn't set
+        pub default_value: u32,
+        /// Whether values set at an address flow to the instructions following it
+        pub flow: bool,
+    }
+
+    /// A context change made by an instruction through `globalset`
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct ContextCommitInfo {
+        pub variable: String,
+        pub value: u32,
+        /// Index of the address space where the change takes effect
+        pub space: i32,
+        /// Address where the change takes effect
+        pub address: u64,
+        /// Whether the value holds from `address` up to the next change point, rather than for
+        /// the instruction at `address` only
+        pub flow: bool,
+    }
+
+    /// An operand of a decoded instruction and the bytes encoding it
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct OperandSpanInfo {
+        /// Name of the operand in the constructor
+        pub name: String,
+        /// The operand as printed in the disassembly
+        pub text: String,
+        /// 0 for the instruction's operands, 1 for operands of their subconstructors and so on
+        pub depth: u32,
+        /// Index in the list of the operand this one belongs to, -1 at depth 0
+        pub parent: i32,
+        /// First byte of the operand, counted from the start of the instruction
+        pub offset: u32,
+        /// Bytes spanned by the operand, which may overlap those of other operands
+        pub length: u32,
+        /// Whether the operand is the value of a single token field, described below
+        pub field: bool,
+        /// First byte holding the field, counted from the start of the instruction
+        pub field_offset: u32,
+        pub field_length: u32,
+        /// Position of the field in the value read from its bytes
+        pub shift: u32,
+        pub bits: u32,
+        /// Whether the field's bytes are read most significant first
+        pub big_endian: bool,
+        pub sign_extended: bool,
+        /// What the operand resolves to
+        pub kind: OperandKind,
+        /// The register, immediate in the constant space, address or temporary the operand
+        /// resolves to. For a dynamic operand, only the space and size of the data pointed to.
+        pub value: PackedVarnode,
+        /// Where the pointer of a dynamic operand is held
+        pub pointer: PackedVarnode,
+    }
+
+    /// A constructor of the `.slaspec` that matched while decoding an instruction
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct ConstructorInfo {
+        /// Name of the table the constructor belongs to, `instruction` for the root
+        pub table: String,
+        /// Index of the constructor in its table
+        pub id: u32,
+        /// The `.slaspec` or included file the constructor was written in, as passed to the
+        /// compiler
+        pub file: String,
+        /// Line of the constructor in `file`
+        pub line: i32,
+        /// The constructor's display section, with its operands filled in
+        pub text: String,
+        /// 0 for the instruction's constructor, 1 for constructors of its operands and so on
+        pub depth: u32,
+        /// Index in the list of the constructor this one is an operand of, -1 at depth 0
+        pub parent: i32,
+        /// Index of that operand in the parent constructor, -1 at depth 0
+        pub operand: i32,
+        /// First byte matched by the constructor, counted from the start of the instruction
+        pub offset: u32,
+        pub length: u32,
+    }
+
+    /// An edge leaving a basic block of a decompiled function
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct BlockEdgeInfo {
+        /// Index of the block the edge goes to
+        pub target: u32,
+        /// Whether the edge goes back to the head of a loop
+        pub back_edge: bool,
+        /// Whether the edge didn't fit the structure and is printed as a `goto`
+        pub unstructured: bool,
+    }
+
+    /// A basic block of a decompiled function
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct BasicBlockInfo {
+        pub index: u32,
+        pub start: u64,
+        /// Last byte of the block's code
+        pub stop: u64,
+        /// Index of the block's immediate dominator, -1 for the entry block
+        pub immediate_dominator: i32,
+        pub successors: Vec<BlockEdgeInfo>,
+    }
+
+    /// A block of the control flow structure the decompiler recovered
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct StructureNodeInfo {
+        pub kind: StructureKind,
+        /// Index in the list of the enclosing block, -1 for the root
+        pub parent: i32,
+        /// Index of the basic block a `Copy` stands for, -1 for other kinds
+        pub basic: i32,
+        pub start: u64,
+        pub stop: u64,
+    }
+
+    /// The control flow of a decompiled function
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct FunctionGraphInfo {
+        /// Basic blocks in the decompiler's order, the entry block first
+        pub blocks: Vec<BasicBlockInfo>,
+        /// The structure tree, each block before the blocks it contains
+        pub structure: Vec<StructureNodeInfo>,
+    }
+
+    /// The kind of a [`StructureNodeInfo`], in the order of the decompiler's
+    /// `FlowBlock::block_type`
+    #[derive(Debug)]
+    #[repr(u32)]
+    enum StructureKind {
+        Plain,
+        Basic,
+        /// A graph of blocks not (yet) collapsed into a single structure
+        Graph,
+        /// A leaf standing for a basic block
+        Copy,
+        /// A block ending in a `goto`, `break` or `continue`
+        Goto,
+        MultiGoto,
+        /// A sequence of blocks run one after the other
+        List,
+        /// Two conditions joined by `&&` or `||`
+        Condition,
+        If,
+        WhileDo,
+        DoWhile,
+        Switch,
+        InfiniteLoop,
+    }
+
+    /// A varnode of a function in SSA form: written by at most one op
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct SsaVarnodeInfo {
+        pub storage: PackedVarnode,
+        /// Index of the op writing the varnode, -1 for inputs and constants
+        pub definition: i32,
+        /// Indices of the ops reading the varnode
+        pub uses: Vec<u32>,
+        /// Whether the varnode holds a value the function is entered with
+        pub input: bool,
+    }
+
+    /// An op of a function in SSA form
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct SsaOpInfo {
+        pub opcode: u32,
+        /// Address of the instruction the op was lifted from
+        pub address: u64,
+        /// Index of the basic block holding the op
+        pub block: u32,
+        /// Index of the varnode written, -1 if none
+        pub output: i32,
+        /// Indices of the varnodes read. The first input of a `LOAD` or `STORE` is a constant
+        /// holding the index of the space accessed, and the first input of a `CALL` is the
+        /// function called. An `INDIRECT` only lists the value it may
+        /// change, the op causing the change is in `cause`.
+        pub inputs: Vec<u32>,
+        /// For an `INDIRECT`, the index of the op that may change its input, -1 otherwise
+        pub cause: i32,
+        /// For a `MULTIEQUAL`, the block each input flows in from, empty otherwise
+        pub input_blocks: Vec<u32>,
+    }
+
+    /// The SSA form of an analysed function
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct FunctionSsaInfo {
+        /// Live ops, block by block in execution order
+        pub ops: Vec<SsaOpInfo>,
+        /// Varnodes in the order the ops first mention them
+        pub varnodes: Vec<SsaVarnodeInfo>,
+    }
+
+    /// A field of a structure
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct FieldInfo {
+        pub name: String,
+        /// Offset of the field in the structure. When defining one, -1 places the field after
+        /// the previous one, aligned.
+        pub offset: i32,
+        pub type_name: String,
+    }
+
+    /// A data type known to the decompiler
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct TypeInfo {
+        /// The name as the decompiler prints it, like `int4`, `point *` or `char [16]`
+        pub name: String,
+        pub kind: TypeKind,
+        pub size: u32,
+        /// The type pointed to or of the elements, empty for other kinds
+        pub element: String,
+        /// Number of elements of an array, 0 for other kinds
+        pub length: u32,
+        /// Fields of a structure, by offset
+        pub fields: Vec<FieldInfo>,
+    }
+
+    /// The kind of a [`TypeInfo`], with the values of the decompiler's `type_metatype`
+    #[derive(Debug)]
+    #[repr(u32)]
+    enum TypeKind {
+        Struct = 0,
+        /// A part of a structure, stored on its own
+        PartialStruct = 1,
+        Array = 2,
+        /// A pointer to an offset into another type
+        RelativePointer = 3,
+        Pointer = 4,
+        Float = 5,
+        Code = 6,
+        Bool = 7,
+        Uint = 8,
+        Int = 9,
+        /// Bytes without a known meaning, treated as unsigned
+        Unknown = 10,
+        Spacebase = 11,
+        Void = 12,
+    }
+
+    /// Where a comment shows in decompiled code, with the values of the decompiler's comment
+    /// types
+    #[derive(Debug)]
+    #[repr(u32)]
+    enum CommentKind {
+        /// Before the statements of its address
+        Pre = 2,
+        /// Also before the statements, the decompiler has no place after them
+        Post = 4,
+        /// In the header of the function
+        Plate = 8,
+    }
+
+    /// An entry of a managed-code constant pool, see [`ConstantPool`](crate::ConstantPool)
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct PoolRecord {
+        pub tag: PoolTag,
+        /// Name of the method, field or class, or the language's token for the check
+        pub token: String,
+        /// The constant itself, for [`PoolTag::Primitive`]
+        pub value: u64,
+        /// Type of the constant, method, field or check result, looked up like `getTypeInfo`
+        /// does
+        pub type_name: String,
+        /// The characters of a [`PoolTag::StringLiteral`], which needs them
+        pub byte_data: Vec<u8>,
+    }
+
+    /// What a [`PoolRecord`] describes, with the values of the decompiler's record tags
+    #[derive(Debug)]
+    #[repr(u32)]
+    enum PoolTag {
+        /// A constant value, which replaces the reference
+        Primitive = 0,
+        StringLiteral = 1,
+        ClassReference = 2,
+        PointerMethod = 3,
+        PointerField = 4,
+        ArrayLength = 5,
+        InstanceOf = 6,
+        CheckCast = 7,
+    }
+
+    /// A switch the decompiler recovered from an indirect branch
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct JumpTableInfo {
+        /// Address of the instruction doing the indirect branch
+        pub address: u64,
+        /// Where the branch goes, one entry per index into the table
+        pub targets: Vec<u64>,
+        /// The value of the switch variable leading to each target, empty if the decompiler
+        /// couldn't work them out. A default target found in a guard before the branch is
+        /// appended to the targets with the placeholder label `0xbad1abe1`.
+        pub labels: Vec<u64>,
+        /// The variable the switch is on, before the arithmetic turning it into an index; -1
+        /// for the space if the table was not normalized
+        pub switch_variable: PackedVarnode,
+        /// Bits of the switch variable the table depends on
+        pub switch_mask: u64,
+        /// Whether values out of the table's range go somewhere else than its targets
+        pub has_default: bool,
+        pub default_target: u64,
+    }
+
+    /// A parameter or the return value in the prototype of a decompiled function
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct ParameterInfo {
+        /// Empty for the return value
+        pub name: String,
+        /// The data-type as the decompiler prints it, like `int4` or `char *`
+        pub type_name: String,
+        pub storage: PackedVarnode,
+    }
+
+    /// A variable of a decompiled function, made of the varnodes the decompiler merged
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct VariableInfo {
+        /// Empty for temporaries, which are only named while printing
+        pub name: String,
+        pub kind: VariableKind,
+        /// Where the variable lives, the storage of one of its varnodes for temporaries
+        pub storage: PackedVarnode,
+        pub type_name: String,
+        /// Position in the prototype of a parameter, -1 for other kinds
+        pub parameter: i32,
+    }
+
+    /// The prototype and variables of a decompiled function
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct FunctionVariablesInfo {
+        /// The calling convention the parameters were recovered with
+        pub model: String,
+        pub return_value: ParameterInfo,
+        pub parameters: Vec<ParameterInfo>,
+        pub variables: Vec<VariableInfo>,
+    }
+
+    /// A parameter of a [`PrototypeSpec`]
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct ParameterSpec {
+        pub name: String,
+        pub type_name: String,
+    }
+
+    /// A signature to lock onto a function before decompiling it
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct PrototypeSpec {
+        /// Name of the calling convention, empty for the default one
+        pub model: String,
+        pub return_type: String,
+        pub parameters: Vec<ParameterSpec>,
+        pub variadic: bool,
+    }
+
+    /// The kind of a [`VariableInfo`]
+    #[derive(Debug)]
+    #[repr(u32)]
+    enum VariableKind {
+        Parameter,
+        Local,
+        Global,
+        /// An intermediate result without a symbol
+        Temporary,
+    }
+
+    /// A global symbol of the `.slaspec`
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct SymbolInfo {
+        pub name: String,
+        pub kind: SymbolKind,
+        /// Unique among the symbols of the language
+        pub id: u32,
+        /// The register of a varnode symbol, or the register holding a context field or bit
+        /// range; the space is -1 for other symbols
+        pub varnode: PackedVarnode,
+        /// The index of a user op or space, or the number of constructors of a subtable; 0 for
+        /// other symbols
+        pub index: u32,
+        /// First and last bit of a context field or bit range in its register
+        pub low: u32,
+        pub high: u32,
+    }
+
+    /// The kind of a [`SymbolInfo`]
+    #[derive(Debug)]
+    #[repr(u32)]
+    enum SymbolKind {
+        Space,
+        Token,
+        /// A `define pcodeop`
+        UserOp,
+        /// A token or context field read as a plain value
+        Value,
+        /// A field mapped to values with `attach values`
+        ValueMap,
+        /// A field mapped to names with `attach names`
+        Name,
+        /// A register
+        Varnode,
+        /// A field mapped to registers with `attach variables`
+        VarnodeList,
+        Operand,
+        /// `inst_start`
+        Start,
+        /// `inst_next`
+        End,
+        Subtable,
+        Macro,
+        Section,
+        Bitrange,
+        /// A field of a context register
+        Context,
+        Epsilon,
+        Label,
+        Dummy,
+    }
+
+    /// The kind of value an operand of a decoded instruction resolves to
+    #[derive(Debug)]
+    #[repr(u32)]
+    enum OperandKind {
+        /// The operand exports nothing, like a subconstructor without `export`
+        None,
+        Register,
+        Immediate,
+        /// A fixed location outside the registers, like a branch target
+        Address,
+        /// A value computed into the unique space by the operand's constructor
+        Temporary,
+        /// Data at an address only known at run time, like `[rs]`
+        Dynamic,
+    }
+
+    /// An address space of the language
+    #[derive(Debug, Clone)]
+    pub struct SpaceInfo {
+        pub name: String,
+        pub index: i32,
+        /// A [`SpaceType`](crate::SpaceType) value
+        pub space_type: u32,
+        pub word_size: u32,
+        pub addr_size: u32,
+        pub big_endian: bool,
+        /// Index of the containing space of a spacebase space, -1 for other spaces
+        pub contain: i32,
+        /// Index of the space an overlay space overlays, -1 for other spaces
+        pub overlay_base: i32,
+        /// Whether some overlay space overlays this one
+        pub overlaid: bool,
+    }
+
+    /// A register acting as the base of a spacebase space, like the stack pointer
+    #[derive(Debug, Clone)]
+    pub struct SpacebaseInfo {
+        /// Index of the spacebase space
+        pub space: i32,
+        pub register_name: String,
+        /// Index of the register's address space
+        pub register_space: i32,
+        pub offset: u64,
+        pub size: u32,
+        pub stack_grows_negative: bool,
+    }
+
+    /// A user-defined op (CALLOTHER) the emulator stopped at
+    #[derive(Debug, Clone)]
+    pub struct CallOtherInfo {
+        /// Whether the last step stopped at a user-defined op at all
+        pub pending: bool,
+        pub name: String,
+        pub index: i32,
+        /// Values of the op's inputs; inputs wider than 8 bytes read as 0
+        pub inputs: Vec<u64>,
+        /// Size of the output, 0 if the op has none
+        pub output_size: u32,
+    }
+
+    /// A memory access refused by the emulator's permission checks
+    #[derive(Debug, Clone)]
+    pub struct MemoryFaultInfo {
+        /// 1 for reads, 2 for writes, 4 for instruction fetches; 0 if there was no fault
+        pub access: u32,
+        pub space: i32,
+        pub offset: u64,
+        pub size: u32,
+        /// Address of the faulting instruction
+        pub pc: u64,
+        /// Whether the address has permissions at all
+        pub mapped: bool,
+    }
+
+    /// A varnode of a [`PackedInstruction`]
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct PackedVarnode {
+        /// Index of the address space, -1 if it has none
+        pub space: i32,
+        pub offset: u64,
+        pub size: u32,
+    }
+
+    /// An op of a [`PackedInstruction`]. Its inputs are the `input_count` varnodes of
+    /// `inputs` from `first_input` on.
+    #[derive(Debug, Clone, Copy)]
+    pub struct PackedOp {
+        pub opcode: u32,
+        pub has_output: bool,
+        pub output: PackedVarnode,
+        pub first_input: u32,
+        pub input_count: u32,
+    }
+
+    /// The pcode of an instruction, collected on the C++ side and handed over at once
+    #[derive(Debug, Clone)]
+    pub struct PackedInstruction {
+        /// Length of the instruction, 0 if it couldn't be translated
+        pub length: i32,
+        pub ops: Vec<PackedOp>,
+        pub inputs: Vec<PackedVarnode>,
+        /// Address of the instruction each op was built for, which is another one for the ops
+        /// of a delay slot. Empty if all ops come from the instruction itself.
+        pub origins: Vec<u64>,
+        /// The `length` bytes the instruction was decoded from
+        pub bytes: Vec<u8>,
+    }
+
+    /// Why the engine couldn't translate or disassemble an instruction
+    #[derive(Debug)]
+    #[repr(u32)]
+    enum TranslateError {
+        None,
+        /// The bytes don't match any constructor
+        BadData,
+        /// The instruction decodes but its semantics are marked `unimpl`
+        Unimplemented,
+        /// The load image has no bytes at the address
+        Unavailable,
+        /// The instruction has more pcode ops than the op limit
+        OpLimit,
+        /// The emit asked to stop; the length is set
+        Stopped,
+        /// Any other error raised by the engine
+        Engine,
+    }
+
+    /// How the decompiler lifts instructions whose semantics the spec marks `unimpl`
+    #[derive(Debug)]
+    #[repr(u32)]
+    enum UnimplementedPolicy {
+        /// Fail with [`DecodeReason::Unimplemented`](crate::error::DecodeReason::Unimplemented)
+        Error,
+        /// Lift the instruction to a single CALLOTHER of the user op
+        /// [`unimplemented_userop`](Decompiler::unimplemented_userop)
+        CallOther,
+        /// Lift the instruction to no pcode at all, like a `nop`
+        Skip,
+    }
+
+    /// Outcome of translating or disassembling one instruction
+    #[derive(Debug, Clone)]
+    pub struct TranslateStatus {
+        /// Length of the instruction; also set for unimplemented ones, 0 otherwise
+        pub length: i32,
+        pub error: TranslateError,
+        pub message: String,
+    }
+
+    /// Version of the decompiler engine, see [`version`](crate::version)
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+    pub struct EngineVersion {
+        pub major: u32,
+        pub minor: u32,
+    }
+
+    /// A LOAD or STORE executed by the emulator
+    #[derive(Debug, Clone)]
+    pub struct MemoryAccessInfo {
+        /// 1 for reads, 2 for writes
+        pub access: u32,
+        pub space: i32,
+        pub offset: u64,
+        pub size: u32,
+        /// Value loaded or stored; 0 for accesses wider than 8 bytes
+        pub value: u64,
+        /// Address of the instruction containing the op
+        pub pc: u64,
     }
 
     unsafe extern "C++" {
@@ -305,6 +1169,7 @@ pub mod ffi {
         fn isInvalid(self: &Address) -> bool;
         fn getAddrSize(self: &Address) -> i32;
         fn isBigEndian(self: &Address) -> bool;
+        /// Null for invalid addresses. See `Decompiler::address_space` for a safe version.
         fn getSpace(self: &Address) -> *mut AddrSpace;
         fn getOffset(self: &Address) -> u64;
         fn toPhysical(self: Pin<&mut Address>);
@@ -326,11 +1191,18 @@ pub mod ffi {
         type VarnodeData;
         fn getVarnodeDataAddress(data: &VarnodeData) -> UniquePtr<Address>;
         fn getVarnodeSize(data: &VarnodeData) -> u32;
+        unsafe fn getVarnodeDataAt(vars: *const VarnodeData, i: i32) -> *const VarnodeData;
+        fn packVarnode(data: &VarnodeData) -> PackedVarnode;
+        /// # Safety
+        ///
+        /// `vars` must point to an array of at least `size` varnodes.
+        unsafe fn packVarnodes(vars: *const VarnodeData, size: i32) -> Vec<PackedVarnode>;
+        /// Distance in bytes between consecutive varnodes of an array
+        fn getVarnodeDataStride() -> usize;
 
         type spacetype;
         type AddrSpace;
         fn getName(self: &AddrSpace) -> &CxxString;
-        //fn getType(self: &AddrSpace) -> spacetype;
         fn getDelay(self: &AddrSpace) -> i32;
         fn getDeadcodeDelay(self: &AddrSpace) -> i32;
         fn getIndex(self: &AddrSpace) -> i32;
@@ -369,31 +1241,602 @@ pub mod ffi {
         fn getDefaultValue(self: &ContextDatabase, nm: &CxxString) -> u32;
         fn setVariable(self: Pin<&mut ContextDatabase>, nm: &CxxString, addr: &Address, val: u32);
         fn getVariable(self: &ContextDatabase, nm: &CxxString, addr: &Address) -> u32;
+        fn setVariableRegion(
+            self: Pin<&mut ContextDatabase>,
+            nm: &CxxString,
+            begad: &Address,
+            endad: &Address,
+            value: u32,
+        ) -> Result<()>;
+        fn setContextChangePoint(
+            self: Pin<&mut ContextDatabase>,
+            addr: &Address,
+            num: i32,
+            mask: u32,
+            value: u32,
+        );
+        fn setContextRegion(
+            self: Pin<&mut ContextDatabase>,
+            addr1: &Address,
+            addr2: &Address,
+            num: i32,
+            mask: u32,
+            value: u32,
+        );
+        fn getContextSize(self: &ContextDatabase) -> i32;
 
         fn newAddress() -> UniquePtr<Address>;
+        fn copyAddress(addr: &Address) -> UniquePtr<Address>;
         fn newContext() -> UniquePtr<ContextDatabase>;
-        fn newDocumentStorage(s: &CxxString) -> UniquePtr<DocumentStorage>;
+        fn newDocumentStorage(s: &CxxString) -> Result<UniquePtr<DocumentStorage>>;
 
+        type Document;
+        type Element;
+        fn parseDocument(s: &CxxString) -> Result<UniquePtr<Document>>;
+        fn getDocumentRoot(doc: &Document) -> &Element;
+        fn getName(self: &Element) -> &CxxString;
+        fn getContent(self: &Element) -> &CxxString;
+        fn getNumAttributes(self: &Element) -> i32;
+        fn getAttributeName(self: &Element, i: i32) -> &CxxString;
+        fn getAttributeValue(self: &Element, i: i32) -> &CxxString;
+        fn getElementNumChildren(el: &Element) -> usize;
+        fn getElementChild(el: &Element, i: usize) -> &Element;
+
+        fn compileSlaspec(
+            input: &CxxString,
+            output: &CxxString,
+            names: &[&str],
+            values: &[&str],
+        ) -> Result<String>;
+        /// Version of the engine linked in
+        fn engineVersion() -> EngineVersion;
+        /// Version of the vendored engine, the one these bindings are written against
+        fn supportedEngineVersion() -> EngineVersion;
+        /// Answer the commands of a Ghidra client on `stream` until it deregisters a program
+        /// or closes the stream between commands. One session runs at a time.
+        ///
+        /// # Safety
+        ///
+        /// `stream` must be valid for the duration of the call.
+        unsafe fn serveGhidraProtocol(stream: *mut RustProtocolStream) -> Result<()>;
+
+        /// Use `AddrSpace::space_type` instead
         fn getAddrSpaceType(addr: &AddrSpace) -> u32;
+        /// The engine's name for `opcode`, empty if there is no such opcode
+        fn getOpName(opcode: u32) -> &'static str;
 
         type Decompiler;
-        unsafe fn translate(self: &Decompiler, emit: *mut RustPCodeEmit, addr: u64) -> i32;
-        unsafe fn disassemble(self: &Decompiler, emit: *mut RustAssemblyEmit, addr: u64) -> i32;
+        /// # Safety
+        ///
+        /// `emit` must point to a live `RustPCodeEmit` for the duration of the call.
+        unsafe fn translate(
+            self: &Decompiler,
+            emit: *mut RustPCodeEmit,
+            addr: u64,
+        ) -> TranslateStatus;
+        /// Parse the instruction at `addr` into the engine's cache. Returns its length, not
+        /// counting delay slots.
+        fn decodeInstruction(self: &Decompiler, addr: u64) -> TranslateStatus;
+        /// Translate the instruction at `addr` into `out`, reusing its vectors
+        fn translatePacked(
+            self: &Decompiler,
+            addr: u64,
+            out: &mut PackedInstruction,
+        ) -> TranslateStatus;
+        /// Translate the instruction at `addr` in the space with index `space` into `out`
+        fn translatePackedIn(
+            self: &Decompiler,
+            space: i32,
+            addr: u64,
+            out: &mut PackedInstruction,
+        ) -> TranslateStatus;
+        /// Fill `buf` with the bytes at `addr` in the default code space from the load image
+        fn fillBytes(self: &Decompiler, addr: u64, buf: &mut [u8]) -> Result<()>;
+        /// Fill `buf` with the bytes at `addr` in the space with index `space`
+        fn fillBytesIn(self: &Decompiler, space: i32, addr: u64, buf: &mut [u8]) -> Result<()>;
+        /// Most pcode ops a translated instruction may have, 0 for no limit
+        fn setOpLimit(self: Pin<&mut Decompiler>, limit: u32);
+        fn getOpLimit(self: &Decompiler) -> u32;
+        fn setUnimplementedPolicy(self: Pin<&mut Decompiler>, policy: UnimplementedPolicy);
+        fn getUnimplementedPolicy(self: &Decompiler) -> UnimplementedPolicy;
+        /// Index of the user op standing in for unimplemented instructions
+        fn getUnimplementedUserOp(self: &Decompiler) -> u32;
+        /// Emit the ops of `pcode` in place of those of the instruction at `addr`, taking it to
+        /// be `length` bytes long, or as long as the instruction decoded there if 0
+        fn setInjection(
+            self: Pin<&mut Decompiler>,
+            addr: u64,
+            length: u32,
+            pcode: &PackedInstruction,
+        ) -> Result<()>;
+        fn removeInjection(self: Pin<&mut Decompiler>, addr: u64) -> bool;
+        fn clearInjections(self: Pin<&mut Decompiler>);
+        /// The index of the user op `name`, adding it after the existing ones if there is none
+        fn addUserOp(self: Pin<&mut Decompiler>, name: &CxxString) -> Result<u32>;
+        /// The names of the user ops, by index
+        fn getUserOps(self: &Decompiler) -> Vec<String>;
+        /// # Safety
+        ///
+        /// `emit` must point to a live `RustAssemblyEmit` for the duration of the call.
+        unsafe fn disassemble(
+            self: &Decompiler,
+            emit: *mut RustAssemblyEmit,
+            addr: u64,
+        ) -> TranslateStatus;
+        /// # Safety
+        ///
+        /// The returned pointer is owned by the decompiler and must not outlive it.
         unsafe fn getContext(self: Pin<&mut Decompiler>) -> *mut ContextDatabase;
+        fn applyProcessorSpec(self: Pin<&mut Decompiler>, pspec: &Element) -> Result<()>;
+        /// Rebuild the context and instruction caches after the context database was changed
+        /// through `getContext`
+        fn resetCaches(self: Pin<&mut Decompiler>);
+        fn getCodeAddress(self: &Decompiler, offset: u64) -> UniquePtr<Address>;
+        fn getDataAddress(self: &Decompiler, offset: u64) -> UniquePtr<Address>;
+        /// The space instructions are read from. See `Decompiler::default_code_space` for a
+        /// safe version.
+        fn getDefaultCodeSpace(self: &Decompiler) -> *mut AddrSpace;
+        /// The space pointers into memory point to by default, which differs from the code
+        /// space on Harvard architectures
+        fn getDefaultDataSpace(self: &Decompiler) -> *mut AddrSpace;
+        fn getContextVariable(self: &Decompiler, name: &CxxString, addr: u64) -> Result<u32>;
+        /// The context blob at `addr` of the default code space, one entry per 32-bit word
+        fn getContextWords(self: &Decompiler, addr: u64) -> Vec<u32>;
+        fn setContextVariableRange(
+            self: Pin<&mut Decompiler>,
+            name: &CxxString,
+            start: u64,
+            end: u64,
+            value: u32,
+        ) -> Result<()>;
+        fn setContextVariableFrom(
+            self: Pin<&mut Decompiler>,
+            name: &CxxString,
+            start: u64,
+            value: u32,
+        ) -> Result<()>;
+        fn setContextVariableEverywhere(
+            self: Pin<&mut Decompiler>,
+            name: &CxxString,
+            value: u32,
+        ) -> Result<()>;
+        fn hasContextVariable(self: &Decompiler, name: &CxxString) -> bool;
+        fn getContextVariables(self: &Decompiler) -> Vec<ContextVariableInfo>;
+        fn getContextCommits(self: &Decompiler, addr: u64) -> Result<Vec<ContextCommitInfo>>;
+        fn getOperandSpans(self: &Decompiler, addr: u64) -> Result<Vec<OperandSpanInfo>>;
+        fn getConstructors(self: &Decompiler, addr: u64) -> Result<Vec<ConstructorInfo>>;
+        /// The global symbol `name`, if any, as a list of at most one
+        fn findSymbolInfo(self: &Decompiler, name: &CxxString) -> Vec<SymbolInfo>;
+        fn getSymbols(self: &Decompiler) -> Vec<SymbolInfo>;
+        fn getRegisterList(self: &Decompiler) -> Vec<RegisterInfo>;
+        fn getConstantSpaceIndex(self: &Decompiler) -> i32;
+        fn getDefaultCodeSpaceIndex(self: &Decompiler) -> i32;
+        fn getDefaultDataSpaceIndex(self: &Decompiler) -> i32;
+        /// Instructions start at multiples of this many bytes
+        fn getInstructionAlignment(self: &Decompiler) -> i32;
+        /// Most bytes a single instruction can be decoded from
+        fn getMaxInstructionLength(self: &Decompiler) -> u32;
+        /// Most bytes any `delayslot` directive of the spec asks for
+        fn getMaxDelaySlotBytes(self: &Decompiler) -> u32;
+        /// Offset in the unique space above every temporary the spec uses
+        fn getUniqueBase(self: &Decompiler) -> u32;
+        /// Address bits mixed into the offsets of an instruction's temporaries
+        fn getUniqueAllocateMask(self: &Decompiler) -> u32;
+        /// The space encoded by the space-id input of a LOAD or STORE, null if `offset` isn't
+        /// one of this decompiler's spaces
+        fn getSpaceFromConst(self: &Decompiler, offset: u64) -> *const AddrSpace;
+        /// The space-id constant standing for the space at `index`, 0 if there is none
+        fn getSpaceConst(self: &Decompiler, index: i32) -> u64;
+        /// Name of the register stored exactly at the given varnode, empty if there is none
+        fn getExactRegisterName(self: &Decompiler, space: i32, offset: u64, size: i32) -> String;
+        /// Apply the unary op `opcode` to `input`, the way the emulator would
+        fn evaluateUnary(
+            self: &Decompiler,
+            opcode: u32,
+            sizeout: i32,
+            sizein: i32,
+            input: u64,
+        ) -> Result<u64>;
+        /// Apply the binary op `opcode` to `in1` and `in2`; `sizein` is the size of `in1`
+        fn evaluateBinary(
+            self: &Decompiler,
+            opcode: u32,
+            sizeout: i32,
+            sizein: i32,
+            in1: u64,
+            in2: u64,
+        ) -> Result<u64>;
+        /// Compile the pcode snippet `body` reading `inputs`, of the given `sizes`, and writing
+        /// `output`. Fails if it doesn't compile or uses something a snippet can't, like memory.
+        fn compileSnippet(
+            self: &Decompiler,
+            body: &CxxString,
+            inputs: &[String],
+            sizes: &[u32],
+            output: &CxxString,
+            outputSize: u32,
+        ) -> Result<UniquePtr<CompiledSnippet>>;
+        /// The context database as a `<context_points>` XML document
+        fn saveContext(self: &Decompiler) -> String;
+        /// Replace the context database with one saved by `saveContext`
+        fn restoreContext(self: Pin<&mut Decompiler>, el: &Element) -> Result<()>;
+        fn getSpaces(self: &Decompiler) -> Vec<SpaceInfo>;
+        fn getSpacebases(self: &Decompiler) -> Vec<SpacebaseInfo>;
+        /// Add the `stack` space over `base`, with `reg` as its stack pointer. Returns the
+        /// index of the new space.
+        fn addStackSpace(
+            self: Pin<&mut Decompiler>,
+            base: &CxxString,
+            reg: &CxxString,
+            growsNegative: bool,
+        ) -> Result<i32>;
+        /// Add an overlay space `name` over `base`. Returns the index of the new space.
+        fn addOverlaySpace(
+            self: Pin<&mut Decompiler>,
+            name: &CxxString,
+            base: &CxxString,
+        ) -> Result<i32>;
+        /// # Safety
+        ///
+        /// `loadImage` must point to a `RustLoadImage` that outlives the returned decompiler.
         unsafe fn newDecompiler(
             loadImage: *mut RustLoadImage,
             spec: UniquePtr<DocumentStorage>,
-        ) -> UniquePtr<Decompiler>;
+        ) -> Result<UniquePtr<Decompiler>>;
+
+        /// A pcode snippet compiled by `Decompiler::compileSnippet`, only valid as long as that
+        /// decompiler is
+        type CompiledSnippet;
+        /// Run the snippet with its inputs set to `values` and return its output
+        fn evaluate(self: Pin<&mut CompiledSnippet>, values: &[u64]) -> Result<u64>;
+
+        /// The part of `Decompiler` that decodes and prints instructions, without pcode
+        type Disassembler;
+        /// # Safety
+        ///
+        /// `emit` must point to a live `RustAssemblyEmit` for the duration of the call.
+        unsafe fn disassemble(
+            self: &Disassembler,
+            emit: *mut RustAssemblyEmit,
+            addr: u64,
+        ) -> TranslateStatus;
+        fn applyProcessorSpec(self: Pin<&mut Disassembler>, pspec: &Element) -> Result<()>;
+        fn getContextVariable(self: &Disassembler, name: &CxxString, addr: u64) -> Result<u32>;
+        fn setContextVariableRange(
+            self: Pin<&mut Disassembler>,
+            name: &CxxString,
+            start: u64,
+            end: u64,
+            value: u32,
+        ) -> Result<()>;
+        fn setContextVariableEverywhere(
+            self: Pin<&mut Disassembler>,
+            name: &CxxString,
+            value: u32,
+        ) -> Result<()>;
+        /// # Safety
+        ///
+        /// `loadImage` must point to a `RustLoadImage` that outlives the returned disassembler.
+        unsafe fn newDisassembler(
+            loadImage: *mut RustLoadImage,
+            spec: UniquePtr<DocumentStorage>,
+        ) -> Result<UniquePtr<Disassembler>>;
+
+        type DecompilerArchitecture;
+        /// # Safety
+        ///
+        /// `loadImage` must point to a `RustLoadImage` that outlives the returned architecture,
+        /// and `pool` be null or point to a `RustConstantPool` that does too.
+        unsafe fn newDecompilerArchitecture(
+            loadImage: *mut RustLoadImage,
+            pool: *mut RustConstantPool,
+            sla: &CxxString,
+            pspec: &CxxString,
+            cspec: &CxxString,
+        ) -> Result<UniquePtr<DecompilerArchitecture>>;
+        /// Decompile the function at `entry` in the default code space to C, running the root
+        /// action `style`. An empty `name` lets the decompiler pick one.
+        fn decompileFunction(
+            self: Pin<&mut DecompilerArchitecture>,
+            entry: u64,
+            name: &CxxString,
+            style: &CxxString,
+        ) -> Result<String>;
+        /// Decompile the function at `entry` and describe its blocks and their structure
+        fn getFunctionGraph(
+            self: Pin<&mut DecompilerArchitecture>,
+            entry: u64,
+            name: &CxxString,
+        ) -> Result<FunctionGraphInfo>;
+        /// Decompile the function at `entry` and describe its prototype and variables
+        fn getFunctionVariables(
+            self: Pin<&mut DecompilerArchitecture>,
+            entry: u64,
+            name: &CxxString,
+        ) -> Result<FunctionVariablesInfo>;
+        /// Analyse the function at `entry` in the given style and collect its SSA varnodes and
+        /// ops with their def-use links
+        fn getFunctionSsa(
+            self: Pin<&mut DecompilerArchitecture>,
+            entry: u64,
+            name: &CxxString,
+            style: &CxxString,
+        ) -> Result<FunctionSsaInfo>;
+        /// Decompile the function at `entry` and describe the jump tables it recovered
+        fn getJumpTables(
+            self: Pin<&mut DecompilerArchitecture>,
+            entry: u64,
+            name: &CxxString,
+        ) -> Result<Vec<JumpTableInfo>>;
+        /// Add an atomic type of the given kind, which must be one of the integer, float,
+        /// boolean or unknown ones
+        fn definePrimitive(
+            self: Pin<&mut DecompilerArchitecture>,
+            name: &CxxString,
+            size: u32,
+            kind: TypeKind,
+        ) -> Result<()>;
+        /// Add a structure with the given fields, at least `size` bytes long (0 to fit the
+        /// fields). A structure of that name can only be defined once.
+        fn defineStruct(
+            self: Pin<&mut DecompilerArchitecture>,
+            name: &CxxString,
+            size: u32,
+            fields: &[FieldInfo],
+        ) -> Result<()>;
+        /// Name the global variable at `addr` in the default data space and lock its type,
+        /// replacing any other variable there
+        fn setGlobal(
+            self: Pin<&mut DecompilerArchitecture>,
+            addr: u64,
+            name: &CxxString,
+            type_name: &CxxString,
+        ) -> Result<()>;
+        /// Describe the type of the given name. Types are looked up by name, followed by a `*`
+        /// for each level of pointer and `[n]` for arrays, as the decompiler prints them.
+        fn getTypeInfo(
+            self: Pin<&mut DecompilerArchitecture>,
+            name: &CxxString,
+        ) -> Result<TypeInfo>;
+        /// Attach a comment to `addr` in the function at `function`
+        fn addComment(
+            self: Pin<&mut DecompilerArchitecture>,
+            function: u64,
+            addr: u64,
+            kind: CommentKind,
+            text: &CxxString,
+        );
+        /// Remove the comments of every kind from the function at `function`
+        fn clearComments(self: Pin<&mut DecompilerArchitecture>, function: u64);
+        /// Lock the prototype of the function at `entry`, creating the function if needed. Types
+        /// are looked up like `getTypeInfo` does.
+        fn setPrototype(
+            self: Pin<&mut DecompilerArchitecture>,
+            entry: u64,
+            name: &CxxString,
+            prototype: &PrototypeSpec,
+        ) -> Result<()>;
+        /// Like `decompileFunction`, but list the function's pcode instead of printing C
+        fn getRawFunction(
+            self: Pin<&mut DecompilerArchitecture>,
+            entry: u64,
+            name: &CxxString,
+            style: &CxxString,
+        ) -> Result<String>;
+
+        type PcodeCacheEmulator;
+        /// # Safety
+        ///
+        /// `translator` must outlive the returned emulator.
+        unsafe fn newPcodeCacheEmulator(translator: &Decompiler) -> UniquePtr<PcodeCacheEmulator>;
+        /// Translate the instruction at `addr` and continue execution there
+        fn setExecuteAddress(self: Pin<&mut PcodeCacheEmulator>, addr: u64) -> Result<()>;
+        fn getExecuteAddress(self: &PcodeCacheEmulator) -> Result<u64>;
+        fn executeCurrentOp(self: Pin<&mut PcodeCacheEmulator>) -> Result<()>;
+        fn executeInstruction(self: Pin<&mut PcodeCacheEmulator>) -> Result<()>;
+        fn isInstructionStart(self: &PcodeCacheEmulator) -> bool;
+        fn numCurrentOps(self: &PcodeCacheEmulator) -> i32;
+        fn getCurrentOpIndex(self: &PcodeCacheEmulator) -> i32;
+        fn getHalt(self: &PcodeCacheEmulator) -> bool;
+        fn setHalt(self: Pin<&mut PcodeCacheEmulator>, halt: bool);
+        /// Halt before executing the instruction at `addr` of the default code space
+        fn addAddressBreak(self: Pin<&mut PcodeCacheEmulator>, addr: u64);
+        fn getValue(self: &PcodeCacheEmulator, space: i32, offset: u64, size: i32) -> Result<u64>;
+        fn setValue(
+            self: Pin<&mut PcodeCacheEmulator>,
+            space: i32,
+            offset: u64,
+            size: i32,
+            value: u64,
+        ) -> Result<()>;
+        fn getChunk(
+            self: &PcodeCacheEmulator,
+            space: i32,
+            offset: u64,
+            buf: &mut [u8],
+        ) -> Result<()>;
+        fn setChunk(
+            self: Pin<&mut PcodeCacheEmulator>,
+            space: i32,
+            offset: u64,
+            buf: &[u8],
+        ) -> Result<()>;
+        fn getRegisterValue(self: &PcodeCacheEmulator, name: &CxxString) -> Result<u64>;
+        fn setRegisterValue(
+            self: Pin<&mut PcodeCacheEmulator>,
+            name: &CxxString,
+            value: u64,
+        ) -> Result<()>;
+
+        type PcodeEmulator;
+        /// # Safety
+        ///
+        /// `loadImage` must point to a `RustLoadImage` that outlives the returned emulator.
+        unsafe fn newPcodeEmulator(
+            loadImage: *mut RustLoadImage,
+            spec: UniquePtr<DocumentStorage>,
+        ) -> Result<UniquePtr<PcodeEmulator>>;
+        fn setPc(self: Pin<&mut PcodeEmulator>, addr: u64);
+        fn getPc(self: &PcodeEmulator) -> u64;
+        fn stepOp(self: Pin<&mut PcodeEmulator>) -> Result<()>;
+        fn stepInstruction(self: Pin<&mut PcodeEmulator>) -> Result<()>;
+        fn isInstructionStart(self: &PcodeEmulator) -> bool;
+        fn isBigEndian(self: &PcodeEmulator) -> bool;
+        fn getRegisterValue(self: &PcodeEmulator, name: &CxxString) -> Result<u64>;
+        fn setRegisterValue(
+            self: Pin<&mut PcodeEmulator>,
+            name: &CxxString,
+            value: u64,
+        ) -> Result<()>;
+        fn getRegisterList(self: &PcodeEmulator) -> Vec<RegisterInfo>;
+        fn readSpace(self: &PcodeEmulator, space: i32, offset: u64, buf: &mut [u8]) -> Result<()>;
+        fn writeSpace(
+            self: Pin<&mut PcodeEmulator>,
+            space: i32,
+            offset: u64,
+            buf: &[u8],
+        ) -> Result<()>;
+        fn getDefaultDataSpaceIndex(self: &PcodeEmulator) -> i32;
+        fn applyProcessorSpec(self: Pin<&mut PcodeEmulator>, pspec: &Element) -> Result<()>;
+        fn setHistoryLimit(self: Pin<&mut PcodeEmulator>, limit: usize);
+        fn getHistoryLength(self: &PcodeEmulator) -> usize;
+        fn stepBackOp(self: Pin<&mut PcodeEmulator>) -> Result<bool>;
+        fn stepBackInstruction(self: Pin<&mut PcodeEmulator>) -> Result<bool>;
+        fn getDefaultCodeSpaceIndex(self: &PcodeEmulator) -> i32;
+        fn setPermissions(
+            self: Pin<&mut PcodeEmulator>,
+            space: i32,
+            start: u64,
+            size: u64,
+            flags: u32,
+        ) -> Result<()>;
+        fn setEnforcePermissions(self: Pin<&mut PcodeEmulator>, enforce: bool);
+        fn takeFault(self: Pin<&mut PcodeEmulator>) -> MemoryFaultInfo;
+        fn ignoreFault(self: Pin<&mut PcodeEmulator>);
+        fn getCodeInvalidations(self: &PcodeEmulator) -> u64;
+        fn getMemoryUsage(self: &PcodeEmulator) -> u64;
+        fn takeCallOther(self: Pin<&mut PcodeEmulator>) -> CallOtherInfo;
+        /// Finish the pending user-defined op, writing `value` to its output if `has_value`
+        fn completeCallOther(
+            self: Pin<&mut PcodeEmulator>,
+            has_value: bool,
+            value: u64,
+        ) -> Result<()>;
+        /// Break before the instruction at `addr` in the default code space runs
+        fn addBreakpoint(self: Pin<&mut PcodeEmulator>, addr: u64);
+        fn removeBreakpoint(self: Pin<&mut PcodeEmulator>, addr: u64) -> bool;
+        /// Whether the last step stopped at a breakpoint, clearing the flag
+        fn takeBreak(self: Pin<&mut PcodeEmulator>) -> bool;
+        /// Let the instruction at the breakpoint hit last run
+        fn passBreak(self: Pin<&mut PcodeEmulator>);
+        fn skipInstruction(self: Pin<&mut PcodeEmulator>) -> Result<()>;
+        /// Why the last step couldn't decode its instruction: 1 if it has no pcode, 2 if the
+        /// bytes don't decode, 0 if decoding didn't fail. Clears the flag.
+        fn takeDecodeFailure(self: Pin<&mut PcodeEmulator>) -> u32;
+        /// Record every LOAD and STORE executed from now on, or stop recording
+        fn setTraceMemory(self: Pin<&mut PcodeEmulator>, trace: bool);
+        /// The accesses recorded since the last call
+        fn takeMemoryAccesses(self: Pin<&mut PcodeEmulator>) -> Vec<MemoryAccessInfo>;
+
+        /// The state of a `PcodeEmulator` at some point of its execution
+        type EmulatorState;
+        /// Save the program counter, registers, temporaries and written memory pages
+        fn saveState(self: &PcodeEmulator) -> UniquePtr<EmulatorState>;
+        /// Go back to a state saved from this emulator
+        fn restoreState(self: Pin<&mut PcodeEmulator>, saved: &EmulatorState) -> Result<()>;
 
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::ffi;
+    use std::ops::ControlFlow;
+
+    use super::{ffi, Opcode, PCodeEmit, RustPCodeEmit, SpaceType, UnknownValue};
+    use crate::pcode::PcodeCollector;
+
     #[test]
     fn test_new() {
         let _a = ffi::newAddress();
         let _a = ffi::newContext();
     }
+
+    #[test]
+    fn test_opcode_predicates() {
+        assert!(Opcode::CBranch.is_branch() && Opcode::CBranch.has_fall_through());
+        assert!(!Opcode::BranchInd.has_fall_through() && Opcode::BranchInd.is_indirect());
+        assert!(Opcode::Call.is_call() && Opcode::Call.has_fall_through());
+        assert!(Opcode::Return.is_flow() && !Opcode::Return.has_fall_through());
+        assert!(!Opcode::CallOther.is_flow());
+        assert!(Opcode::FloatEqual.is_float_op() && Opcode::FloatEqual.is_comparison());
+        assert!(Opcode::FloatRound.is_float_op() && !Opcode::MultiEqual.is_float_op());
+        assert!(Opcode::MultiEqual.is_analysis_only() && !Opcode::Piece.is_analysis_only());
+        assert!(Opcode::BoolXor.is_bool_op() && Opcode::Store.is_memory_access());
+    }
+
+    #[test]
+    fn test_opcode_names() {
+        assert_eq!(Opcode::IntAdd.name(), "INT_ADD");
+        assert_eq!(Opcode::CBranch.name(), "CBRANCH");
+        assert_eq!(Opcode::FloatInt2Float.name(), "INT2FLOAT");
+        assert_eq!(Opcode::MultiEqual.name(), "MULTIEQUAL");
+        assert_eq!(Opcode::PopCount.name(), "POPCOUNT");
+        assert_eq!(Opcode::Max.name(), "");
+    }
+
+    #[test]
+    fn test_enum_conversions() {
+        assert_eq!(Opcode::try_from(19), Ok(Opcode::IntAdd));
+        assert_eq!(Opcode::try_from(0), Err(UnknownValue(0)));
+        assert_eq!(u32::from(Opcode::PopCount), 72);
+        assert_eq!(SpaceType::try_from(6), Ok(SpaceType::Join));
+        assert_eq!(
+            SpaceType::try_from(7).unwrap_err().to_string(),
+            "no variant for the value 7"
+        );
+    }
+
+    #[test]
+    fn test_unknown_opcode() {
+        struct Skip(Vec<u32>);
+
+        impl PCodeEmit for Skip {
+            fn dump(
+                &mut self,
+                _address: &ffi::Address,
+                _opcode: Opcode,
+                _outvar: Option<&ffi::VarnodeData>,
+                _vars: &[&ffi::VarnodeData],
+            ) -> ControlFlow<()> {
+                ControlFlow::Continue(())
+            }
+
+            fn dump_unknown(
+                &mut self,
+                _address: &ffi::Address,
+                opcode: u32,
+                _outvar: Option<&ffi::VarnodeData>,
+                _vars: &[&ffi::VarnodeData],
+            ) -> ControlFlow<()> {
+                self.0.push(opcode);
+                ControlFlow::Continue(())
+            }
+        }
+
+        let addr = ffi::newAddress();
+        let null = std::ptr::null();
+        // Unknown opcodes stop the translation unless the emitter opts into them
+        let mut collector = PcodeCollector::default();
+        let mut emit = RustPCodeEmit::from_internal(&mut collector);
+        assert!(unsafe { emit.dump(&addr, Opcode::Return as u32, null, null, 0) });
+        assert!(!unsafe { emit.dump(&addr, 999, null, null, 0) });
+        assert_eq!(emit.unknown, Some(999));
+        assert_eq!(collector.ops.len(), 1);
+
+        let mut skip = Skip(Vec::new());
+        let mut emit = RustPCodeEmit::from_internal(&mut skip);
+        assert!(unsafe { emit.dump(&addr, 999, null, null, 0) });
+        assert_eq!(emit.unknown, None);
+        assert_eq!(skip.0, [999]);
+    }
 }
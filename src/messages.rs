@@ -0,0 +1,169 @@
+//! Warnings and errors reported by the engine.
+//!
+//! The decompiler reports problems it works around, and the SLEIGH compiler warns about
+//! questionable specs, without failing. These messages go to the handler installed with
+//! [`set_handler`]. Without one they are logged through the `log` crate and emitted as
+//! `tracing` events when the features of those names are enabled, and dropped otherwise.
+
+use std::fmt;
+use std::sync::RwLock;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Level {
+    Warning,
+    Error,
+}
+
+/// The part of the engine a message comes from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Origin {
+    /// The decompiler, while analysing the function at `function` if it is set
+    Decompiler { function: Option<u64> },
+    /// The SLEIGH compiler, see [`compile_slaspec`](crate::compile::compile_slaspec)
+    Compiler,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Message {
+    pub level: Level,
+    pub origin: Origin,
+    pub text: String,
+}
+
+impl fmt::Display for Message {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.origin {
+            Origin::Decompiler {
+                function: Some(function),
+            } => write!(f, "{} (in function at {:#x})", self.text, function),
+            _ => f.write_str(&self.text),
+        }
+    }
+}
+
+type Handler = Box<dyn Fn(&Message) + Send + Sync>;
+
+static HANDLER: RwLock<Option<Handler>> = RwLock::new(None);
+
+/// Send the engine's messages to `handler` from now on, on whichever thread reports them. The
+/// handler must not install another one.
+pub fn set_handler(handler: impl Fn(&Message) + Send + Sync + 'static) {
+    *HANDLER.write().unwrap_or_else(|e| e.into_inner()) = Some(Box::new(handler));
+}
+
+/// Go back to logging the engine's messages
+pub fn clear_handler() {
+    *HANDLER.write().unwrap_or_else(|e| e.into_inner()) = None;
+}
+
+pub(crate) fn report(message: Message) {
+    let handler = HANDLER.read().unwrap_or_else(|e| e.into_inner());
+    match handler.as_ref() {
+        Some(handler) => handler(&message),
+        None => log(&message),
+    }
+}
+
+#[allow(unused_variables)]
+fn log(message: &Message) {
+    #[cfg(feature = "log")]
+    {
+        let level = match message.level {
+            Level::Warning => log::Level::Warn,
+            Level::Error => log::Level::Error,
+        };
+        match message.origin {
+            Origin::Decompiler { .. } => {
+                log::log!(target: "sleigh_sys::decompiler", level, "{}", message)
+            }
+            Origin::Compiler => log::log!(target: "sleigh_sys::compiler", level, "{}", message),
+        }
+    }
+    #[cfg(feature = "tracing")]
+    {
+        let function = match message.origin {
+            Origin::Decompiler { function } => function,
+            Origin::Compiler => None,
+        };
+        match (message.origin, message.level) {
+            (Origin::Decompiler { .. }, Level::Warning) => {
+                tracing::warn!(target: "sleigh_sys::decompiler", function, "{}", message.text)
+            }
+            (Origin::Decompiler { .. }, Level::Error) => {
+                tracing::error!(target: "sleigh_sys::decompiler", function, "{}", message.text)
+            }
+            (Origin::Compiler, Level::Warning) => {
+                tracing::warn!(target: "sleigh_sys::compiler", "{}", message.text)
+            }
+            (Origin::Compiler, Level::Error) => {
+                tracing::error!(target: "sleigh_sys::compiler", "{}", message.text)
+            }
+        }
+    }
+}
+
+/// Called by the decompiler for each message. `analysing` tells whether it came up while
+/// analysing the function at `function`.
+pub(crate) fn report_decompiler_message(function: u64, analysing: bool, text: &cxx::CxxString) {
+    let text = text.to_string_lossy().into_owned();
+    let level = if text.starts_with("ERROR") {
+        Level::Error
+    } else {
+        Level::Warning
+    };
+    report(Message {
+        level,
+        origin: Origin::Decompiler {
+            function: analysing.then_some(function),
+        },
+        text,
+    });
+}
+
+/// Report each line of the compiler's output
+pub(crate) fn report_compiler_output(level: Level, output: &str) {
+    for line in output.lines().filter(|line| !line.trim().is_empty()) {
+        report(Message {
+            level,
+            origin: Origin::Compiler,
+            text: line.to_string(),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use super::{Level, Message, Origin};
+    use crate::compile::compile_slaspec;
+
+    #[test]
+    fn test_handler() {
+        static MESSAGES: Mutex<Vec<Message>> = Mutex::new(Vec::new());
+        super::set_handler(|message| MESSAGES.lock().unwrap().push(message.clone()));
+
+        let dir = std::env::temp_dir().join(format!("sleigh-sys-messages-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let input = dir.join("bad.slaspec");
+        std::fs::write(
+            &input,
+            "define endian=little;\n\
+             define space ram type=ram_space size=4 default;\n\
+             define token instr(8) op = (0,7);\n\
+             :nop is op=0 { undefined_register = 1; }\n",
+        )
+        .unwrap();
+        compile_slaspec(&input, dir.join("bad.sla"), &[]).unwrap_err();
+        super::clear_handler();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let messages = MESSAGES.lock().unwrap();
+        let message = messages
+            .iter()
+            .find(|message| message.text.contains("undefined_register"))
+            .unwrap_or_else(|| panic!("{:?}", messages));
+        assert_eq!(message.level, Level::Error);
+        assert_eq!(message.origin, Origin::Compiler);
+    }
+}
@@ -0,0 +1,145 @@
+//! Lifting on several threads.
+//!
+//! A decompiler can't be shared between threads, so [`ParallelLifter`] gives each worker a
+//! [`Program`] of its own, built from the same language files, a clone of the load image and
+//! the same context. Ranges are handed out to idle workers one at a time and the results come
+//! back in the order the ranges were given. With the `rayon` feature,
+//! [`ParallelLifter::par_sweep`] runs on rayon's thread pool instead.
+
+use std::ops::Range;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+
+use crate::languages::LanguageFiles;
+use crate::project::Program;
+use crate::sweep::Sweep;
+use crate::{LoadImage, SleighError};
+
+pub struct ParallelLifter<L> {
+    language: LanguageFiles,
+    image: L,
+    context: Option<String>,
+    threads: usize,
+}
+
+impl<L: LoadImage + Clone + Send + Sync + 'static> ParallelLifter<L> {
+    /// Lift code from `image` with one worker per available CPU
+    pub fn new(language: LanguageFiles, image: L) -> Self {
+        Self {
+            language,
+            image,
+            context: None,
+            threads: thread::available_parallelism().map_or(1, |n| n.get()),
+        }
+    }
+
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.threads = threads.max(1);
+        self
+    }
+
+    /// Give every worker this context database, as saved by
+    /// [`ffi::Decompiler::save_context`](crate::ffi::Decompiler::save_context)
+    pub fn context(mut self, xml: impl Into<String>) -> Self {
+        self.context = Some(xml.into());
+        self
+    }
+
+    fn worker(&self) -> Result<Program, SleighError> {
+        let mut program = Program::new(self.language.clone(), self.image.clone())?;
+        if let Some(xml) = &self.context {
+            program.restore_context(xml)?;
+        }
+        Ok(program)
+    }
+
+    /// [`linear_sweep`](crate::ffi::Decompiler::linear_sweep) each of `ranges`
+    pub fn sweep(&self, ranges: &[Range<u64>]) -> Result<Vec<Sweep>, SleighError> {
+        let next = AtomicUsize::new(0);
+        let results = Mutex::new(vec![None; ranges.len()]);
+        let threads = self.threads.min(ranges.len());
+        thread::scope(|scope| {
+            let workers: Vec<_> = (0..threads)
+                .map(|_| {
+                    scope.spawn(|| -> Result<(), SleighError> {
+                        let program = self.worker()?;
+                        loop {
+                            let i = next.fetch_add(1, Ordering::Relaxed);
+                            let range = match ranges.get(i) {
+                                Some(range) => range.clone(),
+                                None => return Ok(()),
+                            };
+                            let sweep = program.decompiler().linear_sweep(range)?;
+                            results.lock().unwrap()[i] = Some(sweep);
+                        }
+                    })
+                })
+                .collect();
+            workers
+                .into_iter()
+                .try_for_each(|worker| worker.join().unwrap())
+        })?;
+        Ok(results
+            .into_inner()
+            .unwrap()
+            .into_iter()
+            .map(|sweep| sweep.expect("range left unswept"))
+            .collect())
+    }
+
+    /// [`sweep`](Self::sweep) on rayon's thread pool
+    #[cfg(feature = "rayon")]
+    pub fn par_sweep(&self, ranges: &[Range<u64>]) -> Result<Vec<Sweep>, SleighError> {
+        use rayon::prelude::*;
+
+        ranges
+            .par_iter()
+            .map_init(
+                || None,
+                |program: &mut Option<Program>, range| {
+                    if program.is_none() {
+                        *program = Some(self.worker()?);
+                    }
+                    let program = program.as_ref().unwrap();
+                    program.decompiler().linear_sweep(range.clone())
+                },
+            )
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::toy::{self, ToyImage};
+
+    #[test]
+    fn test_parallel_sweep() {
+        // movi r0, 1 ; nop, repeated
+        let code: Vec<u8> = [0x01, 0x00, 0x01, 0x00].repeat(64);
+        let image = ToyImage {
+            base: 0x1000,
+            code: code.clone(),
+        };
+        let ranges: Vec<_> = (0..16)
+            .map(|i| 0x1000 + i * 16..0x1000 + (i + 1) * 16)
+            .collect();
+        let lifter = ParallelLifter::new(toy::files().clone(), image).threads(4);
+        let sweeps = lifter.sweep(&ranges).unwrap();
+        assert_eq!(sweeps.len(), 16);
+        for (sweep, range) in sweeps.iter().zip(&ranges) {
+            assert_eq!(sweep.instructions.len(), 8);
+            assert_eq!(sweep.instructions[0].address, range.start);
+            assert!(sweep.gaps.is_empty());
+        }
+        assert!(lifter.sweep(&[]).unwrap().is_empty());
+
+        #[cfg(feature = "rayon")]
+        {
+            let par = lifter.par_sweep(&ranges).unwrap();
+            assert_eq!(par.len(), 16);
+            assert_eq!(par[3].instructions[0].address, ranges[3].start);
+        }
+    }
+}
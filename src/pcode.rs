@@ -0,0 +1,896 @@
+//! Owned copies of the pcode handed out by the emit callbacks.
+//!
+//! The `VarnodeData` references passed to [`PCodeEmit::dump`] only live for the duration of
+//! the callback. These types hold the same information so lifted pcode can be stored. They
+//! can be built from Rust as well, starting from [`Varnode::new`] and [`PcodeOp::new`].
+
+use std::fmt;
+use std::ops::ControlFlow;
+
+use crate::error::{DecodeError, DecodeReason};
+use crate::spaces::{Space, SpaceHierarchy};
+use crate::{ffi, Opcode, PCodeEmit, SleighError};
+
+/// Varnodes compare and order by space index, then offset, then size
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(
+    feature = "rkyv",
+    derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize),
+    rkyv(compare(PartialEq), derive(Debug))
+)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct Varnode {
+    /// Index of the address space, as returned by `AddrSpace::getIndex`
+    pub space: i32,
+    pub offset: u64,
+    pub size: u32,
+}
+
+impl Varnode {
+    pub fn from_data(data: &ffi::VarnodeData) -> Self {
+        Self::from_packed(&ffi::packVarnode(data))
+    }
+
+    /// Convert all of `vars`, like the inputs handed to [`PCodeEmit::dump`]. Varnodes laid out
+    /// as one array, as they are there, are converted in a single call to the engine.
+    pub fn from_slice(vars: &[&ffi::VarnodeData]) -> Vec<Self> {
+        let first = match vars.first() {
+            Some(&first) => first as *const ffi::VarnodeData,
+            None => return Vec::new(),
+        };
+        let stride = ffi::getVarnodeDataStride();
+        let contiguous = vars
+            .iter()
+            .enumerate()
+            .all(|(i, &vn)| vn as *const _ as usize == first as usize + i * stride);
+        if !contiguous {
+            return vars.iter().map(|vn| Self::from_data(vn)).collect();
+        }
+        // The references cover the whole array, so it is valid for its full length
+        unsafe { ffi::packVarnodes(first, vars.len() as i32) }
+            .iter()
+            .map(Self::from_packed)
+            .collect()
+    }
+
+    pub fn from_packed(packed: &ffi::PackedVarnode) -> Self {
+        Self {
+            space: packed.space,
+            offset: packed.offset,
+            size: packed.size,
+        }
+    }
+
+    fn in_space(&self, spaces: &SpaceHierarchy, test: fn(&Space) -> bool) -> bool {
+        spaces.space(self.space).is_some_and(test)
+    }
+
+    pub fn is_constant(&self, spaces: &SpaceHierarchy) -> bool {
+        self.in_space(spaces, Space::is_constant)
+    }
+
+    pub fn is_unique(&self, spaces: &SpaceHierarchy) -> bool {
+        self.in_space(spaces, Space::is_unique)
+    }
+
+    pub fn is_register(&self, spaces: &SpaceHierarchy) -> bool {
+        self.in_space(spaces, Space::is_register)
+    }
+
+    /// Whether the varnode lies in a memory space, see [`Space::is_ram`]
+    pub fn is_ram(&self, spaces: &SpaceHierarchy) -> bool {
+        self.in_space(spaces, Space::is_ram)
+    }
+
+    /// Name of the register the varnode is exactly. Parts of registers that have no name of
+    /// their own, like the upper half of a 64-bit register on most targets, have none.
+    pub fn register_name(&self, decompiler: &ffi::Decompiler) -> Option<String> {
+        let name = decompiler.getExactRegisterName(self.space, self.offset, self.size as i32);
+        if name.is_empty() {
+            None
+        } else {
+            Some(name)
+        }
+    }
+}
+
+/// Building varnodes from Rust, e.g. for injected pcode or stubs. Space indexes are those of
+/// `decompiler`, which the pcode must be used with.
+impl Varnode {
+    pub fn new(space: i32, offset: u64, size: u32) -> Self {
+        Self {
+            space,
+            offset,
+            size,
+        }
+    }
+
+    /// The constant `value`
+    pub fn constant(decompiler: &ffi::Decompiler, value: u64, size: u32) -> Self {
+        Self::new(decompiler.getConstantSpaceIndex(), value, size)
+    }
+
+    /// `size` bytes at `offset` in the default data space
+    pub fn ram(decompiler: &ffi::Decompiler, offset: u64, size: u32) -> Self {
+        Self::new(decompiler.getDefaultDataSpaceIndex(), offset, size)
+    }
+
+    /// The register called `name`, if the language has one
+    pub fn register(decompiler: &ffi::Decompiler, name: &str) -> Option<Self> {
+        decompiler
+            .getRegisterList()
+            .into_iter()
+            .find(|reg| reg.name == name)
+            .map(|reg| Self::new(reg.space, reg.offset, reg.size))
+    }
+
+    /// A temporary. The language's own pcode uses offsets below
+    /// [`getUniqueBase`](ffi::Decompiler::getUniqueBase) only, so offsets from there up don't
+    /// clash with its temporaries.
+    pub fn unique(decompiler: &ffi::Decompiler, offset: u64, size: u32) -> Self {
+        let space = decompiler
+            .space_hierarchy()
+            .spaces()
+            .find(|space| space.is_unique())
+            .map_or(-1, |space| space.index);
+        Self::new(space, offset, size)
+    }
+
+    /// The constant standing for the space at `space`, the first input of a LOAD or STORE.
+    /// [`ffi::Decompiler::space_from_const`] turns it back into the space.
+    pub fn space_id(decompiler: &ffi::Decompiler, space: i32) -> Self {
+        Self::constant(
+            decompiler,
+            decompiler.getSpaceConst(space),
+            std::mem::size_of::<usize>() as u32,
+        )
+    }
+}
+
+/// Formats a [`Varnode`] the way Ghidra prints raw varnodes, e.g. `(register,0x20,8)`
+pub struct VarnodeDisplay<'a> {
+    varnode: &'a Varnode,
+    spaces: &'a SpaceHierarchy,
+}
+
+impl fmt::Display for VarnodeDisplay<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let space = self
+            .spaces
+            .space(self.varnode.space)
+            .map_or("?", |s| s.name.as_str());
+        write!(
+            f,
+            "({},{:#x},{})",
+            space, self.varnode.offset, self.varnode.size
+        )
+    }
+}
+
+impl Varnode {
+    /// Display the varnode with the names of `spaces`
+    pub fn display<'a>(&'a self, spaces: &'a SpaceHierarchy) -> VarnodeDisplay<'a> {
+        VarnodeDisplay {
+            varnode: self,
+            spaces,
+        }
+    }
+}
+
+impl fmt::Display for ffi::VarnodeData {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let addr = ffi::getVarnodeDataAddress(self);
+        let space = unsafe { addr.getSpace().as_ref() }
+            .map_or_else(|| "?".into(), |s| s.getName().to_string_lossy());
+        write!(
+            f,
+            "({},{:#x},{})",
+            space,
+            addr.getOffset(),
+            ffi::getVarnodeSize(self)
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(
+    feature = "rkyv",
+    derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize),
+    rkyv(compare(PartialEq), derive(Debug))
+)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct PcodeOp {
+    pub opcode: Opcode,
+    pub output: Option<Varnode>,
+    pub inputs: Vec<Varnode>,
+}
+
+impl PcodeOp {
+    /// Index of the space a LOAD or STORE accesses, decoded from its first input
+    pub fn accessed_space(&self, decompiler: &ffi::Decompiler) -> Option<i32> {
+        match self.opcode {
+            Opcode::Load | Opcode::Store => decompiler
+                .space_from_const(self.inputs.first()?)
+                .map(|space| space.getIndex()),
+            _ => None,
+        }
+    }
+}
+
+/// Building ops from Rust, with varnodes built like [`Varnode::new`]
+impl PcodeOp {
+    pub fn new(opcode: Opcode, output: Option<Varnode>, inputs: Vec<Varnode>) -> Self {
+        Self {
+            opcode,
+            output,
+            inputs,
+        }
+    }
+
+    pub fn copy(output: Varnode, input: Varnode) -> Self {
+        Self::new(Opcode::Copy, Some(output), vec![input])
+    }
+
+    /// An op with an output and one input, like INT_NEGATE or INT_ZEXT
+    pub fn unary(opcode: Opcode, output: Varnode, input: Varnode) -> Self {
+        Self::new(opcode, Some(output), vec![input])
+    }
+
+    /// An op with an output and two inputs, like INT_ADD or INT_EQUAL
+    pub fn binary(opcode: Opcode, output: Varnode, in1: Varnode, in2: Varnode) -> Self {
+        Self::new(opcode, Some(output), vec![in1, in2])
+    }
+
+    /// Load `output` from `pointer` in the space at `space`
+    pub fn load(
+        decompiler: &ffi::Decompiler,
+        output: Varnode,
+        space: i32,
+        pointer: Varnode,
+    ) -> Self {
+        Self::new(
+            Opcode::Load,
+            Some(output),
+            vec![Varnode::space_id(decompiler, space), pointer],
+        )
+    }
+
+    /// Call the user op at `index`, e.g. one added with
+    /// [`add_user_op`](ffi::Decompiler::add_user_op), with `inputs`
+    pub fn call_other(
+        decompiler: &ffi::Decompiler,
+        index: u32,
+        output: Option<Varnode>,
+        inputs: Vec<Varnode>,
+    ) -> Self {
+        let mut all = vec![Varnode::constant(decompiler, index as u64, 4)];
+        all.extend(inputs);
+        Self::new(Opcode::CallOther, output, all)
+    }
+
+    /// Store `value` to `pointer` in the space at `space`
+    pub fn store(
+        decompiler: &ffi::Decompiler,
+        space: i32,
+        pointer: Varnode,
+        value: Varnode,
+    ) -> Self {
+        Self::new(
+            Opcode::Store,
+            None,
+            vec![Varnode::space_id(decompiler, space), pointer, value],
+        )
+    }
+}
+
+/// Formats a [`PcodeOp`] the way Ghidra's listing shows raw pcode, e.g.
+/// `(register, 0x0, 4) INT_ADD (register, 0x0, 4) , (const, 0x1, 4)`. Ops without an output
+/// start with ` --- `. The space-id input of a LOAD or STORE is the decompiler's own constant,
+/// which differs from the one Ghidra shows, so it is shown as the name of the space instead,
+/// like `[ram]`.
+pub struct PcodeOpDisplay<'a> {
+    op: &'a PcodeOp,
+    spaces: &'a SpaceHierarchy,
+}
+
+impl PcodeOpDisplay<'_> {
+    fn varnode(&self, f: &mut fmt::Formatter<'_>, vn: &Varnode) -> fmt::Result {
+        let space = self.spaces.space(vn.space).map_or("?", |s| s.name.as_str());
+        write!(f, "({}, {:#x}, {})", space, vn.offset, vn.size)
+    }
+}
+
+impl fmt::Display for PcodeOpDisplay<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.op.output {
+            Some(vn) => self.varnode(f, vn)?,
+            None => f.write_str(" --- ")?,
+        }
+        // The listing takes its names from Ghidra's Java side, which prefixes the float
+        // conversions and roundings
+        let name = match self.op.opcode {
+            Opcode::FloatInt2Float => "FLOAT_INT2FLOAT",
+            Opcode::FloatFloat2Float => "FLOAT_FLOAT2FLOAT",
+            Opcode::FloatTrunc => "FLOAT_TRUNC",
+            Opcode::FloatCeil => "FLOAT_CEIL",
+            Opcode::FloatFloor => "FLOAT_FLOOR",
+            Opcode::FloatRound => "FLOAT_ROUND",
+            opcode => opcode.name(),
+        };
+        write!(f, " {} ", name)?;
+        let accesses_memory = matches!(self.op.opcode, Opcode::Load | Opcode::Store);
+        for (i, vn) in self.op.inputs.iter().enumerate() {
+            if i > 0 {
+                f.write_str(" , ")?;
+            }
+            match self.spaces.space_from_const(vn) {
+                Some(space) if i == 0 && accesses_memory => write!(f, "[{}]", space.name)?,
+                _ => self.varnode(f, vn)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl PcodeOp {
+    /// Display the op with the names of `spaces`
+    pub fn display<'a>(&'a self, spaces: &'a SpaceHierarchy) -> PcodeOpDisplay<'a> {
+        PcodeOpDisplay { op: self, spaces }
+    }
+}
+
+/// Formats the ops of a [`LiftedInstruction`] like [`PcodeOpDisplay`], one per line
+pub struct PcodeListing<'a> {
+    inst: &'a LiftedInstruction,
+    spaces: &'a SpaceHierarchy,
+}
+
+impl fmt::Display for PcodeListing<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, op) in self.inst.ops.iter().enumerate() {
+            if i > 0 {
+                f.write_str("\n")?;
+            }
+            op.display(self.spaces).fmt(f)?;
+        }
+        Ok(())
+    }
+}
+
+/// The pcode of one machine instruction
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(
+    feature = "rkyv",
+    derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize),
+    rkyv(derive(Debug))
+)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct LiftedInstruction {
+    pub address: u64,
+    pub length: usize,
+    pub ops: Vec<PcodeOp>,
+    /// The bytes the instruction was decoded from, those of its delay slots included, so
+    /// `length` of them. Empty when read from packed pcode, which doesn't carry them.
+    pub bytes: Vec<u8>,
+}
+
+impl LiftedInstruction {
+    /// Unpack the pcode of the instruction at `address`, which must have decoded. Fails if an
+    /// op has an opcode [`Opcode`] doesn't know.
+    pub fn from_packed(address: u64, packed: &ffi::PackedInstruction) -> Result<Self, DecodeError> {
+        let ops = packed
+            .ops
+            .iter()
+            .map(|op| {
+                let first = op.first_input as usize;
+                Ok(PcodeOp {
+                    opcode: Opcode::from_u32(op.opcode).ok_or_else(|| DecodeError {
+                        address,
+                        reason: DecodeReason::UnknownOpcode,
+                        message: format!("unknown pcode opcode {}", op.opcode),
+                        length: 0,
+                    })?,
+                    output: op.has_output.then(|| Varnode::from_packed(&op.output)),
+                    inputs: packed.inputs[first..first + op.input_count as usize]
+                        .iter()
+                        .map(Varnode::from_packed)
+                        .collect(),
+                })
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self {
+            address,
+            length: packed.length.max(0) as usize,
+            ops,
+            bytes: packed.bytes.clone(),
+        })
+    }
+
+    /// The instruction's pcode as Ghidra's listing shows it, for diffing against Ghidra
+    pub fn listing<'a>(&'a self, spaces: &'a SpaceHierarchy) -> PcodeListing<'a> {
+        PcodeListing { inst: self, spaces }
+    }
+}
+
+/// An instruction lifted with the address of the instruction each op was built for. The pcode
+/// of a branch with delay slots includes theirs, where its semantics say `delayslot`, and its
+/// length covers them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TaggedInstruction {
+    pub instruction: LiftedInstruction,
+    /// One address per op
+    pub origins: Vec<u64>,
+}
+
+impl TaggedInstruction {
+    pub fn from_packed(address: u64, packed: &ffi::PackedInstruction) -> Result<Self, DecodeError> {
+        let instruction = LiftedInstruction::from_packed(address, packed)?;
+        let origins = if packed.origins.is_empty() {
+            vec![address; instruction.ops.len()]
+        } else {
+            packed.origins.clone()
+        };
+        Ok(Self {
+            instruction,
+            origins,
+        })
+    }
+
+    /// Each op with the address of the instruction it was built for
+    pub fn ops(&self) -> impl Iterator<Item = (u64, &PcodeOp)> {
+        self.origins.iter().copied().zip(&self.instruction.ops)
+    }
+
+    /// The ops built for delay slots rather than the instruction itself
+    pub fn delay_slot_ops(&self) -> impl Iterator<Item = (u64, &PcodeOp)> {
+        let address = self.instruction.address;
+        self.ops().filter(move |(origin, _)| *origin != address)
+    }
+
+    pub fn has_delay_slot(&self) -> bool {
+        self.delay_slot_ops().next().is_some()
+    }
+}
+
+/// An instruction parsed by [`ffi::Decompiler::decode`]. Its assembly and pcode are rendered
+/// from the engine's cached parse, which holds the last few instructions decoded; one pushed
+/// out by later decodes is parsed again, so the results are the same either way.
+#[derive(Clone, Copy)]
+pub struct DecodedInstruction<'a> {
+    pub(crate) decompiler: &'a ffi::Decompiler,
+    pub address: u64,
+    /// Length of the instruction, not counting its delay slots
+    pub length: usize,
+}
+
+impl fmt::Debug for DecodedInstruction<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DecodedInstruction")
+            .field("address", &self.address)
+            .field("length", &self.length)
+            .finish()
+    }
+}
+
+impl DecodedInstruction<'_> {
+    /// The mnemonic and operands, see [`ffi::Decompiler::disassemble_one`]
+    pub fn assembly(&self) -> Result<(String, String), SleighError> {
+        let (mnemonic, operands, _) = self.decompiler.disassemble_one(self.address)?;
+        Ok((mnemonic, operands))
+    }
+
+    /// The pcode, see [`ffi::Decompiler::try_lift`]. Its length covers the delay slots.
+    pub fn pcode(&self) -> Result<LiftedInstruction, SleighError> {
+        self.decompiler.try_lift(self.address)
+    }
+}
+
+/// Where a BRANCH, CBRANCH or CALL op goes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub enum PcodeTarget {
+    /// The machine instruction at `offset` in the space with index `space`
+    Address { space: i32, offset: u64 },
+    /// The op at this index of the same instruction's ops
+    Op(usize),
+    /// The instruction following this one, reached by branching past the last op
+    Fallthrough,
+}
+
+impl LiftedInstruction {
+    /// The target of the branch or call `self.ops[index]`. SLEIGH encodes branches within the
+    /// instruction as constants holding the distance in ops, which are resolved here. Returns
+    /// `None` for other ops, indirect branches and distances leading out of the instruction.
+    pub fn branch_target(&self, index: usize, decompiler: &ffi::Decompiler) -> Option<PcodeTarget> {
+        let op = self.ops.get(index)?;
+        if !matches!(op.opcode, Opcode::Branch | Opcode::CBranch | Opcode::Call) {
+            return None;
+        }
+        let dest = op.inputs.first()?;
+        if dest.space != decompiler.getConstantSpaceIndex() {
+            return Some(PcodeTarget::Address {
+                space: dest.space,
+                offset: dest.offset,
+            });
+        }
+        // The distance is truncated to the size of the constant
+        let shift = 64 - 8 * dest.size.clamp(1, 8);
+        let distance = ((dest.offset << shift) as i64) >> shift;
+        let target = (index as i64).checked_add(distance)?;
+        match usize::try_from(target).ok()? {
+            i if i < self.ops.len() => Some(PcodeTarget::Op(i)),
+            i if i == self.ops.len() => Some(PcodeTarget::Fallthrough),
+            _ => None,
+        }
+    }
+}
+
+/// Where control can go after an instruction, worked out from its pcode
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FlowInfo {
+    /// Address of the next instruction, if execution can reach it. Branches to the next
+    /// instruction count as falling through.
+    pub fall_through: Option<u64>,
+    /// Static branch targets in the default code space, in op order
+    pub branches: Vec<u64>,
+    /// Static call targets in the default code space, in op order
+    pub calls: Vec<u64>,
+    /// Whether a BRANCHIND can execute
+    pub indirect_branch: bool,
+    /// Whether a CALLIND can execute
+    pub indirect_call: bool,
+    /// Whether a RETURN can execute
+    pub returns: bool,
+}
+
+impl FlowInfo {
+    /// Whether execution never moves on to the next instruction
+    pub fn terminates(&self) -> bool {
+        self.fall_through.is_none()
+    }
+}
+
+impl LiftedInstruction {
+    /// Follow the instruction's pcode from its first op and collect where it leaves. Ops that
+    /// can't be reached, like those skipped by a relative branch, don't contribute.
+    pub fn flow(&self, decompiler: &ffi::Decompiler) -> FlowInfo {
+        let code = decompiler.getDefaultCodeSpaceIndex();
+        // Past the last address of the space, execution goes on at its start
+        let next = decompiler
+            .default_code_space()
+            .wrapOffset(self.address.wrapping_add(self.length as u64));
+        let mut info = FlowInfo::default();
+        let mut falls_through = false;
+        let mut reached = vec![false; self.ops.len()];
+        let mut pending = vec![0];
+        while let Some(index) = pending.pop() {
+            if index == self.ops.len() {
+                falls_through = true;
+                continue;
+            }
+            if reached[index] {
+                continue;
+            }
+            reached[index] = true;
+            match self.branch_target(index, decompiler) {
+                Some(PcodeTarget::Op(target)) => pending.push(target),
+                Some(PcodeTarget::Fallthrough) => falls_through = true,
+                _ => {}
+            }
+            if self.ops[index].opcode.has_fall_through() {
+                pending.push(index + 1);
+            }
+        }
+
+        for (index, op) in self.ops.iter().enumerate() {
+            if !reached[index] {
+                continue;
+            }
+            match op.opcode {
+                Opcode::BranchInd => info.indirect_branch = true,
+                Opcode::CallInd => info.indirect_call = true,
+                Opcode::Return => info.returns = true,
+                _ => {}
+            }
+            let offset = match self.branch_target(index, decompiler) {
+                Some(PcodeTarget::Address { space, offset }) if space == code => offset,
+                _ => continue,
+            };
+            if op.opcode.is_call() {
+                info.calls.push(offset);
+            } else if offset == next {
+                falls_through = true;
+            } else if !info.branches.contains(&offset) {
+                info.branches.push(offset);
+            }
+        }
+        if falls_through {
+            info.fall_through = Some(next);
+        }
+        info
+    }
+}
+
+/// A [`PCodeEmit`] that copies every op it receives. Ops with an opcode [`Opcode`] doesn't
+/// know stop it with an error.
+#[derive(Debug, Default)]
+pub struct PcodeCollector {
+    pub ops: Vec<PcodeOp>,
+}
+
+impl PCodeEmit for PcodeCollector {
+    fn dump(
+        &mut self,
+        _address: &ffi::Address,
+        opcode: Opcode,
+        outvar: Option<&ffi::VarnodeData>,
+        vars: &[&ffi::VarnodeData],
+    ) -> ControlFlow<()> {
+        self.ops.push(PcodeOp {
+            opcode,
+            output: outvar.map(Varnode::from_data),
+            inputs: Varnode::from_slice(vars),
+        });
+        ControlFlow::Continue(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::toy;
+
+    #[test]
+    fn test_from_slice() {
+        // st [r1], r0
+        let program = toy::program(0x1000, &[0x05, 0x10]);
+        let decompiler = program.decompiler();
+        let mut collector = PcodeCollector::default();
+        assert!(decompiler
+            .emit_pcode(0x1000, &mut collector)
+            .unwrap()
+            .is_continue());
+        let store = &collector.ops[0];
+        assert_eq!(store.inputs.len(), 3);
+        let r1 = decompiler.getExactRegisterName(store.inputs[1].space, store.inputs[1].offset, 4);
+        assert_eq!(r1, "r1");
+        assert_eq!(
+            store.inputs,
+            decompiler.try_lift(0x1000).unwrap().ops[0].inputs
+        );
+        assert!(Varnode::from_slice(&[]).is_empty());
+    }
+
+    #[test]
+    fn test_builders() {
+        // movi r0, 1 ; ld r1, [r0] ; st [r1], r0 ; add r0, r1
+        let code = [0x01, 0x00, 0x01, 0x04, 0x10, 0x05, 0x10, 0x02, 0x01];
+        let program = toy::program(0x1000, &code);
+        let decompiler = program.decompiler();
+        let r0 = Varnode::register(decompiler, "r0").unwrap();
+        let r1 = Varnode::register(decompiler, "r1").unwrap();
+        assert_eq!(r0, Varnode::new(r0.space, 0, 4));
+        assert_eq!(Varnode::register(decompiler, "r9"), None);
+        let ram = decompiler.getDefaultDataSpaceIndex();
+        assert_eq!(
+            Varnode::ram(decompiler, 0x10, 4),
+            Varnode::new(ram, 0x10, 4)
+        );
+
+        let lifted = |addr| decompiler.try_lift(addr).unwrap().ops;
+        assert_eq!(
+            lifted(0x1000),
+            [PcodeOp::copy(
+                r0.clone(),
+                Varnode::constant(decompiler, 1, 4)
+            )]
+        );
+        assert_eq!(
+            lifted(0x1003),
+            [PcodeOp::load(decompiler, r1.clone(), ram, r0.clone())]
+        );
+        assert_eq!(
+            lifted(0x1005),
+            [PcodeOp::store(decompiler, ram, r1.clone(), r0.clone())]
+        );
+        assert_eq!(
+            lifted(0x1007),
+            [PcodeOp::binary(Opcode::IntAdd, r0.clone(), r0, r1)]
+        );
+
+        let temp = Varnode::unique(decompiler, decompiler.getUniqueBase() as u64, 4);
+        let spaces = decompiler.space_hierarchy();
+        assert!(temp.is_unique(&spaces));
+        let space = decompiler.space_from_const(&Varnode::space_id(decompiler, ram));
+        assert_eq!(space.map(|space| space.getIndex()), Some(ram));
+    }
+
+    #[test]
+    fn test_unknown_opcode() {
+        let op = |opcode| ffi::PackedOp {
+            opcode,
+            has_output: false,
+            output: ffi::PackedVarnode {
+                space: -1,
+                offset: 0,
+                size: 0,
+            },
+            first_input: 0,
+            input_count: 0,
+        };
+        let mut packed = ffi::PackedInstruction {
+            length: 1,
+            ops: vec![op(Opcode::Return as u32)],
+            inputs: vec![],
+            origins: vec![],
+            bytes: vec![0],
+        };
+        let inst = LiftedInstruction::from_packed(0x10, &packed).unwrap();
+        assert_eq!(inst.ops[0].opcode, Opcode::Return);
+        packed.ops.push(op(999));
+        let err = LiftedInstruction::from_packed(0x10, &packed).unwrap_err();
+        assert_eq!(err.reason, DecodeReason::UnknownOpcode);
+        assert_eq!(err.message, "unknown pcode opcode 999");
+    }
+
+    #[test]
+    fn test_display() {
+        // st [r1], r0
+        let mut program = toy::program(0x1000, &[0x05, 0x10]);
+        let spaces = program.decompiler().space_hierarchy();
+        let store = program.lift(0x1000).unwrap().ops[0].clone();
+        assert_eq!(
+            store.inputs[1].display(&spaces).to_string(),
+            "(register,0x4,4)"
+        );
+        let addr = program.decompiler().getCodeAddress(0x401000);
+        assert_eq!(addr.to_string(), "ram:0x401000");
+        assert_eq!(ffi::newAddress().to_string(), "<invalid>");
+
+        // movi r1, 5 ; ret
+        let mut program = toy::program(0x1000, &[0x01, 0x10, 0x05, 0x03]);
+        let movi = program.lift(0x1000).unwrap().clone();
+        let ret = program.lift(0x1003).unwrap().clone();
+        let spaces = program.decompiler().space_hierarchy();
+        assert_eq!(
+            movi.listing(&spaces).to_string(),
+            "(register, 0x4, 4) COPY (const, 0x5, 4)"
+        );
+        let listing = ret.listing(&spaces).to_string();
+        let lines: Vec<_> = listing.lines().collect();
+        assert_eq!(
+            lines,
+            [
+                "(unique, 0x100, 4) LOAD [ram] , (register, 0x10, 4)",
+                "(register, 0x10, 4) INT_ADD (register, 0x10, 4) , (const, 0x4, 4)",
+                " ---  RETURN (unique, 0x100, 4)",
+            ]
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde() {
+        // bump r1
+        let mut program = toy::program(0x1000, &[0x0b, 0x10]);
+        let inst = program.lift(0x1000).unwrap().clone();
+        let json = serde_json::to_string(&inst).unwrap();
+        assert!(json.contains("\"CBranch\""));
+        let back: LiftedInstruction = serde_json::from_str(&json).unwrap();
+        assert_eq!(back, inst);
+    }
+
+    #[test]
+    fn test_branch_target() {
+        // bump r1
+        let mut program = toy::program(0x1000, &[0x0b, 0x10]);
+        let mut bump = program.lift(0x1000).unwrap().clone();
+        let decompiler = program.decompiler();
+        assert_eq!(bump.ops.len(), 5);
+        assert!(matches!(bump.ops[1].opcode, Opcode::CBranch));
+        assert!(matches!(bump.ops[3].opcode, Opcode::Branch));
+        assert_eq!(bump.branch_target(1, decompiler), Some(PcodeTarget::Op(4)));
+        let ram = decompiler.space_hierarchy().by_name("ram").unwrap().index;
+        assert_eq!(
+            bump.branch_target(3, decompiler),
+            Some(PcodeTarget::Address {
+                space: ram,
+                offset: 0x1002
+            })
+        );
+        assert_eq!(bump.branch_target(0, decompiler), None);
+
+        // Distances are signed and relative to the branch
+        let distance = &mut bump.ops[1].inputs[0];
+        distance.offset = 4 & (u64::MAX >> (64 - 8 * distance.size));
+        assert_eq!(
+            bump.branch_target(1, decompiler),
+            Some(PcodeTarget::Fallthrough)
+        );
+        let distance = &mut bump.ops[1].inputs[0];
+        distance.offset = u64::MAX >> (64 - 8 * distance.size);
+        assert_eq!(bump.branch_target(1, decompiler), Some(PcodeTarget::Op(0)));
+        bump.ops[1].inputs[0].offset = 5;
+        assert_eq!(bump.branch_target(1, decompiler), None);
+    }
+
+    #[test]
+    fn test_flow() {
+        // bump r1 ; ret ; nop ; jz r0, 0x20 ; call 0x30 ; jmp 0x40
+        let code = [
+            0x0b, 0x10, 0x03, 0x00, 0x0e, 0x00, 0x20, 0x0d, 0x30, 0x0c, 0x40,
+        ];
+        let mut program = toy::program(0x1000, &code);
+        let [bump, ret, nop, jz, call, jmp] = [0x1000, 0x1002, 0x1003, 0x1004, 0x1007, 0x1009]
+            .map(|addr| program.lift(addr).unwrap().clone());
+        let decompiler = program.decompiler();
+
+        // The branch to the next instruction is a fall-through
+        let flow = bump.flow(decompiler);
+        assert_eq!(flow.fall_through, Some(0x1002));
+        assert!(flow.branches.is_empty() && !flow.returns);
+
+        let flow = ret.flow(decompiler);
+        assert!(flow.terminates() && flow.returns);
+        assert_eq!(nop.flow(decompiler).fall_through, Some(0x1004));
+
+        let flow = jz.flow(decompiler);
+        assert_eq!(
+            (flow.fall_through, flow.branches),
+            (Some(0x1007), vec![0x20])
+        );
+        let flow = call.flow(decompiler);
+        assert_eq!((flow.fall_through, flow.calls), (Some(0x1009), vec![0x30]));
+        let flow = jmp.flow(decompiler);
+        assert!(flow.terminates());
+        assert_eq!(flow.branches, [0x40]);
+
+        // At the end of the space the next instruction is at its start
+        let mut program = toy::program(0xffff_fffe, &code[..2]);
+        let bump = program.lift(0xffff_fffe).unwrap().clone();
+        let flow = bump.flow(program.decompiler());
+        assert_eq!(flow.fall_through, Some(0));
+        assert!(flow.branches.is_empty());
+    }
+
+    #[test]
+    fn test_classification() {
+        // movi r1, 0x40 ; st [r1], r0 ; cpuid r2
+        let mut program = toy::program(0x1000, &[0x01, 0x10, 0x40, 0x05, 0x10, 0x09, 0x20]);
+        let spaces = program.decompiler().space_hierarchy();
+        let movi = program.lift(0x1000).unwrap().ops[0].clone();
+        let output = movi.output.unwrap();
+        assert!(output.is_register(&spaces) && !output.is_ram(&spaces));
+        assert!(movi.inputs[0].is_constant(&spaces));
+        assert_eq!(
+            output.register_name(program.decompiler()).as_deref(),
+            Some("r1")
+        );
+
+        let cpuid = program.lift(0x1005).unwrap().ops.clone();
+        let info = cpuid[0].output.clone().unwrap();
+        let ram = spaces.by_name("ram").unwrap();
+        assert_eq!(
+            cpuid[1].accessed_space(program.decompiler()),
+            Some(ram.index)
+        );
+        assert_eq!(cpuid[0].accessed_space(program.decompiler()), None);
+        assert!(info.is_unique(&spaces) && !info.is_register(&spaces));
+        assert_eq!(info.register_name(program.decompiler()), None);
+
+        // Half of a register has no name of its own
+        let mut half = output.clone();
+        half.size = 2;
+        assert_eq!(half.register_name(program.decompiler()), None);
+
+        assert!(ram.is_ram() && !ram.is_register());
+        let mut mem = output;
+        mem.space = ram.index;
+        assert!(mem.is_ram(&spaces));
+    }
+}
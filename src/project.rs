@@ -0,0 +1,1154 @@
+//! An in-memory program model.
+//!
+//! [`Program`] owns everything needed to analyse a binary: the load image, the language and
+//! its decompiler (which carries the context database), discovered functions, symbols and a
+//! cache of lifted pcode.
+
+use std::collections::BTreeMap;
+use std::ops::{Range, RangeBounds};
+use std::path::PathBuf;
+use std::pin::Pin;
+
+use cxx::UniquePtr;
+
+use crate::cache::PcodeCache;
+use crate::cspec::{CompilerSpec, StackGrowth};
+use crate::isa::Bits;
+use crate::languages::{LanguageError, LanguageFiles};
+use crate::pcode::{LiftedInstruction, PcodeOp};
+use crate::xml::XmlElement;
+use crate::{ffi, ConstantPool, LoadImage, Opcode, RustConstantPool, RustLoadImage, SleighError};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Function {
+    pub entry: u64,
+    pub name: String,
+    /// A signature set with [`Program::set_prototype`], recovered by the decompiler otherwise
+    pub prototype: Option<Prototype>,
+}
+
+/// A known function signature, with types named as the decompiler prints them (`int4`,
+/// `char *`, ...)
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Prototype {
+    /// Calling convention of the `.cspec`, the default one if `None`. It decides where the
+    /// parameters and the return value are stored.
+    pub model: Option<String>,
+    pub return_type: String,
+    /// Names and types of the parameters
+    pub parameters: Vec<(String, String)>,
+    /// Whether more arguments may follow the parameters
+    pub variadic: bool,
+}
+
+impl Prototype {
+    fn to_spec(&self) -> ffi::PrototypeSpec {
+        ffi::PrototypeSpec {
+            model: self.model.clone().unwrap_or_default(),
+            return_type: self.return_type.clone(),
+            parameters: self
+                .parameters
+                .iter()
+                .map(|(name, type_name)| ffi::ParameterSpec {
+                    name: name.clone(),
+                    type_name: type_name.clone(),
+                })
+                .collect(),
+            variadic: self.variadic,
+        }
+    }
+}
+
+/// A data type to add to the decompiler's, see [`Program::define_type`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TypeDefinition {
+    /// An atomic type, like `uint8_t` for a 1 byte [`Uint`](ffi::TypeKind::Uint)
+    Primitive {
+        name: String,
+        size: u32,
+        kind: ffi::TypeKind,
+    },
+    /// A structure, padded to `size` bytes if its fields take less
+    Struct {
+        name: String,
+        size: u32,
+        fields: Vec<ffi::FieldInfo>,
+    },
+}
+
+impl TypeDefinition {
+    fn apply(
+        &self,
+        architecture: Pin<&mut ffi::DecompilerArchitecture>,
+    ) -> Result<(), cxx::Exception> {
+        match self {
+            TypeDefinition::Primitive { name, size, kind } => {
+                cxx::let_cxx_string!(name = name);
+                architecture.definePrimitive(&name, *size, *kind)
+            }
+            TypeDefinition::Struct { name, size, fields } => {
+                cxx::let_cxx_string!(name = name);
+                architecture.defineStruct(&name, *size, fields)
+            }
+        }
+    }
+}
+
+/// A comment for decompiled code
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Comment {
+    /// Entry point of the function the comment belongs to
+    pub function: u64,
+    pub address: u64,
+    pub kind: ffi::CommentKind,
+    pub text: String,
+}
+
+/// How far [`Program::function_pcode`] takes its analysis, after the decompiler's root
+/// actions of the same name
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SimplificationStyle {
+    /// The full analysis, down to data types and structured control flow
+    #[default]
+    Decompile,
+    /// Simplified pcode, without type recovery or control flow structuring
+    Normalize,
+    /// Only enough data flow to tell which registers pass parameters
+    Register,
+    /// Pcode in SSA form with no simplification
+    FirstPass,
+}
+
+impl SimplificationStyle {
+    /// The decompiler's name for the root action
+    pub fn name(self) -> &'static str {
+        match self {
+            SimplificationStyle::Decompile => "decompile",
+            SimplificationStyle::Normalize => "normalize",
+            SimplificationStyle::Register => "register",
+            SimplificationStyle::FirstPass => "firstpass",
+        }
+    }
+}
+
+pub struct Program {
+    // Declared first so they are dropped before the load image they point into.
+    decompiler: UniquePtr<ffi::Decompiler>,
+    /// The full decompiler, set up on the first `decompile_function`
+    architecture: UniquePtr<ffi::DecompilerArchitecture>,
+    proxy: Box<RustLoadImage<'static>>,
+    /// Read by the architecture, which must be rebuilt when it changes
+    constant_pool: Option<Box<RustConstantPool>>,
+    image: *mut dyn LoadImage,
+    language: LanguageFiles,
+    compiler_spec: Option<CompilerSpec>,
+    functions: BTreeMap<u64, Function>,
+    symbols: BTreeMap<u64, String>,
+    comments: Vec<Comment>,
+    types: Vec<TypeDefinition>,
+    /// Name and type of global variables by address
+    globals: BTreeMap<u64, (String, String)>,
+    /// Length and ops of the pcode injected at each address
+    injections: BTreeMap<u64, (Option<usize>, Vec<PcodeOp>)>,
+    /// User ops registered on top of the spec's, in order
+    user_ops: Vec<String>,
+    lifted: PcodeCache,
+}
+
+impl Program {
+    /// Load `image` with the given language
+    pub fn new<L: LoadImage + 'static>(
+        language: LanguageFiles,
+        image: L,
+    ) -> Result<Self, LanguageError> {
+        let compiler_spec = language
+            .cspec
+            .as_ref()
+            .map(CompilerSpec::load)
+            .transpose()?;
+        let image: *mut dyn LoadImage = Box::into_raw(Box::new(image));
+        // The proxy and the decompiler only ever see the image through this reference; it
+        // stays valid until `Drop` frees the image after both of them are gone.
+        let mut proxy = Box::new(RustLoadImage::from_internal(unsafe { &mut *image }));
+        let decompiler = match unsafe { language.new_decompiler(&mut *proxy) }
+            .and_then(|decompiler| Self::add_stack_space(decompiler, &language, &compiler_spec))
+        {
+            Ok(decompiler) => decompiler,
+            Err(e) => {
+                drop(proxy);
+                drop(unsafe { Box::from_raw(image) });
+                return Err(e);
+            }
+        };
+        Ok(Self {
+            decompiler,
+            architecture: UniquePtr::null(),
+            proxy,
+            constant_pool: None,
+            image,
+            language,
+            compiler_spec,
+            functions: BTreeMap::new(),
+            symbols: BTreeMap::new(),
+            comments: Vec::new(),
+            types: Vec::new(),
+            globals: BTreeMap::new(),
+            injections: BTreeMap::new(),
+            user_ops: Vec::new(),
+            lifted: PcodeCache::new(),
+        })
+    }
+
+    /// Give the decompiler the `stack` space described by the compiler spec's stack pointer
+    fn add_stack_space(
+        mut decompiler: UniquePtr<ffi::Decompiler>,
+        language: &LanguageFiles,
+        compiler_spec: &Option<CompilerSpec>,
+    ) -> Result<UniquePtr<ffi::Decompiler>, LanguageError> {
+        if let Some(sp) = compiler_spec
+            .as_ref()
+            .and_then(|c| c.stack_pointer.as_ref())
+        {
+            decompiler
+                .pin_mut()
+                .add_stack_space(&sp.space, &sp.register, sp.growth == StackGrowth::Negative)
+                .map_err(|e| {
+                    let path = language.cspec.clone().unwrap_or_default();
+                    LanguageError::Spec(path, e.what().to_string())
+                })?;
+        }
+        Ok(decompiler)
+    }
+
+    pub fn language(&self) -> &LanguageFiles {
+        &self.language
+    }
+
+    /// The parsed `.cspec`, if the language has one
+    pub fn compiler_spec(&self) -> Option<&CompilerSpec> {
+        self.compiler_spec.as_ref()
+    }
+
+    pub fn decompiler(&self) -> &ffi::Decompiler {
+        &self.decompiler
+    }
+
+    /// The raw context database. Changes made through it are only seen once the decompiler's
+    /// caches are reset, see [`set_context_range`](Self::set_context_range) for a safe way.
+    pub fn context(&mut self) -> Pin<&mut ffi::ContextDatabase> {
+        let ctx = unsafe { self.decompiler.pin_mut().getContext() };
+        unsafe { Pin::new_unchecked(&mut *ctx) }
+    }
+
+    /// Set the context variable `name` to `value` over `[start, end)` and drop the pcode
+    /// lifted so far
+    pub fn set_context_range(
+        &mut self,
+        name: &str,
+        start: u64,
+        end: u64,
+        value: u32,
+    ) -> Result<(), cxx::Exception> {
+        self.decompiler
+            .pin_mut()
+            .set_context_range(name, start, end, value)?;
+        self.clear_lifted();
+        Ok(())
+    }
+
+    /// Set the context variable `name` to `value` from `start` up to its next change point
+    /// and drop the pcode lifted so far
+    pub fn set_context_from(
+        &mut self,
+        name: &str,
+        start: u64,
+        value: u32,
+    ) -> Result<(), cxx::Exception> {
+        self.decompiler
+            .pin_mut()
+            .set_context_from(name, start, value)?;
+        self.clear_lifted();
+        Ok(())
+    }
+
+    /// Set the context variable `name` to `value` at every address and drop the pcode lifted
+    /// so far
+    pub fn set_context_everywhere(&mut self, name: &str, value: u32) -> Result<(), cxx::Exception> {
+        self.decompiler
+            .pin_mut()
+            .set_context_everywhere(name, value)?;
+        self.clear_lifted();
+        Ok(())
+    }
+
+    /// Decode `range` as Thumb or ARM code, see [`ffi::Decompiler::set_thumb`]
+    pub fn set_thumb(&mut self, range: Range<u64>, thumb: bool) -> Result<(), cxx::Exception> {
+        self.decompiler.pin_mut().set_thumb(range, thumb)?;
+        self.clear_lifted();
+        Ok(())
+    }
+
+    /// Decode `range` as MIPS16e or standard MIPS code, see [`ffi::Decompiler::set_mips16`]
+    pub fn set_mips16(&mut self, range: Range<u64>, mips16: bool) -> Result<(), cxx::Exception> {
+        self.decompiler.pin_mut().set_mips16(range, mips16)?;
+        self.clear_lifted();
+        Ok(())
+    }
+
+    /// Decode all code in the given x86 mode, see [`ffi::Decompiler::set_x86_mode`]
+    pub fn set_x86_mode(&mut self, bits: Bits) -> Result<(), cxx::Exception> {
+        self.decompiler.pin_mut().set_x86_mode(bits)?;
+        self.clear_lifted();
+        Ok(())
+    }
+
+    /// Replace the context database with a serialized one, see
+    /// [`ffi::Decompiler::save_context`], and drop the pcode lifted so far
+    pub fn restore_context(&mut self, xml: &str) -> Result<(), cxx::Exception> {
+        self.decompiler.pin_mut().restore_context(xml)?;
+        self.clear_lifted();
+        Ok(())
+    }
+
+    /// Add an overlay space `name` over the space `base`, see
+    /// [`ffi::Decompiler::space_hierarchy`] to navigate it
+    pub fn add_overlay_space(&mut self, name: &str, base: &str) -> Result<i32, cxx::Exception> {
+        self.decompiler.pin_mut().add_overlay_space(name, base)
+    }
+
+    /// Cap the pcode ops of a single instruction, see [`ffi::Decompiler::set_op_limit`], and
+    /// drop the pcode lifted so far
+    pub fn set_op_limit(&mut self, limit: Option<u32>) {
+        self.decompiler.pin_mut().set_op_limit(limit);
+        self.clear_lifted();
+    }
+
+    /// Choose how instructions with `unimpl` semantics are lifted, see
+    /// [`ffi::Decompiler::set_unimplemented_policy`], and drop the pcode lifted so far
+    pub fn set_unimplemented_policy(&mut self, policy: ffi::UnimplementedPolicy) {
+        self.decompiler.pin_mut().set_unimplemented_policy(policy);
+        self.clear_lifted();
+    }
+
+    /// Lift `ops` in place of the pcode of the instruction at `addr`, see
+    /// [`ffi::Decompiler::inject`], and drop the pcode lifted there so far
+    pub fn inject(
+        &mut self,
+        addr: u64,
+        length: Option<usize>,
+        ops: Vec<PcodeOp>,
+    ) -> Result<(), cxx::Exception> {
+        self.decompiler.pin_mut().inject(addr, length, &ops)?;
+        self.injections.insert(addr, (length, ops));
+        self.lifted.invalidate(addr..=addr);
+        Ok(())
+    }
+
+    /// Lift the instruction at `addr` from its bytes again. Returns whether pcode was injected
+    /// there.
+    pub fn remove_injection(&mut self, addr: u64) -> bool {
+        self.lifted.invalidate(addr..=addr);
+        self.injections.remove(&addr);
+        self.decompiler.pin_mut().remove_injection(addr)
+    }
+
+    /// Register the user op `name` for injected pcode to call, see
+    /// [`ffi::Decompiler::add_user_op`]
+    pub fn add_user_op(&mut self, name: &str) -> Result<u32, cxx::Exception> {
+        let index = self.decompiler.pin_mut().add_user_op(name)?;
+        if !self.user_ops.iter().any(|op| op == name) {
+            self.user_ops.push(name.to_string());
+        }
+        Ok(index)
+    }
+
+    /// Load the language again with the `.sla` at `sla`, e.g. after recompiling the
+    /// `.slaspec` under development, and drop the pcode lifted so far. The load image, the
+    /// context, overlay spaces, the op limit, the unimplemented policy, registered user ops and
+    /// injected pcode carry over; context variables the new spec no longer defines are dropped,
+    /// and those it adds start at their `.pspec` defaults. Injected CALLOTHERs follow their user
+    /// op by name if its index changes. On error the program keeps its current language.
+    pub fn reload_spec(&mut self, sla: impl Into<PathBuf>) -> Result<(), SleighError> {
+        let mut language = self.language.clone();
+        language.sla = sla.into();
+        let compiler_spec = language
+            .cspec
+            .as_ref()
+            .map(CompilerSpec::load)
+            .transpose()?;
+        // The new decompiler reads the same image through the same proxy
+        let mut decompiler = unsafe { language.new_decompiler(&mut *self.proxy) }
+            .and_then(|decompiler| Self::add_stack_space(decompiler, &language, &compiler_spec))?;
+        let spaces = self.decompiler.space_hierarchy();
+        for overlay in spaces.spaces().filter(|space| space.is_overlay()) {
+            let base = spaces.overlay_base(overlay).unwrap();
+            decompiler
+                .pin_mut()
+                .add_overlay_space(&overlay.name, &base.name)?;
+        }
+        let context = carried_context(&self.decompiler, &decompiler)?;
+        decompiler.pin_mut().restore_context(&context)?;
+        decompiler
+            .pin_mut()
+            .set_op_limit(self.decompiler.op_limit());
+        decompiler
+            .pin_mut()
+            .set_unimplemented_policy(self.decompiler.unimplemented_policy());
+        for name in &self.user_ops {
+            decompiler.pin_mut().add_user_op(name)?;
+        }
+        let injections = renumber_user_ops(&self.decompiler, &decompiler, &self.injections);
+        for (&addr, (length, ops)) in &injections {
+            decompiler.pin_mut().inject(addr, *length, ops)?;
+        }
+        self.injections = injections;
+        self.decompiler = decompiler;
+        self.architecture = UniquePtr::null();
+        self.language = language;
+        self.compiler_spec = compiler_spec;
+        self.clear_lifted();
+        Ok(())
+    }
+
+    /// Read bytes from the load image
+    pub fn read_bytes(&mut self, addr: &ffi::Address, buf: &mut [u8]) {
+        unsafe { (*self.image).load_fill(buf, addr) }
+    }
+
+    pub fn add_symbol(&mut self, addr: u64, name: impl Into<String>) {
+        self.symbols.insert(addr, name.into());
+    }
+
+    pub fn symbol_at(&self, addr: u64) -> Option<&str> {
+        self.symbols.get(&addr).map(String::as_str)
+    }
+
+    pub fn symbol_address(&self, name: &str) -> Option<u64> {
+        self.symbols
+            .iter()
+            .find(|(_, n)| n.as_str() == name)
+            .map(|(a, _)| *a)
+    }
+
+    pub fn symbols(&self) -> impl Iterator<Item = (u64, &str)> {
+        self.symbols.iter().map(|(a, n)| (*a, n.as_str()))
+    }
+
+    /// Record a function entry point. Without a name the symbol at `entry` is used, falling
+    /// back to `FUN_<entry>` like Ghidra does.
+    pub fn add_function(&mut self, entry: u64, name: Option<&str>) -> &Function {
+        let name = match name {
+            Some(n) => n.to_string(),
+            None => self
+                .symbol_at(entry)
+                .map(str::to_string)
+                .unwrap_or_else(|| format!("FUN_{:08x}", entry)),
+        };
+        let prototype = self.functions.remove(&entry).and_then(|f| f.prototype);
+        self.functions.insert(
+            entry,
+            Function {
+                entry,
+                name,
+                prototype,
+            },
+        );
+        &self.functions[&entry]
+    }
+
+    /// Give the function at `entry` a known signature, recording the function like
+    /// [`add_function`](Self::add_function) if needed. The decompiler then takes it as given
+    /// instead of recovering it, both in the function itself and at calls to it, so library
+    /// functions can be described before decompiling their callers.
+    pub fn set_prototype(&mut self, entry: u64, prototype: Prototype) -> Result<(), SleighError> {
+        let name = self.function_name(entry);
+        cxx::let_cxx_string!(name = name);
+        self.architecture()?
+            .setPrototype(entry, &name, &prototype.to_spec())?;
+        if !self.functions.contains_key(&entry) {
+            self.add_function(entry, None);
+        }
+        self.functions.get_mut(&entry).unwrap().prototype = Some(prototype);
+        Ok(())
+    }
+
+    /// Add a data type for the decompiler to use. Types are named as the decompiler prints
+    /// them, so those defined here can be used in later definitions, globals and prototypes,
+    /// with `*` and `[n]` after the name for pointers and arrays: `point *`, `char [16]`.
+    pub fn define_type(&mut self, definition: TypeDefinition) -> Result<(), SleighError> {
+        definition.apply(self.architecture()?)?;
+        self.types.push(definition);
+        Ok(())
+    }
+
+    /// Name the global variable at `address` and give it a type, which the decompiler then
+    /// takes as given
+    pub fn set_global(
+        &mut self,
+        address: u64,
+        name: &str,
+        type_name: &str,
+    ) -> Result<(), SleighError> {
+        cxx::let_cxx_string!(cxx_name = name);
+        cxx::let_cxx_string!(cxx_type = type_name);
+        self.architecture()?
+            .setGlobal(address, &cxx_name, &cxx_type)?;
+        self.globals
+            .insert(address, (name.to_string(), type_name.to_string()));
+        Ok(())
+    }
+
+    /// Describe a data type by name, for instance one [`function_variables`] reported
+    ///
+    /// [`function_variables`]: Self::function_variables
+    pub fn data_type(&mut self, name: &str) -> Result<ffi::TypeInfo, SleighError> {
+        cxx::let_cxx_string!(name = name);
+        Ok(self.architecture()?.getTypeInfo(&name)?)
+    }
+
+    /// Attach a comment to `address` in the function at `function`, to be printed with its
+    /// decompiled code
+    pub fn add_comment(&mut self, function: u64, address: u64, kind: ffi::CommentKind, text: &str) {
+        let comment = Comment {
+            function,
+            address,
+            kind,
+            text: text.to_string(),
+        };
+        if !self.architecture.is_null() {
+            Self::apply_comment(self.architecture.pin_mut(), &comment);
+        }
+        self.comments.push(comment);
+    }
+
+    /// Comments added so far, in order
+    pub fn comments(&self) -> impl Iterator<Item = &Comment> {
+        self.comments.iter()
+    }
+
+    /// Remove every comment of the function at `function`
+    pub fn clear_comments(&mut self, function: u64) {
+        self.comments.retain(|comment| comment.function != function);
+        if !self.architecture.is_null() {
+            self.architecture.pin_mut().clearComments(function);
+        }
+    }
+
+    fn apply_comment(architecture: Pin<&mut ffi::DecompilerArchitecture>, comment: &Comment) {
+        cxx::let_cxx_string!(text = &comment.text);
+        architecture.addComment(comment.function, comment.address, comment.kind, &text);
+    }
+
+    /// Resolve the `cpool` ops of managed-code languages through `pool` from now on. The
+    /// decompiler asks for each reference once and keeps the record.
+    pub fn set_constant_pool(&mut self, pool: impl ConstantPool + 'static) {
+        self.architecture = UniquePtr::null();
+        self.constant_pool = Some(Box::new(RustConstantPool::new(pool)));
+    }
+
+    /// Decompile the function at `entry` to C, named like [`add_function`](Self::add_function)
+    /// would. The first call sets up the full decompiler from the spec files, which takes a
+    /// `.cspec`; it reads the same load image but starts from the `.pspec` context, not the
+    /// one set on the program.
+    pub fn decompile_function(&mut self, entry: u64) -> Result<String, SleighError> {
+        self.analyze_function(entry, SimplificationStyle::Decompile, false)
+    }
+
+    /// Analyse the function at `entry` in the given style and list its pcode as the
+    /// decompiler left it, block by block. Styles short of
+    /// [`Decompile`](SimplificationStyle::Decompile) skip the costlier stages, for tools that
+    /// only need simplified pcode; only the full analysis can also be printed as C.
+    pub fn function_pcode(
+        &mut self,
+        entry: u64,
+        style: SimplificationStyle,
+    ) -> Result<String, SleighError> {
+        self.analyze_function(entry, style, true)
+    }
+
+    /// Decompile the function at `entry` and return its basic blocks along with the loops,
+    /// conditionals and other structure the decompiler recovered from them
+    pub fn function_graph(&mut self, entry: u64) -> Result<ffi::FunctionGraphInfo, SleighError> {
+        let name = self.function_name(entry);
+        cxx::let_cxx_string!(name = name);
+        Ok(self.architecture()?.getFunctionGraph(entry, &name)?)
+    }
+
+    /// Decompile the function at `entry` and return its recovered prototype along with every
+    /// variable the decompiler settled on, named and typed as in the C it prints
+    pub fn function_variables(
+        &mut self,
+        entry: u64,
+    ) -> Result<ffi::FunctionVariablesInfo, SleighError> {
+        let name = self.function_name(entry);
+        cxx::let_cxx_string!(name = name);
+        Ok(self.architecture()?.getFunctionVariables(entry, &name)?)
+    }
+
+    /// Analyse the function at `entry` in the given style and return its pcode in SSA form,
+    /// with each varnode linked to the op writing it and the ops reading it
+    pub fn function_ssa(
+        &mut self,
+        entry: u64,
+        style: SimplificationStyle,
+    ) -> Result<ffi::FunctionSsaInfo, SleighError> {
+        let name = self.function_name(entry);
+        cxx::let_cxx_string!(name = name);
+        cxx::let_cxx_string!(style = style.name());
+        Ok(self.architecture()?.getFunctionSsa(entry, &name, &style)?)
+    }
+
+    /// Decompile the function at `entry` and return the switches it recovered from indirect
+    /// branches, with the targets they can reach
+    pub fn jump_tables(&mut self, entry: u64) -> Result<Vec<ffi::JumpTableInfo>, SleighError> {
+        let name = self.function_name(entry);
+        cxx::let_cxx_string!(name = name);
+        Ok(self.architecture()?.getJumpTables(entry, &name)?)
+    }
+
+    /// The full decompiler, set up on first use with the types, globals, prototypes and
+    /// comments set so far
+    fn architecture(&mut self) -> Result<Pin<&mut ffi::DecompilerArchitecture>, SleighError> {
+        if self.architecture.is_null() {
+            let pool = self
+                .constant_pool
+                .as_deref_mut()
+                .map_or(std::ptr::null_mut(), |pool| pool as *mut RustConstantPool);
+            let mut architecture =
+                unsafe { self.language.new_architecture(&mut *self.proxy, pool) }?;
+            for definition in &self.types {
+                definition.apply(architecture.pin_mut())?;
+            }
+            for (address, (name, type_name)) in &self.globals {
+                cxx::let_cxx_string!(name = name);
+                cxx::let_cxx_string!(type_name = type_name);
+                architecture
+                    .pin_mut()
+                    .setGlobal(*address, &name, &type_name)?;
+            }
+            for function in self.functions.values() {
+                if let Some(prototype) = &function.prototype {
+                    cxx::let_cxx_string!(name = &function.name);
+                    architecture.pin_mut().setPrototype(
+                        function.entry,
+                        &name,
+                        &prototype.to_spec(),
+                    )?;
+                }
+            }
+            for comment in &self.comments {
+                Self::apply_comment(architecture.pin_mut(), comment);
+            }
+            self.architecture = architecture;
+        }
+        Ok(self.architecture.pin_mut())
+    }
+
+    /// What a function at `entry` is called when decompiled, see
+    /// [`add_function`](Self::add_function)
+    fn function_name(&self, entry: u64) -> String {
+        match self.function(entry) {
+            Some(function) => function.name.clone(),
+            None => self
+                .symbol_at(entry)
+                .map(str::to_string)
+                .unwrap_or_else(|| format!("FUN_{:08x}", entry)),
+        }
+    }
+
+    fn analyze_function(
+        &mut self,
+        entry: u64,
+        style: SimplificationStyle,
+        raw: bool,
+    ) -> Result<String, SleighError> {
+        let name = self.function_name(entry);
+        cxx::let_cxx_string!(name = name);
+        cxx::let_cxx_string!(style = style.name());
+        let architecture = self.architecture()?;
+        Ok(if raw {
+            architecture.getRawFunction(entry, &name, &style)?
+        } else {
+            architecture.decompileFunction(entry, &name, &style)?
+        })
+    }
+
+    pub fn function(&self, entry: u64) -> Option<&Function> {
+        self.functions.get(&entry)
+    }
+
+    pub fn functions(&self) -> impl Iterator<Item = &Function> {
+        self.functions.values()
+    }
+
+    /// Lift the instruction at `addr`, reusing a previous result if its bytes and context are
+    /// unchanged
+    pub fn lift(&mut self, addr: u64) -> Option<&LiftedInstruction> {
+        self.lifted.lift(self.decompiler.pin_mut(), addr).ok()
+    }
+
+    /// The cache behind [`lift`](Self::lift)
+    pub fn pcode_cache(&self) -> &PcodeCache {
+        &self.lifted
+    }
+
+    /// Drop the cached pcode of instructions overlapping `range`, e.g. after code there was
+    /// rewritten
+    pub fn invalidate(&mut self, range: impl RangeBounds<u64>) {
+        self.lifted.invalidate(range);
+    }
+
+    /// Drop all cached pcode, e.g. after the context database was changed
+    pub fn clear_lifted(&mut self) {
+        self.lifted.clear();
+    }
+}
+
+/// The context of `old` as it applies to `new`: defaults start from those of `new`, and values
+/// set in `old` are kept for the variables `new` still defines
+fn carried_context(old: &ffi::Decompiler, new: &ffi::Decompiler) -> Result<String, cxx::Exception> {
+    let mut context = XmlElement::parse(&old.save_context())?;
+    for points in &mut context.children {
+        points.children.retain(|set| {
+            set.name != "set"
+                || set
+                    .attribute("name")
+                    .is_some_and(|name| new.has_context_variable(name))
+        });
+    }
+    // Saved contexts start with the defaults, a point set without an address
+    let defaults = XmlElement::parse(&new.save_context())?.children.remove(0);
+    let old_defaults = &mut context.children[0].children;
+    old_defaults.splice(0..0, defaults.children);
+    Ok(context.to_string())
+}
+
+/// `injections` lifted by `old`, with the user op their CALLOTHERs call renumbered by name for
+/// `new`. Ops `new` has no user op of the same name for are left as they are.
+fn renumber_user_ops(
+    old: &ffi::Decompiler,
+    new: &ffi::Decompiler,
+    injections: &BTreeMap<u64, (Option<usize>, Vec<PcodeOp>)>,
+) -> BTreeMap<u64, (Option<usize>, Vec<PcodeOp>)> {
+    let names = old.user_ops();
+    let constant = old.getConstantSpaceIndex();
+    let mut injections = injections.clone();
+    for op in injections.values_mut().flat_map(|(_, ops)| ops) {
+        match (op.opcode, op.inputs.first_mut()) {
+            (Opcode::CallOther, Some(index)) if index.space == constant => {
+                let name = names.get(index.offset as usize);
+                if let Some(renumbered) = name.and_then(|name| new.user_op(name)) {
+                    index.offset = renumbered as u64;
+                }
+            }
+            _ => {}
+        }
+    }
+    injections
+}
+
+impl Drop for Program {
+    fn drop(&mut self) {
+        self.decompiler = UniquePtr::null();
+        self.architecture = UniquePtr::null();
+        unsafe { drop(Box::from_raw(self.image)) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    use super::{Prototype, SimplificationStyle, TypeDefinition};
+    use crate::compile::compile_slaspec;
+    use crate::ffi::{CommentKind, FieldInfo, PoolRecord, PoolTag, TypeKind, VariableKind};
+    use crate::pcode::PcodeOp;
+    use crate::{toy, ConstantPool};
+
+    #[test]
+    fn test_function_variables() {
+        // movi r1, 5 ; add r0, r1 ; ret
+        let mut program = toy::program(0x1000, &[0x01, 0x10, 0x05, 0x02, 0x01, 0x03]);
+        let vars = program.function_variables(0x1000).unwrap();
+        assert_eq!(vars.model, "__toycall");
+        assert_eq!(vars.return_value.type_name, "int4");
+        assert_eq!(vars.parameters.len(), 1);
+        let param = &vars.parameters[0];
+        assert_eq!(
+            (param.name.as_str(), param.type_name.as_str()),
+            ("param_1", "int4")
+        );
+        // Returned in r0, where the parameter came in
+        assert_eq!(vars.return_value.storage, param.storage);
+        assert_eq!((param.storage.offset, param.storage.size), (0, 4));
+        let var = &vars.variables[0];
+        assert_eq!((var.kind, var.parameter), (VariableKind::Parameter, 0));
+        assert_eq!(var.name, "param_1");
+
+        // ld r0, [r1] ; jz r0, 0 ; ret
+        let mut program = toy::program(0, &[0x04, 0x01, 0x0e, 0x00, 0x00, 0x03]);
+        let vars = program.function_variables(0).unwrap();
+        assert_eq!(vars.return_value.type_name, "void");
+        assert_eq!(vars.return_value.storage.space, -1);
+        let types: Vec<_> = vars
+            .parameters
+            .iter()
+            .map(|p| p.type_name.as_str())
+            .collect();
+        assert_eq!(types, ["xunknown4", "int4 *"]);
+        let pointer = vars.variables.iter().find(|v| v.name == "param_2").unwrap();
+        assert_eq!(
+            (pointer.kind, pointer.parameter),
+            (VariableKind::Parameter, 1)
+        );
+        assert_eq!(pointer.storage, vars.parameters[1].storage);
+        assert_eq!(pointer.type_name, "int4 *");
+        // The loaded value and the condition are never named
+        let condition = vars
+            .variables
+            .iter()
+            .find(|v| v.type_name == "bool")
+            .unwrap();
+        assert_eq!(
+            (condition.kind, condition.name.as_str()),
+            (VariableKind::Temporary, "")
+        );
+    }
+
+    #[test]
+    fn test_set_prototype() {
+        // 0: movi r0, 7 ; call 0x10 ; ret
+        // 0x10: ld r0, [r0] ; ret
+        let mut code = vec![0x01, 0x00, 0x07, 0x0d, 0x10, 0x03];
+        code.resize(0x10, 0);
+        code.extend([0x04, 0x00, 0x03]);
+        let mut program = toy::program(0, &code);
+        let c = program.decompile_function(0).unwrap();
+        assert!(c.contains("func_0x00000010(7);"), "{}", c);
+
+        program.add_function(0x10, Some("deref"));
+        let prototype = Prototype {
+            model: None,
+            return_type: "char".into(),
+            parameters: vec![("s".into(), "char *".into())],
+            variadic: false,
+        };
+        program.set_prototype(0x10, prototype.clone()).unwrap();
+        assert_eq!(program.function(0x10).unwrap().prototype, Some(prototype));
+        let c = program.decompile_function(0x10).unwrap();
+        assert!(c.contains("char deref(char *s)"), "{}", c);
+        // Calls follow the prototype too
+        let c = program.decompile_function(0).unwrap();
+        assert!(c.contains("deref((char *)0x7);"), "{}", c);
+        // and it outlives the decompiler
+        program.reload_spec(toy::files().sla.clone()).unwrap();
+        assert_eq!(program.decompile_function(0).unwrap(), c);
+
+        let err = program
+            .set_prototype(
+                0,
+                Prototype {
+                    return_type: "widget *".into(),
+                    ..Default::default()
+                },
+            )
+            .unwrap_err();
+        assert!(
+            err.to_string().contains("Unknown data-type widget *"),
+            "{}",
+            err
+        );
+        let err = program
+            .set_prototype(
+                0,
+                Prototype {
+                    model: Some("__fastcall".into()),
+                    return_type: "void".into(),
+                    ..Default::default()
+                },
+            )
+            .unwrap_err();
+        assert!(err.to_string().contains("__fastcall"), "{}", err);
+        assert_eq!(program.function(0), None);
+    }
+
+    #[test]
+    fn test_jump_tables() {
+        // 0: jgt r0, 3, 0x30 ; ldt r1, [r0, 0x20] ; jmpr r1
+        let mut code = vec![0x12, 0x00, 0x03, 0x30, 0x13, 0x10, 0x20, 0x11, 0x01];
+        code.resize(0x20, 0);
+        for target in [0x40u32, 0x44, 0x48, 0x4c] {
+            code.extend(target.to_le_bytes());
+        }
+        code.resize(0x30, 0);
+        // movi r0, n ; ret
+        code.extend([0x01, 0x00, 0x00, 0x03]);
+        code.resize(0x40, 0);
+        for n in 1..=4 {
+            code.extend([0x01, 0x00, n, 0x03]);
+        }
+        let mut program = toy::program(0, &code);
+        let tables = program.jump_tables(0).unwrap();
+        assert_eq!(tables.len(), 1);
+        let table = &tables[0];
+        assert_eq!(table.address, 7);
+        // The guard's target is appended to the table
+        assert_eq!(table.targets, [0x40, 0x44, 0x48, 0x4c, 0x30]);
+        assert_eq!(table.labels[..4], [0, 1, 2, 3]);
+        assert_eq!((table.has_default, table.default_target), (true, 0x30));
+        // Switching on r0 directly
+        let r0 = program.decompiler().registers().remove(0);
+        assert_eq!(r0.name, "r0");
+        let var = table.switch_variable;
+        assert_eq!(
+            (var.space, var.offset, var.size),
+            (r0.space, r0.offset, r0.size)
+        );
+        assert_eq!(table.switch_mask, 0xffffffff);
+
+        let c = program.decompile_function(0).unwrap();
+        assert!(c.contains("switch(param_1)"), "{}", c);
+        assert!(program.jump_tables(0x30).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_comments() {
+        // movi r1, 5 ; add r0, r1 ; ret
+        let mut program = toy::program(0x1000, &[0x01, 0x10, 0x05, 0x02, 0x01, 0x03]);
+        program.add_comment(0x1000, 0x1000, CommentKind::Plate, "Adds five");
+        let c = program.decompile_function(0x1000).unwrap();
+        assert!(c.starts_with("\n/* Adds five */\n"), "{}", c);
+        // Added after the decompiler is set up, and printed in order
+        program.add_comment(0x1000, 0x1003, CommentKind::Pre, "the sum");
+        program.add_comment(0x1000, 0x1003, CommentKind::Post, "done");
+        program.add_comment(0x2000, 0x2000, CommentKind::Pre, "elsewhere");
+        let c = program.decompile_function(0x1000).unwrap();
+        let sum = c.find("/* the sum */").unwrap();
+        let done = c.find("/* done */").unwrap();
+        let ret = c.find("return param_1 + 5;").unwrap();
+        assert!(sum < done && done < ret, "{}", c);
+        assert!(!c.contains("elsewhere"), "{}", c);
+        assert_eq!(program.comments().count(), 4);
+
+        // They outlive the decompiler
+        program.reload_spec(toy::files().sla.clone()).unwrap();
+        assert_eq!(program.decompile_function(0x1000).unwrap(), c);
+
+        program.clear_comments(0x1000);
+        let kinds: Vec<_> = program.comments().map(|comment| comment.kind).collect();
+        assert_eq!(kinds, [CommentKind::Pre]);
+        let c = program.decompile_function(0x1000).unwrap();
+        assert!(!c.contains("/*"), "{}", c);
+    }
+
+    #[test]
+    fn test_constant_pool() {
+        struct Pool(Rc<Cell<u32>>);
+
+        impl ConstantPool for Pool {
+            fn resolve(&mut self, refs: &[u64]) -> Option<PoolRecord> {
+                self.0.set(self.0.get() + 1);
+                let (tag, value, type_name, byte_data) = match refs {
+                    [7, 0] => (PoolTag::Primitive, 0x1234, "int4", vec![]),
+                    [8, 0] => (PoolTag::StringLiteral, 0, "char *", b"hi".to_vec()),
+                    [9, 0] => (PoolTag::Primitive, 1, "nosuchtype", vec![]),
+                    _ => return None,
+                };
+                Some(PoolRecord {
+                    tag,
+                    token: String::new(),
+                    value,
+                    type_name: type_name.to_string(),
+                    byte_data,
+                })
+            }
+        }
+
+        // ldc r0, 7 ; ret
+        let mut program = toy::program(0x1000, &[0x14, 0x00, 0x07, 0x03]);
+        let c = program.decompile_function(0x1000).unwrap();
+        assert!(c.contains("UNKNOWNREF"), "{}", c);
+
+        let calls = Rc::new(Cell::new(0));
+        program.set_constant_pool(Pool(calls.clone()));
+        let c = program.decompile_function(0x1000).unwrap();
+        assert!(c.contains("return 0x1234;"), "{}", c);
+        // The record is kept
+        let resolved = calls.get();
+        program.decompile_function(0x1000).unwrap();
+        assert_eq!(calls.get(), resolved);
+
+        // ldc r0, 8 ; ret
+        let mut program = toy::program(0x1000, &[0x14, 0x00, 0x08, 0x03]);
+        program.set_constant_pool(Pool(calls.clone()));
+        let c = program.decompile_function(0x1000).unwrap();
+        assert!(c.contains("\"hi\""), "{}", c);
+
+        // ldc r0, 9 ; ret
+        let mut program = toy::program(0x1000, &[0x14, 0x00, 0x09, 0x03]);
+        program.set_constant_pool(Pool(calls));
+        let err = program.decompile_function(0x1000).unwrap_err();
+        assert!(err.to_string().contains("nosuchtype"), "{}", err);
+    }
+
+    #[test]
+    fn test_data_types() {
+        // movi r1, 0x44 ; ld r0, [r1] ; ret
+        let mut program = toy::program(0, &[0x01, 0x10, 0x44, 0x04, 0x01, 0x03]);
+        let c = program.decompile_function(0).unwrap();
+        assert!(c.contains("return in_ram_00000044;"), "{}", c);
+
+        let u32_type = TypeDefinition::Primitive {
+            name: "u32".into(),
+            size: 4,
+            kind: TypeKind::Uint,
+        };
+        program.define_type(u32_type.clone()).unwrap();
+        let field = |name: &str, type_name: &str| FieldInfo {
+            name: name.into(),
+            offset: -1,
+            type_name: type_name.into(),
+        };
+        let fields = vec![
+            field("flags", "u32"),
+            field("limit", "int4"),
+            field("name", "char [8]"),
+        ];
+        program
+            .define_type(TypeDefinition::Struct {
+                name: "config".into(),
+                size: 0,
+                fields,
+            })
+            .unwrap();
+        let config = program.data_type("config").unwrap();
+        assert_eq!((config.kind, config.size), (TypeKind::Struct, 16));
+        let offsets: Vec<_> = config.fields.iter().map(|f| f.offset).collect();
+        assert_eq!(offsets, [0, 4, 8]);
+        assert_eq!(config.fields[2].type_name, "char [8]");
+        let name = program.data_type(&config.fields[2].type_name).unwrap();
+        assert_eq!(
+            (name.kind, name.element.as_str()),
+            (TypeKind::Array, "char")
+        );
+        assert_eq!((name.size, name.length), (8, 8));
+        let pointer = program.data_type("config *").unwrap();
+        assert_eq!((pointer.kind, pointer.size), (TypeKind::Pointer, 4));
+        assert_eq!(pointer.element, "config");
+        assert_eq!(program.data_type("u32").unwrap().kind, TypeKind::Uint);
+
+        program.set_global(0x40, "settings", "config").unwrap();
+        let c = program.decompile_function(0).unwrap();
+        assert!(c.contains("int4 FUN_00000000(void)"), "{}", c);
+        assert!(c.contains("return settings.limit;"), "{}", c);
+        // Definitions and globals outlive the decompiler
+        program.reload_spec(toy::files().sla.clone()).unwrap();
+        assert_eq!(program.decompile_function(0).unwrap(), c);
+
+        for name in ["widget", "config [0]", "config [", "config &"] {
+            let err = program.data_type(name).unwrap_err();
+            assert!(err.to_string().contains(name), "{}", err);
+        }
+        assert!(program.define_type(u32_type).is_err());
+        let pointer_type = TypeDefinition::Primitive {
+            name: "handle".into(),
+            size: 4,
+            kind: TypeKind::Pointer,
+        };
+        assert!(program.define_type(pointer_type).is_err());
+        assert!(program.set_global(0x50, "missing", "widget").is_err());
+    }
+
+    #[test]
+    fn test_decompile_function() {
+        // movi r1, 5 ; add r0, r1 ; ret
+        let mut program = toy::program(0x1000, &[0x01, 0x10, 0x05, 0x02, 0x01, 0x03]);
+        program.add_function(0x1000, Some("add_five"));
+        let c = program.decompile_function(0x1000).unwrap();
+        assert!(c.contains("int4 add_five(int4 param_1)"), "{}", c);
+        assert!(c.contains("return param_1 + 5;"), "{}", c);
+        // Decompiling again starts over
+        assert_eq!(program.decompile_function(0x1000).unwrap(), c);
+
+        // The first pass only builds SSA, normalizing folds the constant and drops the stack
+        let first = program
+            .function_pcode(0x1000, SimplificationStyle::FirstPass)
+            .unwrap();
+        assert!(first.contains("r0(i) + r1(0x00001000:0)"), "{}", first);
+        let normal = program
+            .function_pcode(0x1000, SimplificationStyle::Normalize)
+            .unwrap();
+        assert!(normal.contains("r0(i) + #0x5"), "{}", normal);
+        assert!(!normal.contains("sp("), "{}", normal);
+        // A lighter style in between doesn't get in the way of printing C
+        assert_eq!(program.decompile_function(0x1000).unwrap(), c);
+
+        let mut files = toy::files().clone();
+        files.cspec = None;
+        let mut program = crate::project::Program::new(
+            files,
+            toy::ToyImage {
+                base: 0,
+                code: vec![0x03],
+            },
+        )
+        .unwrap();
+        let err = program.decompile_function(0).unwrap_err();
+        assert_eq!(err.kind(), crate::error::ErrorKind::Spec);
+    }
+
+    #[test]
+    fn test_reload_spec() {
+        let mut program = toy::program(0, &[0x00]);
+        program.set_context_everywhere("mode", 1).unwrap();
+        program.add_overlay_space("ovl", "ram").unwrap();
+        program.set_op_limit(Some(8));
+        assert_eq!(program.lift(0).unwrap().length, 1);
+        let vmcall = program.add_user_op("vmcall").unwrap();
+        let call = PcodeOp::call_other(program.decompiler(), vmcall, None, Vec::new());
+        program.inject(0x10, Some(1), vec![call]).unwrap();
+
+        let dir = std::env::temp_dir().join(format!("sleigh-reload-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let slaspec = dir.join("toy.slaspec");
+        let sla = dir.join("toy.sla");
+        let edited = toy::SLASPEC
+            .replace(":alt is", ":alt2 is")
+            .replace("  mode = (0,0)", "  mode = (0,0)\n  bank = (1,2)")
+            .replace(
+                "define pcodeop cpuid;",
+                "define pcodeop cpuid;\ndefine pcodeop vmexit;",
+            );
+        std::fs::write(&slaspec, edited).unwrap();
+        compile_slaspec(&slaspec, &sla, &[]).unwrap();
+
+        assert!(program.reload_spec(dir.join("missing.sla")).is_err());
+        assert_eq!(program.decompiler().disassemble_one(0).unwrap().0, "alt");
+        // A spec broken while editing it, as the sleigh compiler would not leave it
+        let broken = dir.join("broken.sla");
+        std::fs::write(&broken, "<sleigh version=\"4\"><spaces").unwrap();
+        assert!(program.reload_spec(&broken).is_err());
+        assert_eq!(program.language().sla, toy::files().sla);
+        assert_eq!(program.decompiler().disassemble_one(0).unwrap().0, "alt");
+        assert_eq!(program.lift(0).unwrap().length, 1);
+
+        program.reload_spec(&sla).unwrap();
+        assert_eq!(program.language().sla, sla);
+        let decompiler = program.decompiler();
+        assert_eq!(decompiler.disassemble_one(0).unwrap().0, "alt2");
+        assert_eq!(decompiler.context_variable("bank", 0).unwrap(), 0);
+        assert!(decompiler.space_hierarchy().by_name("ovl").is_some());
+        assert_eq!(decompiler.op_limit(), Some(8));
+        assert!(program.pcode_cache().is_empty());
+        // The spec's new op comes first, moving vmcall up one
+        assert_eq!(decompiler.user_op("vmcall"), Some(vmcall + 1));
+        let index = &program.lift(0x10).unwrap().ops[0].inputs[0];
+        assert_eq!(index.offset, (vmcall + 1) as u64);
+
+        // Back to the original, which has no `bank`
+        program.reload_spec(&toy::files().sla).unwrap();
+        assert_eq!(program.decompiler().disassemble_one(0).unwrap().0, "alt");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
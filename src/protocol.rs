@@ -0,0 +1,280 @@
+//! Ghidra's decompiler protocol, served in-process.
+//!
+//! Ghidra runs its decompiler as a `decompile` process and drives it over stdin and stdout:
+//! the client registers a program with its specs, asks for functions to be decompiled and
+//! answers the queries the decompiler sends back for bytes, symbols, types and the like.
+//! [`serve`] speaks the same protocol over any pair of streams, so tooling built around that
+//! process can run the decompiler from this crate instead of spawning it.
+
+use std::io::{self, Read, Write};
+
+use crate::ffi;
+
+pub struct RustProtocolStream<'a> {
+    input: &'a mut dyn Read,
+    output: &'a mut dyn Write,
+}
+
+impl RustProtocolStream<'_> {
+    pub(crate) fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            match self.input.read(buf) {
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                res => return res,
+            }
+        }
+    }
+
+    pub(crate) fn write(&mut self, buf: &[u8]) -> io::Result<()> {
+        self.output.write_all(buf)
+    }
+
+    pub(crate) fn flush(&mut self) -> io::Result<()> {
+        self.output.flush()
+    }
+}
+
+/// Answer the commands a Ghidra client sends on `input`, replying and querying it on
+/// `output`, until it deregisters a program or closes `input` between two commands. Programs
+/// still registered then are dropped.
+///
+/// The decompiler's commands share global state, so sessions on other threads wait for this
+/// one to finish, as does anything parsing XML. The streams must not call back into this
+/// crate.
+pub fn serve(mut input: impl Read, mut output: impl Write) -> Result<(), cxx::Exception> {
+    let mut stream = RustProtocolStream {
+        input: &mut input,
+        output: &mut output,
+    };
+    unsafe { ffi::serveGhidraProtocol(&mut stream) }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, BufReader, Read, Write};
+    use std::sync::mpsc::{self, Receiver, Sender};
+    use std::thread;
+
+    use crate::toy;
+
+    /// The writing end of an in-process pipe. Dropping it closes the pipe.
+    struct PipeWriter(Sender<Vec<u8>>);
+
+    impl Write for PipeWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            // An empty chunk would read as the end of the stream
+            if !buf.is_empty() {
+                self.0
+                    .send(buf.to_vec())
+                    .map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))?;
+            }
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// The reading end of an in-process pipe
+    struct PipeReader {
+        chunks: Receiver<Vec<u8>>,
+        chunk: Vec<u8>,
+        pos: usize,
+    }
+
+    impl Read for PipeReader {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.pos == self.chunk.len() {
+                match self.chunks.recv() {
+                    Ok(chunk) => (self.chunk, self.pos) = (chunk, 0),
+                    Err(_) => return Ok(0),
+                }
+            }
+            let len = buf.len().min(self.chunk.len() - self.pos);
+            buf[..len].copy_from_slice(&self.chunk[self.pos..self.pos + len]);
+            self.pos += len;
+            Ok(len)
+        }
+    }
+
+    fn pipe() -> (PipeWriter, PipeReader) {
+        let (tx, rx) = mpsc::channel();
+        let reader = PipeReader {
+            chunks: rx,
+            chunk: Vec::new(),
+            pos: 0,
+        };
+        (PipeWriter(tx), reader)
+    }
+
+    /// The client side of the protocol
+    struct Client {
+        reader: BufReader<PipeReader>,
+        writer: PipeWriter,
+    }
+
+    impl Client {
+        fn burst(&mut self, code: u8) {
+            self.writer.write_all(&[0, 0, 1, code]).unwrap();
+        }
+
+        fn string(&mut self, s: &str) {
+            self.burst(0x0e);
+            self.writer.write_all(s.as_bytes()).unwrap();
+            self.burst(0x0f);
+        }
+
+        fn command(&mut self, name: &str, params: &[&str]) {
+            self.burst(0x02);
+            self.string(name);
+            for param in params {
+                self.string(param);
+            }
+            self.burst(0x03);
+        }
+
+        /// The next burst code, and the bytes before it
+        fn read_burst(&mut self) -> (u8, Vec<u8>) {
+            let mut data = Vec::new();
+            let mut byte = [0];
+            loop {
+                self.reader.read_exact(&mut byte).unwrap();
+                if byte[0] == 1 && data.ends_with(&[0]) {
+                    while data.last() == Some(&0) {
+                        data.pop();
+                    }
+                    self.reader.read_exact(&mut byte).unwrap();
+                    return (byte[0], data);
+                }
+                data.push(byte[0]);
+            }
+        }
+
+        fn read_string(&mut self) -> String {
+            assert_eq!(self.read_burst().0, 0x0e);
+            let (code, data) = self.read_burst();
+            assert_eq!(code, 0x0f);
+            String::from_utf8(data).unwrap()
+        }
+
+        /// Read a query of the decompiler about the toy language and work out the answer
+        fn answer_query(&mut self) -> String {
+            let query = self.read_string();
+            let mut args = Vec::new();
+            loop {
+                match self.read_burst().0 {
+                    0x0e => {
+                        let (code, data) = self.read_burst();
+                        assert_eq!(code, 0x0f);
+                        args.push(String::from_utf8(data).unwrap());
+                    }
+                    0x05 => break,
+                    code => panic!("unexpected burst {:#x}", code),
+                }
+            }
+            match (query.as_str(), args[0].as_str()) {
+                ("getRegister", name) => {
+                    let offset = match name {
+                        "r0" => 0,
+                        "r1" => 4,
+                        "sp" => 0x10,
+                        "lr" => 0x14,
+                        "pc" => 0x18,
+                        _ => panic!("unexpected register {}", name),
+                    };
+                    format!(
+                        "<addr space=\"register\" offset=\"{:#x}\" size=\"4\"/>",
+                        offset
+                    )
+                }
+                ("getUserOpName", index) => ["rdrand", "rdtsc", "cpuid"]
+                    .get(index.parse::<usize>().unwrap())
+                    .map_or(String::new(), |name| name.to_string()),
+                _ => panic!("unexpected query {}", query),
+            }
+        }
+
+        /// The strings of the command's response, answering the decompiler's queries
+        fn response(&mut self) -> Vec<String> {
+            assert_eq!(self.read_burst().0, 0x06);
+            let mut strings = Vec::new();
+            loop {
+                match self.read_burst().0 {
+                    0x04 => {
+                        let answer = self.answer_query();
+                        self.burst(0x08);
+                        self.string(&answer);
+                        self.burst(0x09);
+                    }
+                    0x0e => {
+                        let (code, data) = self.read_burst();
+                        assert_eq!(code, 0x0f);
+                        strings.push(String::from_utf8(data).unwrap());
+                    }
+                    0x10 => {
+                        let (code, data) = self.read_burst();
+                        assert_eq!(code, 0x11);
+                        strings.push(String::from_utf8(data).unwrap());
+                    }
+                    0x07 => return strings,
+                    code => panic!("unexpected burst {:#x}", code),
+                }
+            }
+        }
+    }
+
+    fn session() -> (Client, thread::JoinHandle<Result<(), cxx::Exception>>) {
+        let (writer, input) = pipe();
+        let (output, reader) = pipe();
+        let handle = thread::spawn(move || super::serve(input, output));
+        let client = Client {
+            reader: BufReader::new(reader),
+            writer,
+        };
+        (client, handle)
+    }
+
+    /// The `<sleigh>` document Ghidra sends along with a program: the spaces of the `.sla`
+    fn translator_spec() -> String {
+        let sla = std::fs::read_to_string(&toy::files().sla).unwrap();
+        let start = sla.find("<spaces").unwrap();
+        let end = sla.find("</spaces>").unwrap() + "</spaces>".len();
+        format!(
+            "<sleigh bigendian=\"false\" uniqbase=\"0x10000000\">{}</sleigh>",
+            &sla[start..end]
+        )
+    }
+
+    #[test]
+    fn test_serve() {
+        let (mut client, handle) = session();
+        client.command("nosuchcommand", &[]);
+        assert_eq!(client.response(), ["Bad command: nosuchcommand"]);
+
+        let pspec = std::fs::read_to_string(&toy::files().pspec).unwrap();
+        let cspec = std::fs::read_to_string(toy::files().cspec.as_ref().unwrap()).unwrap();
+        let tspec = translator_spec();
+        client.command("registerProgram", &[&pspec, &cspec, &tspec, "<coretypes/>"]);
+        assert_eq!(client.response(), ["0", ""]);
+        client.command("flushNative", &["0"]);
+        assert_eq!(client.response(), ["0", ""]);
+        // Deregistering ends the session, like it ends the decompiler process
+        client.command("deregisterProgram", &["0"]);
+        assert_eq!(client.response(), ["1"]);
+        handle.join().unwrap().unwrap();
+
+        // Closing the stream between commands ends it too
+        let (client, handle) = session();
+        drop(client.writer);
+        handle.join().unwrap().unwrap();
+
+        // but not in the middle of one, where the decompiler would exit
+        let (mut client, handle) = session();
+        client.burst(0x02);
+        client.string("registerProgram");
+        drop(client.writer);
+        let err = handle.join().unwrap().unwrap_err();
+        assert!(err.what().contains("closed"), "{}", err.what());
+    }
+}
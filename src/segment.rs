@@ -0,0 +1,281 @@
+//! Segmented addressing.
+//!
+//! Languages for segmented targets like real-mode x86 form addresses with a user op, usually
+//! `segment(base, inner)`, which shows up in pcode as a CALLOTHER. The compiler spec defines
+//! what it computes with a `<segmentop>` holding a short pcode snippet, e.g.
+//! `res = (zext(base) << 4) + zext(inner);`. [`SegmentOp`] reads that definition and has the
+//! decompiler compile and run the snippet, so consumers get the linear address without an
+//! emulator.
+
+use std::fmt;
+
+use crate::cspec::Storage;
+use crate::languages::LanguageError;
+use crate::pcode::{PcodeOp, Varnode};
+use crate::xml::{parse_int, XmlElement};
+use crate::{ffi, Opcode};
+
+/// A segment op body that can't be evaluated
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SegmentError {
+    /// The body doesn't compile, or uses something a snippet can't, like memory
+    Syntax(String),
+    /// Number of inputs the op takes and number it was given
+    Inputs { expected: usize, found: usize },
+    /// The engine refused to evaluate an op, e.g. a division by zero
+    Evaluation(String),
+}
+
+impl fmt::Display for SegmentError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SegmentError::Syntax(msg) => write!(f, "bad segment op body: {}", msg),
+            SegmentError::Inputs { expected, found } => {
+                write!(f, "segment op takes {} inputs, given {}", expected, found)
+            }
+            SegmentError::Evaluation(msg) => write!(f, "can't evaluate segment op: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for SegmentError {}
+
+/// A `<segmentop>` of a compiler spec
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SegmentOp {
+    /// Name of the user op the language calls
+    pub userop: String,
+    /// Space the computed addresses are in, the default data space if not given
+    pub space: Option<String>,
+    /// Whether far pointers are stored as a segment and an offset
+    pub far_pointer: bool,
+    /// Names and sizes of the inputs, the base (segment) first, then the inner offset
+    pub inputs: Vec<(String, u32)>,
+    /// Name and size of the output
+    pub output: (String, u32),
+    /// The pcode computing the output from the inputs
+    pub body: String,
+    /// Where the base is read from when only the offset of a pointer is known
+    pub const_resolve: Option<Storage>,
+}
+
+impl SegmentOp {
+    pub(crate) fn from_xml(el: &XmlElement) -> Result<Self, LanguageError> {
+        let bad = |what: &str| LanguageError::Xml(format!("segmentop without {}", what));
+        let pcode = el.child("pcode").ok_or_else(|| bad("<pcode>"))?;
+        let variable = |el: &XmlElement| {
+            let name = el.attribute("name").ok_or_else(|| bad("variable name"))?;
+            let size = el
+                .attribute("size")
+                .and_then(parse_int)
+                .ok_or_else(|| bad("variable size"))?;
+            Ok::<_, LanguageError>((name.to_string(), size as u32))
+        };
+        Ok(Self {
+            userop: el
+                .attribute("userop")
+                .ok_or_else(|| bad("userop"))?
+                .to_string(),
+            space: el.attribute("space").map(str::to_string),
+            far_pointer: matches!(el.attribute("farpointer"), Some("true" | "yes")),
+            inputs: pcode
+                .children_named("input")
+                .map(variable)
+                .collect::<Result<_, _>>()?,
+            output: variable(pcode.child("output").ok_or_else(|| bad("<output>"))?)?,
+            body: pcode
+                .child("body")
+                .ok_or_else(|| bad("<body>"))?
+                .content
+                .clone(),
+            const_resolve: el
+                .child("constresolve")
+                .and_then(|c| c.children.iter().find_map(Storage::from_xml)),
+        })
+    }
+
+    /// The address the op computes from `inputs`, given in the order of
+    /// [`inputs`](Self::inputs)
+    pub fn apply(&self, decompiler: &ffi::Decompiler, inputs: &[u64]) -> Result<u64, SegmentError> {
+        if inputs.len() != self.inputs.len() {
+            return Err(SegmentError::Inputs {
+                expected: self.inputs.len(),
+                found: inputs.len(),
+            });
+        }
+        let names: Vec<String> = self.inputs.iter().map(|(name, _)| name.clone()).collect();
+        let sizes: Vec<u32> = self.inputs.iter().map(|&(_, size)| size).collect();
+        let values: Vec<u64> = inputs
+            .iter()
+            .zip(&sizes)
+            .map(|(&value, &size)| mask(value, size))
+            .collect();
+        let (output, output_size) = &self.output;
+        cxx::let_cxx_string!(body = &self.body);
+        cxx::let_cxx_string!(output = output);
+        let mut snippet = decompiler
+            .compileSnippet(&body, &names, &sizes, &output, *output_size)
+            .map_err(|e| SegmentError::Syntax(e.what().to_string()))?;
+        snippet
+            .pin_mut()
+            .evaluate(&values)
+            .map_err(|e| SegmentError::Evaluation(e.what().to_string()))
+    }
+
+    /// The address `op` computes if it is a call to this segment op. Constant inputs are
+    /// read directly and `value` is asked for the others; `Ok(None)` if `op` is some other op
+    /// or `value` doesn't know an input.
+    pub fn resolve(
+        &self,
+        decompiler: &ffi::Decompiler,
+        op: &PcodeOp,
+        mut value: impl FnMut(&Varnode) -> Option<u64>,
+    ) -> Result<Option<u64>, SegmentError> {
+        let constant = decompiler.getConstantSpaceIndex();
+        let index = match decompiler.symbol(&self.userop) {
+            Some(sym) if sym.kind == ffi::SymbolKind::UserOp => sym.index as u64,
+            _ => return Ok(None),
+        };
+        match op.inputs.split_first() {
+            Some((id, args))
+                if op.opcode == Opcode::CallOther && id.space == constant && id.offset == index =>
+            {
+                let args: Option<Vec<u64>> = args
+                    .iter()
+                    .map(|vn| {
+                        if vn.space == constant {
+                            Some(vn.offset)
+                        } else {
+                            value(vn)
+                        }
+                    })
+                    .collect();
+                args.map(|args| self.apply(decompiler, &args)).transpose()
+            }
+            _ => Ok(None),
+        }
+    }
+}
+
+fn mask(value: u64, size: u32) -> u64 {
+    if size >= 8 {
+        value
+    } else {
+        value & ((1u64 << (size * 8)) - 1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::toy;
+
+    const SEGMENTOP: &str = r#"<segmentop space="ram" userop="cpuid" farpointer="yes">
+  <pcode>
+    <input name="base" size="2"/>
+    <input name="inner" size="2"/>
+    <output name="res" size="4"/>
+    <body><![CDATA[
+      res = (zext(base) << 4) + zext(inner);
+    ]]></body>
+  </pcode>
+  <constresolve><register name="r3"/></constresolve>
+</segmentop>"#;
+
+    #[test]
+    fn test_segment_op() {
+        let op = SegmentOp::from_xml(&XmlElement::parse(SEGMENTOP).unwrap()).unwrap();
+        assert_eq!(op.userop, "cpuid");
+        assert!(op.far_pointer);
+        assert_eq!(
+            op.inputs,
+            [("base".to_string(), 2), ("inner".to_string(), 2)]
+        );
+        assert_eq!(op.output, ("res".to_string(), 4));
+        assert_eq!(op.const_resolve, Some(Storage::Register("r3".to_string())));
+
+        let program = toy::program(0, &[]);
+        let decompiler = program.decompiler();
+        assert_eq!(op.apply(decompiler, &[0xf000, 0xfff0]), Ok(0xffff0));
+        assert_eq!(op.apply(decompiler, &[0xffff, 0x10]), Ok(0x100000));
+        assert_eq!(
+            op.apply(decompiler, &[1]),
+            Err(SegmentError::Inputs {
+                expected: 2,
+                found: 1
+            })
+        );
+
+        // The toy language has no segment op, so its cpuid user op stands in for one
+        let constant = decompiler.getConstantSpaceIndex();
+        let r0 = decompiler.symbol("r0").unwrap().varnode().unwrap();
+        let cpuid = decompiler.symbol("cpuid").unwrap().index as u64;
+        let call = |id| PcodeOp {
+            opcode: Opcode::CallOther,
+            output: None,
+            inputs: vec![
+                Varnode {
+                    space: constant,
+                    offset: id,
+                    size: 4,
+                },
+                r0.clone(),
+                Varnode {
+                    space: constant,
+                    offset: 0x20,
+                    size: 2,
+                },
+            ],
+        };
+        let resolved = op.resolve(decompiler, &call(cpuid), |vn| (*vn == r0).then_some(0x100));
+        assert_eq!(resolved, Ok(Some(0x1020)));
+        assert_eq!(op.resolve(decompiler, &call(cpuid), |_| None), Ok(None));
+        assert_eq!(op.resolve(decompiler, &call(0), |_| Some(0)), Ok(None));
+    }
+
+    #[test]
+    fn test_segment_body() {
+        let program = toy::program(0, &[]);
+        let decompiler = program.decompiler();
+        let op = |body: &str| SegmentOp {
+            userop: "segment".to_string(),
+            space: None,
+            far_pointer: false,
+            inputs: vec![("base".to_string(), 2), ("inner".to_string(), 2)],
+            output: ("res".to_string(), 4),
+            body: body.to_string(),
+            const_resolve: None,
+        };
+        let apply = |body| op(body).apply(decompiler, &[0x1234, 0x8000]);
+        assert_eq!(
+            apply("local t:4 = zext(base) * 0x10; res = t | zext(inner);"),
+            Ok(0x1a340)
+        );
+        assert_eq!(apply("res = sext(inner);"), Ok(0xffff8000));
+        assert_eq!(apply("res = zext(base) << 16 + 4;"), Ok(0x23400000));
+        assert_eq!(
+            apply("res = zext(base) << 8 | zext(inner) & 0xff;"),
+            Ok(0x123400)
+        );
+        assert_eq!(
+            apply("if (inner == 0) goto <done>; res = zext(inner) + 1; <done>"),
+            Ok(0x8001)
+        );
+        for body in [
+            "res = bogus;",
+            "res = (base;",
+            "res = r0;",
+            "res = *[ram]:4 zext(inner);",
+        ] {
+            assert!(
+                matches!(apply(body), Err(SegmentError::Syntax(_))),
+                "{}",
+                body
+            );
+        }
+        assert!(matches!(
+            apply("res = zext(inner) / 0;"),
+            Err(SegmentError::Evaluation(_))
+        ));
+    }
+}
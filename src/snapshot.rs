@@ -0,0 +1,198 @@
+//! Golden-output snapshot tests.
+//!
+//! [`assert_snapshot`] compares text produced by the engine against `src/snapshots/<name>.snap`.
+//! On a mismatch the new output is written next to it as `<name>.snap.new` and the test fails
+//! with a line diff; review it and rename the file, or rerun with `UPDATE_SNAPSHOTS=1` to
+//! accept every change at once. This way changes to specs, options or the vendored engine show
+//! up as diffs in review instead of silently changing results.
+//!
+//! The fixture corpus below is snapshotted as disassembly and pcode listings, and the fixtures
+//! that are whole functions also as the C the decompiler prints for them.
+
+use std::fmt::Write;
+use std::path::PathBuf;
+
+use crate::project::Program;
+use crate::toy;
+
+const UPDATE_VAR: &str = "UPDATE_SNAPSHOTS";
+
+fn snapshot_path(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("src/snapshots")
+        .join(format!("{}.snap", name))
+}
+
+/// Fail unless `actual` matches the snapshot `name`
+pub(crate) fn assert_snapshot(name: &str, actual: &str) {
+    let path = snapshot_path(name);
+    let new = path.with_extension("snap.new");
+    let expected = std::fs::read_to_string(&path).ok();
+    if expected.as_deref() == Some(actual) {
+        let _ = std::fs::remove_file(&new);
+        return;
+    }
+    if std::env::var_os(UPDATE_VAR).is_some() {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, actual).unwrap();
+        let _ = std::fs::remove_file(&new);
+        return;
+    }
+    std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+    std::fs::write(&new, actual).unwrap();
+    match expected {
+        Some(expected) => panic!(
+            "snapshot {} changed, new output in {}:\n{}",
+            name,
+            new.display(),
+            diff(&expected, actual)
+        ),
+        None => panic!(
+            "no snapshot {}, new output in {}; set {} to accept it",
+            name,
+            new.display(),
+            UPDATE_VAR
+        ),
+    }
+}
+
+/// A line diff of `old` and `new`, with `-` for removed and `+` for added lines
+fn diff(old: &str, new: &str) -> String {
+    let old: Vec<&str> = old.lines().collect();
+    let new: Vec<&str> = new.lines().collect();
+    // Longest common subsequence lengths of the suffixes
+    let mut lcs = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lcs[i][j] = if old[i] == new[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+    let mut out = String::new();
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            writeln!(out, " {}", old[i]).unwrap();
+            i += 1;
+            j += 1;
+        } else if i < old.len() && (j == new.len() || lcs[i + 1][j] >= lcs[i][j + 1]) {
+            writeln!(out, "-{}", old[i]).unwrap();
+            i += 1;
+        } else {
+            writeln!(out, "+{}", new[j]).unwrap();
+            j += 1;
+        }
+    }
+    out
+}
+
+/// Disassembly and pcode of the instructions in `[start, end)`
+fn listing(program: &mut Program, start: u64, end: u64) -> String {
+    let spaces = program.decompiler().space_hierarchy();
+    let mut out = String::new();
+    let mut addr = start;
+    while addr < end {
+        let (mnem, body, _) = match program.decompiler().disassemble_one(addr) {
+            Ok(text) => text,
+            Err(_) => {
+                writeln!(out, "{:#x}: <invalid>", addr).unwrap();
+                break;
+            }
+        };
+        writeln!(out, "{:#x}: {} {}", addr, mnem, body).unwrap();
+        let insn = program.lift(addr).unwrap().clone();
+        for op in &insn.ops {
+            let output = op
+                .output
+                .as_ref()
+                .map(|vn| format!("{} = ", vn.display(&spaces)))
+                .unwrap_or_default();
+            let inputs: Vec<String> = op
+                .inputs
+                .iter()
+                .enumerate()
+                .map(|(i, vn)| match op.accessed_space(program.decompiler()) {
+                    // The space id is the address of an AddrSpace, which changes from run to run
+                    Some(space) if i == 0 => format!("[{}]", spaces.space(space).unwrap().name),
+                    _ => vn.display(&spaces).to_string(),
+                })
+                .collect();
+            writeln!(out, "    {}{:?} {}", output, op.opcode, inputs.join(", ")).unwrap();
+        }
+        addr += insn.length as u64;
+    }
+    out
+}
+
+/// A toy program snapshotted by [`test_fixtures`]
+struct Fixture {
+    name: &'static str,
+    base: u64,
+    code: &'static [u8],
+    /// Whether the code is a whole function, so it can be decompiled
+    function: bool,
+}
+
+const FIXTURES: &[Fixture] = &[
+    Fixture {
+        name: "arithmetic",
+        base: 0x1000,
+        // movi r0, 5 ; movi r1, 7 ; add r0, r1 ; ret
+        code: &[0x01, 0x00, 0x05, 0x01, 0x10, 0x07, 0x02, 0x01, 0x03],
+        function: true,
+    },
+    Fixture {
+        name: "memory",
+        base: 0x1000,
+        // movi r1, 0x40 ; st [r1], r0 ; ld r2, [r1]
+        code: &[0x01, 0x10, 0x40, 0x05, 0x10, 0x04, 0x21],
+        function: false,
+    },
+    Fixture {
+        name: "mode_switch",
+        base: 0,
+        // switch 0x4 ; nop ; nop (decoded as alt)
+        code: &[0x06, 0x04, 0x00, 0x00, 0x00],
+        function: false,
+    },
+    Fixture {
+        name: "user_ops",
+        base: 0x1000,
+        // rnd r0 ; tsc r1 ; cpuid r2
+        code: &[0x07, 0x00, 0x08, 0x10, 0x09, 0x20],
+        function: false,
+    },
+    Fixture {
+        name: "branch",
+        base: 0,
+        // jz r0, 0x8 ; movi r0, 1 ; ret ; nop ; movi r0, 2 ; ret
+        code: &[
+            0x0e, 0x00, 0x08, 0x01, 0x00, 0x01, 0x03, 0x00, 0x01, 0x00, 0x02, 0x03,
+        ],
+        function: true,
+    },
+];
+
+#[test]
+fn test_fixtures() {
+    for fixture in FIXTURES {
+        let mut program = toy::program(fixture.base, fixture.code);
+        let end = fixture.base + fixture.code.len() as u64;
+        let text = listing(&mut program, fixture.base, end);
+        assert_snapshot(&format!("listing_{}", fixture.name), &text);
+        if fixture.function {
+            let c = program.decompile_function(fixture.base).unwrap();
+            assert_snapshot(&format!("decompile_{}", fixture.name), &c);
+        }
+    }
+}
+
+#[test]
+fn test_diff() {
+    assert_eq!(diff("a\nb\nc\n", "a\nc\nd\n"), " a\n-b\n c\n+d\n");
+    assert_eq!(diff("", "x\n"), "+x\n");
+    assert_eq!(diff("a\nb\n", "a\nc\n"), " a\n-b\n+c\n");
+}
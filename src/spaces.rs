@@ -0,0 +1,531 @@
+//! A typed view of a language's address spaces and how they relate to each other.
+//!
+//! A spacebase space, like `stack`, is contained in the space its base register points into;
+//! an overlay space shares the offsets of the space it overlays. [`SpaceHierarchy`] resolves
+//! both relations to the [`Space`]s involved, so analyses can go from a stack or overlay
+//! address to the space actually holding the bytes without following `getContain` pointers.
+//! Given the value of a base register, for example read from an emulator, stack varnodes
+//! translate to concrete RAM locations and back.
+
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::pin::Pin;
+
+use cxx::UniquePtr;
+
+use crate::pcode::Varnode;
+use crate::{ffi, SpaceType};
+
+impl fmt::Display for ffi::Address {
+    /// `space:0xoffset`, like `ram:0x401000`
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match unsafe { self.getSpace().as_ref() } {
+            Some(space) if !self.isInvalid() => {
+                write!(
+                    f,
+                    "{}:{:#x}",
+                    space.getName().to_string_lossy(),
+                    self.getOffset()
+                )
+            }
+            _ => write!(f, "<invalid>"),
+        }
+    }
+}
+
+impl fmt::Debug for ffi::Address {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+impl ffi::Address {
+    /// A copy of the address that can be kept after the one handed to a callback is gone
+    pub fn duplicate(&self) -> UniquePtr<ffi::Address> {
+        ffi::copyAddress(self)
+    }
+
+    /// Index of the space and offset, what addresses compare and hash by. Invalid addresses
+    /// have the space index -1 and come first.
+    pub fn key(&self) -> (i32, u64) {
+        match unsafe { self.getSpace().as_ref() } {
+            Some(space) if !self.isInvalid() => (space.getIndex(), self.getOffset()),
+            _ => (-1, 0),
+        }
+    }
+}
+
+impl PartialEq for ffi::Address {
+    fn eq(&self, other: &Self) -> bool {
+        self.key() == other.key()
+    }
+}
+
+impl Eq for ffi::Address {}
+
+impl PartialOrd for ffi::Address {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for ffi::Address {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.key().cmp(&other.key())
+    }
+}
+
+impl Hash for ffi::Address {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.key().hash(state)
+    }
+}
+
+impl ffi::AddrSpace {
+    pub fn space_type(&self) -> SpaceType {
+        // getType only ever returns one of the spacetype values
+        SpaceType::from_u32(ffi::getAddrSpaceType(self)).expect("unknown space type")
+    }
+}
+
+/// A register the offsets of a spacebase space are relative to
+#[derive(Debug, Clone)]
+pub struct Spacebase {
+    pub register: String,
+    pub varnode: Varnode,
+    pub stack_grows_negative: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct Space {
+    pub name: String,
+    pub index: i32,
+    pub kind: SpaceType,
+    pub word_size: u32,
+    pub addr_size: u32,
+    pub big_endian: bool,
+    /// Index of the space containing a spacebase space
+    pub contain: Option<i32>,
+    /// Index of the space an overlay space overlays
+    pub overlay_of: Option<i32>,
+    /// Whether some overlay space overlays this one
+    pub overlaid: bool,
+    pub spacebases: Vec<Spacebase>,
+    /// The constant standing for the space as the first input of a LOAD or STORE, see
+    /// [`Varnode::space_id`]
+    pub id: u64,
+}
+
+impl Space {
+    pub fn is_overlay(&self) -> bool {
+        self.overlay_of.is_some()
+    }
+
+    pub fn is_spacebase(&self) -> bool {
+        self.kind == SpaceType::SpaceBase
+    }
+
+    pub fn is_constant(&self) -> bool {
+        self.kind == SpaceType::Constant
+    }
+
+    /// Whether this is the space of temporaries
+    pub fn is_unique(&self) -> bool {
+        self.kind == SpaceType::Internal
+    }
+
+    /// Whether this is the space of registers. SLEIGH gives it the processor type like
+    /// memory, so it is told apart by its name.
+    pub fn is_register(&self) -> bool {
+        self.kind == SpaceType::Processor && self.name == "register"
+    }
+
+    /// Whether this is a memory space, like `ram` or the code and data spaces of Harvard
+    /// architectures
+    pub fn is_ram(&self) -> bool {
+        self.kind == SpaceType::Processor && !self.is_register()
+    }
+
+    /// Wrap `offset` to the size of the space's addresses
+    pub fn wrap(&self, offset: u64) -> u64 {
+        match self.addr_size {
+            0 | 8.. => offset,
+            n => offset & ((1 << (8 * n)) - 1),
+        }
+    }
+
+    /// `offset` read as a signed displacement, as stack offsets usually are
+    pub fn signed_offset(&self, offset: u64) -> i64 {
+        match self.addr_size {
+            0 | 8.. => offset as i64,
+            n => {
+                let shift = 64 - 8 * n;
+                ((offset << shift) as i64) >> shift
+            }
+        }
+    }
+}
+
+/// Every address space of a language, with containment and overlay relations resolved
+#[derive(Debug, Clone)]
+pub struct SpaceHierarchy {
+    spaces: Vec<Space>,
+}
+
+impl SpaceHierarchy {
+    pub fn new(decompiler: &ffi::Decompiler) -> Self {
+        let spacebases = decompiler.getSpacebases();
+        let index = |i: i32| if i < 0 { None } else { Some(i) };
+        let spaces = decompiler
+            .getSpaces()
+            .into_iter()
+            .map(|info| Space {
+                // getType only ever returns one of the spacetype values
+                kind: SpaceType::from_u32(info.space_type).expect("unknown space type"),
+                contain: index(info.contain),
+                overlay_of: index(info.overlay_base),
+                overlaid: info.overlaid,
+                spacebases: spacebases
+                    .iter()
+                    .filter(|base| base.space == info.index)
+                    .map(|base| Spacebase {
+                        register: base.register_name.clone(),
+                        varnode: Varnode {
+                            space: base.register_space,
+                            offset: base.offset,
+                            size: base.size,
+                        },
+                        stack_grows_negative: base.stack_grows_negative,
+                    })
+                    .collect(),
+                id: decompiler.getSpaceConst(info.index),
+                name: info.name,
+                index: info.index,
+                word_size: info.word_size,
+                addr_size: info.addr_size,
+                big_endian: info.big_endian,
+            })
+            .collect();
+        Self { spaces }
+    }
+
+    pub fn spaces(&self) -> impl Iterator<Item = &Space> {
+        self.spaces.iter()
+    }
+
+    /// The space with index `index`, as found in [`Varnode::space`]
+    pub fn space(&self, index: i32) -> Option<&Space> {
+        self.spaces.iter().find(|s| s.index == index)
+    }
+
+    /// The space encoded by `varnode`, the constant first input of a LOAD or STORE, like
+    /// [`ffi::Decompiler::space_from_const`] does. Returns `None` for other varnodes.
+    pub fn space_from_const(&self, varnode: &Varnode) -> Option<&Space> {
+        let constant = self.spaces.iter().find(|s| s.is_constant())?;
+        if varnode.space != constant.index {
+            return None;
+        }
+        self.spaces.iter().find(|s| s.id == varnode.offset)
+    }
+
+    pub fn by_name(&self, name: &str) -> Option<&Space> {
+        self.spaces.iter().find(|s| s.name == name)
+    }
+
+    /// The `stack` space, if the compiler spec defined a stack pointer
+    pub fn stack(&self) -> Option<&Space> {
+        self.by_name("stack")
+            .filter(|s| s.kind == SpaceType::SpaceBase)
+    }
+
+    /// The space containing the spacebase space `space`
+    pub fn container(&self, space: &Space) -> Option<&Space> {
+        space.contain.and_then(|i| self.space(i))
+    }
+
+    /// The space overlaid by the overlay space `space`
+    pub fn overlay_base(&self, space: &Space) -> Option<&Space> {
+        space.overlay_of.and_then(|i| self.space(i))
+    }
+
+    /// The overlay spaces over `space`
+    pub fn overlays<'a>(&'a self, space: &'a Space) -> impl Iterator<Item = &'a Space> {
+        self.spaces
+            .iter()
+            .filter(move |s| s.overlay_of == Some(space.index))
+    }
+
+    /// The spacebase spaces contained in `space`
+    pub fn contained<'a>(&'a self, space: &'a Space) -> impl Iterator<Item = &'a Space> {
+        self.spaces
+            .iter()
+            .filter(move |s| s.contain == Some(space.index))
+    }
+
+    /// The concrete location of `varnode`, which lies in a spacebase space such as the stack,
+    /// when the base register holds `base`. Returns `None` for varnodes in other spaces.
+    pub fn to_concrete(&self, varnode: &Varnode, base: u64) -> Option<Varnode> {
+        let space = self.space(varnode.space).filter(|s| s.is_spacebase())?;
+        let container = self.container(space)?;
+        let offset = base.wrapping_add(space.signed_offset(varnode.offset) as u64);
+        Some(Varnode {
+            space: container.index,
+            offset: container.wrap(offset),
+            size: varnode.size,
+        })
+    }
+
+    /// The location of `varnode` relative to the spacebase space `space` when its base register
+    /// holds `base`; the inverse of [`to_concrete`](Self::to_concrete). Returns `None` if
+    /// `varnode` isn't in the space containing `space`.
+    pub fn to_spacebase(&self, varnode: &Varnode, space: &Space, base: u64) -> Option<Varnode> {
+        if !space.is_spacebase() || space.contain != Some(varnode.space) {
+            return None;
+        }
+        Some(Varnode {
+            space: space.index,
+            offset: space.wrap(varnode.offset.wrapping_sub(base)),
+            size: varnode.size,
+        })
+    }
+
+    /// The space holding the bytes of `space`, found by following containment and overlays
+    pub fn physical<'a>(&'a self, mut space: &'a Space) -> &'a Space {
+        // Each step moves to a space created earlier, so this ends
+        for _ in 0..self.spaces.len() {
+            match self.container(space).or_else(|| self.overlay_base(space)) {
+                Some(parent) => space = parent,
+                None => break,
+            }
+        }
+        space
+    }
+}
+
+impl ffi::Decompiler {
+    pub fn space_hierarchy(&self) -> SpaceHierarchy {
+        SpaceHierarchy::new(self)
+    }
+
+    /// The space encoded by `varnode`, the constant first input of a LOAD or STORE. Returns
+    /// `None` for other varnodes.
+    pub fn space_from_const(&self, varnode: &Varnode) -> Option<&ffi::AddrSpace> {
+        if varnode.space != self.getConstantSpaceIndex() {
+            return None;
+        }
+        // Only ever one of the decompiler's own spaces, which live as long as it does
+        unsafe { self.getSpaceFromConst(varnode.offset).as_ref() }
+    }
+
+    /// The space of `addr`, if it is one of the decompiler's own. Returns `None` for invalid
+    /// addresses and addresses from another decompiler.
+    pub fn address_space<'a>(&'a self, addr: &ffi::Address) -> Option<&'a ffi::AddrSpace> {
+        let space = addr.getSpace();
+        if space.is_null() || addr.isInvalid() {
+            return None;
+        }
+        // Looked up by pointer among the decompiler's spaces, which live as long as it does,
+        // so a space another decompiler may have freed is never read
+        unsafe { self.getSpaceFromConst(space as u64).as_ref() }
+    }
+
+    /// The space instructions are read from
+    pub fn default_code_space(&self) -> &ffi::AddrSpace {
+        // Set when the spec is loaded and owned by the decompiler from then on
+        unsafe { &*self.getDefaultCodeSpace() }
+    }
+
+    /// The space pointers into memory point to by default, like the RAM of an AVR rather than
+    /// its program memory
+    pub fn default_data_space(&self) -> &ffi::AddrSpace {
+        // Set when the spec is loaded and owned by the decompiler from then on
+        unsafe { &*self.getDefaultDataSpace() }
+    }
+
+    /// Offset in the unique space where the temporaries SLEIGH allocates for the spec end.
+    /// Offsets from here on are free for analyses to create temporaries of their own.
+    pub fn unique_base(&self) -> u64 {
+        self.getUniqueBase() as u64
+    }
+
+    /// The bits the temporaries of the instruction at `addr` carry in their offsets.
+    ///
+    /// Temporaries only live until the end of their instruction, and SLEIGH gives those of a
+    /// constructor the same offsets in every instruction using it, so offsets are reused
+    /// between instructions. Languages with delay slots may instead set a mask of address
+    /// bits that is mixed into the offsets, `(addr & mask) << 4`, to keep the temporaries of
+    /// a branch and of its delay slots apart. Clearing these bits gives an offset that only
+    /// depends on the spec, which is what renaming temporaries deterministically needs.
+    pub fn unique_tag(&self, addr: u64) -> u64 {
+        (addr & self.getUniqueAllocateMask() as u64) << 4
+    }
+
+    /// Add the `stack` space over the space `base`, with `register` as its stack pointer.
+    /// Returns the index of the new space.
+    pub fn add_stack_space(
+        self: Pin<&mut Self>,
+        base: &str,
+        register: &str,
+        grows_negative: bool,
+    ) -> Result<i32, cxx::Exception> {
+        cxx::let_cxx_string!(base = base);
+        cxx::let_cxx_string!(register = register);
+        self.addStackSpace(&base, &register, grows_negative)
+    }
+
+    /// Add an overlay space `name` over the space `base`, e.g. one per bank of a bank-switched
+    /// ROM. Returns the index of the new space, from which instructions can then be lifted
+    /// with [`try_lift_in`](Self::try_lift_in).
+    pub fn add_overlay_space(
+        self: Pin<&mut Self>,
+        name: &str,
+        base: &str,
+    ) -> Result<i32, cxx::Exception> {
+        cxx::let_cxx_string!(name = name);
+        cxx::let_cxx_string!(base = base);
+        self.addOverlaySpace(&name, &base)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::toy;
+
+    #[test]
+    fn test_default_spaces() {
+        let program = toy::program(0, &[]);
+        let decompiler = program.decompiler();
+        let code = decompiler.default_code_space();
+        assert_eq!(code.getName().to_str().unwrap(), "ram");
+        assert_eq!(code.getIndex(), decompiler.getDefaultCodeSpaceIndex());
+        let data = decompiler.default_data_space();
+        assert_eq!(data.getIndex(), decompiler.getDefaultDataSpaceIndex());
+        assert_eq!(decompiler.getDataAddress(0x20).to_string(), "ram:0x20");
+        let space = decompiler.address_space(&decompiler.getDataAddress(0x20));
+        assert!(std::ptr::eq(space.unwrap(), data));
+    }
+
+    #[test]
+    fn test_address_order() {
+        let program = toy::program(0x1000, &[0; 4]);
+        let decompiler = program.decompiler();
+        let (low, high) = (
+            decompiler.getCodeAddress(0x10),
+            decompiler.getCodeAddress(0x20),
+        );
+        assert!(*low < *high && *ffi::newAddress() < *low);
+        assert_eq!(*low, *decompiler.getCodeAddress(0x10));
+        let copy = low.duplicate();
+        drop(low);
+        assert_eq!(copy.to_string(), "ram:0x10");
+        let mut set = std::collections::HashSet::new();
+        set.insert(copy.key());
+        assert!(set.contains(&decompiler.getCodeAddress(0x10).key()));
+
+        let space = decompiler.address_space(&copy).unwrap();
+        assert_eq!(space.getIndex(), decompiler.getDefaultCodeSpaceIndex());
+        assert_eq!(space.space_type(), SpaceType::Processor);
+        assert!(decompiler.address_space(&ffi::newAddress()).is_none());
+        let other = toy::program(0x1000, &[0; 4]);
+        let foreign = other.decompiler().getCodeAddress(0x10);
+        assert!(decompiler.address_space(&foreign).is_none());
+    }
+
+    #[test]
+    fn test_unique_base() {
+        // cpuid r2 at two addresses
+        let mut program = toy::program(0x1000, &[0x09, 0x20, 0x09, 0x20]);
+        let first = program.lift(0x1000).unwrap().ops[0].clone();
+        let second = program.lift(0x1002).unwrap().ops[0].clone();
+        let decompiler = program.decompiler();
+        let (a, b) = (first.output.unwrap(), second.output.unwrap());
+        assert!(a.is_unique(&decompiler.space_hierarchy()));
+        assert!(a.offset < decompiler.unique_base());
+        // Without delay slots, temporaries are at the same offsets in every instruction
+        assert_eq!(decompiler.unique_tag(0x1002), 0);
+        assert_eq!(a.offset, b.offset);
+    }
+
+    #[test]
+    fn test_hierarchy() {
+        let mut program = toy::program(0x1000, &[0; 0x10]);
+        program.add_overlay_space("ovl", "ram").unwrap();
+        assert!(program.add_overlay_space("ovl", "ram").is_err());
+        assert!(program.add_overlay_space("ovl2", "bogus").is_err());
+
+        let spaces = program.decompiler().space_hierarchy();
+        let ram = spaces.by_name("ram").unwrap();
+        assert_eq!(spaces.by_name("const").unwrap().kind, SpaceType::Constant);
+        assert_eq!(ram.kind, SpaceType::Processor);
+        assert!(ram.overlaid);
+
+        // The stack pointer of the compiler spec is the base of the stack space
+        let stack = spaces.stack().unwrap();
+        assert_eq!(spaces.container(stack).unwrap().name, "ram");
+        assert_eq!(stack.spacebases.len(), 1);
+        let sp = &stack.spacebases[0];
+        assert_eq!(sp.register, "sp");
+        assert_eq!(spaces.space(sp.varnode.space).unwrap().name, "register");
+        assert!(sp.stack_grows_negative);
+        assert_eq!(
+            spaces.contained(ram).map(|s| &s.name).collect::<Vec<_>>(),
+            ["stack"]
+        );
+
+        let ovl = spaces.by_name("ovl").unwrap();
+        assert!(program
+            .decompiler()
+            .space_from_const(&Varnode {
+                space: spaces.by_name("const").unwrap().index,
+                offset: 0x1000,
+                size: 8,
+            })
+            .is_none());
+        let id = Varnode::space_id(program.decompiler(), ovl.index);
+        assert_eq!(spaces.space_from_const(&id).unwrap().name, "ovl");
+        assert!(spaces.space_from_const(&sp.varnode).is_none());
+        assert!(ovl.is_overlay());
+        assert_eq!(spaces.overlay_base(ovl).unwrap().name, "ram");
+        assert_eq!(spaces.overlays(ram).count(), 1);
+        for space in [stack, ovl, ram] {
+            assert_eq!(spaces.physical(space).name, "ram");
+        }
+    }
+
+    #[test]
+    fn test_stack_translation() {
+        let program = toy::program(0x1000, &[0; 0x10]);
+        let spaces = program.decompiler().space_hierarchy();
+        let stack = spaces.stack().unwrap();
+        let ram = spaces.by_name("ram").unwrap();
+        assert_eq!(stack.signed_offset(0xffff_fffc), -4);
+
+        let local = Varnode {
+            space: stack.index,
+            offset: 0xffff_fffc,
+            size: 4,
+        };
+        let concrete = spaces.to_concrete(&local, 0x8000).unwrap();
+        assert_eq!((concrete.space, concrete.offset), (ram.index, 0x7ffc));
+        let back = spaces.to_spacebase(&concrete, stack, 0x8000).unwrap();
+        assert_eq!(
+            (back.space, back.offset, back.size),
+            (stack.index, local.offset, 4)
+        );
+
+        // Offsets wrap around the containing space
+        let arg = Varnode {
+            space: stack.index,
+            offset: 8,
+            size: 4,
+        };
+        assert_eq!(spaces.to_concrete(&arg, 0xffff_fffc).unwrap().offset, 4);
+
+        // Only spacebase varnodes are concretized, only container varnodes are relativized
+        let reg = stack.spacebases[0].varnode.clone();
+        assert!(spaces.to_concrete(&reg, 0x8000).is_none());
+        assert!(spaces.to_spacebase(&reg, stack, 0x8000).is_none());
+        assert!(spaces.to_spacebase(&concrete, ram, 0x8000).is_none());
+    }
+}
@@ -0,0 +1,141 @@
+//! Data flow of an analysed function, as the decompiler's SSA form.
+//!
+//! [`Program::function_ssa`](crate::project::Program::function_ssa) lists the ops of every
+//! basic block and the varnodes they read and write, by index. Each varnode is written by at
+//! most one op, so following `definition` and `uses` walks the def-use chains; the values
+//! merged where control flow joins come from `MULTIEQUAL` ops, and those a call or store may
+//! change from `INDIRECT` ops.
+
+use std::collections::BTreeSet;
+
+use crate::ffi;
+
+impl ffi::FunctionSsaInfo {
+    /// The op writing varnode `index`
+    pub fn definition(&self, index: u32) -> Option<&ffi::SsaOpInfo> {
+        let def = self.varnodes[index as usize].definition;
+        (def >= 0).then(|| &self.ops[def as usize])
+    }
+
+    /// The ops reading varnode `index`
+    pub fn uses(&self, index: u32) -> impl Iterator<Item = &ffi::SsaOpInfo> {
+        self.varnodes[index as usize]
+            .uses
+            .iter()
+            .map(|&op| &self.ops[op as usize])
+    }
+
+    /// Indices of the varnodes whose value depends on varnode `index`, itself included
+    pub fn forward_slice(&self, index: u32) -> BTreeSet<u32> {
+        self.slice(index, |varnode| {
+            self.uses(varnode)
+                .filter(|op| op.output >= 0)
+                .map(|op| op.output as u32)
+                .collect()
+        })
+    }
+
+    /// Indices of the varnodes the value of varnode `index` depends on, itself included
+    pub fn backward_slice(&self, index: u32) -> BTreeSet<u32> {
+        self.slice(index, |varnode| match self.definition(varnode) {
+            Some(op) => op.inputs.clone(),
+            None => Vec::new(),
+        })
+    }
+
+    fn slice(&self, index: u32, next: impl Fn(u32) -> Vec<u32>) -> BTreeSet<u32> {
+        let mut seen = BTreeSet::from([index]);
+        let mut pending = vec![index];
+        while let Some(varnode) = pending.pop() {
+            for other in next(varnode) {
+                if seen.insert(other) {
+                    pending.push(other);
+                }
+            }
+        }
+        seen
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::project::SimplificationStyle;
+    use crate::toy;
+    use crate::{ffi, Opcode};
+
+    #[test]
+    fn test_function_ssa() {
+        // 0: ld r0, [r1] ; jz r0, 0 ; ret
+        let mut program = toy::program(0, &[0x04, 0x01, 0x0e, 0x00, 0x00, 0x03]);
+        let ram = program.decompiler().default_code_space().getIndex();
+        let ssa = program
+            .function_ssa(0, SimplificationStyle::FirstPass)
+            .unwrap();
+        let opcode = |op: &ffi::SsaOpInfo| Opcode::from_u32(op.opcode).unwrap();
+        let load = ssa
+            .ops
+            .iter()
+            .find(|op| opcode(op) == Opcode::Load)
+            .unwrap();
+        assert_eq!(load.address, 0);
+        let space = ssa.varnodes[load.inputs[0] as usize].storage;
+        assert_eq!(space.offset, ram as u64);
+        let pointer = &ssa.varnodes[load.inputs[1] as usize];
+        assert!(pointer.input && pointer.definition < 0);
+
+        // r0 and the flag are merged at the head of the loop, from the loop and the entry
+        let merges: Vec<_> = ssa
+            .ops
+            .iter()
+            .filter(|op| opcode(op) == Opcode::MultiEqual)
+            .collect();
+        assert!(!merges.is_empty());
+        for merge in &merges {
+            assert_eq!(merge.block, load.block);
+            assert_eq!(merge.input_blocks.len(), merge.inputs.len());
+            assert!(merge.input_blocks.contains(&load.block));
+        }
+
+        let value = load.output as u32;
+        assert_eq!(ssa.definition(value), Some(load));
+        let mut readers: Vec<_> = ssa.uses(value).map(opcode).collect();
+        readers.sort();
+        assert_eq!(readers, [Opcode::IntEqual, Opcode::MultiEqual]);
+        let branch = ssa
+            .ops
+            .iter()
+            .find(|op| opcode(op) == Opcode::CBranch)
+            .unwrap();
+        let condition = branch.inputs[1];
+        assert!(ssa.forward_slice(value).contains(&condition));
+        assert!(ssa.backward_slice(condition).contains(&load.inputs[1]));
+        assert!(!ssa.forward_slice(condition).contains(&value));
+
+        // 0: movi r0, 7 ; st [r0], r1 ; call 0x10 ; ret
+        let mut code = vec![0x01, 0x00, 0x07, 0x05, 0x01, 0x0d, 0x10, 0x03];
+        code.resize(0x10, 0);
+        code.push(0x03);
+        let mut program = toy::program(0, &code);
+        let ssa = program
+            .function_ssa(0, SimplificationStyle::FirstPass)
+            .unwrap();
+        let call = ssa
+            .ops
+            .iter()
+            .position(|op| opcode(op) == Opcode::Call)
+            .unwrap();
+        let callee = ssa.varnodes[ssa.ops[call].inputs[0] as usize].storage;
+        assert_eq!((callee.space, callee.offset), (ram, 0x10));
+        // What the call may clobber goes through INDIRECTs
+        let mut indirect = 0;
+        for op in &ssa.ops {
+            if opcode(op) == Opcode::Indirect {
+                assert_eq!((op.cause, op.inputs.len()), (call as i32, 1));
+                indirect += 1;
+            } else {
+                assert_eq!(op.cause, -1);
+            }
+        }
+        assert!(indirect > 0);
+    }
+}
@@ -0,0 +1,101 @@
+//! Control flow recovered by the decompiler.
+//!
+//! Decompiling a function builds its basic blocks from the simplified pcode, with edges the
+//! decompiler has already classified, then collapses them into the loops, conditionals and
+//! switches it prints. [`Program::function_graph`](crate::project::Program::function_graph)
+//! hands both over: the blocks with their dominators and edges, and the structure as a tree
+//! listed parent first, whose leaves are copies of the basic blocks.
+
+use crate::ffi;
+
+impl ffi::StructureKind {
+    pub fn is_loop(self) -> bool {
+        matches!(
+            self,
+            ffi::StructureKind::WhileDo
+                | ffi::StructureKind::DoWhile
+                | ffi::StructureKind::InfiniteLoop
+        )
+    }
+}
+
+impl ffi::FunctionGraphInfo {
+    /// The basic block whose code covers `addr`
+    pub fn block_at(&self, addr: u64) -> Option<&ffi::BasicBlockInfo> {
+        self.blocks
+            .iter()
+            .find(|block| block.start <= addr && addr <= block.stop)
+    }
+
+    /// Indices of the blocks with an edge to block `index`
+    pub fn predecessors(&self, index: u32) -> impl Iterator<Item = u32> + '_ {
+        self.blocks
+            .iter()
+            .filter(move |block| block.successors.iter().any(|edge| edge.target == index))
+            .map(|block| block.index)
+    }
+
+    /// Indices in [`structure`](Self::structure) of the blocks directly inside `node`
+    pub fn children(&self, node: usize) -> impl Iterator<Item = usize> + '_ {
+        self.structure
+            .iter()
+            .enumerate()
+            .filter(move |(_, child)| child.parent == node as i32)
+            .map(|(i, _)| i)
+    }
+
+    /// Indices of the basic blocks somewhere inside `node`, in structure order
+    pub fn basic_blocks_in(&self, node: usize) -> Vec<u32> {
+        let mut blocks = Vec::new();
+        let mut pending = vec![node];
+        while let Some(node) = pending.pop() {
+            if self.structure[node].basic >= 0 {
+                blocks.push(self.structure[node].basic as u32);
+            }
+            let children: Vec<_> = self.children(node).collect();
+            pending.extend(children.into_iter().rev());
+        }
+        blocks
+    }
+
+    /// The loops of the function, outer loops before the loops they contain
+    pub fn loops(&self) -> impl Iterator<Item = &ffi::StructureNodeInfo> {
+        self.structure.iter().filter(|node| node.kind.is_loop())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ffi::StructureKind;
+    use crate::toy;
+
+    #[test]
+    fn test_function_graph() {
+        // 0: ld r0, [r1] ; jz r0, 0 ; ret
+        let mut program = toy::program(0, &[0x04, 0x01, 0x0e, 0x00, 0x00, 0x03]);
+        let graph = program.function_graph(0).unwrap();
+        let body = graph.block_at(2).unwrap();
+        assert_eq!((body.start, body.stop), (0, 2));
+        let back: Vec<_> = body
+            .successors
+            .iter()
+            .map(|edge| (edge.target, edge.back_edge))
+            .collect();
+        assert!(back.contains(&(body.index, true)));
+        assert!(graph.predecessors(body.index).any(|i| i == body.index));
+        let exit = graph.block_at(5).unwrap();
+        assert_eq!(exit.immediate_dominator, body.index as i32);
+        assert!(exit.successors.is_empty());
+
+        assert_eq!(graph.structure[0].parent, -1);
+        let loops: Vec<_> = graph.loops().collect();
+        assert_eq!(loops.len(), 1);
+        assert_eq!(loops[0].kind, StructureKind::DoWhile);
+        assert_eq!((loops[0].start, loops[0].stop), (0, 2));
+        let node = graph.structure.iter().position(|n| n == loops[0]).unwrap();
+        assert_eq!(graph.basic_blocks_in(node), [body.index]);
+        let all = graph.basic_blocks_in(0);
+        assert_eq!(all.len(), graph.blocks.len());
+        assert_eq!(*all.last().unwrap(), exit.index);
+    }
+}
@@ -0,0 +1,120 @@
+//! Linear-sweep disassembly.
+//!
+//! Unlike [`basic_blocks`](crate::ffi::Decompiler::basic_blocks), a sweep doesn't follow
+//! flow: it decodes a range from start to end, one instruction after the other. Bytes that
+//! don't decode, like data embedded in firmware code, are recorded as gaps and skipped up to
+//! the next instruction alignment boundary, where decoding picks up again.
+
+use std::ops::Range;
+
+use crate::cancel::Cancel;
+use crate::pcode::LiftedInstruction;
+use crate::{ffi, SleighError};
+
+/// The outcome of [`ffi::Decompiler::linear_sweep`]
+#[derive(Debug, Clone, Default)]
+pub struct Sweep {
+    /// Instructions lying entirely within the range, by address
+    pub instructions: Vec<LiftedInstruction>,
+    /// Runs of bytes that didn't decode, by address. Adjacent runs are merged.
+    pub gaps: Vec<Range<u64>>,
+}
+
+impl Sweep {
+    fn add_gap(&mut self, gap: Range<u64>) {
+        match self.gaps.last_mut() {
+            Some(last) if last.end == gap.start => last.end = gap.end,
+            _ => self.gaps.push(gap),
+        }
+    }
+}
+
+impl ffi::Decompiler {
+    /// Decode every instruction in `range`, in the default code space. An instruction running
+    /// past the end of the range counts as undecodable. Errors other than undecodable bytes,
+    /// like an instruction over the op limit, stop the sweep.
+    pub fn linear_sweep(&self, range: Range<u64>) -> Result<Sweep, SleighError> {
+        self.linear_sweep_cancellable(range, &Cancel::new())
+    }
+
+    /// [`linear_sweep`](Self::linear_sweep), checking `cancel` before each instruction
+    pub fn linear_sweep_cancellable(
+        &self,
+        range: Range<u64>,
+        cancel: &Cancel,
+    ) -> Result<Sweep, SleighError> {
+        let alignment = self.instruction_alignment();
+        let mut sweep = Sweep::default();
+        let mut addr = range.start;
+        while addr < range.end {
+            cancel.check()?;
+            match self.lift_bounded(addr, (range.end - addr) as usize) {
+                Ok(inst) => {
+                    addr += inst.length as u64;
+                    sweep.instructions.push(inst);
+                }
+                Err(SleighError::Decode(_)) => {
+                    // No boundary is left before the end of the space when this overflows
+                    let next = (addr / alignment)
+                        .checked_add(1)
+                        .and_then(|n| n.checked_mul(alignment))
+                        .map_or(range.end, |next| next.min(range.end));
+                    sweep.add_gap(addr..next);
+                    addr = next;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(sweep)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::toy;
+
+    #[test]
+    fn test_linear_sweep() {
+        // movi r0, 1 ; <data> ; nop ; movi r1, <cut off>
+        let code = [0x01, 0x00, 0x01, 0xff, 0xfe, 0x00, 0x01, 0x10];
+        let program = toy::program(0x1000, &code);
+        let sweep = program.decompiler().linear_sweep(0x1000..0x1008).unwrap();
+        let starts: Vec<_> = sweep.instructions.iter().map(|i| i.address).collect();
+        assert_eq!(starts, [0x1000, 0x1005]);
+        assert_eq!(sweep.gaps, [0x1003..0x1005, 0x1006..0x1008]);
+
+        // Going over the op limit is no gap: bump r1
+        let mut program = toy::program(0x1000, &[0x0b, 0x10]);
+        program.set_op_limit(Some(1));
+        assert!(matches!(
+            program.decompiler().linear_sweep(0x1000..0x1002),
+            Err(SleighError::OpLimit(_))
+        ));
+    }
+
+    #[test]
+    fn test_cancel() {
+        let program = toy::program(0x1000, &[0; 0x10]);
+        let cancel = Cancel::new();
+        let token = cancel.clone();
+        token.cancel();
+        assert!(matches!(
+            program
+                .decompiler()
+                .linear_sweep_cancellable(0x1000..0x1010, &cancel),
+            Err(SleighError::Cancelled(_))
+        ));
+        let expired = Cancel::new().timeout(Duration::ZERO);
+        assert!(program
+            .decompiler()
+            .linear_sweep_cancellable(0x1000..0x1010, &expired)
+            .is_err());
+        assert!(program
+            .decompiler()
+            .linear_sweep_cancellable(0x1000..0x1000, &cancel)
+            .is_ok());
+    }
+}
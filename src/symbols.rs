@@ -0,0 +1,87 @@
+//! The symbol table of a language.
+//!
+//! Every name the `.slaspec` defines at the top level, from registers and user ops to token
+//! fields, context fields and subtables, is a symbol of the compiled language. They can be
+//! looked up by name or listed, and tell what kind of symbol they are along with what little
+//! is needed to use them, like the register of a varnode symbol or the index of a user op.
+
+use crate::ffi;
+use crate::pcode::Varnode;
+
+impl ffi::SymbolInfo {
+    /// The register of a varnode symbol, or the register holding a context field or bit range
+    pub fn varnode(&self) -> Option<Varnode> {
+        (self.varnode.space >= 0).then(|| Varnode::from_packed(&self.varnode))
+    }
+}
+
+impl ffi::Decompiler {
+    /// The global symbol `name`
+    pub fn symbol(&self, name: &str) -> Option<ffi::SymbolInfo> {
+        cxx::let_cxx_string!(name = name);
+        self.findSymbolInfo(&name).pop()
+    }
+
+    /// All global symbols, sorted by name
+    pub fn symbols(&self) -> Vec<ffi::SymbolInfo> {
+        self.getSymbols()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ffi::SymbolKind;
+    use crate::toy;
+
+    #[test]
+    fn test_symbols() {
+        let program = toy::program(0, &[]);
+        let decompiler = program.decompiler();
+        let sp = decompiler.symbol("sp").unwrap();
+        assert_eq!(sp.kind, SymbolKind::Varnode);
+        let register = decompiler
+            .space_hierarchy()
+            .by_name("register")
+            .unwrap()
+            .index;
+        let varnode = sp.varnode().unwrap();
+        assert_eq!(
+            (varnode.space, varnode.offset, varnode.size),
+            (register, 0x10, 4)
+        );
+
+        let cpuid = decompiler.symbol("cpuid").unwrap();
+        assert_eq!((cpuid.kind, cpuid.index), (SymbolKind::UserOp, 2));
+        assert_eq!(cpuid.varnode(), None);
+        let mode = decompiler.symbol("mode").unwrap();
+        assert_eq!(
+            (mode.kind, mode.low, mode.high),
+            (SymbolKind::Context, 0, 0)
+        );
+        assert_eq!(mode.varnode().unwrap().offset, 0x100);
+        let target = decompiler.symbol("target").unwrap();
+        assert_eq!((target.kind, target.index), (SymbolKind::Subtable, 1));
+        assert_eq!(
+            decompiler.symbol("rd").unwrap().kind,
+            SymbolKind::VarnodeList
+        );
+        assert_eq!(decompiler.symbol("op").unwrap().kind, SymbolKind::Value);
+        // Tokens only exist while compiling
+        assert!(decompiler.symbol("regs").is_none());
+        assert_eq!(
+            decompiler.symbol("inst_next").unwrap().kind,
+            SymbolKind::End
+        );
+        assert!(decompiler.symbol("r9").is_none());
+
+        let symbols = decompiler.symbols();
+        assert!(symbols.windows(2).all(|w| w[0].name < w[1].name));
+        assert!(symbols.contains(&sp));
+        let user_ops: Vec<_> = symbols
+            .iter()
+            .filter(|s| s.kind == SymbolKind::UserOp)
+            .map(|s| (s.name.as_str(), s.index))
+            .collect();
+        assert_eq!(user_ops, [("cpuid", 2), ("rdrand", 0), ("rdtsc", 1)]);
+    }
+}
@@ -0,0 +1,182 @@
+//! A tiny 32-bit SLEIGH language for tests.
+//!
+//! | bytes         | instruction                                |
+//! |---------------|--------------------------------------------|
+//! | `00`          | `nop` (`alt` when the `mode` context is 1) |
+//! | `01 d0 ii`    | `movi rd, imm8`                            |
+//! | `02 ds`       | `add rd, rs`                               |
+//! | `03`          | `ret` (pops the return address off `sp`)   |
+//! | `04 ds`       | `ld rd, [rs]`                              |
+//! | `05 ds`       | `st [rd], rs`                              |
+//! | `06 aa`       | `switch aa` (sets `mode=1` from `aa` on)   |
+//! | `07 d0`       | `rnd rd` (`rdrand` user op)                |
+//! | `08 d0`       | `tsc rd` (`rdtsc` user op)                 |
+//! | `09 d0`       | `cpuid rd` (leaf `rd`, loads `eax`)        |
+//! | `0a`          | `hlt` (no pcode, `unimpl`)                 |
+//! | `0b d0`       | `bump rd` (`rd + 1`, or 1 when `rd` is 0)  |
+//! | `0c aa`       | `jmp aa`                                   |
+//! | `0d aa`       | `call aa` (return address in `lr`)         |
+//! | `0e d0 aa`    | `jz rd, aa`                                |
+//! | `0f ds`       | `ldx rd, [rs]` (`[rs]` is a subtable)      |
+//! | `10 aa`       | `djmp aa` (with a one byte delay slot)     |
+//! | `11 0s`       | `jmpr rs` (indirect jump)                  |
+//! | `12 d0 ii aa` | `jgt rd, imm8, aa` (unsigned compare)      |
+//! | `13 ds ii`    | `ldt rd, [rs, imm8]` (`[imm8 + rs * 4]`)   |
+//! | `14 d0 ii`    | `ldc rd, imm8` (`cpool` entry `imm8`)      |
+
+use std::sync::OnceLock;
+
+use crate::emulator::Emulator;
+use crate::languages::LanguageFiles;
+use crate::project::Program;
+use crate::{ffi, LoadImage};
+
+pub(crate) const SLASPEC: &str = r#"
+define endian=little;
+define alignment=1;
+define space ram type=ram_space size=4 default;
+define space register type=register_space size=4;
+define register offset=0 size=4 [ r0 r1 r2 r3 sp lr pc ];
+define register offset=0 size=2 [ r0w ];
+define register offset=0 size=1 [ r0b ];
+define register offset=0x100 size=4 contextreg;
+
+define context contextreg
+  mode = (0,0)
+;
+
+define token instr(8) op = (0,7);
+define token regs(8) rd = (4,7) rs = (0,3);
+define token imm(8) imm8 = (0,7);
+
+attach variables [ rd rs ] [ r0 r1 r2 r3 sp lr pc _ _ _ _ _ _ _ _ _ ];
+
+define pcodeop rdrand;
+define pcodeop rdtsc;
+define pcodeop cpuid;
+
+:nop is mode=0 & op=0x00 { }
+:alt is mode=1 & op=0x00 { }
+:movi rd, imm8 is op=0x01; rd; imm8 { rd = imm8; }
+:add rd, rs is op=0x02; rd & rs { rd = rd + rs; }
+:ret is op=0x03 {
+  local target:4 = *:4 sp;
+  sp = sp + 4;
+  return [target];
+}
+:ld rd, [rs] is op=0x04; rd & rs { rd = *:4 rs; }
+:st [rd], rs is op=0x05; rd & rs { *:4 rd = rs; }
+target: imm8 is imm8 { export *[ram]:1 imm8; }
+:switch target is op=0x06; target [ mode = 1; globalset(target, mode); ] { }
+:rnd rd is op=0x07; rd { rd = rdrand(); }
+:tsc rd is op=0x08; rd { rd = rdtsc(); }
+:cpuid rd is op=0x09; rd { local info:4 = cpuid(rd); rd = *:4 info; }
+:hlt is op=0x0a unimpl
+:bump rd is op=0x0b; rd {
+  if (rd == 0) goto <zero>;
+  rd = rd + 1;
+  goto inst_next;
+<zero>
+  rd = 1;
+}
+:jmp target is op=0x0c; target { goto target; }
+:call target is op=0x0d; target { lr = inst_next; call target; }
+:jz rd, target is op=0x0e; rd; target { if (rd == 0) goto target; }
+ptr: [rs] is rs { export *[ram]:4 rs; }
+:ldx rd, ptr is op=0x0f; rd & ptr { rd = ptr; }
+:djmp target is op=0x10; target { delayslot(1); goto target; }
+:jmpr rs is op=0x11; rs { goto [rs]; }
+:jgt rd, imm8, target is op=0x12; rd; imm8; target { if (rd > imm8) goto target; }
+:ldt rd, [rs, imm8] is op=0x13; rd & rs; imm8 { rd = *:4 (imm8 + rs * 4); }
+cref: imm8 is imm8 { export *[const]:4 imm8; }
+:ldc rd, cref is op=0x14; rd; cref { rd = cpool(0:4, cref, 0:4); }
+"#;
+
+const PSPEC: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<processor_spec>
+  <programcounter register="pc"/>
+  <context_data>
+    <context_set space="ram">
+      <set name="mode" val="0"/>
+    </context_set>
+  </context_data>
+</processor_spec>
+"#;
+
+pub(crate) const CSPEC: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<compiler_spec>
+  <stackpointer register="sp" space="ram"/>
+  <returnaddress>
+    <varnode space="stack" offset="0" size="4"/>
+  </returnaddress>
+  <default_proto>
+    <prototype name="__toycall" extrapop="4" stackshift="4">
+      <input>
+        <pentry minsize="1" maxsize="4"><register name="r0"/></pentry>
+        <pentry minsize="1" maxsize="4"><register name="r1"/></pentry>
+        <pentry minsize="1" maxsize="500" align="4"><addr offset="4" space="stack"/></pentry>
+      </input>
+      <output>
+        <pentry minsize="1" maxsize="4"><register name="r0"/></pentry>
+      </output>
+    </prototype>
+  </default_proto>
+</compiler_spec>
+"#;
+
+/// Spec files of the toy language, written to the temp directory and compiled once per test
+/// run
+pub(crate) fn files() -> &'static LanguageFiles {
+    static FILES: OnceLock<LanguageFiles> = OnceLock::new();
+    FILES.get_or_init(|| {
+        let dir = std::env::temp_dir().join("sleigh-sys-toy");
+        std::fs::create_dir_all(&dir).unwrap();
+        let slaspec = dir.join("toy.slaspec");
+        let files = LanguageFiles {
+            sla: dir.join("toy.sla"),
+            pspec: dir.join("toy.pspec"),
+            cspec: Some(dir.join("toy.cspec")),
+        };
+        std::fs::write(&slaspec, SLASPEC).unwrap();
+        std::fs::write(&files.pspec, PSPEC).unwrap();
+        std::fs::write(files.cspec.as_ref().unwrap(), CSPEC).unwrap();
+        crate::compile::compile_slaspec(&slaspec, &files.sla, &[]).unwrap();
+        files
+    })
+}
+
+/// A flat image of `code` mapped at `base`
+#[derive(Clone)]
+pub(crate) struct ToyImage {
+    pub base: u64,
+    pub code: Vec<u8>,
+}
+
+impl LoadImage for ToyImage {
+    fn load_fill(&mut self, ptr: &mut [u8], addr: &ffi::Address) {
+        let start = addr.getOffset();
+        for (i, b) in ptr.iter_mut().enumerate() {
+            let off = start.wrapping_add(i as u64).wrapping_sub(self.base);
+            *b = self.code.get(off as usize).copied().unwrap_or(0);
+        }
+    }
+}
+
+fn image(base: u64, code: &[u8]) -> ToyImage {
+    ToyImage {
+        base,
+        code: code.to_vec(),
+    }
+}
+
+/// An emulator running `code` at `base`, with the toy calling convention loaded
+pub(crate) fn emulator(base: u64, code: &[u8]) -> Emulator {
+    let mut emu = Emulator::new(files(), image(base, code)).unwrap();
+    emu.set_pc(base);
+    emu
+}
+
+/// A program holding `code` at `base`
+pub(crate) fn program(base: u64, code: &[u8]) -> Program {
+    Program::new(files().clone(), image(base, code)).unwrap()
+}
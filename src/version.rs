@@ -0,0 +1,35 @@
+//! The version of the decompiler engine.
+//!
+//! The engine is compiled from the sources vendored with this crate. When a prebuilt one is
+//! linked through `SLEIGH_SYS_LIB_DIR`, compare [`linked`] with [`supported`] to find out
+//! whether it comes from the release these bindings are written against.
+
+use std::fmt;
+
+use crate::ffi;
+pub use crate::ffi::EngineVersion;
+
+impl fmt::Display for EngineVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}", self.major, self.minor)
+    }
+}
+
+/// The version of the engine linked in
+pub fn linked() -> EngineVersion {
+    ffi::engineVersion()
+}
+
+/// The version of the vendored engine, which this crate is written against
+pub fn supported() -> EngineVersion {
+    ffi::supportedEngineVersion()
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_versions() {
+        assert_eq!(super::linked(), super::supported());
+        assert_eq!(super::supported().to_string(), "4.1");
+    }
+}
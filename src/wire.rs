@@ -0,0 +1,373 @@
+//! Ghidra's packed pcode format.
+//!
+//! This is how Ghidra hands the pcode of one instruction to its decompiler process, read back
+//! by `PcodeEmit::restorePackedOp`. Every byte is offset by 0x20 to stay clear of the tags:
+//! an instruction is the `inst` tag, its length, the index of its space and its address, then
+//! one `op` tag per op with the opcode, the output or `void` and the inputs, each op and the
+//! instruction closed by the `end` tag. Offsets are written 6 bits at a time, lowest first,
+//! and closed by the `end` tag. A varnode is the `addrsz` tag followed by its space index,
+//! offset and size, except for the space-id input of a LOAD or STORE, which is the `spaceid`
+//! tag and the index of the space it stands for, since the constant itself is a pointer into
+//! the process that made it.
+
+use std::fmt;
+
+use crate::pcode::{LiftedInstruction, PcodeOp, Varnode};
+use crate::{ffi, Opcode};
+
+const UNIMPL_TAG: u8 = 0x20;
+const INST_TAG: u8 = 0x21;
+const OP_TAG: u8 = 0x22;
+const VOID_TAG: u8 = 0x23;
+const SPACEID_TAG: u8 = 0x24;
+const ADDRSZ_TAG: u8 = 0x25;
+const END_TAG: u8 = 0x60;
+
+/// Largest opcode, space index or varnode size that fits in a single byte
+const MAX_BYTE: u32 = 0xff - 0x20;
+
+/// Pcode that can't be written in, or bytes that can't be read from, the packed format
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WireError {
+    /// The bytes end in the middle of an instruction
+    Truncated,
+    /// An unexpected byte at this position
+    BadTag {
+        position: usize,
+        tag: u8,
+    },
+    /// An offset longer than 64 bits, at this position
+    BadOffset(usize),
+    /// A space index that isn't one of the decompiler's spaces
+    UnknownSpace(i32),
+    UnknownOpcode(u32),
+    /// A varnode too large to be written
+    BadSize(u32),
+    /// The instruction has no pcode; carries its length
+    Unimplemented(u64),
+}
+
+impl fmt::Display for WireError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WireError::Truncated => write!(f, "packed pcode ends early"),
+            WireError::BadTag { position, tag } => {
+                write!(
+                    f,
+                    "unexpected byte {:#x} at {} in packed pcode",
+                    tag, position
+                )
+            }
+            WireError::BadOffset(position) => write!(f, "bad packed offset at {}", position),
+            WireError::UnknownSpace(index) => write!(f, "no address space with index {}", index),
+            WireError::UnknownOpcode(opcode) => write!(f, "unknown opcode {}", opcode),
+            WireError::BadSize(size) => write!(f, "varnode of {} bytes can't be packed", size),
+            WireError::Unimplemented(length) => {
+                write!(f, "instruction of {} bytes has no pcode", length)
+            }
+        }
+    }
+}
+
+impl std::error::Error for WireError {}
+
+struct Writer<'a> {
+    decompiler: &'a ffi::Decompiler,
+    buf: Vec<u8>,
+}
+
+impl Writer<'_> {
+    fn byte(&mut self, value: u32) {
+        self.buf.push(value as u8 + 0x20);
+    }
+
+    fn offset(&mut self, mut value: u64) {
+        while value != 0 {
+            self.buf.push((value & 0x3f) as u8 + 0x20);
+            value >>= 6;
+        }
+        self.buf.push(END_TAG);
+    }
+
+    fn space(&mut self, index: i32) -> Result<(), WireError> {
+        if index < 0 || index as u32 > MAX_BYTE || self.decompiler.getSpaceConst(index) == 0 {
+            return Err(WireError::UnknownSpace(index));
+        }
+        self.byte(index as u32);
+        Ok(())
+    }
+
+    fn varnode(&mut self, vn: &Varnode) -> Result<(), WireError> {
+        if vn.size > MAX_BYTE {
+            return Err(WireError::BadSize(vn.size));
+        }
+        self.buf.push(ADDRSZ_TAG);
+        self.space(vn.space)?;
+        self.offset(vn.offset);
+        self.byte(vn.size);
+        Ok(())
+    }
+
+    fn op(&mut self, op: &PcodeOp) -> Result<(), WireError> {
+        self.buf.push(OP_TAG);
+        self.byte(op.opcode as u32);
+        match &op.output {
+            Some(vn) => self.varnode(vn)?,
+            None => self.buf.push(VOID_TAG),
+        }
+        let space_id = op.accessed_space(self.decompiler);
+        for (i, vn) in op.inputs.iter().enumerate() {
+            match space_id {
+                Some(space) if i == 0 => {
+                    self.buf.push(SPACEID_TAG);
+                    self.space(space)?;
+                }
+                _ => self.varnode(vn)?,
+            }
+        }
+        self.buf.push(END_TAG);
+        Ok(())
+    }
+}
+
+struct Reader<'a> {
+    decompiler: &'a ffi::Decompiler,
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl Reader<'_> {
+    fn peek(&self) -> Result<u8, WireError> {
+        self.bytes
+            .get(self.pos)
+            .copied()
+            .ok_or(WireError::Truncated)
+    }
+
+    fn tag(&mut self) -> Result<u8, WireError> {
+        let tag = self.peek()?;
+        self.pos += 1;
+        Ok(tag)
+    }
+
+    fn byte(&mut self) -> Result<u32, WireError> {
+        let position = self.pos;
+        match self.tag()? {
+            tag @ 0x20..=0xff => Ok((tag - 0x20) as u32),
+            tag => Err(WireError::BadTag { position, tag }),
+        }
+    }
+
+    fn offset(&mut self) -> Result<u64, WireError> {
+        let position = self.pos;
+        let mut value = 0u64;
+        for shift in (0..67).step_by(6) {
+            match self.tag()? {
+                END_TAG => return Ok(value),
+                tag @ 0x20..=0x5f => {
+                    let chunk = (tag - 0x20) as u64;
+                    // An offset with bits past the 64th doesn't fit
+                    match chunk.checked_shl(shift) {
+                        Some(bits) if bits >> shift == chunk => value |= bits,
+                        _ => return Err(WireError::BadOffset(position)),
+                    }
+                }
+                tag => {
+                    return Err(WireError::BadTag {
+                        position: self.pos - 1,
+                        tag,
+                    })
+                }
+            }
+        }
+        Err(WireError::BadOffset(position))
+    }
+
+    fn space(&mut self) -> Result<i32, WireError> {
+        let index = self.byte()? as i32;
+        if self.decompiler.getSpaceConst(index) == 0 {
+            return Err(WireError::UnknownSpace(index));
+        }
+        Ok(index)
+    }
+
+    fn varnode(&mut self) -> Result<Varnode, WireError> {
+        let position = self.pos;
+        match self.tag()? {
+            ADDRSZ_TAG => Ok(Varnode {
+                space: self.space()?,
+                offset: self.offset()?,
+                size: self.byte()?,
+            }),
+            SPACEID_TAG => {
+                let space = self.space()?;
+                Ok(Varnode::space_id(self.decompiler, space))
+            }
+            tag => Err(WireError::BadTag { position, tag }),
+        }
+    }
+
+    fn op(&mut self) -> Result<PcodeOp, WireError> {
+        self.tag()?;
+        let opcode = self.byte()?;
+        let opcode = Opcode::from_u32(opcode).ok_or(WireError::UnknownOpcode(opcode))?;
+        let output = if self.peek()? == VOID_TAG {
+            self.pos += 1;
+            None
+        } else {
+            Some(self.varnode()?)
+        };
+        let mut inputs = Vec::new();
+        while self.peek()? != END_TAG {
+            inputs.push(self.varnode()?);
+        }
+        self.pos += 1;
+        Ok(PcodeOp {
+            opcode,
+            output,
+            inputs,
+        })
+    }
+}
+
+impl LiftedInstruction {
+    /// Write the instruction in Ghidra's packed pcode format, with the address spaces of
+    /// `decompiler`, which it was lifted by
+    pub fn to_packed_pcode(&self, decompiler: &ffi::Decompiler) -> Result<Vec<u8>, WireError> {
+        let mut writer = Writer {
+            decompiler,
+            buf: vec![INST_TAG],
+        };
+        writer.offset(self.length as u64);
+        writer.space(decompiler.getDefaultCodeSpaceIndex())?;
+        writer.offset(self.address);
+        for op in &self.ops {
+            if op.opcode as u32 > MAX_BYTE {
+                return Err(WireError::UnknownOpcode(op.opcode as u32));
+            }
+            writer.op(op)?;
+        }
+        writer.buf.push(END_TAG);
+        Ok(writer.buf)
+    }
+
+    /// Read an instruction written in Ghidra's packed pcode format, for a language with the
+    /// address spaces of `decompiler`. The instruction's address is taken to be in the default
+    /// code space, whichever space the bytes name. Space-id inputs become constants standing
+    /// for the decompiler's own spaces, as [`ffi::Decompiler::space_from_const`] expects.
+    pub fn from_packed_pcode(
+        bytes: &[u8],
+        decompiler: &ffi::Decompiler,
+    ) -> Result<Self, WireError> {
+        let mut reader = Reader {
+            decompiler,
+            bytes,
+            pos: 0,
+        };
+        let tag = reader.tag()?;
+        let length = reader.offset()?;
+        match tag {
+            INST_TAG => {}
+            UNIMPL_TAG => return Err(WireError::Unimplemented(length)),
+            tag => return Err(WireError::BadTag { position: 0, tag }),
+        }
+        reader.space()?;
+        let address = reader.offset()?;
+        let mut ops = Vec::new();
+        // Ghidra's reader stops at the first byte that isn't an op, the closing tag is optional
+        while reader.peek().ok() == Some(OP_TAG) {
+            ops.push(reader.op()?);
+        }
+        Ok(Self {
+            address,
+            length: length as usize,
+            ops,
+            bytes: Vec::new(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::toy;
+
+    #[test]
+    fn test_packed_pcode() {
+        // movi r0, 0x41 ; st [r1], r0 ; ld r2, [r1]
+        let code = [0x01, 0x00, 0x41, 0x05, 0x10, 0x04, 0x21];
+        let mut program = toy::program(0x1000, &code);
+        let movi = program.lift(0x1000).unwrap().clone();
+        let store = program.lift(0x1003).unwrap().clone();
+        let load = program.lift(0x1005).unwrap().clone();
+        let decompiler = program.decompiler();
+        let ram = decompiler.getDefaultCodeSpaceIndex() as u8 + 0x20;
+        let constant = decompiler.getConstantSpaceIndex() as u8 + 0x20;
+        let register = decompiler
+            .space_hierarchy()
+            .by_name("register")
+            .unwrap()
+            .index as u8
+            + 0x20;
+
+        // COPY (register,0x0,4) <- (const,0x41,4), at 0x1000 = 1 << 12
+        let packed = movi.to_packed_pcode(decompiler).unwrap();
+        assert_eq!(
+            packed,
+            [
+                INST_TAG, 0x23, END_TAG, ram, 0x20, 0x20, 0x21, END_TAG, OP_TAG, 0x21, ADDRSZ_TAG,
+                register, END_TAG, 0x24, ADDRSZ_TAG, constant, 0x21, 0x21, END_TAG, 0x24, END_TAG,
+                END_TAG,
+            ]
+        );
+        for inst in [&movi, &store, &load] {
+            let packed = inst.to_packed_pcode(decompiler).unwrap();
+            let unpacked = LiftedInstruction::from_packed_pcode(&packed, decompiler).unwrap();
+            // Packed pcode doesn't carry the instruction's bytes
+            let expected = LiftedInstruction {
+                bytes: Vec::new(),
+                ..inst.clone()
+            };
+            assert_eq!(unpacked, expected);
+        }
+        let packed = store.to_packed_pcode(decompiler).unwrap();
+        assert!(packed.contains(&SPACEID_TAG));
+
+        assert_eq!(
+            LiftedInstruction::from_packed_pcode(&packed[..packed.len() - 4], decompiler),
+            Err(WireError::Truncated)
+        );
+        assert_eq!(
+            LiftedInstruction::from_packed_pcode(&[UNIMPL_TAG, 0x21, END_TAG], decompiler),
+            Err(WireError::Unimplemented(1))
+        );
+        let mut bad = packed.clone();
+        bad[3] = 0x20 + 0x7f;
+        assert_eq!(
+            LiftedInstruction::from_packed_pcode(&bad, decompiler),
+            Err(WireError::UnknownSpace(0x7f))
+        );
+    }
+
+    #[test]
+    fn test_offset_overflow() {
+        let program = toy::program(0x1000, &[]);
+        let offset = |chunks: &[u8]| {
+            let mut bytes: Vec<u8> = chunks.iter().map(|chunk| chunk + 0x20).collect();
+            bytes.push(END_TAG);
+            Reader {
+                decompiler: program.decompiler(),
+                bytes: &bytes,
+                pos: 0,
+            }
+            .offset()
+        };
+        let mut chunks = [0x3f; 11];
+        chunks[10] = 0xf;
+        assert_eq!(offset(&chunks), Ok(u64::MAX));
+        // Bits past the 64th
+        chunks[10] = 0x10;
+        assert_eq!(offset(&chunks), Err(WireError::BadOffset(0)));
+        assert_eq!(offset(&[0; 12]), Err(WireError::BadOffset(0)));
+    }
+}
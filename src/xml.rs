@@ -0,0 +1,142 @@
+//! Owned XML trees built with the decompiler's own XML parser.
+//!
+//! Ghidra's specification files (`.ldefs`, `.pspec`, `.cspec`, ...) are all XML. Rather than
+//! pulling in a second parser, they are parsed by the vendored one and copied into plain Rust
+//! values so they can be inspected without holding on to the C++ document.
+
+use std::fmt;
+
+use crate::ffi;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct XmlElement {
+    pub name: String,
+    pub attributes: Vec<(String, String)>,
+    pub content: String,
+    pub children: Vec<XmlElement>,
+}
+
+impl XmlElement {
+    /// Parse a document and return its root element
+    pub fn parse(text: &str) -> Result<Self, cxx::Exception> {
+        cxx::let_cxx_string!(text = text);
+        let doc = ffi::parseDocument(&text)?;
+        Ok(Self::from_element(ffi::getDocumentRoot(&doc)))
+    }
+
+    fn from_element(el: &ffi::Element) -> Self {
+        let attributes = (0..el.getNumAttributes())
+            .map(|i| {
+                (
+                    el.getAttributeName(i).to_string_lossy().into_owned(),
+                    el.getAttributeValue(i).to_string_lossy().into_owned(),
+                )
+            })
+            .collect();
+        let children = (0..ffi::getElementNumChildren(el))
+            .map(|i| Self::from_element(ffi::getElementChild(el, i)))
+            .collect();
+        Self {
+            name: el.getName().to_string_lossy().into_owned(),
+            attributes,
+            content: el.getContent().to_string_lossy().into_owned(),
+            children,
+        }
+    }
+
+    /// Value of the attribute `name`, if present
+    pub fn attribute(&self, name: &str) -> Option<&str> {
+        self.attributes
+            .iter()
+            .find(|(k, _)| k == name)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// First child element called `name`
+    pub fn child(&self, name: &str) -> Option<&XmlElement> {
+        self.children.iter().find(|c| c.name == name)
+    }
+
+    /// All child elements called `name`
+    pub fn children_named<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a XmlElement> {
+        self.children.iter().filter(move |c| c.name == name)
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+impl fmt::Display for XmlElement {
+    /// The element as XML text, which parses back to an equal element
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "<{}", self.name)?;
+        for (name, value) in &self.attributes {
+            write!(f, " {}=\"{}\"", name, escape(value))?;
+        }
+        if self.content.is_empty() && self.children.is_empty() {
+            return write!(f, "/>");
+        }
+        write!(f, ">{}", escape(&self.content))?;
+        for child in &self.children {
+            write!(f, "{}", child)?;
+        }
+        write!(f, "</{}>", self.name)
+    }
+}
+
+/// Parse an integer attribute the way the decompiler does: decimal, or hex with a `0x` prefix
+pub(crate) fn parse_int(text: &str) -> Option<i64> {
+    let text = text.trim();
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, text),
+    };
+    let value = match digits
+        .strip_prefix("0x")
+        .or_else(|| digits.strip_prefix("0X"))
+    {
+        Some(hex) => u64::from_str_radix(hex, 16).ok()? as i64,
+        None => digits.parse::<u64>().ok()? as i64,
+    };
+    Some(if negative {
+        value.wrapping_neg()
+    } else {
+        value
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_int, XmlElement};
+
+    #[test]
+    fn test_parse_int() {
+        assert_eq!(parse_int("10"), Some(10));
+        assert_eq!(parse_int("0x10"), Some(16));
+        assert_eq!(parse_int("-4"), Some(-4));
+        assert_eq!(parse_int("bogus"), None);
+    }
+
+    #[test]
+    fn test_parse() {
+        let root = XmlElement::parse(r#"<a x="1"><b>text</b><b/><c y="2"/></a>"#).unwrap();
+        assert_eq!(root.name, "a");
+        assert_eq!(root.attribute("x"), Some("1"));
+        assert_eq!(root.children_named("b").count(), 2);
+        assert_eq!(root.child("b").unwrap().content, "text");
+        assert_eq!(root.child("c").unwrap().attribute("y"), Some("2"));
+        assert_eq!(XmlElement::parse(&root.to_string()).unwrap(), root);
+        let quoted = XmlElement::parse(r#"<a x="&quot;&lt;&amp;"/>"#).unwrap();
+        assert_eq!(quoted.to_string(), r#"<a x="&quot;&lt;&amp;"/>"#);
+    }
+
+    #[test]
+    fn test_parse_error() {
+        assert!(XmlElement::parse("<a><b></a>").is_err());
+        assert!(XmlElement::parse("").is_err());
+    }
+}